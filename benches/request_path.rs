@@ -0,0 +1,131 @@
+//! Benchmarks for the SOAP request path.
+//!
+//! Run with: cargo bench --bench request_path
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use mock_igd::matcher::{AddPortMappingRequest, SoapRequest, SoapRequestBody};
+use mock_igd::{Action, Matcher, MockIgdServer, Protocol, Responder, ServiceType};
+
+const MOCK_COUNTS: [u16; 3] = [1, 100, 1000];
+
+fn add_port_mapping_request(external_port: u16) -> SoapRequest {
+    SoapRequest {
+        action_name: "AddPortMapping".to_string(),
+        service_type: ServiceType::WANIPConnection(1),
+        body: SoapRequestBody::AddPortMapping(AddPortMappingRequest {
+            remote_host: String::new(),
+            external_port,
+            protocol: "TCP".to_string(),
+            internal_port: external_port,
+            internal_client: "192.168.1.100".to_string(),
+            enabled: true,
+            description: "bench".to_string(),
+            lease_duration: 0,
+        }),
+        raw_body: add_port_mapping_body(external_port),
+        arguments: Vec::new(),
+        client: None,
+        wan_device: 0,
+    }
+}
+
+fn add_port_mapping_body(external_port: u16) -> String {
+    format!(
+        r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body>
+<u:AddPortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewRemoteHost></NewRemoteHost>
+<NewExternalPort>{external_port}</NewExternalPort>
+<NewProtocol>TCP</NewProtocol>
+<NewInternalPort>{external_port}</NewInternalPort>
+<NewInternalClient>192.168.1.100</NewInternalClient>
+<NewEnabled>1</NewEnabled>
+<NewPortMappingDescription>bench</NewPortMappingDescription>
+<NewLeaseDuration>0</NewLeaseDuration>
+</u:AddPortMapping>
+</s:Body>
+</s:Envelope>"#
+    )
+}
+
+/// Match a request against many mocks and generate the response.
+fn bench_match_and_respond(c: &mut Criterion) {
+    let mut group = c.benchmark_group("match_and_respond");
+    for count in MOCK_COUNTS {
+        let mocks: Vec<(Action, Responder)> = (0..count)
+            .map(|port| {
+                (
+                    Action::add_port_mapping()
+                        .with_external_port(port)
+                        .with_protocol(Protocol::TCP)
+                        .build(),
+                    Responder::success().build(),
+                )
+            })
+            .collect();
+        // Worst case: the matching mock is the last one checked
+        let request = add_port_mapping_request(count - 1);
+
+        group.bench_with_input(
+            BenchmarkId::from_parameter(count),
+            &request,
+            |b, request| {
+                b.iter(|| {
+                    mocks
+                        .iter()
+                        .find(|(action, _)| action.matches(request))
+                        .map(|(_, responder)| responder.respond(request))
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Full HTTP round trip: SOAP parse, match and respond.
+fn bench_http_round_trip(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let client = reqwest::Client::new();
+
+    let mut group = c.benchmark_group("http_round_trip");
+    for count in MOCK_COUNTS {
+        let server = runtime.block_on(async {
+            let server = MockIgdServer::start().await.unwrap();
+            for port in 0..count {
+                server
+                    .mock(
+                        Action::add_port_mapping().with_external_port(port),
+                        Responder::success(),
+                    )
+                    .await;
+            }
+            server
+        });
+        let url = server.control_url();
+        let body = add_port_mapping_body(count - 1);
+
+        group.bench_function(BenchmarkId::from_parameter(count), |b| {
+            b.to_async(&runtime).iter(|| async {
+                client
+                    .post(&url)
+                    .header("Content-Type", "text/xml; charset=\"utf-8\"")
+                    .header(
+                        "SOAPAction",
+                        "\"urn:schemas-upnp-org:service:WANIPConnection:1#AddPortMapping\"",
+                    )
+                    .body(body.clone())
+                    .send()
+                    .await
+                    .unwrap()
+                    .bytes()
+                    .await
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_match_and_respond, bench_http_round_trip);
+criterion_main!(benches);
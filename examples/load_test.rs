@@ -0,0 +1,90 @@
+//! Load-test example firing many concurrent SOAP requests at the mock.
+//!
+//! Run with: cargo run --release --example load_test -- [requests] [concurrency]
+
+use mock_igd::{Action, MockIgdServer, Responder};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Semaphore;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let requests: usize = args
+        .next()
+        .map(|s| s.parse())
+        .transpose()?
+        .unwrap_or(10_000);
+    let concurrency: usize = args.next().map(|s| s.parse()).transpose()?.unwrap_or(100);
+
+    let server = MockIgdServer::start().await?;
+
+    // A realistic scenario with many registered mocks
+    for port in 1..=1000 {
+        server
+            .mock(
+                Action::add_port_mapping().with_external_port(port),
+                Responder::success(),
+            )
+            .await;
+    }
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("203.0.113.42".parse().unwrap()),
+        )
+        .await;
+
+    println!("Sending {requests} requests with concurrency {concurrency}...");
+
+    let client = reqwest::Client::new();
+    let url = server.control_url();
+    let slots = Arc::new(Semaphore::new(concurrency));
+    let started = Instant::now();
+
+    let mut tasks = Vec::with_capacity(requests);
+    for _ in 0..requests {
+        let permit = slots.clone().acquire_owned().await?;
+        let client = client.clone();
+        let url = url.clone();
+        tasks.push(tokio::spawn(async move {
+            let response = client
+                .post(&url)
+                .header("Content-Type", "text/xml; charset=\"utf-8\"")
+                .header(
+                    "SOAPAction",
+                    "\"urn:schemas-upnp-org:service:WANIPConnection:1#GetExternalIPAddress\"",
+                )
+                .body(
+                    r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+<s:Body><u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1"/></s:Body>
+</s:Envelope>"#,
+                )
+                .send()
+                .await;
+            drop(permit);
+            response.map(|r| r.status().is_success()).unwrap_or(false)
+        }));
+    }
+
+    let mut succeeded = 0;
+    for task in tasks {
+        if task.await? {
+            succeeded += 1;
+        }
+    }
+    let elapsed = started.elapsed();
+
+    let metrics = server.metrics().await;
+    println!("Completed {succeeded}/{requests} requests in {elapsed:?}");
+    println!(
+        "Throughput: {:.0} requests/s",
+        requests as f64 / elapsed.as_secs_f64()
+    );
+    if let Some(latency) = metrics.average_latency() {
+        println!("Average mock-side handling latency: {latency:?}");
+    }
+
+    Ok(())
+}
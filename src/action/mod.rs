@@ -1,5 +1,7 @@
 //! UPnP IGD action types and parsing.
 
+mod service;
 mod types;
 
+pub use service::ServiceType;
 pub use types::*;
@@ -0,0 +1,111 @@
+//! UPnP IGD service types.
+
+use std::convert::Infallible;
+use std::str::FromStr;
+
+/// URN prefix shared by all standard UPnP service types.
+const URN_PREFIX: &str = "urn:schemas-upnp-org:service:";
+
+/// A UPnP service type, identified by its URN
+/// (e.g. `urn:schemas-upnp-org:service:WANIPConnection:1`).
+///
+/// Known IGD services carry their version; anything else is kept verbatim
+/// in [`ServiceType::Other`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ServiceType {
+    /// `WANIPConnection` of the given version.
+    WANIPConnection(u32),
+    /// `WANPPPConnection` of the given version.
+    WANPPPConnection(u32),
+    /// `WANCommonInterfaceConfig` of the given version.
+    WANCommonInterfaceConfig(u32),
+    /// `Layer3Forwarding` of the given version.
+    Layer3Forwarding(u32),
+    /// Any other (or malformed) service type string.
+    Other(String),
+}
+
+impl ServiceType {
+    /// Parse a service type URN. Never fails: unknown values become
+    /// [`ServiceType::Other`].
+    pub fn parse(urn: &str) -> Self {
+        let known = urn
+            .strip_prefix(URN_PREFIX)
+            .and_then(|rest| rest.split_once(':'))
+            .and_then(|(name, version)| Some((name, version.parse::<u32>().ok()?)));
+        match known {
+            Some(("WANIPConnection", version)) => ServiceType::WANIPConnection(version),
+            Some(("WANPPPConnection", version)) => ServiceType::WANPPPConnection(version),
+            Some(("WANCommonInterfaceConfig", version)) => {
+                ServiceType::WANCommonInterfaceConfig(version)
+            }
+            Some(("Layer3Forwarding", version)) => ServiceType::Layer3Forwarding(version),
+            _ => ServiceType::Other(urn.to_string()),
+        }
+    }
+
+    /// Render the service type URN.
+    pub fn urn(&self) -> String {
+        match self {
+            ServiceType::Other(urn) => urn.clone(),
+            _ => format!(
+                "{URN_PREFIX}{}:{}",
+                self.name(),
+                self.version().unwrap_or(1)
+            ),
+        }
+    }
+
+    /// The service name, e.g. `WANIPConnection` (the raw string for
+    /// [`ServiceType::Other`]).
+    pub fn name(&self) -> &str {
+        match self {
+            ServiceType::WANIPConnection(_) => "WANIPConnection",
+            ServiceType::WANPPPConnection(_) => "WANPPPConnection",
+            ServiceType::WANCommonInterfaceConfig(_) => "WANCommonInterfaceConfig",
+            ServiceType::Layer3Forwarding(_) => "Layer3Forwarding",
+            ServiceType::Other(urn) => urn,
+        }
+    }
+
+    /// The service version (`None` for [`ServiceType::Other`]).
+    pub fn version(&self) -> Option<u32> {
+        match self {
+            ServiceType::WANIPConnection(version)
+            | ServiceType::WANPPPConnection(version)
+            | ServiceType::WANCommonInterfaceConfig(version)
+            | ServiceType::Layer3Forwarding(version) => Some(*version),
+            ServiceType::Other(_) => None,
+        }
+    }
+
+    /// The version 1 service the mock serves `action_name` on.
+    pub(crate) fn for_action(action_name: &str) -> Self {
+        match action_name {
+            "GetCommonLinkProperties" | "GetTotalBytesReceived" | "GetTotalBytesSent" => {
+                ServiceType::WANCommonInterfaceConfig(1)
+            }
+            _ => ServiceType::WANIPConnection(1),
+        }
+    }
+}
+
+impl std::fmt::Display for ServiceType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.urn())
+    }
+}
+
+impl FromStr for ServiceType {
+    type Err = Infallible;
+
+    fn from_str(urn: &str) -> Result<Self, Self::Err> {
+        Ok(ServiceType::parse(urn))
+    }
+}
+
+impl From<&str> for ServiceType {
+    fn from(urn: &str) -> Self {
+        ServiceType::parse(urn)
+    }
+}
@@ -7,6 +7,8 @@ use std::net::IpAddr;
 pub enum Protocol {
     TCP,
     UDP,
+    /// Either protocol; meant for matchers, not for mapping entries.
+    Any,
 }
 
 impl Protocol {
@@ -14,6 +16,28 @@ impl Protocol {
         match self {
             Protocol::TCP => "TCP",
             Protocol::UDP => "UDP",
+            Protocol::Any => "*",
+        }
+    }
+
+    /// Whether `self`, used as a pattern, accepts `other`.
+    pub fn matches(&self, other: Protocol) -> bool {
+        *self == Protocol::Any || *self == other
+    }
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = crate::Error;
+
+    /// Parse `TCP` or `UDP`, ignoring case and surrounding whitespace.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("TCP") {
+            Ok(Protocol::TCP)
+        } else if s.eq_ignore_ascii_case("UDP") {
+            Ok(Protocol::UDP)
+        } else {
+            Err(crate::Error::InvalidProtocol(s.to_string()))
         }
     }
 }
@@ -98,6 +122,8 @@ pub struct AddPortMappingParams {
     pub protocol: Option<Protocol>,
     pub internal_port: Option<u16>,
     pub internal_client: Option<IpAddr>,
+    /// Required `NewEnabled` value.
+    pub enabled: Option<bool>,
     pub description: Option<String>,
 }
 
@@ -128,6 +154,13 @@ impl AddPortMappingBuilder {
         self
     }
 
+    /// Only match requests whose `NewEnabled` flag is `enabled`, e.g. to
+    /// tell a client adding a disabled mapping from the call enabling it.
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        self.params.enabled = Some(enabled);
+        self
+    }
+
     pub fn with_description(mut self, desc: impl Into<String>) -> Self {
         self.params.description = Some(desc.into());
         self
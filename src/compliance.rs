@@ -0,0 +1,220 @@
+//! UPnP conformance checks for client requests.
+
+use crate::matcher::{SoapRequest, SsdpDelivery};
+use crate::mock::ReceivedSsdpRequest;
+
+/// A UPnP rule that client requests are checked against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ComplianceRule {
+    /// The SOAPAction header value must be enclosed in double quotes.
+    QuotedSoapAction,
+    /// Every input argument of the action must be present.
+    RequiredArguments,
+    /// Input arguments must appear in the order defined by the SCPD.
+    ArgumentOrder,
+    /// Boolean arguments such as `NewEnabled` must be `0`, `1`, `true`,
+    /// `false`, `yes` or `no`.
+    BooleanValues,
+    /// The MAN header of an M-SEARCH must be `"ssdp:discover"`, quotes
+    /// included.
+    SearchMan,
+    /// A multicast M-SEARCH must have an MX header between 1 and 5.
+    ///
+    /// Whether a search was multicast is judged by where it was sent; see
+    /// [`ReceivedSsdpRequest::delivery`].
+    SearchMx,
+    /// A multicast M-SEARCH must be addressed to `239.255.255.250:1900`.
+    ///
+    /// Whether a search was multicast is judged by where it was sent; see
+    /// [`ReceivedSsdpRequest::delivery`].
+    SearchHost,
+}
+
+impl std::fmt::Display for ComplianceRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ComplianceRule::QuotedSoapAction => "quoted-soap-action",
+            ComplianceRule::RequiredArguments => "required-arguments",
+            ComplianceRule::ArgumentOrder => "argument-order",
+            ComplianceRule::BooleanValues => "boolean-values",
+            ComplianceRule::SearchMan => "search-man",
+            ComplianceRule::SearchMx => "search-mx",
+            ComplianceRule::SearchHost => "search-host",
+        };
+        f.write_str(name)
+    }
+}
+
+/// How the server reacts to violations of a [`ComplianceRule`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ComplianceLevel {
+    /// Don't report violations.
+    Ignore,
+    /// Log and record violations, but answer the request normally.
+    #[default]
+    Warn,
+    /// Log and record violations, and answer with UPnP error 402
+    /// (`Invalid Args`) like a strict gateway would; M-SEARCH requests are
+    /// left unanswered.
+    Reject,
+}
+
+/// A client request that violated a [`ComplianceRule`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComplianceViolation {
+    /// The violated rule.
+    pub rule: ComplianceRule,
+    /// The action of the offending request (`M-SEARCH` for discovery).
+    pub action_name: String,
+    /// What exactly was wrong.
+    pub message: String,
+}
+
+impl std::fmt::Display for ComplianceViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} [{}]: {}", self.action_name, self.rule, self.message)
+    }
+}
+
+/// Input arguments of each known action, in SCPD order.
+fn input_arguments(action_name: &str) -> &'static [&'static str] {
+    match action_name {
+        "AddPortMapping" => &[
+            "NewRemoteHost",
+            "NewExternalPort",
+            "NewProtocol",
+            "NewInternalPort",
+            "NewInternalClient",
+            "NewEnabled",
+            "NewPortMappingDescription",
+            "NewLeaseDuration",
+        ],
+        "DeletePortMapping" | "GetSpecificPortMappingEntry" => {
+            &["NewRemoteHost", "NewExternalPort", "NewProtocol"]
+        }
+        "GetGenericPortMappingEntry" => &["NewPortMappingIndex"],
+        _ => &[],
+    }
+}
+
+/// Arguments of boolean type.
+const BOOLEAN_ARGUMENTS: &[&str] = &["NewEnabled"];
+
+/// Check a request against every rule.
+pub(crate) fn check(soap_action: &str, request: &SoapRequest) -> Vec<ComplianceViolation> {
+    let mut violations = Vec::new();
+    let mut violation = |rule, message: String| {
+        violations.push(ComplianceViolation {
+            rule,
+            action_name: request.action_name.clone(),
+            message,
+        })
+    };
+
+    let trimmed = soap_action.trim();
+    if !(trimmed.len() >= 2 && trimmed.starts_with('"') && trimmed.ends_with('"')) {
+        violation(
+            ComplianceRule::QuotedSoapAction,
+            format!("SOAPAction header is not quoted: {soap_action}"),
+        );
+    }
+
+    let expected = input_arguments(&request.action_name);
+    let missing: Vec<&str> = expected
+        .iter()
+        .copied()
+        .filter(|name| request.argument(name).is_none())
+        .collect();
+    if !missing.is_empty() {
+        violation(
+            ComplianceRule::RequiredArguments,
+            format!("missing arguments: {}", missing.join(", ")),
+        );
+    }
+
+    let actual: Vec<&str> = request
+        .arguments
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .filter(|name| expected.contains(name))
+        .collect();
+    let expected_order: Vec<&str> = expected
+        .iter()
+        .copied()
+        .filter(|name| actual.contains(name))
+        .collect();
+    if actual != expected_order {
+        violation(
+            ComplianceRule::ArgumentOrder,
+            format!("arguments in order {actual:?}, expected {expected_order:?}"),
+        );
+    }
+
+    for (name, value) in &request.arguments {
+        let is_boolean = ["0", "1", "true", "false", "yes", "no"].contains(&value.as_str());
+        if BOOLEAN_ARGUMENTS.contains(&name.as_str()) && !is_boolean {
+            violation(
+                ComplianceRule::BooleanValues,
+                format!("{name} has invalid boolean value {value:?}"),
+            );
+        }
+    }
+
+    violations
+}
+
+/// Check an M-SEARCH request against the discovery rules.
+pub(crate) fn check_msearch(request: &ReceivedSsdpRequest) -> Vec<ComplianceViolation> {
+    let mut violations = Vec::new();
+    let mut violation = |rule, message: String| {
+        violations.push(ComplianceViolation {
+            rule,
+            action_name: "M-SEARCH".to_string(),
+            message,
+        })
+    };
+
+    match request.headers.get("MAN") {
+        Some("\"ssdp:discover\"") => {}
+        Some(man) => violation(
+            ComplianceRule::SearchMan,
+            format!("MAN is {man}, expected \"ssdp:discover\""),
+        ),
+        None => violation(
+            ComplianceRule::SearchMan,
+            "MAN header is missing".to_string(),
+        ),
+    }
+
+    // Judged by where the search was sent, not by its HOST header
+    let multicast = request.delivery() == SsdpDelivery::Multicast;
+    match request.headers.get("HOST") {
+        None => violation(
+            ComplianceRule::SearchHost,
+            "HOST header is missing".to_string(),
+        ),
+        Some(host) if multicast => {
+            if host != "239.255.255.250:1900" {
+                violation(
+                    ComplianceRule::SearchHost,
+                    format!("HOST is {host}, expected 239.255.255.250:1900"),
+                );
+            }
+        }
+        // Unicast searches are addressed to the device
+        Some(_) => {}
+    }
+
+    if multicast {
+        match request.headers.get("MX") {
+            Some(mx) if mx.parse::<u32>().is_ok_and(|mx| (1..=5).contains(&mx)) => {}
+            Some(mx) => violation(
+                ComplianceRule::SearchMx,
+                format!("MX is {mx}, expected 1 to 5"),
+            ),
+            None => violation(ComplianceRule::SearchMx, "MX header is missing".to_string()),
+        }
+    }
+
+    violations
+}
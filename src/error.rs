@@ -12,11 +12,50 @@ pub enum Error {
     #[error("failed to bind to address: {0}")]
     Bind(#[from] std::io::Error),
 
+    /// Failed to bind the SSDP socket.
+    #[error("failed to bind SSDP socket to port {port}: {io}")]
+    SsdpBindFailed {
+        /// Port the socket was bound to.
+        port: u16,
+        /// Underlying error.
+        #[source]
+        io: std::io::Error,
+    },
+
+    /// Failed to join the SSDP multicast group.
+    #[error("failed to join SSDP multicast group on interface {interface}: {io}")]
+    MulticastJoinFailed {
+        /// Interface the group was joined on (unspecified = chosen by the OS).
+        interface: std::net::Ipv4Addr,
+        /// Underlying error.
+        #[source]
+        io: std::io::Error,
+    },
+
+    /// Builder options that can't be used together or have invalid values.
+    #[error("invalid server configuration: {reason}")]
+    InvalidBuilderConfig {
+        /// What is wrong and how to fix it.
+        reason: String,
+    },
+
     /// Invalid SOAP action.
     #[error("invalid SOAP action: {0}")]
     InvalidAction(String),
 
+    /// Not a port mapping protocol.
+    #[error("invalid protocol: {0}")]
+    InvalidProtocol(String),
+
+    /// No scenario with the given name was loaded.
+    #[error("unknown scenario: {0}")]
+    UnknownScenario(String),
+
     /// Server is not running.
     #[error("server is not running")]
     ServerNotRunning,
+
+    /// A server task died or panicked.
+    #[error("server failure: {0}")]
+    ServerFailure(String),
 }
@@ -23,16 +23,36 @@
 //! ```
 
 pub mod action;
+pub mod compliance;
 pub mod error;
+mod macros;
+pub mod mapping;
 pub mod matcher;
+pub mod metrics;
 pub mod mock;
 pub mod responder;
+mod rng;
+pub mod scenario;
 pub mod server;
+#[cfg(all(feature = "validate", debug_assertions))]
+mod validate;
 
 // Re-exports for convenience
-pub use action::{Action, Protocol};
+pub use action::{Action, Protocol, ServiceType};
+pub use compliance::{ComplianceLevel, ComplianceRule, ComplianceViolation};
 pub use error::{Error, Result};
-pub use matcher::Matcher;
-pub use mock::{ReceivedRequest, ReceivedSsdpRequest};
-pub use responder::Responder;
-pub use server::MockIgdServer;
+pub use mapping::{MappingQuirk, PortMapping};
+pub use matcher::{FieldMismatch, Matcher, SsdpDelivery, SsdpMatcher};
+pub use metrics::{ActionCoverage, LatencyStats, Metrics};
+pub use mock::{
+    ClientActivity, ClientAgentStats, ExhaustionPolicy, Mock, NearMiss, ReceivedRequest,
+    ReceivedSsdpNotification, ReceivedSsdpRequest, ResponderPanic, SentSsdpNotification,
+    SsdpHeaders,
+};
+pub use responder::{Responder, SsdpResponse};
+pub use scenario::Scenario;
+pub use server::{
+    CallbackValidation, ClientFilter, ClientRejection, ClockSkew, DeliveryOutcome,
+    DescriptionRedirect, EventDelivery, EventSeqFault, KeepAlive, MockIgdServer, ReservedPort,
+    ResponseEncoding, ResponseLint, UsnFormat,
+};
@@ -0,0 +1,50 @@
+//! Assertion macros for received requests.
+
+/// Assert that a [`ReceivedRequest`](crate::ReceivedRequest) is an
+/// `AddPortMapping` request whose fields equal the given values.
+///
+/// Fields are those of
+/// [`AddPortMappingRequest`](crate::matcher::AddPortMappingRequest).
+///
+/// # Example
+///
+/// ```ignore
+/// let requests = server.received_requests().await;
+/// assert_add_port_mapping!(requests[0], external_port = 8080, protocol = "TCP");
+/// ```
+#[macro_export]
+macro_rules! assert_add_port_mapping {
+    ($request:expr $(, $field:ident = $value:expr)* $(,)?) => {{
+        let request: &$crate::ReceivedRequest = &$request;
+        let Some(add) = request.as_add_port_mapping() else {
+            panic!("expected an AddPortMapping request, got {}", request.action_name);
+        };
+        $(
+            assert_eq!(add.$field, $value, concat!("AddPortMapping ", stringify!($field)));
+        )*
+    }};
+}
+
+/// Assert that a [`ReceivedRequest`](crate::ReceivedRequest) is a
+/// `DeletePortMapping` request whose fields equal the given values.
+///
+/// Fields are those of
+/// [`DeletePortMappingRequest`](crate::matcher::DeletePortMappingRequest).
+///
+/// # Example
+///
+/// ```ignore
+/// assert_delete_port_mapping!(requests[1], external_port = 8080);
+/// ```
+#[macro_export]
+macro_rules! assert_delete_port_mapping {
+    ($request:expr $(, $field:ident = $value:expr)* $(,)?) => {{
+        let request: &$crate::ReceivedRequest = &$request;
+        let Some(delete) = request.as_delete_port_mapping() else {
+            panic!("expected a DeletePortMapping request, got {}", request.action_name);
+        };
+        $(
+            assert_eq!(delete.$field, $value, concat!("DeletePortMapping ", stringify!($field)));
+        )*
+    }};
+}
@@ -0,0 +1,370 @@
+//! Stateful port mapping table.
+//!
+//! When enabled with
+//! [`MockIgdServerBuilder::with_port_mapping_table`](crate::server::MockIgdServerBuilder::with_port_mapping_table),
+//! port mapping actions that no mock matches are answered from an in-memory
+//! table, the way a real gateway would.
+
+use crate::action::Protocol;
+use crate::matcher::{
+    AddPortMappingRequest, DeletePortMappingRequest, SoapRequest, SoapRequestBody, UnknownRequest,
+};
+use crate::responder::{Responder, ResponseBody, SuccessResponseBuilder};
+use crate::server::events::EventPublisher;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// A port mapping held by the gateway.
+///
+/// The same type describes entries of the port mapping table, seeded
+/// mappings and the mapping a received `AddPortMapping` asked for (see
+/// [`ReceivedRequest::port_mapping`](crate::ReceivedRequest::port_mapping)).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortMapping {
+    /// Remote host the mapping is restricted to (empty for any host).
+    pub remote_host: String,
+    pub external_port: u16,
+    pub protocol: Protocol,
+    pub internal_port: u16,
+    pub internal_client: IpAddr,
+    pub enabled: bool,
+    pub description: String,
+    /// Lease duration in seconds (0 for a permanent mapping).
+    pub lease_duration: u32,
+}
+
+/// Firmware behaviors the port mapping table can reproduce.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MappingQuirk {
+    /// Accept any `NewLeaseDuration` but store the mapping as permanent, so
+    /// `GetSpecificPortMappingEntry` reports a lease duration of 0.
+    IgnoreLeaseDuration,
+    /// Report success but create mappings this many ports above the
+    /// requested external port. The real port is only visible through
+    /// `GetGenericPortMappingEntry` or `AddAnyPortMapping`'s
+    /// `NewReservedPort`. Requests shifted past port 65535 fail with 728
+    /// (`NoPortMapsAvailable`).
+    ShiftExternalPort(u16),
+    /// Never event `PortMappingNumberOfEntries`, so subscribers must poll
+    /// to notice changes.
+    NoEventing,
+    /// Accept and list mappings but never forward traffic through them, so
+    /// [`MockIgdServer::is_port_reachable`](crate::MockIgdServer::is_port_reachable)
+    /// reports every port closed, like routers that lie about mappings.
+    NotForwarded,
+}
+
+/// In-memory port mapping table shared by all control endpoints.
+///
+/// Changes to the number of entries are evented to subscribers as
+/// `PortMappingNumberOfEntries`. Mappings with a lease are dropped once it
+/// runs out, measured on tokio's clock so paused-time tests can skip ahead.
+pub(crate) struct PortMappingTable {
+    mappings: Mutex<Vec<Entry>>,
+    quirks: Vec<MappingQuirk>,
+    /// Quirks of the active scenario, applied on top of `quirks`.
+    scenario_quirks: Mutex<Vec<MappingQuirk>>,
+    events: Arc<EventPublisher>,
+    /// Signalled whenever a mapping is added, replaced or removed.
+    changes: watch::Sender<()>,
+}
+
+impl PortMappingTable {
+    /// Create an empty table applying the given quirks.
+    pub fn new(quirks: Vec<MappingQuirk>, events: Arc<EventPublisher>) -> Self {
+        PortMappingTable {
+            mappings: Mutex::new(Vec::new()),
+            quirks,
+            scenario_quirks: Mutex::new(Vec::new()),
+            events,
+            changes: watch::Sender::new(()),
+        }
+    }
+
+    /// Apply `quirks` on top of the table's own, replacing those of the
+    /// previously active scenario.
+    pub fn set_scenario_quirks(&self, quirks: Vec<MappingQuirk>) {
+        *self
+            .scenario_quirks
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = quirks;
+    }
+
+    /// Watch for mappings being added, replaced or removed.
+    pub fn watch(&self) -> watch::Receiver<()> {
+        self.changes.subscribe()
+    }
+
+    /// Number of mappings.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Current mappings, in insertion order.
+    pub fn mappings(&self) -> Vec<PortMapping> {
+        self.lock().iter().map(Entry::current).collect()
+    }
+
+    /// Add a mapping, replacing any with the same remote host, external port
+    /// and protocol.
+    pub fn insert(&self, mapping: PortMapping) {
+        let mut mappings = self.lock();
+        match mappings
+            .iter_mut()
+            .find(|e| is_same_mapping(&e.mapping, &mapping))
+        {
+            Some(existing) => {
+                *existing = Entry::new(mapping);
+                self.changes.send_replace(());
+            }
+            None => {
+                mappings.push(Entry::new(mapping));
+                let count = mappings.len();
+                drop(mappings);
+                self.publish_count(count);
+            }
+        }
+    }
+
+    /// The mappings traffic is forwarded through: the enabled ones, unless
+    /// the table applies [`MappingQuirk::NotForwarded`].
+    pub fn forwarded(&self) -> Vec<PortMapping> {
+        if self.has_quirk(&MappingQuirk::NotForwarded) {
+            return Vec::new();
+        }
+        self.mappings().into_iter().filter(|m| m.enabled).collect()
+    }
+
+    /// Remove mappings of `external_port` and `protocol` (for any remote
+    /// host); returns `false` if there were none.
+    pub fn remove(&self, external_port: u16, protocol: Protocol) -> bool {
+        let mut mappings = self.lock();
+        let before = mappings.len();
+        mappings
+            .retain(|e| e.mapping.external_port != external_port || e.mapping.protocol != protocol);
+        let count = mappings.len();
+        drop(mappings);
+        if count == before {
+            return false;
+        }
+        self.publish_count(count);
+        true
+    }
+
+    /// Remove every mapping.
+    pub fn clear(&self) {
+        self.lock().clear();
+        self.changes.send_replace(());
+    }
+
+    /// Answer a port mapping request from the table, or `None` for other
+    /// actions.
+    pub fn respond(&self, request: &SoapRequest) -> Option<ResponseBody> {
+        let responder = match &request.body {
+            SoapRequestBody::AddPortMapping(req) => match self.add(req, false) {
+                Ok(_) => Responder::success().build(),
+                Err(error) => error,
+            },
+            SoapRequestBody::Unknown(req) if req.action_name == "AddAnyPortMapping" => {
+                match self.add(&add_any_port_mapping_request(req), true) {
+                    Ok(port) => Responder::success().with_reserved_port(port).build(),
+                    Err(error) => error,
+                }
+            }
+            SoapRequestBody::DeletePortMapping(req) => self.delete(req),
+            SoapRequestBody::GetSpecificPortMappingEntry(req) => {
+                match self.find(&req.remote_host, req.external_port, &req.protocol) {
+                    Some(mapping) => SuccessResponseBuilder::from_mapping(mapping).build(),
+                    None => Responder::error(714, "NoSuchEntryInArray"),
+                }
+            }
+            SoapRequestBody::GetGenericPortMappingEntry(req) => {
+                match self.lock().get(req.index as usize).map(Entry::current) {
+                    Some(mapping) => SuccessResponseBuilder::from_mapping(mapping).build(),
+                    None => Responder::error(713, "SpecifiedArrayIndexInvalid"),
+                }
+            }
+            _ => return None,
+        };
+        Some(responder.respond(request))
+    }
+
+    /// Add or update a mapping, returning the external port actually used.
+    ///
+    /// With `any_port`, a port taken by another client is skipped instead
+    /// of failing with 718.
+    fn add(&self, req: &AddPortMappingRequest, any_port: bool) -> Result<u16, Responder> {
+        if req.external_port == 0 {
+            return Err(Responder::error(716, "WildCardNotPermittedInExtPort"));
+        }
+        let Some(mut mapping) = req.to_port_mapping() else {
+            return Err(Responder::error(402, "Invalid Args"));
+        };
+        if self.has_quirk(&MappingQuirk::IgnoreLeaseDuration) {
+            mapping.lease_duration = 0;
+        }
+        for quirk in self.quirks() {
+            if let MappingQuirk::ShiftExternalPort(offset) = quirk {
+                mapping.external_port = match mapping.external_port.checked_add(offset) {
+                    Some(port) => port,
+                    None => return Err(Responder::error(728, "NoPortMapsAvailable")),
+                };
+            }
+        }
+
+        let mut mappings = self.lock();
+        loop {
+            match mappings
+                .iter_mut()
+                .find(|e| is_same_mapping(&e.mapping, &mapping))
+            {
+                // Only the client owning a mapping may update it
+                Some(existing) if existing.mapping.internal_client != mapping.internal_client => {
+                    if !any_port {
+                        return Err(Responder::error(718, "ConflictInMappingEntry"));
+                    }
+                    mapping.external_port = match mapping.external_port.checked_add(1) {
+                        Some(port) => port,
+                        None => return Err(Responder::error(728, "NoPortMapsAvailable")),
+                    };
+                }
+                Some(existing) => {
+                    let port = mapping.external_port;
+                    *existing = Entry::new(mapping);
+                    self.changes.send_replace(());
+                    return Ok(port);
+                }
+                None => {
+                    let port = mapping.external_port;
+                    mappings.push(Entry::new(mapping));
+                    let count = mappings.len();
+                    drop(mappings);
+                    self.publish_count(count);
+                    return Ok(port);
+                }
+            }
+        }
+    }
+
+    fn delete(&self, req: &DeletePortMappingRequest) -> Responder {
+        let mut mappings = self.lock();
+        let position = mappings.iter().position(|Entry { mapping: m, .. }| {
+            m.remote_host == req.remote_host
+                && m.external_port == req.external_port
+                && Some(m.protocol) == parse_protocol(&req.protocol)
+        });
+        match position {
+            Some(index) => {
+                mappings.remove(index);
+                let count = mappings.len();
+                drop(mappings);
+                self.publish_count(count);
+                Responder::success().build()
+            }
+            None => Responder::error(714, "NoSuchEntryInArray"),
+        }
+    }
+
+    fn find(&self, remote_host: &str, external_port: u16, protocol: &str) -> Option<PortMapping> {
+        let protocol = parse_protocol(protocol)?;
+        self.lock()
+            .iter()
+            .find(|Entry { mapping: m, .. }| {
+                m.remote_host == remote_host
+                    && m.external_port == external_port
+                    && m.protocol == protocol
+            })
+            .map(Entry::current)
+    }
+
+    /// Event the new number of entries to subscribers, and signal the
+    /// change to watchers.
+    fn publish_count(&self, count: usize) {
+        self.changes.send_replace(());
+        if self.has_quirk(&MappingQuirk::NoEventing) {
+            return;
+        }
+        self.events.publish(vec![(
+            "PortMappingNumberOfEntries".to_string(),
+            count.to_string(),
+        )]);
+    }
+
+    fn has_quirk(&self, quirk: &MappingQuirk) -> bool {
+        self.quirks().contains(quirk)
+    }
+
+    /// The table's own quirks, then the active scenario's.
+    fn quirks(&self) -> Vec<MappingQuirk> {
+        let scenario_quirks = self
+            .scenario_quirks
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        self.quirks
+            .iter()
+            .chain(scenario_quirks.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Lock the table, dropping mappings whose lease ran out.
+    fn lock(&self) -> MutexGuard<'_, Vec<Entry>> {
+        let mut mappings = self
+            .mappings
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let before = mappings.len();
+        let now = Instant::now();
+        mappings.retain(|e| e.expires.is_none_or(|expires| expires > now));
+        if mappings.len() != before {
+            self.publish_count(mappings.len());
+        }
+        mappings
+    }
+}
+
+/// A mapping in the table.
+struct Entry {
+    mapping: PortMapping,
+    /// When the lease runs out (`None` for a permanent mapping).
+    expires: Option<Instant>,
+}
+
+impl Entry {
+    fn new(mapping: PortMapping) -> Self {
+        let lease = Duration::from_secs(mapping.lease_duration.into());
+        Entry {
+            expires: (mapping.lease_duration > 0).then(|| Instant::now() + lease),
+            mapping,
+        }
+    }
+
+    /// The mapping with its remaining lease, rounded up to whole seconds.
+    fn current(&self) -> PortMapping {
+        let mut mapping = self.mapping.clone();
+        if let Some(expires) = self.expires {
+            let remaining = expires.saturating_duration_since(Instant::now());
+            mapping.lease_duration = remaining.as_millis().div_ceil(1000) as u32;
+        }
+        mapping
+    }
+}
+
+/// Whether two mappings share the same remote host, external port and protocol.
+fn is_same_mapping(a: &PortMapping, b: &PortMapping) -> bool {
+    a.remote_host == b.remote_host && a.external_port == b.external_port && a.protocol == b.protocol
+}
+
+/// Read the arguments of an `AddAnyPortMapping` request, which has the same
+/// inputs as `AddPortMapping`.
+pub(crate) fn add_any_port_mapping_request(req: &UnknownRequest) -> AddPortMappingRequest {
+    AddPortMappingRequest::from_arguments(|name| req.argument(name).map(str::to_string))
+}
+
+/// Parse a request's protocol argument; mapping entries are TCP or UDP.
+fn parse_protocol(protocol: &str) -> Option<Protocol> {
+    protocol.parse().ok()
+}
@@ -1,16 +1,40 @@
 //! Request matching logic.
 
+mod ssdp;
+
+pub use ssdp::{SsdpDelivery, SsdpMatcher};
+
 use crate::action::{
     Action, AddPortMappingParams, DeletePortMappingParams, GetGenericPortMappingEntryParams,
-    GetSpecificPortMappingEntryParams,
+    GetSpecificPortMappingEntryParams, Protocol, ServiceType,
 };
+use crate::mapping::PortMapping;
+use std::net::SocketAddr;
 
 /// A parsed SOAP request that can be matched against.
 #[derive(Debug, Clone)]
 pub struct SoapRequest {
     pub action_name: String,
-    pub service_type: String,
+    pub service_type: ServiceType,
     pub body: SoapRequestBody,
+    /// The raw HTTP request body (the SOAP envelope).
+    pub raw_body: String,
+    /// Every argument of the action element (names without namespace
+    /// prefixes) in document order, whether or not the action is known.
+    pub arguments: Vec<(String, String)>,
+    /// Address of the client that sent the request (`None` for requests
+    /// not received over the network).
+    pub client: Option<SocketAddr>,
+    /// Index of the WANDevice whose control URL received the request
+    /// (0 for the first).
+    pub wan_device: usize,
+}
+
+impl SoapRequest {
+    /// Get the value of the first argument with the given name.
+    pub fn argument(&self, name: &str) -> Option<&str> {
+        find_argument(&self.arguments, name)
+    }
 }
 
 /// The body of a SOAP request, parsed into a known action type.
@@ -25,7 +49,33 @@ pub enum SoapRequestBody {
     GetCommonLinkProperties,
     GetTotalBytesReceived,
     GetTotalBytesSent,
-    Unknown(String),
+    Unknown(UnknownRequest),
+}
+
+/// A request for an action without a dedicated parser.
+///
+/// Carries the action's arguments so mocks for vendor-specific or
+/// unsupported actions can still inspect them.
+#[derive(Debug, Clone)]
+pub struct UnknownRequest {
+    pub action_name: String,
+    /// Argument names (namespace prefixes stripped) and values, in
+    /// document order.
+    pub arguments: Vec<(String, String)>,
+}
+
+impl UnknownRequest {
+    /// Get the value of the first argument with the given name.
+    pub fn argument(&self, name: &str) -> Option<&str> {
+        find_argument(&self.arguments, name)
+    }
+}
+
+fn find_argument<'a>(arguments: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    arguments
+        .iter()
+        .find(|(arg, _)| arg == name)
+        .map(|(_, value)| value.as_str())
 }
 
 /// Parsed AddPortMapping request.
@@ -41,6 +91,49 @@ pub struct AddPortMappingRequest {
     pub lease_duration: u32,
 }
 
+impl AddPortMappingRequest {
+    /// Read the arguments of an `AddPortMapping` request (or an action with
+    /// the same inputs) through `argument`, which looks one up by name.
+    ///
+    /// Missing or invalid numbers read as 0, a missing protocol as TCP and
+    /// a missing `NewEnabled` as enabled.
+    pub(crate) fn from_arguments(argument: impl Fn(&str) -> Option<String>) -> Self {
+        AddPortMappingRequest {
+            remote_host: argument("NewRemoteHost").unwrap_or_default(),
+            external_port: argument("NewExternalPort")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            protocol: argument("NewProtocol").unwrap_or_else(|| "TCP".to_string()),
+            internal_port: argument("NewInternalPort")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            internal_client: argument("NewInternalClient").unwrap_or_default(),
+            enabled: argument("NewEnabled")
+                .map(|s| s == "1" || s.to_lowercase() == "true")
+                .unwrap_or(true),
+            description: argument("NewPortMappingDescription").unwrap_or_default(),
+            lease_duration: argument("NewLeaseDuration")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+        }
+    }
+
+    /// The mapping this request asks for, or `None` if its protocol or
+    /// internal client is invalid.
+    pub fn to_port_mapping(&self) -> Option<PortMapping> {
+        Some(PortMapping {
+            remote_host: self.remote_host.clone(),
+            external_port: self.external_port,
+            protocol: self.protocol.parse().ok()?,
+            internal_port: self.internal_port,
+            internal_client: self.internal_client.parse().ok()?,
+            enabled: self.enabled,
+            description: self.description.clone(),
+            lease_duration: self.lease_duration,
+        })
+    }
+}
+
 /// Parsed DeletePortMapping request.
 #[derive(Debug, Clone)]
 pub struct DeletePortMappingRequest {
@@ -122,30 +215,35 @@ impl Matcher for Action {
 }
 
 fn matches_add_port_mapping(params: &AddPortMappingParams, req: &AddPortMappingRequest) -> bool {
-    if let Some(port) = params.external_port {
-        if req.external_port != port {
-            return false;
-        }
+    if let Some(port) = params.external_port
+        && req.external_port != port
+    {
+        return false;
     }
-    if let Some(protocol) = &params.protocol {
-        if req.protocol.to_uppercase() != protocol.as_str() {
-            return false;
-        }
+    if let Some(protocol) = &params.protocol
+        && !matches_protocol(*protocol, &req.protocol)
+    {
+        return false;
     }
-    if let Some(port) = params.internal_port {
-        if req.internal_port != port {
-            return false;
-        }
+    if let Some(port) = params.internal_port
+        && req.internal_port != port
+    {
+        return false;
     }
-    if let Some(client) = &params.internal_client {
-        if req.internal_client != client.to_string() {
-            return false;
-        }
+    if let Some(client) = &params.internal_client
+        && req.internal_client != client.to_string()
+    {
+        return false;
     }
-    if let Some(desc) = &params.description {
-        if !req.description.contains(desc.as_str()) {
-            return false;
-        }
+    if let Some(enabled) = params.enabled
+        && req.enabled != enabled
+    {
+        return false;
+    }
+    if let Some(desc) = &params.description
+        && !req.description.contains(desc.as_str())
+    {
+        return false;
     }
     true
 }
@@ -154,15 +252,15 @@ fn matches_delete_port_mapping(
     params: &DeletePortMappingParams,
     req: &DeletePortMappingRequest,
 ) -> bool {
-    if let Some(port) = params.external_port {
-        if req.external_port != port {
-            return false;
-        }
+    if let Some(port) = params.external_port
+        && req.external_port != port
+    {
+        return false;
     }
-    if let Some(protocol) = &params.protocol {
-        if req.protocol.to_uppercase() != protocol.as_str() {
-            return false;
-        }
+    if let Some(protocol) = &params.protocol
+        && !matches_protocol(*protocol, &req.protocol)
+    {
+        return false;
     }
     true
 }
@@ -171,10 +269,10 @@ fn matches_get_generic_port_mapping_entry(
     params: &GetGenericPortMappingEntryParams,
     req: &GetGenericPortMappingEntryRequest,
 ) -> bool {
-    if let Some(index) = params.index {
-        if req.index != index {
-            return false;
-        }
+    if let Some(index) = params.index
+        && req.index != index
+    {
+        return false;
     }
     true
 }
@@ -183,15 +281,144 @@ fn matches_get_specific_port_mapping_entry(
     params: &GetSpecificPortMappingEntryParams,
     req: &GetSpecificPortMappingEntryRequest,
 ) -> bool {
-    if let Some(port) = params.external_port {
-        if req.external_port != port {
-            return false;
+    if let Some(port) = params.external_port
+        && req.external_port != port
+    {
+        return false;
+    }
+    if let Some(protocol) = &params.protocol
+        && !matches_protocol(*protocol, &req.protocol)
+    {
+        return false;
+    }
+    true
+}
+
+/// A request field that kept a mock from matching.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldMismatch {
+    /// The field, e.g. `external_port` or `service_type`.
+    pub field: String,
+    /// What the mock expects.
+    pub expected: String,
+    /// What the request carried.
+    pub actual: String,
+}
+
+impl FieldMismatch {
+    pub(crate) fn new(field: &str, expected: impl ToString, actual: impl ToString) -> Self {
+        FieldMismatch {
+            field: field.to_string(),
+            expected: expected.to_string(),
+            actual: actual.to_string(),
         }
     }
-    if let Some(protocol) = &params.protocol {
-        if req.protocol.to_uppercase() != protocol.as_str() {
-            return false;
+}
+
+impl std::fmt::Display for FieldMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: expected {}, got {}",
+            self.field, self.expected, self.actual
+        )
+    }
+}
+
+impl Action {
+    /// The arguments of `request` that keep it from matching, or `None` if
+    /// it is a request for another action.
+    pub(crate) fn mismatches(&self, request: &SoapRequest) -> Option<Vec<FieldMismatch>> {
+        let mut mismatches = Vec::new();
+        match (self, &request.body) {
+            (Action::AddPortMapping(params), SoapRequestBody::AddPortMapping(req)) => {
+                if let Some(port) = params.external_port
+                    && req.external_port != port
+                {
+                    mismatches.push(FieldMismatch::new("external_port", port, req.external_port));
+                }
+                mismatches.extend(protocol_mismatch(params.protocol, &req.protocol));
+                if let Some(port) = params.internal_port
+                    && req.internal_port != port
+                {
+                    mismatches.push(FieldMismatch::new("internal_port", port, req.internal_port));
+                }
+                if let Some(client) = &params.internal_client
+                    && req.internal_client != client.to_string()
+                {
+                    mismatches.push(FieldMismatch::new(
+                        "internal_client",
+                        client,
+                        &req.internal_client,
+                    ));
+                }
+                if let Some(enabled) = params.enabled
+                    && req.enabled != enabled
+                {
+                    mismatches.push(FieldMismatch::new("enabled", enabled, req.enabled));
+                }
+                if let Some(desc) = &params.description
+                    && !req.description.contains(desc.as_str())
+                {
+                    mismatches.push(FieldMismatch::new(
+                        "description",
+                        format!("containing {desc:?}"),
+                        format!("{:?}", req.description),
+                    ));
+                }
+            }
+            (Action::DeletePortMapping(params), SoapRequestBody::DeletePortMapping(req)) => {
+                if let Some(port) = params.external_port
+                    && req.external_port != port
+                {
+                    mismatches.push(FieldMismatch::new("external_port", port, req.external_port));
+                }
+                mismatches.extend(protocol_mismatch(params.protocol, &req.protocol));
+            }
+            (
+                Action::GetSpecificPortMappingEntry(params),
+                SoapRequestBody::GetSpecificPortMappingEntry(req),
+            ) => {
+                if let Some(port) = params.external_port
+                    && req.external_port != port
+                {
+                    mismatches.push(FieldMismatch::new("external_port", port, req.external_port));
+                }
+                mismatches.extend(protocol_mismatch(params.protocol, &req.protocol));
+            }
+            (
+                Action::GetGenericPortMappingEntry(params),
+                SoapRequestBody::GetGenericPortMappingEntry(req),
+            ) => {
+                if let Some(index) = params.index
+                    && req.index != index
+                {
+                    mismatches.push(FieldMismatch::new("index", index, req.index));
+                }
+            }
+            (Action::Any, _)
+            | (Action::GetExternalIPAddress, SoapRequestBody::GetExternalIPAddress)
+            | (Action::GetStatusInfo, SoapRequestBody::GetStatusInfo)
+            | (Action::GetCommonLinkProperties, SoapRequestBody::GetCommonLinkProperties)
+            | (Action::GetTotalBytesReceived, SoapRequestBody::GetTotalBytesReceived)
+            | (Action::GetTotalBytesSent, SoapRequestBody::GetTotalBytesSent) => {}
+            _ => return None,
         }
+        Some(mismatches)
     }
-    true
+}
+
+/// The mismatch of a request's protocol argument, if it doesn't satisfy
+/// `expected`.
+fn protocol_mismatch(expected: Option<Protocol>, requested: &str) -> Option<FieldMismatch> {
+    let expected = expected?;
+    (!matches_protocol(expected, requested))
+        .then(|| FieldMismatch::new("protocol", expected, requested))
+}
+
+/// Whether the protocol argument of a request satisfies `protocol`.
+fn matches_protocol(protocol: Protocol, requested: &str) -> bool {
+    requested
+        .parse()
+        .is_ok_and(|requested| protocol.matches(requested))
 }
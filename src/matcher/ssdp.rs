@@ -0,0 +1,87 @@
+//! SSDP M-SEARCH matching.
+
+use crate::mock::ReceivedSsdpRequest;
+
+/// How an M-SEARCH reached the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SsdpDelivery {
+    /// Sent to the SSDP multicast group.
+    Multicast,
+    /// Sent directly to the device (UPnP 1.1 unicast search).
+    Unicast,
+}
+
+/// Matches M-SEARCH requests for [`MockIgdServer::mock_ssdp`].
+///
+/// Every criterion left unset matches any request.
+///
+/// [`MockIgdServer::mock_ssdp`]: crate::MockIgdServer::mock_ssdp
+#[derive(Debug, Clone, Default)]
+pub struct SsdpMatcher {
+    search_target: Option<String>,
+    mx: Option<Option<u32>>,
+    source_port: Option<u16>,
+    delivery: Option<SsdpDelivery>,
+}
+
+impl SsdpMatcher {
+    /// Match any M-SEARCH.
+    pub fn any() -> Self {
+        Self::default()
+    }
+
+    /// Match searches for this ST value.
+    pub fn with_search_target(mut self, search_target: impl Into<String>) -> Self {
+        self.search_target = Some(search_target.into());
+        self
+    }
+
+    /// Match searches with this MX value.
+    pub fn with_mx(mut self, mx: u32) -> Self {
+        self.mx = Some(Some(mx));
+        self
+    }
+
+    /// Match searches without a (valid) MX header.
+    pub fn without_mx(mut self) -> Self {
+        self.mx = Some(None);
+        self
+    }
+
+    /// Match searches sent from this UDP source port.
+    pub fn with_source_port(mut self, port: u16) -> Self {
+        self.source_port = Some(port);
+        self
+    }
+
+    /// Match searches delivered this way, as told by
+    /// [`ReceivedSsdpRequest::delivery`].
+    pub fn with_delivery(mut self, delivery: SsdpDelivery) -> Self {
+        self.delivery = Some(delivery);
+        self
+    }
+
+    pub(crate) fn matches(&self, request: &ReceivedSsdpRequest) -> bool {
+        if let Some(search_target) = &self.search_target
+            && request.search_target != *search_target
+        {
+            return false;
+        }
+        if let Some(mx) = self.mx
+            && request.mx != mx
+        {
+            return false;
+        }
+        if let Some(port) = self.source_port
+            && request.source.port() != port
+        {
+            return false;
+        }
+        if let Some(delivery) = self.delivery
+            && request.delivery() != delivery
+        {
+            return false;
+        }
+        true
+    }
+}
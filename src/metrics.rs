@@ -0,0 +1,332 @@
+//! Request metrics collected by the mock server.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// A snapshot of request metrics collected by the server.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    /// Number of SOAP requests received, keyed by action name.
+    pub requests_by_action: BTreeMap<String, u64>,
+    /// Number of SOAP requests answered, keyed by mock identifier.
+    pub requests_by_mock: BTreeMap<u64, u64>,
+    /// Number of SOAP requests that did not match any mock.
+    pub unmatched_requests: u64,
+    /// Number of SSDP M-SEARCH requests answered.
+    pub ssdp_searches_answered: u64,
+    /// Total number of SOAP requests handled.
+    pub total_requests: u64,
+    /// Sum of the handling latency of all SOAP requests.
+    pub total_latency: Duration,
+    /// Handling latency summary of SOAP requests, keyed by action name.
+    pub latency_by_action: BTreeMap<String, LatencyStats>,
+}
+
+/// Summary of the mock-side handling latency of one action.
+///
+/// `min`, `max` and `mean` are exact; percentiles are estimated from
+/// log-scaled buckets, four per power of two microseconds, and overstate the
+/// latency by less than a quarter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// Number of requests.
+    pub count: usize,
+    pub min: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+    /// Median.
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+}
+
+impl LatencyStats {
+    /// Summarize latency samples as the server does (`None` if there are
+    /// none).
+    pub fn from_samples(samples: &[Duration]) -> Option<Self> {
+        let mut histogram = LatencyHistogram::default();
+        for &sample in samples {
+            histogram.record(sample);
+        }
+        histogram.stats()
+    }
+}
+
+/// Buckets per power of two microseconds.
+const SUB_BUCKETS: u64 = 4;
+
+/// Number of buckets; the last one also holds latencies above 2^33 µs.
+const BUCKETS: usize = 128;
+
+/// Latency samples of one action, counted in fixed log-scaled buckets so
+/// memory stays constant however many requests are handled.
+#[derive(Debug, Clone)]
+pub(crate) struct LatencyHistogram {
+    buckets: [u64; BUCKETS],
+    count: usize,
+    min: Duration,
+    max: Duration,
+    total: Duration,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            buckets: [0; BUCKETS],
+            count: 0,
+            min: Duration::MAX,
+            max: Duration::ZERO,
+            total: Duration::ZERO,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    /// Count one sample.
+    pub(crate) fn record(&mut self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[bucket_index(micros)] += 1;
+        self.count += 1;
+        self.min = self.min.min(latency);
+        self.max = self.max.max(latency);
+        self.total += latency;
+    }
+
+    /// Summarize the samples counted (`None` if there are none).
+    pub(crate) fn stats(&self) -> Option<LatencyStats> {
+        if self.count == 0 {
+            return None;
+        }
+        // Nearest-rank percentile, at the upper bound of its bucket
+        let percentile = |p: usize| {
+            let rank = (self.count * p).div_ceil(100).max(1) as u64;
+            let mut seen = 0;
+            let index = self
+                .buckets
+                .iter()
+                .position(|&count| {
+                    seen += count;
+                    seen >= rank
+                })
+                .unwrap_or(BUCKETS - 1);
+            let upper = Duration::from_micros(bucket_start(index + 1) - 1);
+            upper.clamp(self.min, self.max)
+        };
+        Some(LatencyStats {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean: self.total.div_f64(self.count as f64),
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        })
+    }
+}
+
+/// Bucket holding a latency of `micros`.
+fn bucket_index(micros: u64) -> usize {
+    if micros < SUB_BUCKETS {
+        return micros as usize;
+    }
+    let octave = u64::from(micros.ilog2());
+    let sub = (micros >> (octave - 2)) & (SUB_BUCKETS - 1);
+    (((octave - 1) * SUB_BUCKETS + sub) as usize).min(BUCKETS - 1)
+}
+
+/// Smallest latency in microseconds counted in bucket `index`.
+fn bucket_start(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let octave = index / SUB_BUCKETS + 1;
+    (SUB_BUCKETS + index % SUB_BUCKETS) << (octave - 2)
+}
+
+/// Metrics as they are collected, with a latency histogram per action
+/// behind the summaries of the [`Metrics`] snapshot.
+#[derive(Debug, Default)]
+pub(crate) struct MetricsRecorder {
+    metrics: Metrics,
+    latencies: BTreeMap<String, LatencyHistogram>,
+}
+
+impl MetricsRecorder {
+    /// Record a handled SOAP request.
+    pub(crate) fn record_soap_request(
+        &mut self,
+        action_name: &str,
+        mock_id: Option<u64>,
+        latency: Duration,
+    ) {
+        let metrics = &mut self.metrics;
+        *metrics
+            .requests_by_action
+            .entry(action_name.to_string())
+            .or_default() += 1;
+        match mock_id {
+            Some(id) => *metrics.requests_by_mock.entry(id).or_default() += 1,
+            None => metrics.unmatched_requests += 1,
+        }
+        metrics.total_requests += 1;
+        metrics.total_latency += latency;
+        self.latencies
+            .entry(action_name.to_string())
+            .or_default()
+            .record(latency);
+    }
+
+    /// Record that an SSDP M-SEARCH request was answered.
+    pub(crate) fn record_ssdp_answered(&mut self) {
+        self.metrics.ssdp_searches_answered += 1;
+    }
+
+    /// Snapshot of the metrics collected so far.
+    pub(crate) fn snapshot(&self) -> Metrics {
+        let mut metrics = self.metrics.clone();
+        metrics.latency_by_action = self
+            .latencies
+            .iter()
+            .filter_map(|(action, histogram)| Some((action.clone(), histogram.stats()?)))
+            .collect();
+        metrics
+    }
+}
+
+/// IGD actions the mock knows, in the order of their SCPDs.
+const KNOWN_ACTIONS: &[&str] = &[
+    "GetExternalIPAddress",
+    "GetStatusInfo",
+    "AddPortMapping",
+    "DeletePortMapping",
+    "GetGenericPortMappingEntry",
+    "GetSpecificPortMappingEntry",
+    "GetCommonLinkProperties",
+    "GetTotalBytesReceived",
+    "GetTotalBytesSent",
+];
+
+/// Which known IGD actions a client exercised.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ActionCoverage {
+    /// Known actions the client called, with request counts.
+    pub exercised: BTreeMap<String, u64>,
+    /// Known actions the client never called.
+    pub missing: Vec<String>,
+    /// Actions the client called that the mock doesn't know, with request counts.
+    pub unknown: BTreeMap<String, u64>,
+}
+
+impl ActionCoverage {
+    /// Fraction of known actions exercised, between 0.0 and 1.0.
+    pub fn ratio(&self) -> f64 {
+        self.exercised.len() as f64 / KNOWN_ACTIONS.len() as f64
+    }
+}
+
+impl std::fmt::Display for ActionCoverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "IGD action coverage: {}/{} ({:.0}%)",
+            self.exercised.len(),
+            KNOWN_ACTIONS.len(),
+            self.ratio() * 100.0
+        )?;
+        for action in KNOWN_ACTIONS {
+            match self.exercised.get(*action) {
+                Some(count) => writeln!(f, "  [x] {action} ({count})")?,
+                None => writeln!(f, "  [ ] {action}")?,
+            }
+        }
+        for (action, count) in &self.unknown {
+            writeln!(f, "  [?] {action} ({count})")?;
+        }
+        Ok(())
+    }
+}
+
+impl Metrics {
+    /// Report which known IGD actions were requested.
+    pub fn action_coverage(&self) -> ActionCoverage {
+        let mut coverage = ActionCoverage::default();
+        for action in KNOWN_ACTIONS {
+            match self.requests_by_action.get(*action) {
+                Some(&count) => {
+                    coverage.exercised.insert(action.to_string(), count);
+                }
+                None => coverage.missing.push(action.to_string()),
+            }
+        }
+        for (action, &count) in &self.requests_by_action {
+            if !KNOWN_ACTIONS.contains(&action.as_str()) {
+                coverage.unknown.insert(action.clone(), count);
+            }
+        }
+        coverage
+    }
+
+    /// Average handling latency of SOAP requests (`None` if nothing was handled).
+    pub fn average_latency(&self) -> Option<Duration> {
+        if self.total_requests == 0 {
+            return None;
+        }
+        Some(self.total_latency.div_f64(self.total_requests as f64))
+    }
+
+    /// Latency summary of each requested action.
+    pub fn latency_stats(&self) -> BTreeMap<String, LatencyStats> {
+        self.latency_by_action.clone()
+    }
+
+    /// Render the metrics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# TYPE mock_igd_soap_requests_total counter\n");
+        for (action, count) in &self.requests_by_action {
+            let _ = writeln!(
+                out,
+                "mock_igd_soap_requests_total{{action=\"{action}\"}} {count}"
+            );
+        }
+
+        out.push_str("# TYPE mock_igd_mock_matches_total counter\n");
+        for (mock_id, count) in &self.requests_by_mock {
+            let _ = writeln!(
+                out,
+                "mock_igd_mock_matches_total{{mock_id=\"{mock_id}\"}} {count}"
+            );
+        }
+
+        out.push_str("# TYPE mock_igd_unmatched_requests_total counter\n");
+        let _ = writeln!(
+            out,
+            "mock_igd_unmatched_requests_total {}",
+            self.unmatched_requests
+        );
+
+        out.push_str("# TYPE mock_igd_ssdp_searches_answered_total counter\n");
+        let _ = writeln!(
+            out,
+            "mock_igd_ssdp_searches_answered_total {}",
+            self.ssdp_searches_answered
+        );
+
+        out.push_str("# TYPE mock_igd_request_latency_seconds summary\n");
+        let _ = writeln!(
+            out,
+            "mock_igd_request_latency_seconds_sum {}",
+            self.total_latency.as_secs_f64()
+        );
+        let _ = writeln!(
+            out,
+            "mock_igd_request_latency_seconds_count {}",
+            self.total_requests
+        );
+
+        out
+    }
+}
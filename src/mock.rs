@@ -1,13 +1,44 @@
 //! Mock registration and management.
 
-use crate::action::Action;
-use crate::matcher::{Matcher, SoapRequest};
-use crate::responder::{ResponseBody, Responder};
-use std::net::SocketAddr;
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
-use std::time::Instant;
-use tokio::sync::RwLock;
+use crate::action::{Action, Protocol, ServiceType};
+use crate::compliance::ComplianceViolation;
+use crate::mapping::{MappingQuirk, PortMapping, PortMappingTable, add_any_port_mapping_request};
+use crate::matcher::{
+    AddPortMappingRequest, DeletePortMappingRequest, FieldMismatch,
+    GetGenericPortMappingEntryRequest, GetSpecificPortMappingEntryRequest, Matcher, SoapRequest,
+    SoapRequestBody, SsdpDelivery, SsdpMatcher,
+};
+use crate::metrics::{Metrics, MetricsRecorder};
+use crate::responder::{Responder, ResponseBody, SsdpResponse};
+use crate::rng::SimRng;
+use crate::scenario::Scenario;
+use crate::server::TRACE_TARGET;
+use crate::server::events::EventPublisher;
+use arc_swap::ArcSwap;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// A custom responder closure that panicked while answering a request.
+///
+/// The client got a `501 ActionFailed` fault instead.
+#[derive(Debug, Clone)]
+pub struct ResponderPanic {
+    /// Identifier of the mock whose responder panicked.
+    pub mock_id: u64,
+    /// Name of the mock, if any.
+    pub mock_name: Option<String>,
+    /// Action of the request being answered.
+    pub action_name: String,
+    /// The panic message.
+    pub message: String,
+    /// Time since server start.
+    pub timestamp: Duration,
+}
 
 /// A received SOAP request with metadata.
 #[derive(Debug, Clone)]
@@ -15,11 +46,37 @@ pub struct ReceivedRequest {
     /// The action name (e.g., "GetExternalIPAddress", "AddPortMapping").
     pub action_name: String,
     /// The service type from the SOAPAction header.
-    pub service_type: String,
+    pub service_type: ServiceType,
     /// The parsed request body.
-    pub body: crate::matcher::SoapRequestBody,
+    pub body: SoapRequestBody,
+    /// The raw HTTP request body (the SOAP envelope).
+    pub raw_body: String,
+    /// Every argument of the action element, in document order.
+    pub arguments: Vec<(String, String)>,
+    /// Address of the client that sent the request.
+    pub client: Option<SocketAddr>,
+    /// Index of the WANDevice the request was sent to (0 for the first).
+    pub wan_device: usize,
     /// When the request was received (relative to server start).
     pub timestamp: std::time::Duration,
+    /// When the request was received, by the system clock, for correlating
+    /// with client-side logs.
+    pub received_at: SystemTime,
+    /// Description of the responder that answered the request
+    /// (`None` if no mock matched).
+    pub responder: Option<String>,
+    /// Name of the mock that answered the request, if it was named.
+    pub mock_name: Option<String>,
+    /// Identifier of the mock that answered the request
+    /// (`None` if no mock matched).
+    pub mock_id: Option<u64>,
+    /// The response body sent back (`None` if neither a mock nor a
+    /// fallback answered and the default 401 "Invalid Action" was sent).
+    pub response: Option<ResponseBody>,
+    /// The mock for the same action that came closest to matching, if
+    /// neither a mock nor a fallback such as the port mapping table
+    /// answered.
+    pub near_miss: Option<NearMiss>,
 }
 
 impl ReceivedRequest {
@@ -28,8 +85,189 @@ impl ReceivedRequest {
             action_name: request.action_name.clone(),
             service_type: request.service_type.clone(),
             body: request.body.clone(),
+            raw_body: request.raw_body.clone(),
+            arguments: request.arguments.clone(),
+            client: request.client,
+            wan_device: request.wan_device,
             timestamp: start_time.elapsed(),
+            received_at: SystemTime::now(),
+            responder: None,
+            mock_name: None,
+            mock_id: None,
+            response: None,
+            near_miss: None,
+        }
+    }
+
+    /// Rebuild the request as it was matched.
+    pub(crate) fn to_soap_request(&self) -> SoapRequest {
+        SoapRequest {
+            action_name: self.action_name.clone(),
+            service_type: self.service_type.clone(),
+            body: self.body.clone(),
+            raw_body: self.raw_body.clone(),
+            arguments: self.arguments.clone(),
+            client: self.client,
+            wan_device: self.wan_device,
+        }
+    }
+
+    /// The parsed arguments, if this is an `AddPortMapping` request.
+    pub fn as_add_port_mapping(&self) -> Option<&AddPortMappingRequest> {
+        match &self.body {
+            SoapRequestBody::AddPortMapping(req) => Some(req),
+            _ => None,
+        }
+    }
+
+    /// The parsed arguments, if this is a `DeletePortMapping` request.
+    pub fn as_delete_port_mapping(&self) -> Option<&DeletePortMappingRequest> {
+        match &self.body {
+            SoapRequestBody::DeletePortMapping(req) => Some(req),
+            _ => None,
+        }
+    }
+
+    /// The parsed arguments, if this is a `GetGenericPortMappingEntry`
+    /// request.
+    pub fn as_get_generic_port_mapping_entry(&self) -> Option<&GetGenericPortMappingEntryRequest> {
+        match &self.body {
+            SoapRequestBody::GetGenericPortMappingEntry(req) => Some(req),
+            _ => None,
+        }
+    }
+
+    /// The parsed arguments, if this is a `GetSpecificPortMappingEntry`
+    /// request.
+    pub fn as_get_specific_port_mapping_entry(
+        &self,
+    ) -> Option<&GetSpecificPortMappingEntryRequest> {
+        match &self.body {
+            SoapRequestBody::GetSpecificPortMappingEntry(req) => Some(req),
+            _ => None,
+        }
+    }
+
+    /// The mapping requested by an `AddPortMapping` or `AddAnyPortMapping`
+    /// request (`None` for other actions or invalid arguments).
+    pub fn port_mapping(&self) -> Option<PortMapping> {
+        match &self.body {
+            SoapRequestBody::AddPortMapping(req) => req.to_port_mapping(),
+            SoapRequestBody::Unknown(req) if req.action_name == "AddAnyPortMapping" => {
+                add_any_port_mapping_request(req).to_port_mapping()
+            }
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ReceivedRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "[{:>10.6}s] SOAP {}#{}",
+            self.timestamp.as_secs_f64(),
+            self.service_type,
+            self.action_name
+        )?;
+        writeln!(f, "    request: {:?}", self.body)?;
+        match (&self.responder, &self.mock_name) {
+            (Some(responder), Some(name)) => write!(f, "    response: {responder} [mock {name:?}]"),
+            (Some(responder), None) => write!(f, "    response: {responder}"),
+            (None, _) => write!(f, "    response: no matching mock (401 Invalid Action)"),
+        }?;
+        if let Some(near_miss) = &self.near_miss {
+            write!(f, "\n    nearest miss: {near_miss}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The mock that came closest to matching a request no mock matched, and
+/// what kept it from matching.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NearMiss {
+    /// Identifier of the mock.
+    pub mock_id: u64,
+    /// Name of the mock, if any.
+    pub mock_name: Option<String>,
+    /// The request fields and mock conditions that didn't match.
+    pub mismatches: Vec<FieldMismatch>,
+}
+
+impl std::fmt::Display for NearMiss {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.mock_name {
+            Some(name) => write!(f, "mock {name:?}")?,
+            None => write!(f, "mock #{}", self.mock_id)?,
+        }
+        for (i, mismatch) in self.mismatches.iter().enumerate() {
+            write!(f, "{}{mismatch}", if i == 0 { ": " } else { "; " })?;
         }
+        Ok(())
+    }
+}
+
+/// The SSDP searches and SOAP requests of one client, identified by its
+/// source IP address.
+#[derive(Debug, Clone)]
+pub struct ClientActivity {
+    /// The client's IP address.
+    pub ip: IpAddr,
+    /// SSDP M-SEARCH requests from the client, in order.
+    pub ssdp_requests: Vec<Arc<ReceivedSsdpRequest>>,
+    /// SOAP requests from the client, in order.
+    pub requests: Vec<Arc<ReceivedRequest>>,
+}
+
+impl ClientActivity {
+    /// The activity of `ip` in `clients`, added if missing.
+    fn entry(clients: &mut Vec<ClientActivity>, ip: IpAddr) -> &mut ClientActivity {
+        let index = match clients.iter().position(|c| c.ip == ip) {
+            Some(index) => index,
+            None => {
+                clients.push(ClientActivity {
+                    ip,
+                    ssdp_requests: Vec::new(),
+                    requests: Vec::new(),
+                });
+                clients.len() - 1
+            }
+        };
+        &mut clients[index]
+    }
+
+    /// When the client was first seen (relative to server start).
+    fn first_seen(&self) -> Duration {
+        let ssdp = self.ssdp_requests.first().map(|r| r.timestamp);
+        let soap = self.requests.first().map(|r| r.timestamp);
+        ssdp.into_iter().chain(soap).min().unwrap_or_default()
+    }
+}
+
+/// The client software seen at one IP address, for telling which client
+/// versions touched a mock shared by several test suites.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientAgentStats {
+    /// The client's IP address.
+    pub ip: IpAddr,
+    /// `User-Agent` values of HTTP requests and `USER-AGENT` values of SSDP
+    /// searches, with the number of requests carrying each.
+    pub user_agents: BTreeMap<String, u64>,
+    /// `SOAPAction` header forms, with the action name replaced by `*`
+    /// (e.g. `"urn:schemas-upnp-org:service:WANIPConnection:1#*"`), with the
+    /// number of requests using each.
+    pub soap_actions: BTreeMap<String, u64>,
+}
+
+impl ClientAgentStats {
+    /// The stats of `ip` in `clients`, added if missing.
+    fn entry(clients: &mut BTreeMap<IpAddr, ClientAgentStats>, ip: IpAddr) -> &mut Self {
+        clients.entry(ip).or_insert_with(|| ClientAgentStats {
+            ip,
+            user_agents: BTreeMap::new(),
+            soap_actions: BTreeMap::new(),
+        })
     }
 }
 
@@ -38,28 +276,245 @@ impl ReceivedRequest {
 pub struct ReceivedSsdpRequest {
     /// The source address of the request.
     pub source: SocketAddr,
+    /// The address the request was sent to, if the platform reports it;
+    /// see [`delivery`](Self::delivery).
+    pub destination: Option<IpAddr>,
     /// The search target (ST header value).
     pub search_target: String,
     /// The MAN header value (e.g., "ssdp:discover").
     pub man: String,
     /// The MX header value (maximum wait time in seconds).
     pub mx: Option<u32>,
+    /// The USER-AGENT header value, if sent.
+    pub user_agent: Option<String>,
+    /// Every header of the request (e.g. `CPFN.UPNP.ORG`, `TCPPORT.UPNP.ORG`).
+    pub headers: SsdpHeaders,
     /// The raw request string.
     pub raw: String,
     /// When the request was received (relative to server start).
     pub timestamp: std::time::Duration,
+    /// Whether the server answered the search.
+    pub answered: bool,
+}
+
+impl ReceivedSsdpRequest {
+    /// How the search was sent, judging by the address it was sent to.
+    ///
+    /// Where that address isn't reported (platforms other than Linux), this
+    /// falls back to the HOST header: searches addressed to anything but the
+    /// multicast group count as unicast. A unicast search wrongly carrying
+    /// the multicast HOST then passes for a multicast one.
+    pub fn delivery(&self) -> SsdpDelivery {
+        if let Some(destination) = self.destination {
+            return if destination.is_multicast() {
+                SsdpDelivery::Multicast
+            } else {
+                SsdpDelivery::Unicast
+            };
+        }
+        match self.headers.get("HOST") {
+            Some(host) if !host.starts_with("239.255.255.250") => SsdpDelivery::Unicast,
+            _ => SsdpDelivery::Multicast,
+        }
+    }
+}
+
+/// A received SSDP advertisement (NOTIFY) multicast by another UPnP device.
+#[derive(Debug, Clone)]
+pub struct ReceivedSsdpNotification {
+    /// The source address of the advertisement.
+    pub source: SocketAddr,
+    /// The notification type (NT header value).
+    pub notification_type: String,
+    /// The notification subtype (NTS header value, e.g. "ssdp:alive").
+    pub notification_subtype: String,
+    /// The USN header value.
+    pub usn: String,
+    /// The LOCATION header value (absent in "ssdp:byebye").
+    pub location: Option<String>,
+    /// Every header of the advertisement.
+    pub headers: SsdpHeaders,
+    /// The raw advertisement string.
+    pub raw: String,
+    /// When the advertisement was received (relative to server start).
+    pub timestamp: std::time::Duration,
+}
+
+impl std::fmt::Display for ReceivedSsdpNotification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "[{:>10.6}s] SSDP NOTIFY from {}",
+            self.timestamp.as_secs_f64(),
+            self.source
+        )?;
+        writeln!(f, "    NT: {}", self.notification_type)?;
+        writeln!(f, "    NTS: {}", self.notification_subtype)?;
+        write!(f, "    USN: {}", self.usn)?;
+        if let Some(location) = &self.location {
+            write!(f, "\n    LOCATION: {location}")?;
+        }
+        Ok(())
+    }
 }
 
-/// A registered mock that matches requests and generates responses.
-pub(crate) struct Mock {
+/// An SSDP advertisement (NOTIFY) multicast by the mock gateway.
+#[derive(Debug, Clone)]
+pub struct SentSsdpNotification {
+    /// The notification type (NT header value).
+    pub notification_type: String,
+    /// The notification subtype (NTS header value, e.g. "ssdp:alive").
+    pub notification_subtype: String,
+    /// The USN header value.
+    pub usn: String,
+    /// The raw advertisement string.
+    pub raw: String,
+    /// When the advertisement was sent (relative to server start).
+    pub timestamp: std::time::Duration,
+}
+
+/// Headers of an SSDP message, looked up case-insensitively.
+///
+/// Names are kept as sent and values are trimmed; iteration follows the
+/// order of the message.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SsdpHeaders {
+    headers: Vec<(String, String)>,
+}
+
+impl SsdpHeaders {
+    pub(crate) fn new(headers: Vec<(String, String)>) -> Self {
+        SsdpHeaders { headers }
+    }
+
+    /// Get the value of the first header with the given name, ignoring case.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether a header with the given name is present, ignoring case.
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Iterate over header names and values.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Number of headers.
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    /// Whether there are no headers.
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+}
+
+impl std::fmt::Display for ReceivedSsdpRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "[{:>10.6}s] SSDP M-SEARCH from {}",
+            self.timestamp.as_secs_f64(),
+            self.source
+        )?;
+        writeln!(f, "    ST: {}", self.search_target)?;
+        writeln!(f, "    MAN: {}", self.man)?;
+        if let Some(mx) = self.mx {
+            writeln!(f, "    MX: {mx}")?;
+        }
+        if let Some(user_agent) = &self.user_agent {
+            writeln!(f, "    USER-AGENT: {user_agent}")?;
+        }
+        if self.answered {
+            write!(f, "    response: search response")
+        } else {
+            write!(f, "    response: none (ignored)")
+        }
+    }
+}
+
+/// What a `times(n)`-limited mock does once its matches are used up and no
+/// other mock matches the request.
+#[derive(Debug, Clone, Default)]
+pub enum ExhaustionPolicy {
+    /// Stop matching, so the request falls through to the next mock
+    /// (or the default 401 "Invalid Action" when nothing else matches).
+    #[default]
+    FallThrough,
+    /// Keep answering with the mock's responder.
+    RepeatLast,
+    /// Answer with the given UPnP error.
+    Error { code: u16, description: String },
+    /// Panic in the request handler, naming the exhausted mock. The client
+    /// gets a `500 Internal Server Error` and the panic is reported by
+    /// [`MockIgdServer::health`](crate::MockIgdServer::health). The request
+    /// is still recorded, and activates mocks waiting for it.
+    Panic,
+}
+
+/// A mock that matches requests and generates responses.
+///
+/// Most tests register mocks through [`MockIgdServer::mock`]; build a `Mock`
+/// directly and pass it to [`MockIgdServer::register`] to combine options
+/// such as names, priorities and match limits.
+///
+/// # Example
+///
+/// ```ignore
+/// server
+///     .register(
+///         Mock::new(
+///             Action::add_port_mapping().with_external_port(80),
+///             Responder::error(718, "ConflictInMappingEntry"),
+///         )
+///         .named("conflict-on-80")
+///         .times(1),
+///     )
+///     .await;
+/// ```
+///
+/// [`MockIgdServer::mock`]: crate::MockIgdServer::mock
+/// [`MockIgdServer::register`]: crate::MockIgdServer::register
+pub struct Mock {
+    /// Identifier assigned on registration.
+    id: u64,
+    /// Optional human-readable name.
+    name: Option<String>,
     /// The action matcher.
     action: Action,
+    /// Service type the request must be addressed to (None = any).
+    service_type: Option<ServiceType>,
+    /// Client IP the request must come from (None = any).
+    client: Option<IpAddr>,
     /// The responder to use when matched.
     responder: Responder,
     /// Priority for matching (higher = checked first).
     priority: u32,
     /// Maximum number of times this mock can be matched (None = unlimited).
     max_times: Option<u32>,
+    /// Behavior once `max_times` is reached.
+    exhaustion_policy: ExhaustionPolicy,
+    /// Content-Type header sent instead of the default.
+    content_type: Option<String>,
+    /// How long after registration this mock keeps matching (None = forever).
+    expires_after: Option<Duration>,
+    /// When this mock was registered.
+    registered_at: Instant,
+    /// Request that must be seen before this mock becomes active.
+    activated_by: Option<Action>,
+    /// Whether `activated_by` has been seen.
+    activated: AtomicBool,
+    /// Scenario this mock was registered by, if any.
+    scenario: Option<String>,
     /// Number of times this mock has been matched.
     match_count: AtomicU32,
 }
@@ -68,10 +523,43 @@ impl Mock {
     /// Create a new mock with the given action and responder.
     pub fn new(action: impl Into<Action>, responder: impl Into<Responder>) -> Self {
         Mock {
+            id: 0,
+            name: None,
             action: action.into(),
+            service_type: None,
+            client: None,
             responder: responder.into(),
             priority: 0,
             max_times: None,
+            exhaustion_policy: ExhaustionPolicy::default(),
+            content_type: None,
+            expires_after: None,
+            registered_at: Instant::now(),
+            activated_by: None,
+            activated: AtomicBool::new(false),
+            scenario: None,
+            match_count: AtomicU32::new(0),
+        }
+    }
+
+    /// Copy this mock's configuration with fresh match state.
+    fn fresh_copy(&self) -> Mock {
+        Mock {
+            id: 0,
+            name: self.name.clone(),
+            action: self.action.clone(),
+            service_type: self.service_type.clone(),
+            client: self.client,
+            responder: self.responder.clone(),
+            priority: self.priority,
+            max_times: self.max_times,
+            exhaustion_policy: self.exhaustion_policy.clone(),
+            content_type: self.content_type.clone(),
+            expires_after: self.expires_after,
+            registered_at: Instant::now(),
+            activated_by: self.activated_by.clone(),
+            activated: AtomicBool::new(false),
+            scenario: self.scenario.clone(),
             match_count: AtomicU32::new(0),
         }
     }
@@ -88,125 +576,1171 @@ impl Mock {
         self
     }
 
-    /// Check if this mock matches the given request.
-    pub fn matches(&self, request: &SoapRequest) -> bool {
-        // Check if we've exceeded max_times
-        if let Some(max) = self.max_times {
-            if self.match_count.load(Ordering::SeqCst) >= max {
-                return false;
+    /// Set what happens once a `times(n)`-limited mock is exhausted
+    /// and no other mock matches (default: [`ExhaustionPolicy::FallThrough`]).
+    pub fn on_exhausted(mut self, policy: ExhaustionPolicy) -> Self {
+        self.exhaustion_policy = policy;
+        self
+    }
+
+    /// Give this mock a name that appears in logs and request dumps.
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Only match requests addressed to the given service type, e.g. to answer
+    /// `WANIPConnection:2` clients differently from `WANIPConnection:1` ones.
+    pub fn for_service(mut self, service_type: ServiceType) -> Self {
+        self.service_type = Some(service_type);
+        self
+    }
+
+    /// Only match requests from the client with IP address `ip`, so several
+    /// simulated clients of one server can get different behaviors.
+    ///
+    /// Scoped mocks follow the usual priority order, so give them a higher
+    /// priority than unscoped mocks for the same action.
+    pub fn for_client(mut self, ip: IpAddr) -> Self {
+        self.client = Some(ip);
+        self
+    }
+
+    /// Only match requests received within `duration` after registration.
+    ///
+    /// Combine with a lower-priority mock to model time-dependent behavior,
+    /// e.g. a router reporting `Disconnected` for the first seconds after
+    /// boot and `Connected` afterwards.
+    pub fn expires_after(mut self, duration: Duration) -> Self {
+        self.expires_after = Some(duration);
+        self
+    }
+
+    /// Only match once a request matching `action` has been received.
+    ///
+    /// Gives lightweight stateful behavior, e.g. a `GetSpecificPortMappingEntry`
+    /// mock that only succeeds after the corresponding `AddPortMapping`.
+    /// Requests received before registration count too; the triggering
+    /// request itself is answered by other mocks.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// server
+    ///     .register(
+    ///         Mock::new(
+    ///             Action::get_specific_port_mapping_entry().with_external_port(8080),
+    ///             Responder::success()
+    ///                 .with_internal_port(8080)
+    ///                 .with_internal_client("192.168.1.100"),
+    ///         )
+    ///         .after(Action::add_port_mapping().with_external_port(8080)),
+    ///     )
+    ///     .await;
+    /// ```
+    pub fn after(mut self, action: impl Into<Action>) -> Self {
+        self.activated_by = Some(action.into());
+        self
+    }
+
+    /// Send `content_type` as the Content-Type header of this mock's responses.
+    ///
+    /// Useful for non-canonical values real routers return, such as
+    /// `text/xml` without a charset, `application/xml` or a charset that
+    /// doesn't match the body.
+    pub fn with_content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.content_type = Some(content_type.into());
+        self
+    }
+
+    /// Check if this mock matches the given request, counting the match.
+    ///
+    /// The count is checked against `max_times` and incremented in one
+    /// atomic step, so concurrent requests can't overrun the limit.
+    pub(crate) fn matches(&self, request: &SoapRequest) -> bool {
+        // Check if we've outlived the deadline or are still waiting for the
+        // activating request
+        if self.is_expired() || !self.is_active() || !self.matches_target(request) {
+            return false;
+        }
+        let Some(max) = self.max_times else {
+            self.match_count.fetch_add(1, Ordering::SeqCst);
+            return true;
+        };
+        self.match_count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                (count < max).then_some(count + 1)
+            })
+            .is_ok()
+    }
+
+    /// Check if this mock is exhausted but would still handle the request
+    /// through its exhaustion policy.
+    pub(crate) fn matches_exhausted(&self, request: &SoapRequest) -> bool {
+        !matches!(self.exhaustion_policy, ExhaustionPolicy::FallThrough)
+            && self.is_exhausted()
+            && !self.is_expired()
+            && self.matches_target(request)
+    }
+
+    /// Generate a response for a request handled through the exhaustion policy.
+    ///
+    /// Returns the panic message if a custom responder panicked.
+    pub(crate) fn respond_exhausted(
+        &self,
+        request: &SoapRequest,
+    ) -> Result<(ResponseBody, Duration), String> {
+        match &self.exhaustion_policy {
+            ExhaustionPolicy::FallThrough | ExhaustionPolicy::RepeatLast => {
+                self.responder.try_respond(request)
+            }
+            ExhaustionPolicy::Error { code, description } => {
+                let body = ResponseBody::SoapFault {
+                    code: *code,
+                    description: description.clone(),
+                };
+                Ok((body, Duration::ZERO))
+            }
+            ExhaustionPolicy::Panic => panic!("{}", self.exhaustion_panic_message(request)),
+        }
+    }
+
+    /// The message [`ExhaustionPolicy::Panic`] panics with for `request`.
+    fn exhaustion_panic_message(&self, request: &SoapRequest) -> String {
+        format!(
+            "mock {} exhausted after {} matches, but received another {} request",
+            self.label(),
+            self.max_times.unwrap_or_default(),
+            request.action_name
+        )
+    }
+
+    /// Describe the response sent through the exhaustion policy.
+    fn describe_exhausted(&self) -> String {
+        match &self.exhaustion_policy {
+            ExhaustionPolicy::Error { code, description } => {
+                format!("error {code} ({description}), mock exhausted")
             }
+            ExhaustionPolicy::Panic => "panic, mock exhausted".to_string(),
+            _ => format!("{}, mock exhausted", self.responder.describe()),
+        }
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.max_times
+            .is_some_and(|max| self.match_count.load(Ordering::SeqCst) >= max)
+    }
+
+    /// Check the action and service type, ignoring match limits.
+    fn matches_target(&self, request: &SoapRequest) -> bool {
+        self.service_type
+            .as_ref()
+            .is_none_or(|service| *service == request.service_type)
+            && self
+                .client
+                .is_none_or(|ip| request.client.is_some_and(|client| client.ip() == ip))
+            && self.action.matches(request)
+    }
+
+    fn is_active(&self) -> bool {
+        self.activated_by.is_none() || self.activated.load(Ordering::SeqCst)
+    }
+
+    /// Activate this mock if `request` matches its activating action.
+    fn observe(&self, request: &SoapRequest) {
+        if let Some(trigger) = &self.activated_by
+            && trigger.matches(request)
+        {
+            self.activated.store(true, Ordering::SeqCst);
         }
-        self.action.matches(request)
     }
 
-    /// Generate a response for the given request and increment match count.
-    pub fn respond(&self, request: &SoapRequest) -> ResponseBody {
-        self.match_count.fetch_add(1, Ordering::SeqCst);
-        self.responder.respond(request)
+    fn is_expired(&self) -> bool {
+        self.expires_after
+            .is_some_and(|duration| self.registered_at.elapsed() >= duration)
+    }
+
+    /// What keeps this mock from matching `request`, or `None` if it is a
+    /// mock for another action.
+    fn mismatches(&self, request: &SoapRequest) -> Option<Vec<FieldMismatch>> {
+        let mut mismatches = self.action.mismatches(request)?;
+        if let Some(service) = &self.service_type
+            && *service != request.service_type
+        {
+            mismatches.push(FieldMismatch::new(
+                "service_type",
+                service,
+                &request.service_type,
+            ));
+        }
+        if let Some(ip) = self.client
+            && request.client.is_none_or(|client| client.ip() != ip)
+        {
+            let actual = request
+                .client
+                .map_or("unknown".to_string(), |c| c.ip().to_string());
+            mismatches.push(FieldMismatch::new("client", ip, actual));
+        }
+        if let Some(max) = self.max_times
+            && self.is_exhausted()
+        {
+            mismatches.push(FieldMismatch::new(
+                "times",
+                format!("at most {max}"),
+                format!("{} already", self.match_count.load(Ordering::SeqCst)),
+            ));
+        }
+        if let Some(duration) = self.expires_after
+            && self.is_expired()
+        {
+            mismatches.push(FieldMismatch::new(
+                "expires_after",
+                format!("{duration:?}"),
+                format!("{:?} since registration", self.registered_at.elapsed()),
+            ));
+        }
+        if !self.is_active() {
+            mismatches.push(FieldMismatch::new(
+                "after",
+                "an activating request first",
+                "none yet",
+            ));
+        }
+        Some(mismatches)
+    }
+
+    /// Label identifying this mock in messages: its name, or its identifier.
+    fn label(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{name:?}"),
+            None => format!("#{}", self.id),
+        }
+    }
+
+    /// Generate a response and its delay for a request this mock
+    /// [`matches`](Self::matches).
+    ///
+    /// Returns the panic message if a custom responder panicked.
+    pub(crate) fn respond(
+        &self,
+        request: &SoapRequest,
+    ) -> Result<(ResponseBody, Duration), String> {
+        self.responder.try_respond(request)
     }
 
     /// Get the priority of this mock.
-    pub fn priority(&self) -> u32 {
+    pub(crate) fn priority(&self) -> u32 {
         self.priority
     }
+
+    /// Get the identifier assigned to this mock on registration.
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Names of response fields the action needs but the responder lacks.
+    pub(crate) fn missing_response_fields(&self) -> Vec<&'static str> {
+        self.responder.missing_fields(&self.action)
+    }
+
+    /// Describe this mock in lint messages.
+    pub(crate) fn describe(&self) -> String {
+        format!("mock {} for {:?}", self.label(), self.action)
+    }
+
+    /// Get the name of this mock, if any.
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
 }
 
 impl std::fmt::Debug for Mock {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Mock")
+            .field("id", &self.id)
+            .field("name", &self.name)
             .field("action", &self.action)
+            .field("service_type", &self.service_type)
+            .field("client", &self.client)
             .field("responder", &self.responder)
             .field("priority", &self.priority)
             .field("max_times", &self.max_times)
+            .field("exhaustion_policy", &self.exhaustion_policy)
+            .field("content_type", &self.content_type)
+            .field("expires_after", &self.expires_after)
+            .field("activated_by", &self.activated_by)
+            .field("scenario", &self.scenario)
             .field("match_count", &self.match_count.load(Ordering::SeqCst))
             .finish()
     }
 }
 
+/// A response produced by a matched mock.
+#[derive(Debug)]
+pub(crate) struct MatchedResponse {
+    /// Identifier of the mock that produced the response (`None` when the
+    /// port mapping table answered).
+    pub mock_id: Option<u64>,
+    /// Name of the mock that produced the response, if any.
+    pub mock_name: Option<String>,
+    /// The response to send.
+    pub body: ResponseBody,
+    /// Content-Type header overriding the default, if any.
+    pub content_type: Option<String>,
+    /// How long to wait before sending the response.
+    pub delay: Duration,
+}
+
+/// External addresses reported by the WAN connection services.
+#[derive(Debug, Default)]
+struct ExternalIps {
+    /// Address shared by WANIPConnection and WANPPPConnection.
+    shared: Option<IpAddr>,
+    /// Address WANPPPConnection reports instead of `shared`, if set.
+    ppp: Option<IpAddr>,
+}
+
+/// A second WANDevice, with its own address and port mappings.
+struct SecondWan {
+    external_ip: IpAddr,
+    port_mappings: Option<PortMappingTable>,
+    /// GENA subscribers to its WANIPConnection service.
+    events: Arc<EventPublisher>,
+}
+
 /// Registry of mocks for matching requests.
+///
+/// The mock list is published as an immutable snapshot that request handlers
+/// load without locking; registration swaps in a new snapshot. Request history
+/// and metrics sit behind short-lived synchronous locks that are never held
+/// across an `.await`, so concurrent requests do not queue behind each other.
 pub(crate) struct MockRegistry {
-    mocks: RwLock<Vec<Arc<Mock>>>,
-    received_requests: RwLock<Vec<ReceivedRequest>>,
-    received_ssdp_requests: RwLock<Vec<ReceivedSsdpRequest>>,
+    mocks: ArcSwap<Vec<Arc<Mock>>>,
+    next_mock_id: AtomicU64,
+    received_requests: Mutex<Vec<Arc<ReceivedRequest>>>,
+    received_ssdp_requests: Mutex<Vec<Arc<ReceivedSsdpRequest>>>,
+    received_ssdp_notifications: Mutex<Vec<Arc<ReceivedSsdpNotification>>>,
+    sent_ssdp_notifications: Mutex<Vec<Arc<SentSsdpNotification>>>,
+    ssdp_mocks: Mutex<Vec<(SsdpMatcher, SsdpResponse)>>,
+    description_override: Mutex<Option<String>>,
+    boot_id: AtomicU32,
+    config_id: AtomicU32,
+    scpd_overrides: Mutex<HashMap<String, String>>,
+    metrics: Mutex<MetricsRecorder>,
+    compliance_violations: Mutex<Vec<ComplianceViolation>>,
+    server_errors: Mutex<Vec<String>>,
+    /// The latest incoming HTTP request or SSDP search, updated on every
+    /// arrival.
+    traffic: watch::Sender<Option<String>>,
+    client_agents: Mutex<BTreeMap<IpAddr, ClientAgentStats>>,
+    responder_panics: Mutex<Vec<ResponderPanic>>,
+    scenarios: Mutex<HashMap<String, Scenario>>,
+    active_scenario: Mutex<Option<String>>,
+    port_mappings: Option<PortMappingTable>,
+    /// Upstream and downstream bit rates of the simulated link.
+    link_rates: Option<(u32, u32)>,
+    status_info: bool,
+    unmatched_index_invalid: bool,
+    external_ips: Mutex<ExternalIps>,
+    events: Arc<EventPublisher>,
+    second_wan: Option<SecondWan>,
+    rng: SimRng,
     start_time: Instant,
+    /// When the simulated gateway last booted.
+    booted_at: Mutex<Instant>,
+    /// System clock time of `start_time`.
+    started_at: SystemTime,
 }
 
 impl MockRegistry {
     /// Create a new empty registry.
     pub fn new() -> Self {
         MockRegistry {
-            mocks: RwLock::new(Vec::new()),
-            received_requests: RwLock::new(Vec::new()),
-            received_ssdp_requests: RwLock::new(Vec::new()),
+            mocks: ArcSwap::from_pointee(Vec::new()),
+            next_mock_id: AtomicU64::new(1),
+            received_requests: Mutex::new(Vec::new()),
+            received_ssdp_requests: Mutex::new(Vec::new()),
+            received_ssdp_notifications: Mutex::new(Vec::new()),
+            sent_ssdp_notifications: Mutex::new(Vec::new()),
+            ssdp_mocks: Mutex::new(Vec::new()),
+            description_override: Mutex::new(None),
+            boot_id: AtomicU32::new(1),
+            config_id: AtomicU32::new(1),
+            scpd_overrides: Mutex::new(HashMap::new()),
+            metrics: Mutex::new(MetricsRecorder::default()),
+            compliance_violations: Mutex::new(Vec::new()),
+            server_errors: Mutex::new(Vec::new()),
+            traffic: watch::Sender::new(None),
+            client_agents: Mutex::new(BTreeMap::new()),
+            responder_panics: Mutex::new(Vec::new()),
+            scenarios: Mutex::new(HashMap::new()),
+            active_scenario: Mutex::new(None),
+            port_mappings: None,
+            link_rates: None,
+            status_info: false,
+            unmatched_index_invalid: false,
+            external_ips: Mutex::new(ExternalIps::default()),
+            events: Arc::new(EventPublisher::new()),
+            second_wan: None,
+            rng: SimRng::from_entropy(),
             start_time: Instant::now(),
+            booted_at: Mutex::new(Instant::now()),
+            started_at: SystemTime::now(),
         }
     }
 
-    /// Register a new mock.
-    pub async fn register(&self, mock: Mock) {
-        let mut mocks = self.mocks.write().await;
-        mocks.push(Arc::new(mock));
-        // Sort by priority (highest first)
-        mocks.sort_by(|a, b| b.priority().cmp(&a.priority()));
+    /// Answer port mapping requests no mock matches from a table applying
+    /// the given quirks.
+    pub fn with_port_mapping_table(mut self, quirks: Vec<MappingQuirk>) -> Self {
+        self.port_mappings = Some(PortMappingTable::new(quirks, self.events.clone()));
+        self
+    }
+
+    /// Add a second WANDevice reporting `external_ip`, with a port mapping
+    /// table of its own if `quirks` are given.
+    pub fn with_second_wan(
+        mut self,
+        external_ip: IpAddr,
+        quirks: Option<Vec<MappingQuirk>>,
+    ) -> Self {
+        let events = Arc::new(EventPublisher::new());
+        self.second_wan = Some(SecondWan {
+            external_ip,
+            port_mappings: quirks.map(|quirks| PortMappingTable::new(quirks, events.clone())),
+            events,
+        });
+        self
+    }
+
+    /// Answer `GetCommonLinkProperties` requests no mock matches with the
+    /// given upstream and downstream bit rates.
+    pub fn with_link_rates(mut self, upstream: u32, downstream: u32) -> Self {
+        self.link_rates = Some((upstream, downstream));
+        self
+    }
+
+    /// Answer `GetStatusInfo` requests no mock matches as a connected gateway
+    /// with the current uptime.
+    pub fn with_status_info(mut self) -> Self {
+        self.status_info = true;
+        self
+    }
+
+    /// Answer `GetGenericPortMappingEntry` requests nothing else answers with
+    /// 713 "SpecifiedArrayIndexInvalid", the end of the mapping list.
+    pub fn with_unmatched_index_invalid(mut self) -> Self {
+        self.unmatched_index_invalid = true;
+        self
+    }
+
+    /// Draw randomized behaviors from a generator seeded with `seed`.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = SimRng::new(seed);
+        self
     }
 
-    /// Find a mock that matches the given request and generate a response.
+    /// Source of randomness for simulated behaviors.
+    pub(crate) fn rng(&self) -> &SimRng {
+        &self.rng
+    }
+
+    /// Register a new mock and return its identifier.
+    pub fn register(&self, mock: Mock) -> u64 {
+        let mock = self.prepare(mock);
+        let id = mock.id;
+        self.mocks.rcu(|mocks| {
+            let mut mocks = Vec::clone(mocks);
+            mocks.push(mock.clone());
+            sort_by_priority(&mut mocks);
+            mocks
+        });
+        id
+    }
+
+    /// Assign an identifier to a mock about to be registered and catch it up
+    /// on the request history.
+    fn prepare(&self, mut mock: Mock) -> Arc<Mock> {
+        mock.id = self.next_mock_id.fetch_add(1, Ordering::SeqCst);
+        mock.registered_at = Instant::now();
+        if mock.activated_by.is_some() {
+            for received in lock(&self.received_requests).iter() {
+                mock.observe(&received.to_soap_request());
+            }
+        }
+        Arc::new(mock)
+    }
+
+    /// Store a scenario so it can be switched to later, replacing any
+    /// scenario with the same name.
+    pub fn load_scenario(&self, scenario: Scenario) {
+        lock(&self.scenarios).insert(scenario.name.clone(), scenario);
+    }
+
+    /// Replace the active scenario's mocks and mapping quirks with fresh
+    /// copies of the named scenario's. Returns `false` if no such scenario
+    /// was loaded.
+    pub fn switch_scenario(&self, name: &str) -> bool {
+        let incoming: Vec<Arc<Mock>> = {
+            let scenarios = lock(&self.scenarios);
+            let Some(scenario) = scenarios.get(name) else {
+                return false;
+            };
+            for wan_device in 0..self.wan_device_count() {
+                if let Some(table) = self.mapping_table(wan_device) {
+                    table.set_scenario_quirks(scenario.mapping_quirks.clone());
+                }
+            }
+            scenario
+                .mocks
+                .iter()
+                .map(|template| {
+                    let mut mock = template.fresh_copy();
+                    mock.scenario = Some(name.to_string());
+                    self.prepare(mock)
+                })
+                .collect()
+        };
+        self.mocks.rcu(|mocks| {
+            let mut mocks: Vec<_> = mocks
+                .iter()
+                .filter(|m| m.scenario.is_none())
+                .cloned()
+                .collect();
+            mocks.extend(incoming.iter().cloned());
+            sort_by_priority(&mut mocks);
+            mocks
+        });
+        *lock(&self.active_scenario) = Some(name.to_string());
+        true
+    }
+
+    /// Name of the scenario switched to last, if any.
+    pub fn active_scenario(&self) -> Option<String> {
+        lock(&self.active_scenario).clone()
+    }
+
+    /// Find a mock that matches the given request and generate a response,
+    /// or the nearest miss if neither a mock nor a fallback answers it.
     /// Also records the request.
-    pub async fn find_response(&self, request: &SoapRequest) -> Option<ResponseBody> {
+    pub fn find_response(
+        &self,
+        request: &SoapRequest,
+    ) -> (Option<MatchedResponse>, Option<NearMiss>) {
+        let mut received = ReceivedRequest::from_soap_request(request, self.start_time);
+
+        let mocks = self.mocks.load();
+        let matched = mocks.iter().find(|mock| mock.matches(request));
+        // Nothing else matched: let exhausted mocks apply their policy
+        let exhausted = match matched {
+            Some(_) => None,
+            None => mocks.iter().find(|mock| mock.matches_exhausted(request)),
+        };
+        // Record the request before panicking, so it can be debugged
+        if let Some(mock) = exhausted
+            && let ExhaustionPolicy::Panic = mock.exhaustion_policy
+        {
+            received.responder = Some(mock.describe_exhausted());
+            received.mock_name = mock.name().map(str::to_string);
+            received.mock_id = Some(mock.id());
+            self.record_and_observe(&mocks, request, received);
+            panic!("{}", mock.exhaustion_panic_message(request));
+        }
+        let matched = match (matched, exhausted) {
+            (Some(mock), _) => Some((mock, mock.responder.describe(), mock.respond(request))),
+            (None, Some(mock)) => Some((
+                mock,
+                mock.describe_exhausted(),
+                mock.respond_exhausted(request),
+            )),
+            (None, None) => None,
+        };
+        let response = match matched {
+            Some((mock, description, response)) => {
+                let (body, delay) = response.unwrap_or_else(|message| {
+                    (
+                        self.record_responder_panic(mock, request, message),
+                        Duration::ZERO,
+                    )
+                });
+                received.responder = Some(description);
+                received.mock_name = mock.name().map(str::to_string);
+                received.mock_id = Some(mock.id());
+                received.response = Some(body.clone());
+                Some(MatchedResponse {
+                    mock_id: Some(mock.id()),
+                    mock_name: mock.name().map(str::to_string),
+                    body,
+                    content_type: mock.content_type.clone(),
+                    delay,
+                })
+            }
+            // Fall back to the port mapping table and link rates, if enabled
+            None => self
+                .mapping_table(request.wan_device)
+                .and_then(|table| table.respond(request))
+                .map(|body| ("port mapping table", body))
+                .or_else(|| {
+                    self.status_info_response(request)
+                        .map(|body| ("uptime", body))
+                })
+                .or_else(|| {
+                    self.link_rates_response(request)
+                        .map(|body| ("link rates", body))
+                })
+                .or_else(|| {
+                    self.external_ip_response(request)
+                        .map(|body| ("external address", body))
+                })
+                .or_else(|| {
+                    self.index_invalid_response(request).map(|body| {
+                        (
+                            "error 713 (SpecifiedArrayIndexInvalid), no matching mock",
+                            body,
+                        )
+                    })
+                })
+                .map(|(responder, body)| {
+                    received.responder = Some(responder.to_string());
+                    received.response = Some(body.clone());
+                    MatchedResponse {
+                        mock_id: None,
+                        mock_name: None,
+                        body,
+                        content_type: None,
+                        delay: Duration::ZERO,
+                    }
+                }),
+        };
+
+        if response.is_none() {
+            received.near_miss = self.nearest_miss(request);
+        }
+        let near_miss = received.near_miss.clone();
+        self.record_and_observe(&mocks, request, received);
+        (response, near_miss)
+    }
+
+    /// Record a request and activate the mocks waiting for it.
+    fn record_and_observe(
+        &self,
+        mocks: &[Arc<Mock>],
+        request: &SoapRequest,
+        received: ReceivedRequest,
+    ) {
+        // Activate mocks waiting for this request (after matching, so the
+        // trigger itself isn't answered by the mocks it activates)
+        for mock in mocks {
+            mock.observe(request);
+        }
+
         // Record the request
+        lock(&self.received_requests).push(Arc::new(received));
+    }
+
+    /// The mock for the action of `request` with the fewest mismatches,
+    /// preferring higher priorities.
+    pub fn nearest_miss(&self, request: &SoapRequest) -> Option<NearMiss> {
+        self.mocks
+            .load()
+            .iter()
+            .filter_map(|mock| Some((mock, mock.mismatches(request)?)))
+            .min_by_key(|(_, mismatches)| mismatches.len())
+            .map(|(mock, mismatches)| NearMiss {
+                mock_id: mock.id(),
+                mock_name: mock.name().map(str::to_string),
+                mismatches,
+            })
+    }
+
+    /// The `GetStatusInfo` response of a connected gateway with the current
+    /// uptime, if enabled and `request` asks for it.
+    fn status_info_response(&self, request: &SoapRequest) -> Option<ResponseBody> {
+        if !self.status_info || !matches!(request.body, SoapRequestBody::GetStatusInfo) {
+            return None;
+        }
+        let uptime = u32::try_from(self.uptime().as_secs()).unwrap_or(u32::MAX);
+        Some(
+            Responder::success()
+                .with_uptime(uptime)
+                .build()
+                .respond(request),
+        )
+    }
+
+    /// The 713 fault ending a client's walk over the mapping list, if
+    /// enabled and `request` is a `GetGenericPortMappingEntry`.
+    fn index_invalid_response(&self, request: &SoapRequest) -> Option<ResponseBody> {
+        if !self.unmatched_index_invalid
+            || !matches!(request.body, SoapRequestBody::GetGenericPortMappingEntry(_))
         {
-            let received = ReceivedRequest::from_soap_request(request, self.start_time);
-            let mut requests = self.received_requests.write().await;
-            requests.push(received);
+            return None;
         }
+        Some(ResponseBody::SoapFault {
+            code: 713,
+            description: "SpecifiedArrayIndexInvalid".to_string(),
+        })
+    }
+
+    /// The `GetCommonLinkProperties` response for the simulated link rates,
+    /// if set and `request` asks for them.
+    fn link_rates_response(&self, request: &SoapRequest) -> Option<ResponseBody> {
+        let (upstream, downstream) = self.link_rates?;
+        if !matches!(request.body, SoapRequestBody::GetCommonLinkProperties) {
+            return None;
+        }
+        let responder = Responder::success()
+            .with_layer1_upstream_max_bit_rate(upstream)
+            .with_layer1_downstream_max_bit_rate(downstream)
+            .build();
+        Some(responder.respond(request))
+    }
 
-        let mocks = self.mocks.read().await;
-        for mock in mocks.iter() {
-            if mock.matches(request) {
-                return Some(mock.respond(request));
+    /// The `GetExternalIPAddress` response for the requested WAN connection
+    /// service, if its address is set and `request` asks for it.
+    fn external_ip_response(&self, request: &SoapRequest) -> Option<ResponseBody> {
+        if !matches!(request.body, SoapRequestBody::GetExternalIPAddress) {
+            return None;
+        }
+        if request.wan_device > 0 {
+            let ip = self.second_wan.as_ref()?.external_ip;
+            return Some(
+                Responder::success()
+                    .with_external_ip(ip)
+                    .build()
+                    .respond(request),
+            );
+        }
+        let ip = {
+            let ips = lock(&self.external_ips);
+            match request.service_type {
+                ServiceType::WANPPPConnection(_) => ips.ppp.or(ips.shared),
+                _ => ips.shared,
             }
+        }?;
+        Some(
+            Responder::success()
+                .with_external_ip(ip)
+                .build()
+                .respond(request),
+        )
+    }
+
+    /// Record a panic of `mock`'s custom responder and return the fault
+    /// sent instead.
+    fn record_responder_panic(
+        &self,
+        mock: &Mock,
+        request: &SoapRequest,
+        message: String,
+    ) -> ResponseBody {
+        self.record_server_error(format!(
+            "custom responder of mock {} panicked while answering {}: {}",
+            mock.label(),
+            request.action_name,
+            message
+        ));
+        lock(&self.responder_panics).push(ResponderPanic {
+            mock_id: mock.id(),
+            mock_name: mock.name().map(str::to_string),
+            action_name: request.action_name.clone(),
+            message,
+            timestamp: self.start_time.elapsed(),
+        });
+        ResponseBody::SoapFault {
+            code: 501,
+            description: "ActionFailed".to_string(),
+        }
+    }
+
+    /// Get all recorded custom responder panics.
+    pub fn responder_panics(&self) -> Vec<ResponderPanic> {
+        lock(&self.responder_panics).clone()
+    }
+
+    /// Current contents of the port mapping table (empty if disabled).
+    pub fn port_mappings(&self) -> Vec<PortMapping> {
+        self.wan_port_mappings(0)
+    }
+
+    /// Current contents of a WANDevice's port mapping table (empty if
+    /// disabled).
+    pub fn wan_port_mappings(&self, wan_device: usize) -> Vec<PortMapping> {
+        self.mapping_table(wan_device)
+            .map(PortMappingTable::mappings)
+            .unwrap_or_default()
+    }
+
+    /// The mapping traffic to `external_port` and `protocol` on the first
+    /// WANDevice would be forwarded through, if any.
+    pub fn forwarding(&self, external_port: u16, protocol: Protocol) -> Option<PortMapping> {
+        self.forwarded_port_mappings()
+            .into_iter()
+            .find(|m| m.external_port == external_port && m.protocol == protocol)
+    }
+
+    /// The mappings of the first WANDevice traffic is forwarded through.
+    pub fn forwarded_port_mappings(&self) -> Vec<PortMapping> {
+        self.mapping_table(0)
+            .map(PortMappingTable::forwarded)
+            .unwrap_or_default()
+    }
+
+    /// Watch the first WANDevice's port mapping table for changes, if
+    /// enabled.
+    pub fn watch_port_mappings(&self) -> Option<watch::Receiver<()>> {
+        self.mapping_table(0).map(PortMappingTable::watch)
+    }
+
+    /// Number of entries in a WANDevice's port mapping table (0 if
+    /// disabled).
+    pub fn port_mapping_count(&self, wan_device: usize) -> usize {
+        self.mapping_table(wan_device)
+            .map_or(0, PortMappingTable::len)
+    }
+
+    /// The port mapping table of a WANDevice, if enabled.
+    fn mapping_table(&self, wan_device: usize) -> Option<&PortMappingTable> {
+        match wan_device {
+            0 => self.port_mappings.as_ref(),
+            _ => self.second_wan.as_ref()?.port_mappings.as_ref(),
+        }
+    }
+
+    /// Number of simulated WANDevices.
+    pub fn wan_device_count(&self) -> usize {
+        if self.second_wan.is_some() { 2 } else { 1 }
+    }
+
+    /// Set the external address reported by both WAN connection services
+    /// and notify subscribers of the change.
+    pub fn set_external_ip(&self, ip: IpAddr) {
+        let changed = lock(&self.external_ips).shared.replace(ip) != Some(ip);
+        if changed {
+            self.events
+                .publish(vec![("ExternalIPAddress".to_string(), ip.to_string())]);
+        }
+    }
+
+    /// Make WANPPPConnection report `ip` instead of the shared address.
+    pub fn set_ppp_external_ip(&self, ip: IpAddr) {
+        lock(&self.external_ips).ppp = Some(ip);
+    }
+
+    /// GENA subscribers to the WANIPConnection service.
+    pub fn events(&self) -> &EventPublisher {
+        &self.events
+    }
+
+    /// GENA subscribers to a WANDevice's WANIPConnection service.
+    pub fn wan_events(&self, wan_device: usize) -> &EventPublisher {
+        match (wan_device, &self.second_wan) {
+            (1.., Some(second_wan)) => &second_wan.events,
+            _ => &self.events,
+        }
+    }
+
+    /// Add a mapping to the port mapping table; returns `false` if disabled.
+    pub fn insert_port_mapping(&self, mapping: PortMapping) -> bool {
+        self.port_mappings
+            .as_ref()
+            .map(|table| table.insert(mapping))
+            .is_some()
+    }
+
+    /// Remove mappings from the port mapping table; returns `false` if
+    /// there were none.
+    pub fn remove_port_mapping(&self, external_port: u16, protocol: Protocol) -> bool {
+        self.port_mappings
+            .as_ref()
+            .is_some_and(|table| table.remove(external_port, protocol))
+    }
+
+    /// Remove every mapping from the port mapping tables.
+    pub fn clear_port_mappings(&self) {
+        for table in (0..self.wan_device_count()).filter_map(|wan| self.mapping_table(wan)) {
+            table.clear();
         }
-        None
     }
 
     /// Get all received requests.
-    pub async fn received_requests(&self) -> Vec<ReceivedRequest> {
-        let requests = self.received_requests.read().await;
-        requests.clone()
+    pub fn received_requests(&self) -> Vec<Arc<ReceivedRequest>> {
+        lock(&self.received_requests).clone()
+    }
+
+    /// Group SSDP searches and SOAP requests by client IP, in order of
+    /// first appearance.
+    pub fn client_activity(&self) -> Vec<ClientActivity> {
+        let mut clients: Vec<ClientActivity> = Vec::new();
+        for request in self.received_ssdp_requests() {
+            ClientActivity::entry(&mut clients, request.source.ip())
+                .ssdp_requests
+                .push(request);
+        }
+        for request in self.received_requests() {
+            if let Some(address) = request.client {
+                ClientActivity::entry(&mut clients, address.ip())
+                    .requests
+                    .push(request);
+            }
+        }
+        clients.sort_by_key(ClientActivity::first_seen);
+        clients
+    }
+
+    /// Get received requests starting at index `n`.
+    pub fn received_requests_since(&self, n: usize) -> Vec<Arc<ReceivedRequest>> {
+        let requests = lock(&self.received_requests);
+        requests.get(n..).map(<[_]>::to_vec).unwrap_or_default()
     }
 
     /// Clear all registered mocks.
-    pub async fn clear(&self) {
-        let mut mocks = self.mocks.write().await;
-        mocks.clear();
+    pub fn clear(&self) {
+        self.mocks.store(Arc::new(Vec::new()));
+        lock(&self.ssdp_mocks).clear();
+        *lock(&self.active_scenario) = None;
     }
 
     /// Clear all received requests.
-    pub async fn clear_received_requests(&self) {
-        let mut requests = self.received_requests.write().await;
-        requests.clear();
+    pub fn clear_received_requests(&self) {
+        lock(&self.received_requests).clear();
+    }
+
+    /// Record compliance violations found in a client request.
+    pub fn record_compliance_violations(&self, violations: Vec<ComplianceViolation>) {
+        lock(&self.compliance_violations).extend(violations);
+    }
+
+    /// Get all recorded compliance violations.
+    pub fn compliance_violations(&self) -> Vec<ComplianceViolation> {
+        lock(&self.compliance_violations).clone()
+    }
+
+    /// Record a failure of the server itself (a dead task or a panic while
+    /// answering a request).
+    pub fn record_server_error(&self, error: String) {
+        tracing::error!(target: TRACE_TARGET, "{}", error);
+        lock(&self.server_errors).push(error);
+    }
+
+    /// Get all recorded server failures.
+    pub fn server_errors(&self) -> Vec<String> {
+        lock(&self.server_errors).clone()
+    }
+
+    /// Register how to react to M-SEARCH requests matching `matcher`.
+    pub fn register_ssdp(&self, matcher: SsdpMatcher, response: SsdpResponse) {
+        lock(&self.ssdp_mocks).push((matcher, response));
+    }
+
+    /// How to react to an M-SEARCH: the first matching SSDP mock decides,
+    /// and searches no mock matches are answered.
+    pub fn ssdp_response(&self, request: &ReceivedSsdpRequest) -> SsdpResponse {
+        lock(&self.ssdp_mocks)
+            .iter()
+            .find(|(matcher, _)| matcher.matches(request))
+            .map_or(SsdpResponse::Answer, |(_, response)| *response)
+    }
+
+    /// Serve `xml` instead of the generated device description.
+    pub fn set_description_xml(&self, xml: String) {
+        *lock(&self.description_override) = Some(xml);
+        self.config_id.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// The device description set with `set_description_xml`, if any.
+    pub fn description_xml(&self) -> Option<String> {
+        lock(&self.description_override).clone()
+    }
+
+    /// Serve `xml` instead of the generated SCPD of the named service.
+    pub fn set_scpd_xml(&self, service_name: &str, xml: String) {
+        lock(&self.scpd_overrides).insert(service_name.to_string(), xml);
+        self.config_id.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Current BOOTID.UPNP.ORG value.
+    pub fn boot_id(&self) -> u32 {
+        self.boot_id.load(Ordering::SeqCst)
+    }
+
+    /// Increase BOOTID.UPNP.ORG and reset the uptime, as on a device
+    /// restart.
+    pub fn next_boot(&self) {
+        self.boot_id.fetch_add(1, Ordering::SeqCst);
+        *lock(&self.booted_at) = Instant::now();
+    }
+
+    /// Time since the simulated gateway last booted.
+    pub fn uptime(&self) -> Duration {
+        lock(&self.booted_at).elapsed()
+    }
+
+    /// Current CONFIGID.UPNP.ORG value, increased whenever a description
+    /// document changes.
+    pub fn config_id(&self) -> u32 {
+        self.config_id.load(Ordering::SeqCst)
+    }
+
+    /// The SCPD set with `set_scpd_xml` for the named service, if any.
+    pub fn scpd_xml(&self, service_name: &str) -> Option<String> {
+        lock(&self.scpd_overrides).get(service_name).cloned()
     }
 
     /// Record a received SSDP request.
-    pub async fn record_ssdp_request(&self, request: ReceivedSsdpRequest) {
-        let mut requests = self.received_ssdp_requests.write().await;
-        requests.push(request);
+    pub fn record_ssdp_request(&self, request: ReceivedSsdpRequest) {
+        self.record_traffic(format!(
+            "M-SEARCH {} from {}",
+            request.search_target, request.source
+        ));
+        if let Some(user_agent) = &request.user_agent {
+            self.record_user_agent(request.source.ip(), user_agent);
+        }
+        lock(&self.received_ssdp_requests).push(Arc::new(request));
     }
 
     /// Get all received SSDP requests.
-    pub async fn received_ssdp_requests(&self) -> Vec<ReceivedSsdpRequest> {
-        let requests = self.received_ssdp_requests.read().await;
-        requests.clone()
+    pub fn received_ssdp_requests(&self) -> Vec<Arc<ReceivedSsdpRequest>> {
+        lock(&self.received_ssdp_requests).clone()
+    }
+
+    /// Get received SSDP requests starting at index `n`.
+    pub fn received_ssdp_requests_since(&self, n: usize) -> Vec<Arc<ReceivedSsdpRequest>> {
+        let requests = lock(&self.received_ssdp_requests);
+        requests.get(n..).map(<[_]>::to_vec).unwrap_or_default()
     }
 
     /// Clear all received SSDP requests.
-    pub async fn clear_received_ssdp_requests(&self) {
-        let mut requests = self.received_ssdp_requests.write().await;
-        requests.clear();
+    pub fn clear_received_ssdp_requests(&self) {
+        lock(&self.received_ssdp_requests).clear();
+    }
+
+    /// Record a received SSDP advertisement.
+    pub fn record_ssdp_notification(&self, notification: ReceivedSsdpNotification) {
+        lock(&self.received_ssdp_notifications).push(Arc::new(notification));
+    }
+
+    /// Get all received SSDP advertisements.
+    pub fn received_ssdp_notifications(&self) -> Vec<Arc<ReceivedSsdpNotification>> {
+        lock(&self.received_ssdp_notifications).clone()
+    }
+
+    /// Clear all received SSDP advertisements.
+    pub fn clear_received_ssdp_notifications(&self) {
+        lock(&self.received_ssdp_notifications).clear();
+    }
+
+    /// Record an SSDP advertisement sent by the server.
+    pub fn record_sent_ssdp_notification(&self, notification: SentSsdpNotification) {
+        lock(&self.sent_ssdp_notifications).push(Arc::new(notification));
+    }
+
+    /// Get all SSDP advertisements sent by the server.
+    pub fn sent_ssdp_notifications(&self) -> Vec<Arc<SentSsdpNotification>> {
+        lock(&self.sent_ssdp_notifications).clone()
+    }
+
+    /// Note an incoming HTTP request or SSDP search.
+    pub fn record_traffic(&self, description: String) {
+        self.traffic.send_replace(Some(description));
+    }
+
+    /// Watch for incoming traffic; the receiver sees arrivals after this
+    /// call as changes.
+    pub fn watch_traffic(&self) -> watch::Receiver<Option<String>> {
+        self.traffic.subscribe()
+    }
+
+    /// Count a `User-Agent` sent by `client`.
+    pub fn record_user_agent(&self, client: IpAddr, user_agent: &str) {
+        let mut clients = lock(&self.client_agents);
+        let stats = ClientAgentStats::entry(&mut clients, client);
+        *stats.user_agents.entry(user_agent.to_string()).or_default() += 1;
+    }
+
+    /// Count the form of a `SOAPAction` header sent by `client` for
+    /// `action_name`.
+    pub fn record_soap_action(&self, client: IpAddr, soap_action: &str, action_name: &str) {
+        let form = soap_action.replacen(&format!("#{action_name}"), "#*", 1);
+        let mut clients = lock(&self.client_agents);
+        let stats = ClientAgentStats::entry(&mut clients, client);
+        *stats.soap_actions.entry(form).or_default() += 1;
+    }
+
+    /// Get the client software seen so far, by IP address.
+    pub fn client_agents(&self) -> Vec<ClientAgentStats> {
+        lock(&self.client_agents).values().cloned().collect()
+    }
+
+    /// Record a request that is deliberately left unanswered.
+    pub fn record_request(&self, request: &SoapRequest) {
+        let received = ReceivedRequest::from_soap_request(request, self.start_time);
+        lock(&self.received_requests).push(Arc::new(received));
+    }
+
+    /// Record metrics for a handled SOAP request.
+    pub fn record_soap_metrics(
+        &self,
+        action_name: &str,
+        mock_id: Option<u64>,
+        latency: std::time::Duration,
+    ) {
+        lock(&self.metrics).record_soap_request(action_name, mock_id, latency);
+    }
+
+    /// Record that an SSDP M-SEARCH request was answered.
+    pub fn record_ssdp_answered(&self) {
+        lock(&self.metrics).record_ssdp_answered();
+    }
+
+    /// Get a snapshot of the collected metrics.
+    pub fn metrics(&self) -> Metrics {
+        lock(&self.metrics).snapshot()
+    }
+
+    /// Format all received SOAP and SSDP requests as a human-readable log,
+    /// ordered by arrival time.
+    pub fn dump_requests(&self) -> String {
+        let requests = self.received_requests();
+        let ssdp_requests = self.received_ssdp_requests();
+        let ssdp_notifications = self.received_ssdp_notifications();
+
+        let mut entries: Vec<(std::time::Duration, String)> = requests
+            .iter()
+            .map(|r| (r.timestamp, r.to_string()))
+            .chain(ssdp_requests.iter().map(|r| (r.timestamp, r.to_string())))
+            .chain(
+                ssdp_notifications
+                    .iter()
+                    .map(|n| (n.timestamp, n.to_string())),
+            )
+            .collect();
+        entries.sort_by_key(|(timestamp, _)| *timestamp);
+
+        let mut dump = String::new();
+        for (_, entry) in entries {
+            dump.push_str(&entry);
+            dump.push('\n');
+        }
+        dump
     }
 
     /// Get the start time of the registry.
     pub fn start_time(&self) -> Instant {
         self.start_time
     }
+
+    /// Get the system clock time the registry was created at.
+    pub fn started_at(&self) -> SystemTime {
+        self.started_at
+    }
+}
+
+/// Lock a mutex, recovering the data if a previous holder panicked.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Sort mocks by priority (highest first), keeping registration order for ties.
+fn sort_by_priority(mocks: &mut [Arc<Mock>]) {
+    mocks.sort_by_key(|m| std::cmp::Reverse(m.priority()));
 }
@@ -1,6 +1,7 @@
 //! Builder for success responses.
 
 use super::{Responder, ResponderInner, SuccessResponse};
+use crate::mapping::PortMapping;
 use std::net::IpAddr;
 use std::sync::Arc;
 
@@ -11,6 +12,20 @@ pub struct SuccessResponseBuilder {
 }
 
 impl SuccessResponseBuilder {
+    /// Start from all the port mapping response fields of `mapping` (for
+    /// GetGenericPortMappingEntry / GetSpecificPortMappingEntry).
+    pub fn from_mapping(mapping: PortMapping) -> Self {
+        SuccessResponseBuilder::default()
+            .with_remote_host(mapping.remote_host)
+            .with_external_port(mapping.external_port)
+            .with_protocol(mapping.protocol.as_str())
+            .with_internal_port(mapping.internal_port)
+            .with_internal_client(mapping.internal_client.to_string())
+            .with_enabled(mapping.enabled)
+            .with_description(mapping.description)
+            .with_lease_duration(mapping.lease_duration)
+    }
+
     /// Set the external IP address (for GetExternalIPAddress).
     pub fn with_external_ip(mut self, ip: IpAddr) -> Self {
         self.response.external_ip = Some(ip);
@@ -19,19 +34,20 @@ impl SuccessResponseBuilder {
 
     /// Set the connection status (for GetStatusInfo).
     /// Common values: "Connected", "Disconnected", "Connecting".
+    /// Defaults to "Connected".
     pub fn with_connection_status(mut self, status: impl Into<String>) -> Self {
         self.response.connection_status = Some(status.into());
         self
     }
 
     /// Set the last connection error (for GetStatusInfo).
-    /// Common value: "ERROR_NONE".
+    /// Common value: "ERROR_NONE" (the default).
     pub fn with_last_connection_error(mut self, error: impl Into<String>) -> Self {
         self.response.last_connection_error = Some(error.into());
         self
     }
 
-    /// Set the uptime in seconds (for GetStatusInfo).
+    /// Set the uptime in seconds (for GetStatusInfo). Defaults to 0.
     pub fn with_uptime(mut self, uptime: u32) -> Self {
         self.response.uptime = Some(uptime);
         self
@@ -85,6 +101,12 @@ impl SuccessResponseBuilder {
         self
     }
 
+    /// Set the external port actually reserved (for AddAnyPortMapping).
+    pub fn with_reserved_port(mut self, port: u16) -> Self {
+        self.response.reserved_port = Some(port);
+        self
+    }
+
     /// Set the WAN access type (for GetCommonLinkProperties).
     pub fn with_wan_access_type(mut self, access_type: impl Into<String>) -> Self {
         self.response.wan_access_type = Some(access_type.into());
@@ -110,15 +132,48 @@ impl SuccessResponseBuilder {
     }
 
     /// Set total bytes (for GetTotalBytesReceived/Sent).
+    ///
+    /// Real devices report a `ui4`; values above `u32::MAX` are sent as
+    /// given unless [`with_counter_wraparound`](Self::with_counter_wraparound)
+    /// is set.
     pub fn with_total_bytes(mut self, bytes: u64) -> Self {
         self.response.total_bytes = Some(bytes);
         self
     }
 
+    /// Report total bytes modulo 2^32, like a `ui4` counter that wrapped
+    /// (for GetTotalBytesReceived/Sent).
+    pub fn with_counter_wraparound(mut self) -> Self {
+        self.response.wrap_counters = true;
+        self
+    }
+
+    /// Add a `NewDNSServers` element listing `servers`, comma-separated.
+    ///
+    /// Appended to the response of whatever action this responder answers,
+    /// like the DNS server lists some routers report next to their
+    /// connection info.
+    pub fn with_dns_servers(mut self, servers: impl IntoIterator<Item = IpAddr>) -> Self {
+        self.response.dns_servers = Some(servers.into_iter().collect());
+        self
+    }
+
+    /// Add a vendor-specific element, e.g. `NewX_AVM_DE_DNSServer1`.
+    ///
+    /// Appended to the response of whatever action this responder answers,
+    /// after the standard elements and in the order added. The value is
+    /// XML-escaped.
+    pub fn with_vendor_field(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.response
+            .vendor_fields
+            .push((name.into(), value.into()));
+        self
+    }
+
     /// Build the responder.
     pub fn build(self) -> Responder {
         Responder {
-            inner: Arc::new(ResponderInner::Success(self.response)),
+            inner: Arc::new(ResponderInner::Success(Box::new(self.response))),
         }
     }
 }
@@ -7,9 +7,18 @@ pub use builder::SuccessResponseBuilder;
 pub(crate) use templates::generate_soap_fault;
 use templates::generate_success_response;
 
-use crate::matcher::SoapRequest;
+use crate::action::Action;
+use crate::mapping::PortMapping;
+use crate::matcher::{SoapRequest, SoapRequestBody};
+use crate::server::panic_message;
+use bytes::Bytes;
+use futures_core::Stream;
 use std::net::IpAddr;
-use std::sync::Arc;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 /// A responder that generates responses for matched requests.
 #[derive(Clone)]
@@ -18,9 +27,29 @@ pub struct Responder {
 }
 
 enum ResponderInner {
-    Success(SuccessResponse),
-    Error { code: u16, description: String },
+    Success(Box<SuccessResponse>),
+    Error {
+        code: u16,
+        description: String,
+    },
     Custom(Arc<dyn Fn(&SoapRequest) -> ResponseBody + Send + Sync>),
+    Delayed {
+        inner: Responder,
+        delay: Duration,
+    },
+    RepeatThen {
+        first: Responder,
+        times: u32,
+        then: Responder,
+        /// Responses sent by `first` so far.
+        sent: AtomicU32,
+    },
+    MappingList(Vec<PortMapping>),
+    ByteCounter {
+        /// Value reported for the next request, before wrapping.
+        next: AtomicU64,
+        step: u64,
+    },
 }
 
 /// The body of a response.
@@ -32,6 +61,84 @@ pub enum ResponseBody {
     SoapFault { code: u16, description: String },
     /// A raw HTTP response body.
     Raw { content_type: String, body: String },
+    /// A raw HTTP response body of arbitrary bytes, e.g. invalid UTF-8,
+    /// another character encoding or compressed content.
+    RawBytes { content_type: String, body: Vec<u8> },
+    /// A raw HTTP response body delivered as a stream of chunks.
+    Stream {
+        content_type: String,
+        body: ResponseStream,
+    },
+    /// A complete HTTP response: any status code, headers sent as given
+    /// (repeats allowed) and body. No header is added except
+    /// `Content-Length`.
+    Http {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: String,
+    },
+}
+
+impl ResponseBody {
+    /// Create a streaming response body from an async byte stream.
+    ///
+    /// Useful for very large payloads or for throttled delivery, where
+    /// chunks are produced over time instead of all at once.
+    pub fn stream<S>(content_type: impl Into<String>, stream: S) -> Self
+    where
+        S: Stream<Item = std::io::Result<Bytes>> + Send + 'static,
+    {
+        ResponseBody::Stream {
+            content_type: content_type.into(),
+            body: ResponseStream::new(stream),
+        }
+    }
+}
+
+type BoxByteStream = Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send>>;
+
+/// An async byte stream used as a response body.
+///
+/// The stream can only be sent once: clones share the same underlying
+/// stream, and sending an already consumed stream produces an empty body.
+#[derive(Clone)]
+pub struct ResponseStream {
+    inner: Arc<Mutex<Option<BoxByteStream>>>,
+}
+
+impl ResponseStream {
+    /// Wrap an async byte stream.
+    pub fn new<S>(stream: S) -> Self
+    where
+        S: Stream<Item = std::io::Result<Bytes>> + Send + 'static,
+    {
+        ResponseStream {
+            inner: Arc::new(Mutex::new(Some(Box::pin(stream)))),
+        }
+    }
+
+    /// Take the underlying stream, leaving this (and every clone) empty.
+    pub(crate) fn take(&self) -> Option<BoxByteStream> {
+        self.inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .take()
+    }
+}
+
+impl std::fmt::Debug for ResponseStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ResponseStream").finish()
+    }
+}
+
+/// How the server reacts to an M-SEARCH matched by an SSDP mock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SsdpResponse {
+    /// Send the usual search response.
+    Answer,
+    /// Stay silent, like a device discarding the search.
+    Ignore,
 }
 
 /// Data for successful responses.
@@ -55,6 +162,9 @@ pub(crate) struct SuccessResponse {
     pub(crate) description: Option<String>,
     pub(crate) lease_duration: Option<u32>,
 
+    // AddAnyPortMapping
+    pub(crate) reserved_port: Option<u16>,
+
     // GetCommonLinkProperties
     pub(crate) wan_access_type: Option<String>,
     pub(crate) layer1_upstream_max_bit_rate: Option<u32>,
@@ -63,6 +173,38 @@ pub(crate) struct SuccessResponse {
 
     // GetTotalBytesReceived / GetTotalBytesSent
     pub(crate) total_bytes: Option<u64>,
+    pub(crate) wrap_counters: bool,
+
+    // Extra elements appended to any action's response
+    pub(crate) dns_servers: Option<Vec<IpAddr>>,
+    pub(crate) vendor_fields: Vec<(String, String)>,
+}
+
+impl SuccessResponse {
+    /// Names of the fields required by `action` that have not been set.
+    fn missing_fields(&self, action: &Action) -> Vec<&'static str> {
+        let required: &[(&'static str, bool)] = match action {
+            Action::GetExternalIPAddress => &[("external_ip", self.external_ip.is_some())],
+            Action::GetGenericPortMappingEntry(_) => &[
+                ("external_port", self.external_port.is_some()),
+                ("internal_port", self.internal_port.is_some()),
+                ("internal_client", self.internal_client.is_some()),
+            ],
+            Action::GetSpecificPortMappingEntry(_) => &[
+                ("internal_port", self.internal_port.is_some()),
+                ("internal_client", self.internal_client.is_some()),
+            ],
+            Action::GetTotalBytesReceived | Action::GetTotalBytesSent => {
+                &[("total_bytes", self.total_bytes.is_some())]
+            }
+            _ => &[],
+        };
+        required
+            .iter()
+            .filter(|(_, is_set)| !is_set)
+            .map(|(name, _)| *name)
+            .collect()
+    }
 }
 
 impl Responder {
@@ -91,11 +233,105 @@ impl Responder {
         }
     }
 
+    /// Wrap `inner` so its responses are sent after `delay`, e.g. to test
+    /// client timeouts. Composes with any responder, including custom ones
+    /// and other delayed ones (delays add up).
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let slow = Responder::delayed(
+    ///     Responder::success().with_external_ip(ip),
+    ///     Duration::from_secs(3),
+    /// );
+    /// ```
+    pub fn delayed(inner: impl Into<Responder>, delay: Duration) -> Self {
+        Responder {
+            inner: Arc::new(ResponderInner::Delayed {
+                inner: inner.into(),
+                delay,
+            }),
+        }
+    }
+
+    /// Answer with `first` for the first `times` requests, then with `then`,
+    /// e.g. to express retry scenarios in one registration.
+    ///
+    /// Clones share the count, as with any responder state.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// // Three conflicts, then success
+    /// let responder = Responder::repeat_then(
+    ///     Responder::error(718, "ConflictInMappingEntry"),
+    ///     3,
+    ///     Responder::success(),
+    /// );
+    /// ```
+    pub fn repeat_then(
+        first: impl Into<Responder>,
+        times: u32,
+        then: impl Into<Responder>,
+    ) -> Self {
+        Responder {
+            inner: Arc::new(ResponderInner::RepeatThen {
+                first: first.into(),
+                times,
+                then: then.into(),
+                sent: AtomicU32::new(0),
+            }),
+        }
+    }
+
+    /// Answer port mapping enumeration from `mappings`:
+    /// `GetGenericPortMappingEntry` index N gets the Nth entry and 713
+    /// beyond the end, and `GetSpecificPortMappingEntry` gets the matching
+    /// entry or 714. Other actions get 401 "Invalid Action".
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// server.mock(
+    ///     Action::get_generic_port_mapping_entry(),
+    ///     Responder::port_mapping_list(vec![web, ssh]),
+    /// ).await;
+    /// ```
+    pub fn port_mapping_list(mappings: Vec<PortMapping>) -> Self {
+        Responder {
+            inner: Arc::new(ResponderInner::MappingList(mappings)),
+        }
+    }
+
+    /// Answer GetTotalBytesReceived/Sent with a counter starting at `start`
+    /// and growing by `step` per request, wrapping at 2^32 like the `ui4`
+    /// counters of real devices.
+    ///
+    /// Clones share the counter, as with any responder state.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// // Wraps between the second and third request
+    /// let responder = Responder::byte_counter(u32::MAX as u64 - 1_500_000, 1_000_000);
+    /// ```
+    pub fn byte_counter(start: u64, step: u64) -> Self {
+        Responder {
+            inner: Arc::new(ResponderInner::ByteCounter {
+                next: AtomicU64::new(start),
+                step,
+            }),
+        }
+    }
+
     /// Generate a response for the given request.
+    ///
+    /// Delays are not applied here; the server applies them when answering.
     pub fn respond(&self, request: &SoapRequest) -> ResponseBody {
         match self.inner.as_ref() {
             ResponderInner::Success(data) => {
-                let xml = generate_success_response(&request.action_name, data);
+                let xml =
+                    generate_success_response(&request.action_name, &request.service_type, data);
                 ResponseBody::Soap(xml)
             }
             ResponderInner::Error { code, description } => ResponseBody::SoapFault {
@@ -103,20 +339,170 @@ impl Responder {
                 description: description.clone(),
             },
             ResponderInner::Custom(f) => f(request),
+            ResponderInner::Delayed { inner, .. } => inner.respond(request),
+            ResponderInner::RepeatThen { .. } => self.next_in_sequence().respond(request),
+            ResponderInner::MappingList(mappings) => mapping_list_response(mappings, request),
+            ResponderInner::ByteCounter { next, step } => {
+                let bytes = next.fetch_add(*step, Ordering::SeqCst);
+                let data = SuccessResponse {
+                    total_bytes: Some(bytes),
+                    wrap_counters: true,
+                    ..SuccessResponse::default()
+                };
+                let xml =
+                    generate_success_response(&request.action_name, &request.service_type, &data);
+                ResponseBody::Soap(xml)
+            }
+        }
+    }
+
+    /// Generate a response and the delay to send it after, catching panics
+    /// of custom closures.
+    ///
+    /// Returns the panic message if a closure panicked.
+    pub(crate) fn try_respond(
+        &self,
+        request: &SoapRequest,
+    ) -> Result<(ResponseBody, Duration), String> {
+        match self.inner.as_ref() {
+            ResponderInner::Custom(f) => std::panic::catch_unwind(AssertUnwindSafe(|| f(request)))
+                .map(|body| (body, Duration::ZERO))
+                .map_err(|panic| panic_message(panic.as_ref())),
+            ResponderInner::Delayed { inner, delay } => {
+                let (body, inner_delay) = inner.try_respond(request)?;
+                Ok((body, inner_delay + *delay))
+            }
+            ResponderInner::RepeatThen { .. } => self.next_in_sequence().try_respond(request),
+            _ => Ok((self.respond(request), Duration::ZERO)),
+        }
+    }
+
+    /// The responder answering the next request of a `repeat_then` sequence.
+    fn next_in_sequence(&self) -> &Responder {
+        let ResponderInner::RepeatThen {
+            first,
+            times,
+            then,
+            sent,
+        } = self.inner.as_ref()
+        else {
+            return self;
+        };
+        let counted = sent.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |sent| {
+            (sent < *times).then_some(sent + 1)
+        });
+        if counted.is_ok() { first } else { then }
+    }
+
+    /// Names of the builder fields a success responder should set for the
+    /// given action but doesn't (always empty for other responders).
+    pub(crate) fn missing_fields(&self, action: &Action) -> Vec<&'static str> {
+        match self.inner.as_ref() {
+            ResponderInner::Success(data) => data.missing_fields(action),
+            ResponderInner::Delayed { inner, .. } => inner.missing_fields(action),
+            ResponderInner::RepeatThen { first, then, .. } => {
+                let mut missing = first.missing_fields(action);
+                missing.extend(then.missing_fields(action));
+                missing.sort_unstable();
+                missing.dedup();
+                missing
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Short human-readable description of this responder.
+    pub(crate) fn describe(&self) -> String {
+        match self.inner.as_ref() {
+            ResponderInner::Success(_) => "success".to_string(),
+            ResponderInner::Error { code, description } => format!("error {code} ({description})"),
+            ResponderInner::Custom(_) => "custom".to_string(),
+            ResponderInner::Delayed { inner, delay } => {
+                format!("{} after {:?}", inner.describe(), delay)
+            }
+            ResponderInner::RepeatThen {
+                first, times, then, ..
+            } => format!(
+                "{} {times} times, then {}",
+                first.describe(),
+                then.describe()
+            ),
+            ResponderInner::MappingList(mappings) => {
+                format!("port mapping list ({} entries)", mappings.len())
+            }
+            ResponderInner::ByteCounter { step, .. } => format!("byte counter (+{step})"),
         }
     }
 }
 
+/// Answer an enumeration request from a fixed list of mappings.
+fn mapping_list_response(mappings: &[PortMapping], request: &SoapRequest) -> ResponseBody {
+    let mapping = match &request.body {
+        SoapRequestBody::GetGenericPortMappingEntry(req) => {
+            match mappings.get(req.index as usize) {
+                Some(mapping) => mapping,
+                None => {
+                    return Responder::error(713, "SpecifiedArrayIndexInvalid").respond(request);
+                }
+            }
+        }
+        SoapRequestBody::GetSpecificPortMappingEntry(req) => {
+            let protocol = req.protocol.parse().ok();
+            let found = mappings.iter().find(|m| {
+                m.remote_host == req.remote_host
+                    && m.external_port == req.external_port
+                    && Some(m.protocol) == protocol
+            });
+            match found {
+                Some(mapping) => mapping,
+                None => return Responder::error(714, "NoSuchEntryInArray").respond(request),
+            }
+        }
+        _ => return Responder::error(401, "Invalid Action").respond(request),
+    };
+    SuccessResponseBuilder::from_mapping(mapping.clone())
+        .build()
+        .respond(request)
+}
+
 impl std::fmt::Debug for Responder {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.inner.as_ref() {
-            ResponderInner::Success(data) => f.debug_tuple("Responder::Success").field(data).finish(),
+            ResponderInner::Success(data) => {
+                f.debug_tuple("Responder::Success").field(data).finish()
+            }
             ResponderInner::Error { code, description } => f
                 .debug_struct("Responder::Error")
                 .field("code", code)
                 .field("description", description)
                 .finish(),
             ResponderInner::Custom(_) => f.debug_tuple("Responder::Custom").finish(),
+            ResponderInner::Delayed { inner, delay } => f
+                .debug_struct("Responder::Delayed")
+                .field("inner", inner)
+                .field("delay", delay)
+                .finish(),
+            ResponderInner::RepeatThen {
+                first,
+                times,
+                then,
+                sent,
+            } => f
+                .debug_struct("Responder::RepeatThen")
+                .field("first", first)
+                .field("times", times)
+                .field("then", then)
+                .field("sent", &sent.load(Ordering::SeqCst))
+                .finish(),
+            ResponderInner::MappingList(mappings) => f
+                .debug_tuple("Responder::MappingList")
+                .field(mappings)
+                .finish(),
+            ResponderInner::ByteCounter { next, step } => f
+                .debug_struct("Responder::ByteCounter")
+                .field("next", &next.load(Ordering::SeqCst))
+                .field("step", step)
+                .finish(),
         }
     }
 }
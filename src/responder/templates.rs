@@ -1,6 +1,7 @@
 //! XML/SOAP response templates.
 
 use super::SuccessResponse;
+use crate::action::ServiceType;
 
 /// SOAP envelope template.
 const SOAP_ENVELOPE_START: &str = r#"<?xml version="1.0"?>
@@ -32,43 +33,92 @@ pub(crate) fn generate_soap_fault(code: u16, description: &str) -> String {
 }
 
 /// Generate a successful SOAP response for the given action.
-pub(crate) fn generate_success_response(action_name: &str, data: &SuccessResponse) -> String {
+///
+/// The response element uses the namespace of the requested service, so
+/// e.g. `WANIPConnection:2` clients get `WANIPConnection:2` responses.
+pub(crate) fn generate_success_response(
+    action_name: &str,
+    service_type: &ServiceType,
+    data: &SuccessResponse,
+) -> String {
+    let service = match service_type {
+        ServiceType::Other(_) => ServiceType::for_action(action_name),
+        known => known.clone(),
+    };
     let body = match action_name {
-        "GetExternalIPAddress" => generate_get_external_ip_response(data),
-        "GetStatusInfo" => generate_get_status_info_response(data),
-        "AddPortMapping" => generate_add_port_mapping_response(),
-        "DeletePortMapping" => generate_delete_port_mapping_response(),
-        "GetGenericPortMappingEntry" => generate_get_port_mapping_entry_response(data),
-        "GetSpecificPortMappingEntry" => generate_get_port_mapping_entry_response(data),
-        "GetCommonLinkProperties" => generate_get_common_link_properties_response(data),
-        "GetTotalBytesReceived" => generate_get_total_bytes_received_response(data),
-        "GetTotalBytesSent" => generate_get_total_bytes_sent_response(data),
-        _ => format!(
-            "<u:{action_name}Response xmlns:u=\"urn:schemas-upnp-org:service:WANIPConnection:1\"></u:{action_name}Response>"
-        ),
+        "GetExternalIPAddress" => generate_get_external_ip_response(&service, data),
+        "GetStatusInfo" => generate_get_status_info_response(&service, data),
+        "AddPortMapping" => generate_add_port_mapping_response(&service),
+        "DeletePortMapping" => generate_delete_port_mapping_response(&service),
+        "AddAnyPortMapping" => generate_add_any_port_mapping_response(&service, data),
+        "GetGenericPortMappingEntry" => generate_get_port_mapping_entry_response(&service, data),
+        "GetSpecificPortMappingEntry" => {
+            generate_get_specific_port_mapping_entry_response(&service, data)
+        }
+        "GetCommonLinkProperties" => generate_get_common_link_properties_response(&service, data),
+        "GetTotalBytesReceived" => generate_get_total_bytes_received_response(&service, data),
+        "GetTotalBytesSent" => generate_get_total_bytes_sent_response(&service, data),
+        _ => format!("<u:{action_name}Response xmlns:u=\"{service}\"></u:{action_name}Response>"),
     };
+    let body = append_extra_fields(body, data);
 
     format!("{SOAP_ENVELOPE_START}\n{body}\n{SOAP_ENVELOPE_END}")
 }
 
-fn generate_get_external_ip_response(data: &SuccessResponse) -> String {
+/// Insert the DNS server list and vendor fields before the closing tag of
+/// the action response element.
+fn append_extra_fields(mut body: String, data: &SuccessResponse) -> String {
+    let mut extra = String::new();
+    if let Some(servers) = &data.dns_servers {
+        let servers: Vec<String> = servers.iter().map(|ip| ip.to_string()).collect();
+        extra.push_str(&format!(
+            "<NewDNSServers>{}</NewDNSServers>\n",
+            servers.join(",")
+        ));
+    }
+    for (name, value) in &data.vendor_fields {
+        extra.push_str(&format!("<{name}>{}</{name}>\n", escape_text(value)));
+    }
+    if extra.is_empty() {
+        return body;
+    }
+
+    let close = body.rfind("</u:").unwrap_or(body.len());
+    if !body[..close].ends_with('\n') {
+        extra.insert(0, '\n');
+    }
+    body.insert_str(close, &extra);
+    body
+}
+
+/// Escape `&`, `<` and `>` for use as element text.
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn generate_get_external_ip_response(service: &ServiceType, data: &SuccessResponse) -> String {
     let ip = data
         .external_ip
         .map(|ip| ip.to_string())
         .unwrap_or_default();
     format!(
-        r#"<u:GetExternalIPAddressResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        r#"<u:GetExternalIPAddressResponse xmlns:u="{service}">
 <NewExternalIPAddress>{ip}</NewExternalIPAddress>
 </u:GetExternalIPAddressResponse>"#
     )
 }
 
-fn generate_get_status_info_response(data: &SuccessResponse) -> String {
+fn generate_get_status_info_response(service: &ServiceType, data: &SuccessResponse) -> String {
     let connection_status = data.connection_status.as_deref().unwrap_or("Connected");
-    let last_connection_error = data.last_connection_error.as_deref().unwrap_or("ERROR_NONE");
+    let last_connection_error = data
+        .last_connection_error
+        .as_deref()
+        .unwrap_or("ERROR_NONE");
     let uptime = data.uptime.unwrap_or(0);
     format!(
-        r#"<u:GetStatusInfoResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        r#"<u:GetStatusInfoResponse xmlns:u="{service}">
 <NewConnectionStatus>{connection_status}</NewConnectionStatus>
 <NewLastConnectionError>{last_connection_error}</NewLastConnectionError>
 <NewUptime>{uptime}</NewUptime>
@@ -76,30 +126,48 @@ fn generate_get_status_info_response(data: &SuccessResponse) -> String {
     )
 }
 
-fn generate_add_port_mapping_response() -> String {
-    r#"<u:AddPortMappingResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+fn generate_add_port_mapping_response(service: &ServiceType) -> String {
+    format!(
+        r#"<u:AddPortMappingResponse xmlns:u="{service}">
 </u:AddPortMappingResponse>"#
-        .to_string()
+    )
 }
 
-fn generate_delete_port_mapping_response() -> String {
-    r#"<u:DeletePortMappingResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+fn generate_delete_port_mapping_response(service: &ServiceType) -> String {
+    format!(
+        r#"<u:DeletePortMappingResponse xmlns:u="{service}">
 </u:DeletePortMappingResponse>"#
-        .to_string()
+    )
+}
+
+fn generate_add_any_port_mapping_response(service: &ServiceType, data: &SuccessResponse) -> String {
+    let reserved_port = data.reserved_port.unwrap_or(0);
+    format!(
+        r#"<u:AddAnyPortMappingResponse xmlns:u="{service}">
+<NewReservedPort>{reserved_port}</NewReservedPort>
+</u:AddAnyPortMappingResponse>"#
+    )
 }
 
-fn generate_get_port_mapping_entry_response(data: &SuccessResponse) -> String {
+fn generate_get_port_mapping_entry_response(
+    service: &ServiceType,
+    data: &SuccessResponse,
+) -> String {
     let remote_host = data.remote_host.as_deref().unwrap_or("");
     let external_port = data.external_port.unwrap_or(0);
     let protocol = data.protocol.as_deref().unwrap_or("TCP");
     let internal_port = data.internal_port.unwrap_or(0);
     let internal_client = data.internal_client.as_deref().unwrap_or("");
-    let enabled = if data.enabled.unwrap_or(true) { "1" } else { "0" };
+    let enabled = if data.enabled.unwrap_or(true) {
+        "1"
+    } else {
+        "0"
+    };
     let description = data.description.as_deref().unwrap_or("");
     let lease_duration = data.lease_duration.unwrap_or(0);
 
     format!(
-        r#"<u:GetGenericPortMappingEntryResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        r#"<u:GetGenericPortMappingEntryResponse xmlns:u="{service}">
 <NewRemoteHost>{remote_host}</NewRemoteHost>
 <NewExternalPort>{external_port}</NewExternalPort>
 <NewProtocol>{protocol}</NewProtocol>
@@ -112,14 +180,42 @@ fn generate_get_port_mapping_entry_response(data: &SuccessResponse) -> String {
     )
 }
 
-fn generate_get_common_link_properties_response(data: &SuccessResponse) -> String {
+fn generate_get_specific_port_mapping_entry_response(
+    service: &ServiceType,
+    data: &SuccessResponse,
+) -> String {
+    let internal_port = data.internal_port.unwrap_or(0);
+    let internal_client = data.internal_client.as_deref().unwrap_or("");
+    let enabled = if data.enabled.unwrap_or(true) {
+        "1"
+    } else {
+        "0"
+    };
+    let description = data.description.as_deref().unwrap_or("");
+    let lease_duration = data.lease_duration.unwrap_or(0);
+
+    format!(
+        r#"<u:GetSpecificPortMappingEntryResponse xmlns:u="{service}">
+<NewInternalPort>{internal_port}</NewInternalPort>
+<NewInternalClient>{internal_client}</NewInternalClient>
+<NewEnabled>{enabled}</NewEnabled>
+<NewPortMappingDescription>{description}</NewPortMappingDescription>
+<NewLeaseDuration>{lease_duration}</NewLeaseDuration>
+</u:GetSpecificPortMappingEntryResponse>"#
+    )
+}
+
+fn generate_get_common_link_properties_response(
+    service: &ServiceType,
+    data: &SuccessResponse,
+) -> String {
     let wan_access_type = data.wan_access_type.as_deref().unwrap_or("Cable");
     let upstream = data.layer1_upstream_max_bit_rate.unwrap_or(10000000);
     let downstream = data.layer1_downstream_max_bit_rate.unwrap_or(100000000);
     let status = data.physical_link_status.as_deref().unwrap_or("Up");
 
     format!(
-        r#"<u:GetCommonLinkPropertiesResponse xmlns:u="urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1">
+        r#"<u:GetCommonLinkPropertiesResponse xmlns:u="{service}">
 <NewWANAccessType>{wan_access_type}</NewWANAccessType>
 <NewLayer1UpstreamMaxBitRate>{upstream}</NewLayer1UpstreamMaxBitRate>
 <NewLayer1DownstreamMaxBitRate>{downstream}</NewLayer1DownstreamMaxBitRate>
@@ -128,19 +224,32 @@ fn generate_get_common_link_properties_response(data: &SuccessResponse) -> Strin
     )
 }
 
-fn generate_get_total_bytes_received_response(data: &SuccessResponse) -> String {
+/// The total bytes to report, wrapped to a `ui4` if requested.
+fn total_bytes(data: &SuccessResponse) -> u64 {
     let bytes = data.total_bytes.unwrap_or(0);
+    if data.wrap_counters {
+        bytes % (1 << 32)
+    } else {
+        bytes
+    }
+}
+
+fn generate_get_total_bytes_received_response(
+    service: &ServiceType,
+    data: &SuccessResponse,
+) -> String {
+    let bytes = total_bytes(data);
     format!(
-        r#"<u:GetTotalBytesReceivedResponse xmlns:u="urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1">
+        r#"<u:GetTotalBytesReceivedResponse xmlns:u="{service}">
 <NewTotalBytesReceived>{bytes}</NewTotalBytesReceived>
 </u:GetTotalBytesReceivedResponse>"#
     )
 }
 
-fn generate_get_total_bytes_sent_response(data: &SuccessResponse) -> String {
-    let bytes = data.total_bytes.unwrap_or(0);
+fn generate_get_total_bytes_sent_response(service: &ServiceType, data: &SuccessResponse) -> String {
+    let bytes = total_bytes(data);
     format!(
-        r#"<u:GetTotalBytesSentResponse xmlns:u="urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1">
+        r#"<u:GetTotalBytesSentResponse xmlns:u="{service}">
 <NewTotalBytesSent>{bytes}</NewTotalBytesSent>
 </u:GetTotalBytesSentResponse>"#
     )
@@ -0,0 +1,65 @@
+//! Seedable randomness for simulated behaviors.
+//!
+//! Everything the server does "at random" draws from one [`SimRng`] per
+//! server, so a run can be reproduced with
+//! [`MockIgdServerBuilder::with_seed`](crate::server::MockIgdServerBuilder::with_seed).
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// SplitMix64 generator; small, fast and good enough for test scenarios.
+#[derive(Debug)]
+pub(crate) struct SimRng {
+    seed: u64,
+    state: Mutex<u64>,
+}
+
+impl SimRng {
+    /// Create a generator producing the sequence of `seed`.
+    pub fn new(seed: u64) -> Self {
+        SimRng {
+            seed,
+            state: Mutex::new(seed),
+        }
+    }
+
+    /// Create a generator with a random seed.
+    pub fn from_entropy() -> Self {
+        Self::new(uuid::Uuid::new_v4().as_u64_pair().0)
+    }
+
+    /// The seed the generator started from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Next 64 random bits.
+    pub fn next_u64(&self) -> u64 {
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// 16 random bytes.
+    pub fn next_bytes(&self) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&self.next_u64().to_le_bytes());
+        bytes[8..].copy_from_slice(&self.next_u64().to_le_bytes());
+        bytes
+    }
+
+    /// A uniformly distributed duration in `[0, max)`.
+    pub fn duration_below(&self, max: Duration) -> Duration {
+        let nanos = max.as_nanos().min(u64::MAX as u128) as u64;
+        if nanos == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos(self.next_u64() % nanos)
+    }
+}
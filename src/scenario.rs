@@ -0,0 +1,83 @@
+//! Named sets of mocks and quirks that can be switched at runtime.
+
+use crate::action::Action;
+use crate::mapping::MappingQuirk;
+use crate::mock::Mock;
+use crate::responder::Responder;
+
+/// A named set of mocks and port mapping quirks describing one behavior of
+/// the simulated router.
+///
+/// Load scenarios with [`MockIgdServer::load_scenario`] and activate one
+/// with [`MockIgdServer::switch_scenario`]; switching replaces the previous
+/// scenario's mocks and quirks, so long-running tests can flip the router
+/// between behaviors mid-test. Mocks registered outside scenarios and quirks
+/// set on the builder stay in place. Other state, such as the port mapping
+/// table's entries or the external address, is not part of a scenario.
+///
+/// # Example
+///
+/// ```ignore
+/// server
+///     .load_scenario(
+///         Scenario::new("degraded")
+///             .mock(
+///                 Action::GetStatusInfo,
+///                 Responder::success().with_connection_status("Disconnected"),
+///             )
+///             .mock(Action::add_port_mapping(), Responder::error(501, "ActionFailed"))
+///             .with_mapping_quirk(MappingQuirk::NotForwarded),
+///     )
+///     .await;
+/// server.switch_scenario("degraded").await?;
+/// ```
+///
+/// [`MockIgdServer::load_scenario`]: crate::MockIgdServer::load_scenario
+/// [`MockIgdServer::switch_scenario`]: crate::MockIgdServer::switch_scenario
+#[derive(Debug)]
+pub struct Scenario {
+    pub(crate) name: String,
+    pub(crate) mocks: Vec<Mock>,
+    pub(crate) mapping_quirks: Vec<MappingQuirk>,
+}
+
+impl Scenario {
+    /// Create an empty scenario.
+    pub fn new(name: impl Into<String>) -> Self {
+        Scenario {
+            name: name.into(),
+            mocks: Vec::new(),
+            mapping_quirks: Vec::new(),
+        }
+    }
+
+    /// Add a mock for the given action.
+    pub fn mock(self, action: impl Into<Action>, responder: impl Into<Responder>) -> Self {
+        self.with_mock(Mock::new(action, responder))
+    }
+
+    /// Add a fully configured [`Mock`].
+    ///
+    /// Match counts and deadlines start afresh every time the scenario is
+    /// switched to.
+    pub fn with_mock(mut self, mock: Mock) -> Self {
+        self.mocks.push(mock);
+        self
+    }
+
+    /// Reproduce a firmware quirk in the port mapping table while the
+    /// scenario is active, on top of those set with
+    /// [`MockIgdServerBuilder::with_mapping_quirk`]. Has no effect unless
+    /// the port mapping table is enabled.
+    ///
+    /// [`MockIgdServerBuilder::with_mapping_quirk`]: crate::server::MockIgdServerBuilder::with_mapping_quirk
+    pub fn with_mapping_quirk(mut self, quirk: MappingQuirk) -> Self {
+        self.mapping_quirks.push(quirk);
+        self
+    }
+
+    /// The scenario's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
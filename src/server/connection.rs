@@ -0,0 +1,195 @@
+//! HTTP connection handling with configurable keep-alive behavior.
+
+use super::{ClientFilter, TRACE_TARGET};
+use axum::Router;
+use axum::extract::ConnectInfo;
+use axum::http::{HeaderValue, Request, header};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
+use socket2::SockRef;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Notify, watch};
+use tokio::task::JoinSet;
+
+/// How the HTTP server treats persistent connections.
+///
+/// Embedded routers differ widely here, and pooled HTTP clients that assume
+/// a connection stays usable misbehave against some of them.
+///
+/// # Example
+///
+/// ```ignore
+/// // Keep connections open for at most 10 requests or 2 idle seconds
+/// let keep_alive = KeepAlive::enabled()
+///     .with_max_requests(10)
+///     .with_idle_timeout(Duration::from_secs(2));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepAlive {
+    enabled: bool,
+    idle_timeout: Option<Duration>,
+    max_requests: Option<u32>,
+}
+
+impl KeepAlive {
+    /// Close every connection after one response (`Connection: close`).
+    pub fn close() -> Self {
+        KeepAlive {
+            enabled: false,
+            idle_timeout: None,
+            max_requests: None,
+        }
+    }
+
+    /// Keep connections open until the client closes them.
+    pub fn enabled() -> Self {
+        KeepAlive {
+            enabled: true,
+            idle_timeout: None,
+            max_requests: None,
+        }
+    }
+
+    /// Close connections that stay idle between requests for this long.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Close connections after this many requests; the last response
+    /// carries `Connection: close`.
+    pub fn with_max_requests(mut self, requests: u32) -> Self {
+        self.max_requests = Some(requests);
+        self
+    }
+}
+
+/// Activity on one connection, updated by its requests.
+#[derive(Default)]
+struct ConnectionActivity {
+    /// Requests started so far.
+    requests: AtomicU32,
+    /// Requests being handled.
+    in_flight: AtomicUsize,
+    /// Signalled whenever a request starts or ends.
+    changed: Notify,
+}
+
+/// Serve `app` on `listener`, applying `keep_alive` to every connection and
+/// resetting those of clients `refused` rejects, until `shutdown` completes.
+/// Open connections then finish their in-flight requests and close.
+pub(crate) async fn serve(
+    listener: TcpListener,
+    app: Router,
+    keep_alive: KeepAlive,
+    refused: Option<ClientFilter>,
+    shutdown: impl Future<Output = ()>,
+) {
+    tokio::pin!(shutdown);
+    // Dropped on shutdown, which closes the open connections
+    let (closing_tx, closing) = watch::channel(());
+    // Aborted along with this task
+    let mut connections = JoinSet::new();
+
+    loop {
+        let (stream, remote) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tracing::warn!(target: TRACE_TARGET, "Failed to accept connection: {}", e);
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+        if let Some(filter) = &refused
+            && !filter.admits(remote.ip())
+        {
+            tracing::debug!(target: TRACE_TARGET, %remote, "refused filtered client");
+            // Closing with a zero linger sends a RST, as a closed port would
+            let _ = SockRef::from(&stream).set_linger(Some(Duration::ZERO));
+            continue;
+        }
+        let app = app.clone();
+        let closing = closing.clone();
+        while connections.try_join_next().is_some() {}
+        connections.spawn(serve_connection(stream, remote, app, keep_alive, closing));
+    }
+
+    // Release the port, then wait for the open connections, as axum::serve does
+    drop(listener);
+    drop(closing_tx);
+    while connections.join_next().await.is_some() {}
+}
+
+/// Serve the requests of one connection.
+async fn serve_connection(
+    stream: TcpStream,
+    remote: SocketAddr,
+    app: Router,
+    keep_alive: KeepAlive,
+    mut shutdown: watch::Receiver<()>,
+) {
+    let activity = Arc::new(ConnectionActivity::default());
+    let app = TowerToHyperService::new(app.layer(axum::Extension(ConnectInfo(remote))));
+    let service_activity = activity.clone();
+    let service = hyper::service::service_fn(move |request: Request<Incoming>| {
+        let app = app.clone();
+        let activity = service_activity.clone();
+        async move {
+            let number = activity.requests.fetch_add(1, Ordering::SeqCst) + 1;
+            activity.in_flight.fetch_add(1, Ordering::SeqCst);
+            activity.changed.notify_one();
+
+            let mut response = hyper::service::Service::call(&app, request).await?;
+            if keep_alive.max_requests.is_some_and(|max| number >= max) {
+                response
+                    .headers_mut()
+                    .insert(header::CONNECTION, HeaderValue::from_static("close"));
+            }
+
+            activity.in_flight.fetch_sub(1, Ordering::SeqCst);
+            activity.changed.notify_one();
+            Ok::<_, Infallible>(response)
+        }
+    });
+
+    let connection = http1::Builder::new()
+        .keep_alive(keep_alive.enabled)
+        .serve_connection(TokioIo::new(stream), service);
+    tokio::pin!(connection);
+
+    let idle_timeout = keep_alive.idle_timeout.unwrap_or_default();
+    let mut closing = false;
+    loop {
+        let idle = keep_alive.idle_timeout.is_some()
+            && !closing
+            && activity.in_flight.load(Ordering::SeqCst) == 0;
+        tokio::select! {
+            result = connection.as_mut() => {
+                if let Err(e) = result {
+                    tracing::debug!(target: TRACE_TARGET, %remote, "Connection error: {}", e);
+                }
+                return;
+            }
+            // Restart the idle timer
+            _ = activity.changed.notified() => {}
+            _ = tokio::time::sleep(idle_timeout), if idle => {
+                tracing::debug!(target: TRACE_TARGET, %remote, "closing idle connection");
+                connection.as_mut().graceful_shutdown();
+                closing = true;
+            }
+            _ = shutdown.changed(), if !closing => {
+                connection.as_mut().graceful_shutdown();
+                closing = true;
+            }
+        }
+    }
+}
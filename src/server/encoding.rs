@@ -0,0 +1,64 @@
+//! Character encodings for served XML documents.
+
+/// Character encoding used for served descriptions and SOAP responses.
+///
+/// Real firmwares ship all of these, so client XML parsers need coverage.
+/// Raw and streamed responder bodies are sent unchanged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResponseEncoding {
+    /// Plain UTF-8.
+    #[default]
+    Utf8,
+    /// UTF-8 preceded by a byte order mark.
+    Utf8Bom,
+    /// Little-endian UTF-16 with a byte order mark, declared in the XML prolog.
+    Utf16,
+    /// ISO-8859-1, declared in the XML prolog. Characters outside Latin-1 are
+    /// replaced with `?`.
+    Latin1,
+}
+
+impl ResponseEncoding {
+    /// The `Content-Type` header value for an XML document in this encoding.
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            ResponseEncoding::Utf8 | ResponseEncoding::Utf8Bom => "text/xml; charset=\"utf-8\"",
+            ResponseEncoding::Utf16 => "text/xml; charset=\"utf-16\"",
+            ResponseEncoding::Latin1 => "text/xml; charset=\"iso-8859-1\"",
+        }
+    }
+
+    /// Encode an XML document, adjusting its prolog when needed.
+    pub(crate) fn encode(self, xml: &str) -> Vec<u8> {
+        match self {
+            ResponseEncoding::Utf8 => xml.as_bytes().to_vec(),
+            ResponseEncoding::Utf8Bom => {
+                let mut bytes = vec![0xEF, 0xBB, 0xBF];
+                bytes.extend_from_slice(xml.as_bytes());
+                bytes
+            }
+            ResponseEncoding::Utf16 => {
+                let xml = declare_encoding(xml, "UTF-16");
+                let mut bytes = vec![0xFF, 0xFE];
+                bytes.extend(xml.encode_utf16().flat_map(u16::to_le_bytes));
+                bytes
+            }
+            ResponseEncoding::Latin1 => declare_encoding(xml, "ISO-8859-1")
+                .chars()
+                .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+                .collect(),
+        }
+    }
+}
+
+/// Replace (or add) the XML declaration with one naming `encoding`.
+fn declare_encoding(xml: &str, encoding: &str) -> String {
+    let body = match xml
+        .strip_prefix("<?xml")
+        .and_then(|rest| rest.split_once("?>"))
+    {
+        Some((_, body)) => body,
+        None => xml,
+    };
+    format!("<?xml version=\"1.0\" encoding=\"{encoding}\"?>{body}")
+}
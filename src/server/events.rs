@@ -0,0 +1,497 @@
+//! GENA event subscriptions and NOTIFY delivery.
+
+use super::TRACE_TARGET;
+use super::forward::AbortOnDrop;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Subscription duration granted when the subscriber doesn't ask for one.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1800);
+
+/// How long to wait for a subscriber to accept a NOTIFY.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How strictly the `CALLBACK` header of a new subscription is checked.
+///
+/// Subscriptions failing the check are answered with 412 Precondition
+/// Failed, as the UPnP Device Architecture requires.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CallbackValidation {
+    /// Accept the first `http://` URL listed, ignoring the others.
+    #[default]
+    Lenient,
+    /// Require every listed URL to be a well-formed `http://` URL whose
+    /// host is a private, loopback or link-local IP address, since devices
+    /// can only deliver events within the local network.
+    Strict,
+}
+
+/// A deliberate error in the `SEQ` numbers of the events sent to each
+/// subscriber, to test client-side sequence validation.
+///
+/// Positions count the events sent to a subscriber, starting at 0 for the
+/// initial event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventSeqFault {
+    /// Skip a number before the event at this position
+    /// (e.g. `SkipAt(2)`: 0, 1, 3, 4, ...).
+    SkipAt(u32),
+    /// Repeat the previous number for the event at this position
+    /// (e.g. `DuplicateAt(2)`: 0, 1, 1, 2, ...).
+    DuplicateAt(u32),
+    /// Send 0, reserved for the initial event, for the event at this
+    /// position (e.g. `ZeroAt(2)`: 0, 1, 0, 3, ...).
+    ZeroAt(u32),
+}
+
+/// The result of one NOTIFY delivery attempt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeliveryOutcome {
+    /// The subscriber answered with this HTTP status code.
+    Status(u16),
+    /// Connecting, sending or reading the response failed.
+    Failed(String),
+    /// The subscriber didn't answer in time.
+    TimedOut,
+}
+
+/// A NOTIFY request sent to a subscriber.
+#[derive(Debug, Clone)]
+pub struct EventDelivery {
+    /// Subscription the event was sent for.
+    pub sid: String,
+    /// `SEQ` header of the event.
+    pub seq: u32,
+    /// Callback URL the event was sent to.
+    pub callback: String,
+    /// Evented state variables as name/value pairs.
+    pub properties: Vec<(String, String)>,
+    /// How the delivery went.
+    pub outcome: DeliveryOutcome,
+    /// How long the delivery took.
+    pub duration: Duration,
+}
+
+/// Evented state variables as name/value pairs.
+pub(crate) type Properties = Vec<(String, String)>;
+
+/// Where a subscriber wants its events delivered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Callback {
+    /// `host:port` to connect to.
+    host: String,
+    /// Request target of the NOTIFY.
+    path: String,
+}
+
+impl Callback {
+    /// Parse a `CALLBACK` header, using the first `http://` URL it lists.
+    pub fn parse(header: &str, validation: CallbackValidation) -> Option<Self> {
+        if validation == CallbackValidation::Strict && !is_strictly_valid(header) {
+            return None;
+        }
+        Self::parse_first(header)
+    }
+
+    fn parse_first(header: &str) -> Option<Self> {
+        let url = header.trim().strip_prefix('<')?.split('>').next()?;
+        let rest = url.strip_prefix("http://")?;
+        let (host, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        let host = if host.rsplit(']').next().is_some_and(|h| h.contains(':')) {
+            host.to_string()
+        } else {
+            format!("{host}:80")
+        };
+        Some(Callback {
+            host,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// Whether every URL of a `CALLBACK` header is a well-formed `http://` URL
+/// pointing into the local network.
+fn is_strictly_valid(header: &str) -> bool {
+    let mut rest = header.trim();
+    if rest.is_empty() {
+        return false;
+    }
+    while !rest.is_empty() {
+        let Some((url, remainder)) = rest.strip_prefix('<').and_then(|r| r.split_once('>')) else {
+            return false;
+        };
+        if !is_lan_url(url) {
+            return false;
+        }
+        rest = remainder.trim_start();
+    }
+    true
+}
+
+/// Whether `url` is `http://` + an IP address in the local network, an
+/// optional port and a path.
+fn is_lan_url(url: &str) -> bool {
+    let Some(rest) = url.strip_prefix("http://") else {
+        return false;
+    };
+    let authority = rest.split('/').next().unwrap_or_default();
+    let ip = match authority.parse::<SocketAddr>() {
+        Ok(address) => address.ip(),
+        Err(_) => match authority
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+        {
+            Ok(ip) => ip,
+            Err(_) => return false,
+        },
+    };
+    match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local(),
+        IpAddr::V6(ip) => {
+            let prefix = ip.segments()[0];
+            // Loopback, unique local (fc00::/7) or link-local (fe80::/10)
+            ip.is_loopback() || prefix & 0xfe00 == 0xfc00 || prefix & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+/// Subscription duration requested in a `TIMEOUT` header (`None` = infinite).
+pub(crate) fn parse_timeout(header: Option<&str>) -> Option<Duration> {
+    match header.map(|h| h.trim().to_ascii_lowercase()) {
+        Some(h) if h == "second-infinite" => None,
+        Some(h) => Some(
+            h.strip_prefix("second-")
+                .and_then(|secs| secs.parse().ok())
+                .map_or(DEFAULT_TIMEOUT, Duration::from_secs),
+        ),
+        None => Some(DEFAULT_TIMEOUT),
+    }
+}
+
+/// `TIMEOUT` header value for a granted subscription duration.
+pub(crate) fn timeout_header(timeout: Option<Duration>) -> String {
+    match timeout {
+        Some(timeout) => format!("Second-{}", timeout.as_secs()),
+        None => "Second-infinite".to_string(),
+    }
+}
+
+struct Subscription {
+    sid: String,
+    /// When the subscription lapses (`None` = never).
+    expires: Option<Instant>,
+    /// Queue of events for this subscriber's delivery task.
+    events: mpsc::UnboundedSender<Properties>,
+    /// The delivery task, stopped along with the subscription.
+    _delivery: AbortOnDrop,
+}
+
+impl Subscription {
+    fn is_expired(&self) -> bool {
+        self.expires
+            .is_some_and(|expires| Instant::now() >= expires)
+    }
+}
+
+/// How events are delivered to new subscriptions.
+#[derive(Debug, Clone, Default)]
+struct DeliveryOptions {
+    /// Errors injected into the `SEQ` numbers.
+    seq_faults: Vec<EventSeqFault>,
+    /// Minimum time between events carrying a moderated variable.
+    moderation: HashMap<String, Duration>,
+}
+
+/// Subscribers to the WANIPConnection service's events.
+///
+/// Each subscription has its own delivery task, so events reach every
+/// subscriber in order with consecutive `SEQ` numbers, and a slow subscriber
+/// doesn't hold up the others. The task is aborted when the subscription
+/// ends, so events still queued for it are dropped.
+pub(crate) struct EventPublisher {
+    subscriptions: Mutex<Vec<Subscription>>,
+    next_sid: AtomicU64,
+    /// Every delivery attempt, in order of completion.
+    deliveries: Arc<Mutex<Vec<EventDelivery>>>,
+    /// Delivery behavior of new subscriptions.
+    options: Mutex<DeliveryOptions>,
+}
+
+impl EventPublisher {
+    /// Create a publisher without subscribers.
+    pub fn new() -> Self {
+        EventPublisher {
+            subscriptions: Mutex::new(Vec::new()),
+            next_sid: AtomicU64::new(1),
+            deliveries: Arc::new(Mutex::new(Vec::new())),
+            options: Mutex::new(DeliveryOptions::default()),
+        }
+    }
+
+    /// Add a subscription and queue its initial event; returns the SID.
+    pub fn subscribe(
+        &self,
+        callback: Callback,
+        timeout: Option<Duration>,
+        initial: Properties,
+    ) -> String {
+        let sid = format!(
+            "uuid:00000000-0000-0000-0000-{:012x}",
+            self.next_sid.fetch_add(1, Ordering::SeqCst)
+        );
+        let (events, queue) = mpsc::unbounded_channel();
+        let _ = events.send(initial);
+        let options = self.options().clone();
+        let delivery = tokio::spawn(deliver(
+            sid.clone(),
+            callback,
+            queue,
+            options,
+            self.deliveries.clone(),
+        ));
+
+        self.lock().push(Subscription {
+            sid: sid.clone(),
+            expires: timeout.map(|timeout| Instant::now() + timeout),
+            events,
+            _delivery: AbortOnDrop(delivery),
+        });
+        sid
+    }
+
+    /// Extend a subscription; returns `false` if it doesn't exist (anymore).
+    pub fn renew(&self, sid: &str, timeout: Option<Duration>) -> bool {
+        let mut subscriptions = self.lock();
+        subscriptions.retain(|s| !s.is_expired());
+        match subscriptions.iter_mut().find(|s| s.sid == sid) {
+            Some(subscription) => {
+                subscription.expires = timeout.map(|timeout| Instant::now() + timeout);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Cancel a subscription; returns `false` if it doesn't exist (anymore).
+    pub fn unsubscribe(&self, sid: &str) -> bool {
+        let mut subscriptions = self.lock();
+        subscriptions.retain(|s| !s.is_expired());
+        let before = subscriptions.len();
+        subscriptions.retain(|s| s.sid != sid);
+        subscriptions.len() != before
+    }
+
+    /// Queue an event for every live subscriber.
+    pub fn publish(&self, properties: Properties) {
+        let mut subscriptions = self.lock();
+        subscriptions.retain(|s| !s.is_expired());
+        for subscription in subscriptions.iter() {
+            let _ = subscription.events.send(properties.clone());
+        }
+    }
+
+    /// Inject `faults` into the `SEQ` numbers of subscriptions added from
+    /// now on.
+    pub fn set_seq_faults(&self, faults: Vec<EventSeqFault>) {
+        self.options().seq_faults = faults;
+    }
+
+    /// Send events carrying `variable` at most once per `interval` to
+    /// subscriptions added from now on, coalescing the changes in between.
+    pub fn moderate(&self, variable: String, interval: Duration) {
+        self.options().moderation.insert(variable, interval);
+    }
+
+    /// Drop every subscription.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Every delivery attempt so far, in order of completion.
+    pub fn deliveries(&self) -> Vec<EventDelivery> {
+        self.deliveries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Subscription>> {
+        self.subscriptions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn options(&self) -> MutexGuard<'_, DeliveryOptions> {
+        self.options.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Deliver queued events to one subscriber until the subscription ends,
+/// recording each attempt in `deliveries`.
+async fn deliver(
+    sid: String,
+    callback: Callback,
+    mut queue: mpsc::UnboundedReceiver<Properties>,
+    options: DeliveryOptions,
+    deliveries: Arc<Mutex<Vec<EventDelivery>>>,
+) {
+    let mut seq: u32 = 0;
+    let mut position: u32 = 0;
+    // When each moderated variable was last sent
+    let mut last_sent: HashMap<String, Instant> = HashMap::new();
+    while let Some(mut properties) = queue.recv().await {
+        // Hold back moderated variables until their interval has passed,
+        // folding in the changes queued meanwhile
+        let ready_at = properties
+            .iter()
+            .filter_map(|(name, _)| Some(*last_sent.get(name)? + *options.moderation.get(name)?))
+            .max();
+        if let Some(ready_at) = ready_at {
+            let deadline = tokio::time::sleep_until(ready_at);
+            tokio::pin!(deadline);
+            loop {
+                tokio::select! {
+                    _ = &mut deadline => break,
+                    more = queue.recv() => match more {
+                        Some(more) => merge_properties(&mut properties, more),
+                        None => break,
+                    },
+                }
+            }
+        }
+        let now = Instant::now();
+        for (name, _) in &properties {
+            if options.moderation.contains_key(name) {
+                last_sent.insert(name.clone(), now);
+            }
+        }
+
+        let mut sent_seq = seq;
+        for fault in &options.seq_faults {
+            match *fault {
+                EventSeqFault::SkipAt(at) if at == position => {
+                    seq = next_seq(seq);
+                    sent_seq = seq;
+                }
+                EventSeqFault::DuplicateAt(at) if at == position && seq > 0 => {
+                    seq -= 1;
+                    sent_seq = seq;
+                }
+                EventSeqFault::ZeroAt(at) if at == position => sent_seq = 0,
+                _ => {}
+            }
+        }
+        position = position.saturating_add(1);
+
+        let started = Instant::now();
+        let notify = send_notify(&callback, &sid, sent_seq, &properties);
+        let outcome = match tokio::time::timeout(DELIVERY_TIMEOUT, notify).await {
+            Ok(Ok(status)) => DeliveryOutcome::Status(status),
+            Ok(Err(e)) => {
+                tracing::warn!(target: TRACE_TARGET, %sid, "Failed to deliver event: {}", e);
+                DeliveryOutcome::Failed(e.to_string())
+            }
+            Err(_) => {
+                tracing::warn!(target: TRACE_TARGET, %sid, "Event delivery timed out");
+                DeliveryOutcome::TimedOut
+            }
+        };
+        let delivery = EventDelivery {
+            sid: sid.clone(),
+            seq: sent_seq,
+            callback: format!("http://{}{}", callback.host, callback.path),
+            properties,
+            outcome,
+            duration: started.elapsed(),
+        };
+        deliveries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(delivery);
+        seq = next_seq(seq);
+    }
+}
+
+/// Update `properties` with the values in `changes`.
+fn merge_properties(properties: &mut Properties, changes: Properties) {
+    for (name, value) in changes {
+        match properties
+            .iter_mut()
+            .find(|(existing, _)| *existing == name)
+        {
+            Some(property) => property.1 = value,
+            None => properties.push((name, value)),
+        }
+    }
+}
+
+/// The `SEQ` following `seq`; wraps to 1, as 0 is reserved for the initial
+/// event.
+fn next_seq(seq: u32) -> u32 {
+    seq.checked_add(1).unwrap_or(1)
+}
+
+/// Send one NOTIFY request and return the status code of the subscriber's
+/// response.
+async fn send_notify(
+    callback: &Callback,
+    sid: &str,
+    seq: u32,
+    properties: &Properties,
+) -> std::io::Result<u16> {
+    let body = generate_property_set(properties);
+    let request = format!(
+        "NOTIFY {path} HTTP/1.1\r\n\
+         HOST: {host}\r\n\
+         CONTENT-TYPE: text/xml; charset=\"utf-8\"\r\n\
+         NT: upnp:event\r\n\
+         NTS: upnp:propchange\r\n\
+         SID: {sid}\r\n\
+         SEQ: {seq}\r\n\
+         CONTENT-LENGTH: {length}\r\n\
+         CONNECTION: close\r\n\
+         \r\n\
+         {body}",
+        path = callback.path,
+        host = callback.host,
+        length = body.len(),
+    );
+
+    let mut stream = TcpStream::connect(&callback.host).await?;
+    stream.write_all(request.as_bytes()).await?;
+    // "HTTP/1.1 200"
+    let mut status_line = [0u8; 12];
+    stream.read_exact(&mut status_line).await?;
+    std::str::from_utf8(&status_line[9..])
+        .ok()
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid status line"))
+}
+
+/// Generate the `propertyset` body of a NOTIFY request.
+fn generate_property_set(properties: &Properties) -> String {
+    let properties: String = properties
+        .iter()
+        .map(|(name, value)| format!("<e:property>\n<{name}>{value}</{name}>\n</e:property>\n"))
+        .collect();
+    format!(
+        "<?xml version=\"1.0\"?>\n\
+         <e:propertyset xmlns:e=\"urn:schemas-upnp-org:event-1-0\">\n\
+         {properties}\
+         </e:propertyset>\n"
+    )
+}
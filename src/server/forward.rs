@@ -0,0 +1,205 @@
+//! Local stand-ins for the traffic flowing through port mappings.
+
+use super::TRACE_TARGET;
+use crate::action::Protocol;
+use crate::mapping::PortMapping;
+use crate::mock::MockRegistry;
+use std::collections::HashMap;
+use std::collections::hash_map;
+use std::future::Future;
+use std::hash::Hash;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::task::{JoinHandle, JoinSet};
+
+/// A task aborted when dropped.
+pub(crate) struct AbortOnDrop(pub(crate) JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Keep one `serve` task running per distinct `key` of the `mappings` in
+/// the port mapping table, stopping tasks whose mappings are gone.
+async fn follow_mappings<K, F, Fut>(
+    registry: Arc<MockRegistry>,
+    mappings: fn(&MockRegistry) -> Vec<PortMapping>,
+    key: fn(&PortMapping) -> K,
+    serve: F,
+) where
+    K: Eq + Hash,
+    F: Fn(PortMapping) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let Some(mut changes) = registry.watch_port_mappings() else {
+        return;
+    };
+    let mut running: HashMap<K, AbortOnDrop> = HashMap::new();
+    loop {
+        let mappings = mappings(&registry);
+        let mut wanted: HashMap<K, &PortMapping> = HashMap::new();
+        for mapping in &mappings {
+            wanted.entry(key(mapping)).or_insert(mapping);
+        }
+        running.retain(|key, _| wanted.contains_key(key));
+        for (key, mapping) in wanted {
+            running
+                .entry(key)
+                .or_insert_with(|| AbortOnDrop(tokio::spawn(serve(mapping.clone()))));
+        }
+
+        // Look again when the table changes or the next lease runs out
+        let next_expiry = mappings
+            .iter()
+            .filter(|m| m.lease_duration > 0)
+            .map(|m| Duration::from_secs(m.lease_duration.into()))
+            .min();
+        tokio::select! {
+            changed = changes.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            _ = tokio::time::sleep(next_expiry.unwrap_or_default()), if next_expiry.is_some() => {}
+        }
+    }
+}
+
+/// Echo traffic sent to the internal client and port of every mapping in
+/// the port mapping table, until aborted.
+pub(crate) async fn run_echo_endpoints(registry: Arc<MockRegistry>) {
+    let key = |m: &PortMapping| {
+        (
+            m.protocol,
+            SocketAddr::new(m.internal_client, m.internal_port),
+        )
+    };
+    follow_mappings(registry, MockRegistry::port_mappings, key, |mapping| {
+        echo(
+            mapping.protocol,
+            SocketAddr::new(mapping.internal_client, mapping.internal_port),
+        )
+    })
+    .await;
+}
+
+/// Echo TCP connections or UDP datagrams received on `addr`.
+async fn echo(protocol: Protocol, addr: SocketAddr) {
+    let result = match protocol {
+        Protocol::UDP => echo_udp(addr).await,
+        _ => echo_tcp(addr).await,
+    };
+    if let Err(e) = result {
+        tracing::warn!(target: TRACE_TARGET, %addr, "Echo endpoint failed: {}", e);
+    }
+}
+
+async fn echo_tcp(addr: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    // Aborted along with this task
+    let mut connections = JoinSet::new();
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        while connections.try_join_next().is_some() {}
+        connections.spawn(async move {
+            let (mut reader, mut writer) = stream.split();
+            let _ = tokio::io::copy(&mut reader, &mut writer).await;
+        });
+    }
+}
+
+async fn echo_udp(addr: SocketAddr) -> std::io::Result<()> {
+    let socket = UdpSocket::bind(addr).await?;
+    let mut buf = vec![0u8; 65535];
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).await?;
+        socket.send_to(&buf[..len], peer).await?;
+    }
+}
+
+/// Forward traffic sent to `127.0.0.1` on the external port of every
+/// forwarded mapping to its internal client and port, until aborted.
+pub(crate) async fn run_nat(registry: Arc<MockRegistry>) {
+    let key = |m: &PortMapping| {
+        let internal = SocketAddr::new(m.internal_client, m.internal_port);
+        (m.protocol, m.external_port, internal)
+    };
+    follow_mappings(
+        registry,
+        MockRegistry::forwarded_port_mappings,
+        key,
+        |mapping| {
+            let external = SocketAddr::from((Ipv4Addr::LOCALHOST, mapping.external_port));
+            let internal = SocketAddr::new(mapping.internal_client, mapping.internal_port);
+            forward(mapping.protocol, external, internal)
+        },
+    )
+    .await;
+}
+
+/// Relay TCP connections or UDP datagrams received on `external` to
+/// `internal`.
+async fn forward(protocol: Protocol, external: SocketAddr, internal: SocketAddr) {
+    let result = match protocol {
+        Protocol::UDP => forward_udp(external, internal).await,
+        _ => forward_tcp(external, internal).await,
+    };
+    if let Err(e) = result {
+        tracing::warn!(target: TRACE_TARGET, %external, %internal, "Forwarding failed: {}", e);
+    }
+}
+
+async fn forward_tcp(external: SocketAddr, internal: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(external).await?;
+    let mut connections = JoinSet::new();
+    loop {
+        let (mut inbound, peer) = listener.accept().await?;
+        while connections.try_join_next().is_some() {}
+        connections.spawn(async move {
+            match TcpStream::connect(internal).await {
+                Ok(mut outbound) => {
+                    let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                }
+                Err(e) => tracing::debug!(
+                    target: TRACE_TARGET,
+                    %peer,
+                    %internal,
+                    "Forwarded connection refused: {}",
+                    e
+                ),
+            }
+        });
+    }
+}
+
+/// Relay datagrams like a NAT would: each outside peer gets its own socket
+/// towards `internal`, whose replies go back to that peer.
+async fn forward_udp(external: SocketAddr, internal: SocketAddr) -> std::io::Result<()> {
+    let socket = Arc::new(UdpSocket::bind(external).await?);
+    let mut sessions: HashMap<SocketAddr, Arc<UdpSocket>> = HashMap::new();
+    let mut replies = JoinSet::new();
+    let mut buf = vec![0u8; 65535];
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).await?;
+        let session = match sessions.entry(peer) {
+            hash_map::Entry::Occupied(entry) => entry.get().clone(),
+            hash_map::Entry::Vacant(entry) => {
+                let session = Arc::new(UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?);
+                session.connect(internal).await?;
+                let (session_rx, socket) = (session.clone(), socket.clone());
+                replies.spawn(async move {
+                    let mut buf = vec![0u8; 65535];
+                    while let Ok(len) = session_rx.recv(&mut buf).await {
+                        let _ = socket.send_to(&buf[..len], peer).await;
+                    }
+                });
+                entry.insert(session).clone()
+            }
+        };
+        let _ = session.send(&buf[..len]).await;
+    }
+}
@@ -1,134 +1,701 @@
 //! HTTP/SOAP server implementation.
 
+use super::connection;
+use super::events::{self, Callback};
+use super::{
+    ClientRejection, DescriptionRedirect, DeviceUdns, KeepAlive, ResponseEncoding, ServerConfig,
+    TRACE_TARGET, WIRE_TRACE_TARGET, panic_message,
+};
+use crate::action::ServiceType;
+use crate::compliance::{self, ComplianceLevel};
 use crate::matcher::{
     AddPortMappingRequest, DeletePortMappingRequest, GetGenericPortMappingEntryRequest,
-    GetSpecificPortMappingEntryRequest, SoapRequest, SoapRequestBody,
+    GetSpecificPortMappingEntryRequest, SoapRequest, SoapRequestBody, UnknownRequest,
 };
 use crate::mock::MockRegistry;
-use crate::responder::{generate_soap_fault, ResponseBody};
+use crate::responder::{ResponseBody, generate_soap_fault};
+#[cfg(all(feature = "validate", debug_assertions))]
+use crate::validate;
 use axum::{
+    Router,
     body::Body,
-    extract::State,
-    http::{header, HeaderMap, StatusCode},
+    extract::{ConnectInfo, DefaultBodyLimit, Extension, Request, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, header},
+    middleware::{self, Next},
     response::{IntoResponse, Response},
-    routing::{get, post},
-    Router,
+    routing::{any, get, post},
 };
-use std::sync::Arc;
+use base64::prelude::*;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
 use tokio::net::TcpListener;
-use tokio::sync::oneshot;
+use tokio::sync::{Semaphore, oneshot, watch};
+use tokio::time::Instant;
 
 /// Shared state for the HTTP server.
 struct AppState {
     registry: Arc<MockRegistry>,
+    config: ServerConfig,
+    /// Limits concurrently handled SOAP requests (if configured).
+    request_slots: Option<Semaphore>,
+    /// Throttles chatty clients (if configured).
+    rate_limiter: Option<RateLimiter>,
+    /// Becomes `true` when the server stops.
+    stopping: watch::Receiver<bool>,
+}
+
+/// Per-client fixed-window request counter.
+struct RateLimiter {
+    requests_per_sec: u32,
+    /// Start of the current one-second window and requests seen in it.
+    windows: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_sec: u32) -> Self {
+        Self {
+            requests_per_sec,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a request from `client`, returning `false` if it exceeds the rate.
+    fn allow(&self, client: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap_or_else(PoisonError::into_inner);
+        let (start, count) = windows.entry(client).or_insert((now, 0));
+        if now.duration_since(*start) >= Duration::from_secs(1) {
+            *start = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= self.requests_per_sec
+    }
 }
 
 /// Run the HTTP server.
 pub async fn run_http_server(
     listener: TcpListener,
     registry: Arc<MockRegistry>,
-    shutdown_rx: oneshot::Receiver<()>,
+    config: ServerConfig,
+    stop_rx: oneshot::Receiver<()>,
 ) {
-    let state = Arc::new(AppState { registry });
+    let keep_alive = config.keep_alive;
+    let client_filter = config.client_filter.clone();
+    let request_slots = config.max_concurrent_requests.map(Semaphore::new);
+    let rate_limiter = config.rate_limit.map(RateLimiter::new);
+    // Let requests held open in observer mode end when the server stops
+    let (stopping_tx, stopping) = watch::channel(false);
+    let shutdown = async move {
+        let _ = stop_rx.await;
+        let _ = stopping_tx.send(true);
+    };
+    let state = Arc::new(AppState {
+        registry,
+        config,
+        request_slots,
+        rate_limiter,
+        stopping,
+    });
 
-    let app = Router::new()
-        .route("/rootDesc.xml", get(handle_root_desc))
-        .route("/WANIPCn.xml", get(handle_wan_ip_connection_scpd))
-        .route("/WANCommonIFC1.xml", get(handle_wan_common_ifc_scpd))
+    let mut app = Router::new()
         .route("/ctl/IPConn", post(handle_soap_action))
         .route("/ctl/WANCommonIFC1", post(handle_soap_action))
-        .with_state(state);
+        .route("/evt/IPConn", any(handle_event_subscription));
+    if state.config.second_wan_ip.is_some() {
+        let second_wan = Extension(WanDevice(1));
+        app = app
+            .route("/ctl/IPConn2", post(handle_soap_action).layer(second_wan))
+            .route(
+                "/ctl/WANCommonIFC2",
+                post(handle_soap_action).layer(second_wan),
+            )
+            .route(
+                "/evt/IPConn2",
+                any(handle_event_subscription).layer(second_wan),
+            );
+    }
+    if state.config.wan_ppp_connection {
+        app = app
+            .route("/ctl/PPPConn", post(handle_soap_action))
+            .route("/evt/PPPConn", any(handle_event_subscription));
+    }
+    if !state.config.disable_description {
+        app = app.route("/rootDesc.xml", get(handle_root_desc));
+        // Serve the description at a redirect target on this server
+        if let Some(redirect) = &state.config.description_redirect
+            && redirect.location().starts_with('/')
+            && !RESERVED_PATHS.contains(&redirect.location())
+        {
+            app = app.route(redirect.location(), get(handle_canonical_desc));
+        }
+    }
+    if !state.config.disable_scpds {
+        app = app
+            .route("/WANIPCn.xml", get(handle_wan_ip_connection_scpd))
+            .route("/WANCommonIFC1.xml", get(handle_wan_common_ifc_scpd));
+        if state.config.wan_ppp_connection {
+            app = app.route("/WANPPPCn.xml", get(handle_wan_ppp_connection_scpd));
+        }
+    }
+    let mut app = app.layer(middleware::from_fn_with_state(state.clone(), note_traffic));
+    if state.config.client_filter.is_some() {
+        app = app.layer(middleware::from_fn_with_state(
+            state.clone(),
+            filter_clients,
+        ));
+    }
+    if state.config.clock_skew.is_some() {
+        app = app.layer(middleware::from_fn_with_state(state.clone(), skew_date));
+    }
+    let app = match state.config.max_request_body_size {
+        Some(limit) => app.layer(DefaultBodyLimit::max(limit)),
+        None => app,
+    }
+    .with_state(state);
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(async move {
-            let _ = shutdown_rx.await;
-        })
-        .await
-        .ok();
+    // Connections of refused clients are reset before axum sees them
+    let refused = client_filter.filter(|filter| filter.rejection() == ClientRejection::Refuse);
+    if keep_alive.is_some() || refused.is_some() {
+        let keep_alive = keep_alive.unwrap_or_else(KeepAlive::enabled);
+        connection::serve(listener, app, keep_alive, refused, shutdown).await;
+        return;
+    }
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown)
+    .await
+    .ok();
 }
 
+/// Paths served by default, which a description redirect can't take over.
+const RESERVED_PATHS: &[&str] = &[
+    "/rootDesc.xml",
+    "/WANIPCn.xml",
+    "/WANCommonIFC1.xml",
+    "/ctl/IPConn",
+    "/ctl/WANCommonIFC1",
+    "/evt/IPConn",
+    "/WANPPPCn.xml",
+    "/ctl/PPPConn",
+    "/evt/PPPConn",
+    "/ctl/IPConn2",
+    "/ctl/WANCommonIFC2",
+    "/evt/IPConn2",
+];
+
+/// Note every request, for `MockIgdServer::assert_quiet_for`, and the
+/// client's `User-Agent`.
+async fn note_traffic(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response<Body> {
+    let mut description = format!("{} {}", request.method(), request.uri().path());
+    if let Some(ConnectInfo(remote)) = request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        description.push_str(&format!(" from {remote}"));
+        if let Some(user_agent) = request.headers().get(header::USER_AGENT)
+            && let Ok(user_agent) = user_agent.to_str()
+        {
+            state.registry.record_user_agent(remote.ip(), user_agent);
+        }
+    }
+    state.registry.record_traffic(description);
+    next.run(request).await
+}
+
+/// Answer clients the client filter rejects with 403, unrecorded.
+async fn filter_clients(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response<Body> {
+    let remote = request.extensions().get::<ConnectInfo<SocketAddr>>();
+    if let (Some(filter), Some(ConnectInfo(remote))) = (&state.config.client_filter, remote)
+        && !filter.admits(remote.ip())
+    {
+        tracing::debug!(target: TRACE_TARGET, %remote, "rejected request from filtered client");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    next.run(request).await
+}
+
+/// Send the `Date` of the skewed clock, which hyper then leaves alone.
+async fn skew_date(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response<Body> {
+    let mut response = next.run(request).await;
+    if let Some(skew) = &state.config.clock_skew
+        && let Ok(date) = HeaderValue::from_str(&httpdate::fmt_http_date(skew.now()))
+    {
+        response.headers_mut().insert(header::DATE, date);
+    }
+    response
+}
+
+/// Index of the WANDevice a route belongs to; routes without it belong to
+/// the first.
+#[derive(Debug, Clone, Copy)]
+struct WanDevice(usize);
+
 /// Handle device description request.
-async fn handle_root_desc() -> impl IntoResponse {
-    let xml = generate_device_description();
+async fn handle_root_desc(State(state): State<Arc<AppState>>) -> Response<Body> {
+    if let Some(delay) = state.config.description_delay {
+        tokio::time::sleep(delay).await;
+    }
+    let Some(redirect) = &state.config.description_redirect else {
+        return handle_canonical_desc(State(state)).await;
+    };
+    let status = match redirect {
+        DescriptionRedirect::MovedPermanently(_) => StatusCode::MOVED_PERMANENTLY,
+        DescriptionRedirect::Found(_) => StatusCode::FOUND,
+    };
     Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "text/xml; charset=\"utf-8\"")
-        .body(Body::from(xml))
+        .status(status)
+        .header(header::LOCATION, redirect.location())
+        .body(Body::empty())
         .unwrap()
 }
 
+/// Serve the device description document.
+async fn handle_canonical_desc(State(state): State<Arc<AppState>>) -> Response<Body> {
+    if let Some(xml) = state.registry.description_xml() {
+        return xml_response(StatusCode::OK, &xml, state.config.response_encoding);
+    }
+    let xml = device_description(&state.config);
+    #[cfg(all(feature = "validate", debug_assertions))]
+    assert_valid(
+        "device description",
+        validate::validate_device_description(&xml),
+    );
+    xml_response(StatusCode::OK, &xml, state.config.response_encoding)
+}
+
 /// Handle WANIPConnection SCPD request.
-async fn handle_wan_ip_connection_scpd() -> impl IntoResponse {
-    let xml = generate_wan_ip_connection_scpd();
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "text/xml; charset=\"utf-8\"")
-        .body(Body::from(xml))
-        .unwrap()
+async fn handle_wan_ip_connection_scpd(State(state): State<Arc<AppState>>) -> Response<Body> {
+    if let Some(delay) = state.config.scpd_delay {
+        tokio::time::sleep(delay).await;
+    }
+    if let Some(xml) = state.registry.scpd_xml("WANIPConnection") {
+        return xml_response(StatusCode::OK, &xml, state.config.response_encoding);
+    }
+    #[cfg(all(feature = "validate", debug_assertions))]
+    assert_valid("SCPD", validate::validate_scpd(WAN_IP_CONNECTION_SCPD));
+    xml_response(
+        StatusCode::OK,
+        WAN_IP_CONNECTION_SCPD,
+        state.config.response_encoding,
+    )
+}
+
+/// Handle WANPPPConnection SCPD request.
+///
+/// The port mapping actions are the same as WANIPConnection's, so the same
+/// document is served.
+async fn handle_wan_ppp_connection_scpd(State(state): State<Arc<AppState>>) -> Response<Body> {
+    if let Some(delay) = state.config.scpd_delay {
+        tokio::time::sleep(delay).await;
+    }
+    if let Some(xml) = state.registry.scpd_xml("WANPPPConnection") {
+        return xml_response(StatusCode::OK, &xml, state.config.response_encoding);
+    }
+    xml_response(
+        StatusCode::OK,
+        WAN_IP_CONNECTION_SCPD,
+        state.config.response_encoding,
+    )
 }
 
 /// Handle WANCommonInterfaceConfig SCPD request.
-async fn handle_wan_common_ifc_scpd() -> impl IntoResponse {
-    let xml = generate_wan_common_ifc_scpd();
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "text/xml; charset=\"utf-8\"")
-        .body(Body::from(xml))
-        .unwrap()
+async fn handle_wan_common_ifc_scpd(State(state): State<Arc<AppState>>) -> Response<Body> {
+    if let Some(delay) = state.config.scpd_delay {
+        tokio::time::sleep(delay).await;
+    }
+    if let Some(xml) = state.registry.scpd_xml("WANCommonInterfaceConfig") {
+        return xml_response(StatusCode::OK, &xml, state.config.response_encoding);
+    }
+    #[cfg(all(feature = "validate", debug_assertions))]
+    assert_valid("SCPD", validate::validate_scpd(WAN_COMMON_IFC_SCPD));
+    xml_response(
+        StatusCode::OK,
+        WAN_COMMON_IFC_SCPD,
+        state.config.response_encoding,
+    )
 }
 
 /// Handle SOAP action requests.
 async fn handle_soap_action(
     State(state): State<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    wan_device: Option<Extension<WanDevice>>,
     headers: HeaderMap,
     body: String,
 ) -> impl IntoResponse {
+    let started = Instant::now();
+
+    if let Some(limiter) = &state.rate_limiter
+        && !limiter.allow(client.ip())
+    {
+        tracing::info!(target: TRACE_TARGET, %client, "rate limit exceeded");
+        return Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(header::RETRY_AFTER, "1")
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    if let Some((user, pass)) = &state.config.basic_auth
+        && !is_authorized(&headers, user, pass)
+    {
+        tracing::info!(target: TRACE_TARGET, %client, "missing or invalid credentials");
+        return Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(header::WWW_AUTHENTICATE, "Basic realm=\"mock-igd\"")
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    // Wait for a free slot when concurrency is limited; queuing time counts
+    // towards the request latency.
+    let _permit = match &state.request_slots {
+        Some(slots) => Some(slots.acquire().await.expect("semaphore is never closed")),
+        None => None,
+    };
+
     // Parse SOAP action from header
     let soap_action = headers
         .get("SOAPACTION")
         .and_then(|v| v.to_str().ok())
         .unwrap_or("");
 
+    if state.config.wire_logging {
+        tracing::debug!(
+            target: WIRE_TRACE_TARGET,
+            soap_action,
+            headers = ?headers,
+            body = %body,
+            "received SOAP request"
+        );
+    }
+
     // Parse the request
     let request = match parse_soap_request(soap_action, &body) {
-        Ok(req) => req,
+        Ok(req) => SoapRequest {
+            client: Some(client),
+            wan_device: wan_device.map_or(0, |Extension(WanDevice(index))| index),
+            ..req
+        },
         Err(e) => {
-            tracing::warn!("Failed to parse SOAP request: {}", e);
-            return soap_error_response(401, "Invalid Action");
+            tracing::warn!(target: TRACE_TARGET, "Failed to parse SOAP request: {}", e);
+            return soap_error_response(401, "Invalid Action", state.config.response_encoding);
         }
     };
+    state
+        .registry
+        .record_soap_action(client.ip(), soap_action, &request.action_name);
 
-    // Find a matching mock
-    match state.registry.find_response(&request).await {
-        Some(response) => match response {
-            ResponseBody::Soap(xml) => Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, "text/xml; charset=\"utf-8\"")
-                .body(Body::from(xml))
-                .unwrap(),
-            ResponseBody::SoapFault { code, description } => {
-                soap_error_response(code, &description)
+    if state.config.compliance_checks {
+        let violations: Vec<_> = compliance::check(soap_action, &request)
+            .into_iter()
+            .filter(|v| state.config.compliance_level(v.rule) != ComplianceLevel::Ignore)
+            .collect();
+        for violation in &violations {
+            tracing::warn!(target: TRACE_TARGET, %violation, "non-compliant request");
+        }
+        let reject = violations
+            .iter()
+            .any(|v| state.config.compliance_level(v.rule) == ComplianceLevel::Reject);
+        state.registry.record_compliance_violations(violations);
+        if reject {
+            return soap_error_response(402, "Invalid Args", state.config.response_encoding);
+        }
+    }
+
+    // In observer mode, record the request and leave the client waiting
+    if let Some(allowed) = &state.config.observer
+        && !allowed.contains(&request.action_name)
+    {
+        tracing::info!(
+            target: TRACE_TARGET,
+            action = %request.action_name,
+            "observed SOAP request; not answering"
+        );
+        state.registry.record_request(&request);
+        let mut stopping = state.stopping.clone();
+        let _ = stopping.wait_for(|stopping| *stopping).await;
+        return Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    // Find a matching mock; a panicking mock or interceptor must not take the
+    // connection down
+    let found = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        let (matched, near_miss) = state.registry.find_response(&request);
+        let matched = match &state.config.interceptor {
+            Some(interceptor) => interceptor.intercept(&request, matched),
+            None => matched,
+        };
+        if matched.is_none() && state.config.strict_matching {
+            match near_miss {
+                Some(near_miss) => panic!(
+                    "no mock matched {} request; nearest miss: {near_miss}",
+                    request.action_name
+                ),
+                None => panic!("no mock matched {} request", request.action_name),
+            }
+        }
+        matched
+    }));
+    let matched = match found {
+        Ok(matched) => matched,
+        Err(panic) => {
+            let message = panic_message(panic.as_ref());
+            state.registry.record_server_error(format!(
+                "panic while answering {}: {}",
+                request.action_name, message
+            ));
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(message))
+                .unwrap();
+        }
+    };
+    let (mock_id, mock_name, response) = match matched {
+        Some(matched) => {
+            if !matched.delay.is_zero() {
+                tokio::time::sleep(matched.delay).await;
+            }
+            // Reported like a panic: recorded for `health()` and answered with 500
+            #[cfg(all(feature = "validate", debug_assertions))]
+            if let Err(e) = validate::validate_response_body(
+                &request.action_name,
+                &matched.body,
+                &[WAN_IP_CONNECTION_SCPD, WAN_COMMON_IFC_SCPD],
+            ) {
+                let message = format!("mock-igd served an invalid SOAP response: {e}");
+                state.registry.record_server_error(format!(
+                    "invalid response to {}: {}",
+                    request.action_name, message
+                ));
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from(message))
+                    .unwrap();
+            }
+            if state.config.wire_logging {
+                tracing::debug!(
+                    target: WIRE_TRACE_TARGET,
+                    response = ?matched.body,
+                    "sending SOAP response"
+                );
+            }
+            let mut response = response_from_body(matched.body, state.config.response_encoding);
+            if let Some(content_type) = matched.content_type {
+                match HeaderValue::try_from(content_type) {
+                    Ok(value) => _ = response.headers_mut().insert(header::CONTENT_TYPE, value),
+                    Err(e) => tracing::warn!(target: TRACE_TARGET, "Invalid Content-Type: {}", e),
+                }
+            }
+            (matched.mock_id, matched.mock_name, response)
+        }
+        None => {
+            tracing::debug!(
+                target: TRACE_TARGET,
+                "No mock found for action: {}",
+                request.action_name
+            );
+            let response =
+                soap_error_response(401, "Invalid Action", state.config.response_encoding);
+            (None, None, response)
+        }
+    };
+
+    let latency = started.elapsed();
+    state
+        .registry
+        .record_soap_metrics(&request.action_name, mock_id, latency);
+
+    tracing::info!(
+        target: TRACE_TARGET,
+        action = %request.action_name,
+        service_type = %request.service_type,
+        mock_id,
+        mock_name,
+        status = response.status().as_u16(),
+        latency_us = latency.as_micros() as u64,
+        "handled SOAP request"
+    );
+
+    response
+}
+
+/// Handle GENA `SUBSCRIBE` (new or renewal) and `UNSUBSCRIBE` requests for
+/// a WANIPConnection service.
+async fn handle_event_subscription(
+    State(state): State<Arc<AppState>>,
+    wan_device: Option<Extension<WanDevice>>,
+    method: Method,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let wan_device = wan_device.map_or(0, |Extension(WanDevice(index))| index);
+    let publisher = state.registry.wan_events(wan_device);
+    let status = |status: StatusCode| {
+        Response::builder()
+            .status(status)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    match (method.as_str(), header("SID")) {
+        ("SUBSCRIBE", Some(sid)) => {
+            // A renewal must not repeat the initial subscription headers
+            if header("NT").is_some() || header("CALLBACK").is_some() {
+                return status(StatusCode::BAD_REQUEST);
+            }
+            let timeout = events::parse_timeout(header("TIMEOUT"));
+            if !publisher.renew(sid, timeout) {
+                return status(StatusCode::PRECONDITION_FAILED);
+            }
+            subscription_response(sid, timeout, &state.config)
+        }
+        ("SUBSCRIBE", None) => {
+            let callback = header("CALLBACK")
+                .and_then(|h| Callback::parse(h, state.config.callback_validation));
+            let (Some("upnp:event"), Some(callback)) = (header("NT"), callback) else {
+                return status(StatusCode::PRECONDITION_FAILED);
+            };
+            let timeout = events::parse_timeout(header("TIMEOUT"));
+            let initial = vec![(
+                "PortMappingNumberOfEntries".to_string(),
+                state.registry.port_mapping_count(wan_device).to_string(),
+            )];
+            let sid = publisher.subscribe(callback, timeout, initial);
+            tracing::info!(target: TRACE_TARGET, %sid, "event subscription added");
+            subscription_response(&sid, timeout, &state.config)
+        }
+        ("UNSUBSCRIBE", Some(sid)) => {
+            if !publisher.unsubscribe(sid) {
+                return status(StatusCode::PRECONDITION_FAILED);
             }
-            ResponseBody::Raw { content_type, body } => Response::builder()
+            tracing::info!(target: TRACE_TARGET, %sid, "event subscription cancelled");
+            status(StatusCode::OK)
+        }
+        ("UNSUBSCRIBE", None) => status(StatusCode::PRECONDITION_FAILED),
+        _ => status(StatusCode::METHOD_NOT_ALLOWED),
+    }
+}
+
+/// Accept a subscription with the given SID and duration, reporting the
+/// duration as skewed by the configured clock skew.
+fn subscription_response(
+    sid: &str,
+    timeout: Option<Duration>,
+    config: &ServerConfig,
+) -> Response<Body> {
+    let timeout = match &config.clock_skew {
+        Some(skew) => skew.reported_timeout(timeout),
+        None => timeout,
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("SID", sid)
+        .header("TIMEOUT", events::timeout_header(timeout))
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Check the request's `Authorization` header against the expected Basic credentials.
+fn is_authorized(headers: &HeaderMap, user: &str, pass: &str) -> bool {
+    let expected = format!("Basic {}", BASE64_STANDARD.encode(format!("{user}:{pass}")));
+    headers
+        .get(header::AUTHORIZATION)
+        .is_some_and(|value| value.as_bytes() == expected.as_bytes())
+}
+
+/// Convert a mock response body into an HTTP response.
+fn response_from_body(body: ResponseBody, encoding: ResponseEncoding) -> Response<Body> {
+    match body {
+        ResponseBody::Soap(xml) => xml_response(StatusCode::OK, &xml, encoding),
+        ResponseBody::SoapFault { code, description } => {
+            soap_error_response(code, &description, encoding)
+        }
+        ResponseBody::Raw { content_type, body } => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap(),
+        ResponseBody::RawBytes { content_type, body } => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap(),
+        ResponseBody::Stream { content_type, body } => {
+            let body = match body.take() {
+                Some(stream) => Body::from_stream(stream),
+                None => Body::empty(),
+            };
+            Response::builder()
                 .status(StatusCode::OK)
                 .header(header::CONTENT_TYPE, content_type)
-                .body(Body::from(body))
-                .unwrap(),
-        },
-        None => {
-            tracing::debug!("No mock found for action: {}", request.action_name);
-            soap_error_response(401, "Invalid Action")
+                .body(body)
+                .unwrap()
         }
+        ResponseBody::Http {
+            status,
+            headers,
+            body,
+        } => {
+            let mut response = Response::builder().status(status);
+            for (name, value) in headers {
+                response = response.header(name, value);
+            }
+            response.body(Body::from(body)).unwrap_or_else(|e| {
+                tracing::warn!(target: TRACE_TARGET, "Invalid HTTP response: {}", e);
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::empty())
+                    .unwrap()
+            })
+        }
+    }
+}
+
+/// Panic if a served document is invalid, so template regressions and
+/// malformed responders fail loudly.
+#[cfg(all(feature = "validate", debug_assertions))]
+fn assert_valid(document: &str, result: Result<(), String>) {
+    if let Err(e) = result {
+        panic!("mock-igd served an invalid {document}: {e}");
     }
 }
 
 /// Generate a SOAP error response.
-fn soap_error_response(code: u16, description: &str) -> Response<Body> {
+fn soap_error_response(code: u16, description: &str, encoding: ResponseEncoding) -> Response<Body> {
     let xml = generate_soap_fault(code, description);
+    #[cfg(all(feature = "validate", debug_assertions))]
+    assert_valid(
+        "SOAP fault",
+        validate::validate_soap_response("", &xml, &[]),
+    );
+    xml_response(StatusCode::INTERNAL_SERVER_ERROR, &xml, encoding)
+}
+
+/// Build an XML response in the configured character encoding.
+fn xml_response(status: StatusCode, xml: &str, encoding: ResponseEncoding) -> Response<Body> {
     Response::builder()
-        .status(StatusCode::INTERNAL_SERVER_ERROR)
-        .header(header::CONTENT_TYPE, "text/xml; charset=\"utf-8\"")
-        .body(Body::from(xml))
+        .status(status)
+        .header(header::CONTENT_TYPE, encoding.content_type())
+        .body(Body::from(encoding.encode(xml)))
         .unwrap()
 }
 
@@ -143,20 +710,26 @@ fn parse_soap_request(soap_action: &str, body: &str) -> Result<SoapRequest, Stri
         .unwrap_or("")
         .to_string();
 
-    let service_type = soap_action
-        .trim_matches('"')
-        .split('#')
-        .next()
-        .unwrap_or("")
-        .to_string();
+    let service_type = ServiceType::parse(
+        soap_action
+            .trim_matches('"')
+            .split('#')
+            .next()
+            .unwrap_or(""),
+    );
 
     // Parse body based on action
     let request_body = parse_soap_body(&action_name, body)?;
+    let arguments = extract_arguments(body, &action_name);
 
     Ok(SoapRequest {
         action_name,
         service_type,
         body: request_body,
+        raw_body: body.to_string(),
+        arguments,
+        client: None,
+        wan_device: 0,
     })
 }
 
@@ -172,10 +745,83 @@ fn parse_soap_body(action_name: &str, body: &str) -> Result<SoapRequestBody, Str
         "GetCommonLinkProperties" => Ok(SoapRequestBody::GetCommonLinkProperties),
         "GetTotalBytesReceived" => Ok(SoapRequestBody::GetTotalBytesReceived),
         "GetTotalBytesSent" => Ok(SoapRequestBody::GetTotalBytesSent),
-        _ => Ok(SoapRequestBody::Unknown(action_name.to_string())),
+        _ => Ok(SoapRequestBody::Unknown(UnknownRequest {
+            action_name: action_name.to_string(),
+            arguments: extract_arguments(body, action_name),
+        })),
     }
 }
 
+/// Extract the child elements of the action element as name/value pairs
+/// (simple implementation).
+fn extract_arguments(body: &str, action_name: &str) -> Vec<(String, String)> {
+    let Some(content) = action_element_content(body, action_name) else {
+        return Vec::new();
+    };
+
+    let mut arguments = Vec::new();
+    let mut rest = content;
+    while let Some(open) = rest.find('<') {
+        rest = &rest[open + 1..];
+        let Some(tag_end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..tag_end];
+        rest = &rest[tag_end + 1..];
+        if tag.starts_with(['/', '!', '?']) {
+            continue;
+        }
+        let qualified_name = tag
+            .trim_end_matches('/')
+            .split_whitespace()
+            .next()
+            .unwrap_or("");
+        let name = local_name(qualified_name).to_string();
+        if tag.ends_with('/') {
+            arguments.push((name, String::new()));
+            continue;
+        }
+        let end_tag = format!("</{qualified_name}>");
+        let Some(end) = rest.find(&end_tag) else {
+            break;
+        };
+        arguments.push((name, rest[..end].trim().to_string()));
+        rest = &rest[end + end_tag.len()..];
+    }
+    arguments
+}
+
+/// Find the content of the element named `action_name`, with or without a
+/// namespace prefix.
+fn action_element_content<'a>(body: &'a str, action_name: &str) -> Option<&'a str> {
+    let mut offset = 0;
+    while let Some(open) = body[offset..].find('<') {
+        let start = offset + open + 1;
+        let tag_end = start + body[start..].find('>')?;
+        let tag = &body[start..tag_end];
+        offset = tag_end + 1;
+        let qualified_name = tag
+            .trim_end_matches('/')
+            .split_whitespace()
+            .next()
+            .unwrap_or("");
+        if local_name(qualified_name) != action_name {
+            continue;
+        }
+        if tag.ends_with('/') {
+            return Some("");
+        }
+        let end = body[offset..].find(&format!("</{qualified_name}>"))?;
+        return Some(&body[offset..offset + end]);
+    }
+    None
+}
+
+/// Strip the namespace prefix from an element name.
+fn local_name(qualified_name: &str) -> &str {
+    qualified_name.rsplit(':').next().unwrap_or(qualified_name)
+}
+
 /// Extract a value from XML by tag name (simple implementation).
 fn extract_xml_value(body: &str, tag: &str) -> Option<String> {
     let start_tag = format!("<{}", tag);
@@ -191,34 +837,21 @@ fn extract_xml_value(body: &str, tag: &str) -> Option<String> {
 }
 
 fn parse_add_port_mapping(body: &str) -> Result<SoapRequestBody, String> {
-    Ok(SoapRequestBody::AddPortMapping(AddPortMappingRequest {
-        remote_host: extract_xml_value(body, "NewRemoteHost").unwrap_or_default(),
-        external_port: extract_xml_value(body, "NewExternalPort")
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(0),
-        protocol: extract_xml_value(body, "NewProtocol").unwrap_or_else(|| "TCP".to_string()),
-        internal_port: extract_xml_value(body, "NewInternalPort")
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(0),
-        internal_client: extract_xml_value(body, "NewInternalClient").unwrap_or_default(),
-        enabled: extract_xml_value(body, "NewEnabled")
-            .map(|s| s == "1" || s.to_lowercase() == "true")
-            .unwrap_or(true),
-        description: extract_xml_value(body, "NewPortMappingDescription").unwrap_or_default(),
-        lease_duration: extract_xml_value(body, "NewLeaseDuration")
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(0),
-    }))
+    Ok(SoapRequestBody::AddPortMapping(
+        AddPortMappingRequest::from_arguments(|name| extract_xml_value(body, name)),
+    ))
 }
 
 fn parse_delete_port_mapping(body: &str) -> Result<SoapRequestBody, String> {
-    Ok(SoapRequestBody::DeletePortMapping(DeletePortMappingRequest {
-        remote_host: extract_xml_value(body, "NewRemoteHost").unwrap_or_default(),
-        external_port: extract_xml_value(body, "NewExternalPort")
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(0),
-        protocol: extract_xml_value(body, "NewProtocol").unwrap_or_else(|| "TCP".to_string()),
-    }))
+    Ok(SoapRequestBody::DeletePortMapping(
+        DeletePortMappingRequest {
+            remote_host: extract_xml_value(body, "NewRemoteHost").unwrap_or_default(),
+            external_port: extract_xml_value(body, "NewExternalPort")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            protocol: extract_xml_value(body, "NewProtocol").unwrap_or_else(|| "TCP".to_string()),
+        },
+    ))
 }
 
 fn parse_get_generic_port_mapping_entry(body: &str) -> Result<SoapRequestBody, String> {
@@ -243,9 +876,53 @@ fn parse_get_specific_port_mapping_entry(body: &str) -> Result<SoapRequestBody,
     ))
 }
 
+/// The generated device description for `config`.
+pub(super) fn device_description(config: &ServerConfig) -> String {
+    generate_device_description(
+        &config.udns,
+        config.description_padding,
+        config.wan_ppp_connection,
+    )
+}
+
 /// Generate the UPnP device description XML.
-fn generate_device_description() -> String {
-    r#"<?xml version="1.0"?>
+fn generate_device_description(
+    udns: &DeviceUdns,
+    padding_devices: usize,
+    wan_ppp_connection: bool,
+) -> String {
+    let padding: String = (0..padding_devices).map(generate_padding_device).collect();
+    let ppp_service = if wan_ppp_connection {
+        format!(
+            r#"
+              <service>
+                <serviceType>{}</serviceType>
+                <serviceId>urn:upnp-org:serviceId:WANPPPConn1</serviceId>
+                <SCPDURL>/WANPPPCn.xml</SCPDURL>
+                <controlURL>/ctl/PPPConn</controlURL>
+                <eventSubURL>/evt/PPPConn</eventSubURL>
+              </service>"#,
+            ServiceType::WANPPPConnection(1)
+        )
+    } else {
+        String::new()
+    };
+    let wan_ip_connection = ServiceType::WANIPConnection(1);
+    let wan_common_ifc = ServiceType::WANCommonInterfaceConfig(1);
+    let second_wan = match &udns.second_wan {
+        Some((wan_device, wan_connection_device)) => {
+            generate_second_wan_device(wan_device, wan_connection_device)
+        }
+        None => String::new(),
+    };
+    let DeviceUdns {
+        root,
+        wan_device,
+        wan_connection_device,
+        ..
+    } = udns;
+    format!(
+        r#"<?xml version="1.0"?>
 <root xmlns="urn:schemas-upnp-org:device-1-0">
   <specVersion>
     <major>1</major>
@@ -256,47 +933,107 @@ fn generate_device_description() -> String {
     <friendlyName>Mock IGD</friendlyName>
     <manufacturer>mock-igd</manufacturer>
     <modelName>Mock Internet Gateway Device</modelName>
-    <UDN>uuid:mock-igd-001</UDN>
+    <UDN>{root}</UDN>
     <deviceList>
       <device>
         <deviceType>urn:schemas-upnp-org:device:WANDevice:1</deviceType>
         <friendlyName>WANDevice</friendlyName>
-        <UDN>uuid:mock-igd-wan-001</UDN>
+        <UDN>{wan_device}</UDN>
         <deviceList>
           <device>
             <deviceType>urn:schemas-upnp-org:device:WANConnectionDevice:1</deviceType>
             <friendlyName>WANConnectionDevice</friendlyName>
-            <UDN>uuid:mock-igd-wanconn-001</UDN>
+            <UDN>{wan_connection_device}</UDN>
             <serviceList>
               <service>
-                <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+                <serviceType>{wan_ip_connection}</serviceType>
                 <serviceId>urn:upnp-org:serviceId:WANIPConn1</serviceId>
                 <SCPDURL>/WANIPCn.xml</SCPDURL>
                 <controlURL>/ctl/IPConn</controlURL>
                 <eventSubURL>/evt/IPConn</eventSubURL>
-              </service>
+              </service>{ppp_service}
             </serviceList>
           </device>
         </deviceList>
         <serviceList>
           <service>
-            <serviceType>urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1</serviceType>
+            <serviceType>{wan_common_ifc}</serviceType>
             <serviceId>urn:upnp-org:serviceId:WANCommonIFC1</serviceId>
             <SCPDURL>/WANCommonIFC1.xml</SCPDURL>
             <controlURL>/ctl/WANCommonIFC1</controlURL>
             <eventSubURL>/evt/WANCommonIFC1</eventSubURL>
           </service>
         </serviceList>
-      </device>
+      </device>{second_wan}{padding}
     </deviceList>
   </device>
 </root>"#
-        .to_string()
+    )
+}
+
+/// Generate the second WANDevice of a multi-homed gateway, whose services
+/// have URLs ending in 2.
+fn generate_second_wan_device(wan_device: &str, wan_connection_device: &str) -> String {
+    let wan_ip_connection = ServiceType::WANIPConnection(1);
+    let wan_common_ifc = ServiceType::WANCommonInterfaceConfig(1);
+    format!(
+        r#"
+      <device>
+        <deviceType>urn:schemas-upnp-org:device:WANDevice:1</deviceType>
+        <friendlyName>WANDevice 2</friendlyName>
+        <UDN>{wan_device}</UDN>
+        <deviceList>
+          <device>
+            <deviceType>urn:schemas-upnp-org:device:WANConnectionDevice:1</deviceType>
+            <friendlyName>WANConnectionDevice 2</friendlyName>
+            <UDN>{wan_connection_device}</UDN>
+            <serviceList>
+              <service>
+                <serviceType>{wan_ip_connection}</serviceType>
+                <serviceId>urn:upnp-org:serviceId:WANIPConn1</serviceId>
+                <SCPDURL>/WANIPCn.xml</SCPDURL>
+                <controlURL>/ctl/IPConn2</controlURL>
+                <eventSubURL>/evt/IPConn2</eventSubURL>
+              </service>
+            </serviceList>
+          </device>
+        </deviceList>
+        <serviceList>
+          <service>
+            <serviceType>{wan_common_ifc}</serviceType>
+            <serviceId>urn:upnp-org:serviceId:WANCommonIFC1</serviceId>
+            <SCPDURL>/WANCommonIFC1.xml</SCPDURL>
+            <controlURL>/ctl/WANCommonIFC2</controlURL>
+            <eventSubURL>/evt/WANCommonIFC2</eventSubURL>
+          </service>
+        </serviceList>
+      </device>"#
+    )
 }
 
-/// Generate the WANIPConnection SCPD XML.
-fn generate_wan_ip_connection_scpd() -> String {
-    r#"<?xml version="1.0"?>
+/// Generate a filler embedded device for padded descriptions.
+fn generate_padding_device(index: usize) -> String {
+    format!(
+        r#"
+      <device>
+        <deviceType>urn:mock-igd:device:PaddingDevice:1</deviceType>
+        <friendlyName>Padding Device {index}</friendlyName>
+        <UDN>uuid:mock-igd-padding-{index:06}</UDN>
+        <serviceList>
+          <service>
+            <serviceType>urn:mock-igd:service:PaddingService:1</serviceType>
+            <serviceId>urn:mock-igd:serviceId:Padding{index}</serviceId>
+            <SCPDURL>/padding/{index}.xml</SCPDURL>
+            <controlURL>/ctl/padding/{index}</controlURL>
+            <eventSubURL>/evt/padding/{index}</eventSubURL>
+          </service>
+        </serviceList>
+      </device>"#
+    )
+}
+
+/// The WANIPConnection SCPD XML.
+const WAN_IP_CONNECTION_SCPD: &str = r#"<?xml version="1.0"?>
 <scpd xmlns="urn:schemas-upnp-org:service-1-0">
   <specVersion>
     <major>1</major>
@@ -557,13 +1294,10 @@ fn generate_wan_ip_connection_scpd() -> String {
       <dataType>ui2</dataType>
     </stateVariable>
   </serviceStateTable>
-</scpd>"#
-        .to_string()
-}
+</scpd>"#;
 
-/// Generate the WANCommonInterfaceConfig SCPD XML.
-fn generate_wan_common_ifc_scpd() -> String {
-    r#"<?xml version="1.0"?>
+/// The WANCommonInterfaceConfig SCPD XML.
+const WAN_COMMON_IFC_SCPD: &str = r#"<?xml version="1.0"?>
 <scpd xmlns="urn:schemas-upnp-org:service-1-0">
   <specVersion>
     <major>1</major>
@@ -652,6 +1386,4 @@ fn generate_wan_common_ifc_scpd() -> String {
       <dataType>ui4</dataType>
     </stateVariable>
   </serviceStateTable>
-</scpd>"#
-        .to_string()
-}
+</scpd>"#;
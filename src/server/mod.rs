@@ -1,26 +1,473 @@
 //! Mock IGD server implementation.
 
+mod connection;
+mod encoding;
+pub(crate) mod events;
+mod forward;
 mod http;
 mod ssdp;
 
-use crate::action::Action;
-use crate::mock::{Mock, MockRegistry, ReceivedRequest, ReceivedSsdpRequest};
-use crate::responder::Responder;
-use crate::Result;
-use std::net::SocketAddr;
+pub use connection::KeepAlive;
+pub use encoding::ResponseEncoding;
+pub use events::{CallbackValidation, DeliveryOutcome, EventDelivery, EventSeqFault};
+pub use ssdp::UsnFormat;
+
+use crate::action::{Action, Protocol, ServiceType};
+use crate::compliance::{ComplianceLevel, ComplianceRule, ComplianceViolation};
+use crate::mapping::{MappingQuirk, PortMapping};
+use crate::matcher::{SoapRequest, SsdpMatcher};
+use crate::metrics::{ActionCoverage, LatencyStats, Metrics};
+use crate::mock::{
+    ClientActivity, ClientAgentStats, MatchedResponse, Mock, MockRegistry, ReceivedRequest,
+    ReceivedSsdpNotification, ReceivedSsdpRequest, ResponderPanic, SentSsdpNotification,
+};
+use crate::responder::{Responder, ResponseBody, SsdpResponse};
+use crate::rng::SimRng;
+use crate::scenario::Scenario;
+use crate::{Error, Result};
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
 use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use uuid::Builder;
+
+/// Tracing target used for request-level events.
+pub(crate) const TRACE_TARGET: &str = "mock_igd";
+
+/// Tracing target used for verbose wire-level events.
+pub(crate) const WIRE_TRACE_TARGET: &str = "mock_igd::wire";
+
+/// Runtime options shared by the HTTP and SSDP servers.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ServerConfig {
+    /// Log raw request and response contents under [`WIRE_TRACE_TARGET`].
+    pub(crate) wire_logging: bool,
+    /// Maximum number of SOAP requests handled at once (`None` = unlimited).
+    pub(crate) max_concurrent_requests: Option<usize>,
+    /// How to report success responders missing required fields.
+    pub(crate) response_lint: ResponseLint,
+    /// Persistent connection behavior (`None` = keep connections open).
+    pub(crate) keep_alive: Option<KeepAlive>,
+    /// Maximum SOAP requests per second accepted from one client (`None` = unlimited).
+    pub(crate) rate_limit: Option<u32>,
+    /// Maximum accepted SOAP request body size in bytes (`None` = axum's default).
+    pub(crate) max_request_body_size: Option<usize>,
+    /// Username and password required on control URLs (`None` = no auth).
+    pub(crate) basic_auth: Option<(String, String)>,
+    /// Redirect answered for `/rootDesc.xml` (`None` = serve the description).
+    pub(crate) description_redirect: Option<DescriptionRedirect>,
+    /// Delay before answering `/rootDesc.xml`.
+    pub(crate) description_delay: Option<Duration>,
+    /// Delay before answering SCPD fetches.
+    pub(crate) scpd_delay: Option<Duration>,
+    /// Number of filler embedded devices added to the description.
+    pub(crate) description_padding: usize,
+    /// Character encoding of served XML documents.
+    pub(crate) response_encoding: ResponseEncoding,
+    /// Check client requests against UPnP rules.
+    pub(crate) compliance_checks: bool,
+    /// Per-rule reaction to violations (rules not listed use the default).
+    pub(crate) compliance_levels: HashMap<ComplianceRule, ComplianceLevel>,
+    /// Answer unmatched port mapping actions from an in-memory table.
+    pub(crate) port_mapping_table: bool,
+    /// How strictly GENA `CALLBACK` headers are checked.
+    pub(crate) callback_validation: CallbackValidation,
+    /// Errors injected into the `SEQ` numbers of GENA events.
+    pub(crate) event_seq_faults: Vec<EventSeqFault>,
+    /// Minimum time between GENA events carrying each moderated variable.
+    pub(crate) event_moderation: Vec<(String, Duration)>,
+    /// Upstream and downstream bit rates reported for the simulated link.
+    pub(crate) link_rates: Option<(u32, u32)>,
+    /// Answer unmatched `GetStatusInfo` requests with the gateway's uptime.
+    pub(crate) status_info: bool,
+    /// Also advertise and serve a WANPPPConnection service.
+    pub(crate) wan_ppp_connection: bool,
+    /// Address reported by both WAN connection services.
+    pub(crate) external_ip: Option<IpAddr>,
+    /// Address WANPPPConnection reports instead of `external_ip`.
+    pub(crate) ppp_external_ip: Option<IpAddr>,
+    /// External address of a second WANDevice, if simulated.
+    pub(crate) second_wan_ip: Option<IpAddr>,
+    /// Firmware quirks applied by the port mapping table.
+    pub(crate) mapping_quirks: Vec<MappingQuirk>,
+    /// Keep the port mapping table across [`MockIgdServer::simulate_reboot`].
+    pub(crate) persistent_mappings: bool,
+    /// Answer unmatched `GetGenericPortMappingEntry` requests with 713
+    /// instead of 401.
+    pub(crate) unmatched_index_invalid: bool,
+    /// Panic on requests nothing answers, naming the nearest miss.
+    pub(crate) strict_matching: bool,
+    /// Echo traffic sent to the internal side of port mappings.
+    pub(crate) echo_endpoints: bool,
+    /// Forward traffic sent to the external port of mappings on localhost.
+    pub(crate) nat_forwarding: bool,
+    /// Interface joining the SSDP multicast group (`None` = chosen by the OS).
+    pub(crate) ssdp_interface: Option<Ipv4Addr>,
+    /// IP_MULTICAST_TTL of the SSDP socket (`None` = OS default, usually 1).
+    pub(crate) ssdp_multicast_ttl: Option<u32>,
+    /// IP_MULTICAST_LOOP of the SSDP socket (`None` = OS default).
+    pub(crate) ssdp_multicast_loop: Option<bool>,
+    /// Answer `/rootDesc.xml` with 404.
+    pub(crate) disable_description: bool,
+    /// Answer SCPD fetches with 404.
+    pub(crate) disable_scpds: bool,
+    /// Hook observing or replacing the response chosen for each SOAP request.
+    pub(crate) interceptor: Option<Interceptor>,
+    /// Seed of the generator behind randomized behaviors (`None` = random).
+    pub(crate) seed: Option<u64>,
+    /// Delay M-SEARCH responses by a random time up to the request's MX.
+    pub(crate) ssdp_mx_delay: bool,
+    /// Unique device names, generated when the server starts.
+    pub(crate) udns: DeviceUdns,
+    /// Root device UDN replacing the generated one.
+    pub(crate) root_udn: Option<String>,
+    /// Format of the `USN` header in SSDP responses.
+    pub(crate) usn_format: UsnFormat,
+    /// Record traffic without answering it, except for the SOAP actions
+    /// (and `M-SEARCH`) listed.
+    pub(crate) observer: Option<Vec<String>>,
+    /// Search targets answered instead of the default IGD ones.
+    pub(crate) ssdp_search_targets: Option<Vec<String>>,
+    /// Send M-SEARCH responses from an ephemeral port instead of the SSDP
+    /// port.
+    pub(crate) ssdp_ephemeral_reply_port: bool,
+    /// SSDP `CACHE-CONTROL` max-age in seconds (1800 if unset).
+    pub(crate) ssdp_max_age: Option<u32>,
+    /// Number of `ssdp:alive` announcements sent after startup.
+    pub(crate) ssdp_announcements: u32,
+    /// Clients served over SSDP only, on top of `client_filter` (quiet SSDP).
+    pub(crate) ssdp_client_filter: Option<ClientFilter>,
+    /// Clients served over HTTP and SSDP (`None` = everyone).
+    pub(crate) client_filter: Option<ClientFilter>,
+    /// Skew of the `Date` headers and GENA `TIMEOUT`s sent (`None` = exact).
+    pub(crate) clock_skew: Option<ClockSkew>,
+}
+
+/// Unique device names of the root device and its embedded devices.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DeviceUdns {
+    pub(crate) root: String,
+    pub(crate) wan_device: String,
+    pub(crate) wan_connection_device: String,
+    /// WANDevice and WANConnectionDevice UDNs of the second WANDevice.
+    pub(crate) second_wan: Option<(String, String)>,
+}
+
+impl DeviceUdns {
+    /// Generate random UDNs, so concurrently running servers are
+    /// distinguishable by clients that deduplicate devices by UDN.
+    fn generate(rng: &SimRng, second_wan: bool) -> Self {
+        let udn = || {
+            format!(
+                "uuid:{}",
+                Builder::from_random_bytes(rng.next_bytes()).into_uuid()
+            )
+        };
+        DeviceUdns {
+            root: udn(),
+            wan_device: udn(),
+            wan_connection_device: udn(),
+            second_wan: second_wan.then(|| (udn(), udn())),
+        }
+    }
+}
+
+type InterceptorFn =
+    dyn Fn(&SoapRequest, Option<ResponseBody>) -> Option<ResponseBody> + Send + Sync;
+
+/// Hook registered with [`MockIgdServerBuilder::with_interceptor`].
+#[derive(Clone)]
+pub(crate) struct Interceptor(Arc<InterceptorFn>);
+
+impl Interceptor {
+    /// Let the hook observe or replace the response planned for `request`.
+    pub(crate) fn intercept(
+        &self,
+        request: &SoapRequest,
+        matched: Option<MatchedResponse>,
+    ) -> Option<MatchedResponse> {
+        let (planned, matched) = match matched {
+            Some(MatchedResponse {
+                mock_id,
+                mock_name,
+                body,
+                content_type,
+                delay,
+            }) => (Some(body), Some((mock_id, mock_name, content_type, delay))),
+            None => (None, None),
+        };
+        let body = (self.0)(request, planned)?;
+        let (mock_id, mock_name, content_type, delay) = matched.unwrap_or_default();
+        Some(MatchedResponse {
+            mock_id,
+            mock_name,
+            body,
+            content_type,
+            delay,
+        })
+    }
+}
+
+impl std::fmt::Debug for Interceptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Interceptor").finish()
+    }
+}
+
+impl ServerConfig {
+    /// How violations of `rule` are handled.
+    pub(crate) fn compliance_level(&self, rule: ComplianceRule) -> ComplianceLevel {
+        self.compliance_levels
+            .get(&rule)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Whether SSDP datagrams from `client` are handled.
+    pub(crate) fn admits_ssdp_client(&self, client: IpAddr) -> bool {
+        [&self.client_filter, &self.ssdp_client_filter]
+            .into_iter()
+            .flatten()
+            .all(|filter| filter.admits(client))
+    }
+}
+
+/// How `/rootDesc.xml` redirects to the canonical description URL.
+///
+/// The location is either a path on the mock server, where the description
+/// is then served, or an absolute URL (e.g. another port).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DescriptionRedirect {
+    /// Answer with `301 Moved Permanently`.
+    MovedPermanently(String),
+    /// Answer with `302 Found`.
+    Found(String),
+}
+
+impl DescriptionRedirect {
+    /// The redirect target.
+    pub fn location(&self) -> &str {
+        match self {
+            DescriptionRedirect::MovedPermanently(location)
+            | DescriptionRedirect::Found(location) => location,
+        }
+    }
+}
+
+/// How the server reports success responders that lack the fields required
+/// by the action they are mocked for (e.g. `GetExternalIPAddress` without
+/// `with_external_ip`), which would otherwise emit empty elements.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResponseLint {
+    /// Don't check responders.
+    #[default]
+    Off,
+    /// Emit a tracing warning when the mock is registered.
+    Warn,
+    /// Panic when the mock is registered.
+    Deny,
+}
+
+/// Which clients the server talks to, by source IP address.
+///
+/// Rejected clients' SSDP datagrams are ignored without being recorded, and
+/// their HTTP connections are turned away as set by
+/// [`with_rejection`](Self::with_rejection).
+///
+/// # Example
+///
+/// ```ignore
+/// // Only talk to the test host, answering anyone else with 403
+/// let filter = ClientFilter::allow(["192.168.1.50".parse()?])
+///     .with_rejection(ClientRejection::Forbidden);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientFilter {
+    allowed: Option<Vec<IpAddr>>,
+    denied: Vec<IpAddr>,
+    rejection: ClientRejection,
+}
+
+impl ClientFilter {
+    /// Admit only clients with these addresses.
+    pub fn allow(clients: impl IntoIterator<Item = IpAddr>) -> Self {
+        ClientFilter {
+            allowed: Some(clients.into_iter().collect()),
+            denied: Vec::new(),
+            rejection: ClientRejection::default(),
+        }
+    }
+
+    /// Admit every client except those with these addresses.
+    pub fn deny(clients: impl IntoIterator<Item = IpAddr>) -> Self {
+        ClientFilter {
+            allowed: None,
+            denied: clients.into_iter().collect(),
+            rejection: ClientRejection::default(),
+        }
+    }
+
+    /// Set how rejected HTTP clients are turned away.
+    pub fn with_rejection(mut self, rejection: ClientRejection) -> Self {
+        self.rejection = rejection;
+        self
+    }
+
+    /// Also admit `client`, on top of the allowed clients.
+    pub(crate) fn admit(&mut self, client: IpAddr) {
+        self.allowed
+            .get_or_insert_with(Vec::new)
+            .push(client.to_canonical());
+    }
+
+    /// Whether the server talks to `client`.
+    pub(crate) fn admits(&self, client: IpAddr) -> bool {
+        let client = client.to_canonical();
+        self.allowed
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(&client))
+            && !self.denied.contains(&client)
+    }
+
+    /// How rejected HTTP clients are turned away.
+    pub(crate) fn rejection(&self) -> ClientRejection {
+        self.rejection
+    }
+}
+
+/// How HTTP clients rejected by a [`ClientFilter`] are turned away.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClientRejection {
+    /// Reset connections as soon as they are accepted, as if nothing
+    /// listened on the port.
+    #[default]
+    Refuse,
+    /// Answer every request with `403 Forbidden`.
+    Forbidden,
+}
+
+/// Error in the clock of a simulated router, applied to the timestamps it
+/// reports.
+///
+/// SSDP responses and announcements carry no `DATE` header, so only HTTP
+/// responses and GENA subscriptions show the skew.
+///
+/// # Example
+///
+/// ```ignore
+/// // A router an hour behind that overstates subscription timeouts by a minute
+/// let skew = ClockSkew::behind(Duration::from_secs(3600)).with_timeout_skew(60);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSkew {
+    offset: Duration,
+    direction: SkewDirection,
+    timeout_skew: i64,
+}
+
+/// Which way a [`ClockSkew`] moves the clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SkewDirection {
+    Ahead,
+    Behind,
+}
+
+impl ClockSkew {
+    /// Send `Date` headers `offset` later than the real time.
+    pub fn ahead(offset: Duration) -> Self {
+        ClockSkew {
+            offset,
+            direction: SkewDirection::Ahead,
+            timeout_skew: 0,
+        }
+    }
+
+    /// Send `Date` headers `offset` earlier than the real time.
+    pub fn behind(offset: Duration) -> Self {
+        ClockSkew {
+            offset,
+            direction: SkewDirection::Behind,
+            timeout_skew: 0,
+        }
+    }
+
+    /// Report GENA subscription `TIMEOUT`s `seconds` longer (or shorter, if
+    /// negative) than the subscriptions actually last, so clients renewing
+    /// based on them renew too late (or too early).
+    pub fn with_timeout_skew(mut self, seconds: i64) -> Self {
+        self.timeout_skew = seconds;
+        self
+    }
+
+    /// The current time on the skewed clock.
+    pub(crate) fn now(&self) -> SystemTime {
+        let now = SystemTime::now();
+        let skewed = if self.direction == SkewDirection::Ahead {
+            now.checked_add(self.offset)
+        } else {
+            now.checked_sub(self.offset)
+        };
+        skewed.unwrap_or(now)
+    }
+
+    /// The `TIMEOUT` reported for a subscription granted for `timeout`.
+    pub(crate) fn reported_timeout(&self, timeout: Option<Duration>) -> Option<Duration> {
+        let secs = timeout?.as_secs().saturating_add_signed(self.timeout_skew);
+        Some(Duration::from_secs(secs))
+    }
+}
+
+/// An HTTP port held for a server that hasn't started yet; see
+/// [`MockIgdServer::reserve_port`].
+#[derive(Debug)]
+pub struct ReservedPort {
+    listener: std::net::TcpListener,
+}
+
+impl ReservedPort {
+    /// The reserved port.
+    pub fn port(&self) -> u16 {
+        self.addr().port()
+    }
+
+    /// The reserved address.
+    pub fn addr(&self) -> SocketAddr {
+        self.listener
+            .local_addr()
+            .expect("a bound listener has a local address")
+    }
+}
 
 /// A mock UPnP IGD server for testing.
 pub struct MockIgdServer {
-    /// HTTP server address.
-    http_addr: SocketAddr,
+    /// HTTP server address (`None` in discovery-only mode).
+    http_addr: Option<SocketAddr>,
     /// SSDP server address (if enabled).
     ssdp_addr: Option<SocketAddr>,
     /// Mock registry.
     registry: Arc<MockRegistry>,
+    /// Runtime configuration.
+    config: ServerConfig,
+    /// LOCATION advertised over SSDP (if enabled).
+    ssdp_location: Option<String>,
     /// Shutdown signal sender.
     shutdown_tx: Option<oneshot::Sender<()>>,
+    /// Task running the HTTP server.
+    http_task: Option<JoinHandle<()>>,
+    /// Task running the SSDP server.
+    ssdp_task: Option<JoinHandle<()>>,
+    /// Task sending periodic SSDP announcements.
+    announce_task: Option<JoinHandle<()>>,
+    /// Tasks standing in for traffic through port mappings, kept across
+    /// restarts and aborted when the server is dropped.
+    _mapping_tasks: Vec<forward::AbortOnDrop>,
 }
 
 impl MockIgdServer {
@@ -29,29 +476,167 @@ impl MockIgdServer {
         Self::builder().start().await
     }
 
+    /// Start a server that behaves like a healthy gateway with an empty
+    /// port mapping table.
+    ///
+    /// Answers `GetExternalIPAddress` with `external_ip`, `AddPortMapping`
+    /// and `DeletePortMapping` with success, and `GetGenericPortMappingEntry`
+    /// with 713 (`SpecifiedArrayIndexInvalid`), which covers most simple
+    /// client tests. More mocks can be registered on top.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::start_simple("203.0.113.1".parse()?).await?;
+    /// ```
+    pub async fn start_simple(external_ip: IpAddr) -> Result<Self> {
+        let server = Self::start().await?;
+        server
+            .mock(
+                Action::GetExternalIPAddress,
+                Responder::success().with_external_ip(external_ip),
+            )
+            .await;
+        server
+            .mock(Action::add_port_mapping(), Responder::success())
+            .await;
+        server
+            .mock(Action::delete_port_mapping(), Responder::success())
+            .await;
+        server
+            .mock(
+                Action::get_generic_port_mapping_entry(),
+                Responder::error(713, "SpecifiedArrayIndexInvalid"),
+            )
+            .await;
+        Ok(server)
+    }
+
     /// Create a builder for configuring the server.
     pub fn builder() -> MockIgdServerBuilder {
         MockIgdServerBuilder::default()
     }
 
+    /// Reserve an ephemeral HTTP port on 127.0.0.1 for a server started
+    /// later with [`MockIgdServerBuilder::with_reserved_port`].
+    ///
+    /// The port stays bound until the server takes it over, so URLs built
+    /// from it beforehand (e.g. an SSDP LOCATION served by another server)
+    /// can't be invalidated by a concurrent test binding the same port.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let reserved = MockIgdServer::reserve_port()?;
+    /// let location = format!("http://127.0.0.1:{}/rootDesc.xml", reserved.port());
+    /// let discovery = MockIgdServer::builder().ssdp_only(location).start().await?;
+    /// let server = MockIgdServer::builder().with_reserved_port(reserved).start().await?;
+    /// ```
+    pub fn reserve_port() -> Result<ReservedPort> {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        Ok(ReservedPort { listener })
+    }
+
     /// Get the URL of the HTTP server (for SOAP requests).
     pub fn url(&self) -> String {
-        format!("http://{}", self.http_addr)
+        format!("http://{}", self.http_addr())
     }
 
     /// Get the control URL for SOAP actions.
     pub fn control_url(&self) -> String {
-        format!("http://{}/ctl/IPConn", self.http_addr)
+        format!("http://{}/ctl/IPConn", self.http_addr())
+    }
+
+    /// Get the control URL of the WANPPPConnection service (see
+    /// [`MockIgdServerBuilder::with_wan_ppp_connection`]).
+    pub fn ppp_control_url(&self) -> String {
+        format!("http://{}/ctl/PPPConn", self.http_addr())
+    }
+
+    /// Get the control URL of the second WANDevice's WANIPConnection service
+    /// (see [`MockIgdServerBuilder::with_second_wan_device`]).
+    pub fn second_wan_control_url(&self) -> String {
+        format!("http://{}/ctl/IPConn2", self.http_addr())
+    }
+
+    /// Get the event subscription URL of the second WANDevice's
+    /// WANIPConnection service.
+    pub fn second_wan_event_url(&self) -> String {
+        format!("http://{}/evt/IPConn2", self.http_addr())
+    }
+
+    /// Get the event subscription URL of the WANIPConnection service.
+    pub fn event_url(&self) -> String {
+        format!("http://{}/evt/IPConn", self.http_addr())
     }
 
     /// Get the device description URL.
     pub fn description_url(&self) -> String {
-        format!("http://{}/rootDesc.xml", self.http_addr)
+        format!("http://{}/rootDesc.xml", self.http_addr())
+    }
+
+    /// Get the unique device name of the root device (`uuid:...`).
+    ///
+    /// Generated randomly for each server unless set with
+    /// [`MockIgdServerBuilder::with_udn`], and used in the description and
+    /// SSDP `USN` headers.
+    pub fn udn(&self) -> &str {
+        &self.config.udns.root
+    }
+
+    /// Get the current `BOOTID.UPNP.ORG` value sent in SSDP messages.
+    ///
+    /// Starts at 1 and increases with every [`simulate_reboot`](Self::simulate_reboot).
+    pub fn boot_id(&self) -> u32 {
+        self.registry.boot_id()
+    }
+
+    /// Get the time since the simulated gateway booted, as reported in the
+    /// `NewUptime` of unmatched `GetStatusInfo` requests with
+    /// [`with_status_info`](MockIgdServerBuilder::with_status_info).
+    ///
+    /// Starts over with every [`simulate_reboot`](Self::simulate_reboot) and
+    /// follows tokio's clock, so paused-time tests can age the gateway. Use
+    /// it as the epoch of any other protocol the test simulates, e.g.
+    /// NAT-PMP's seconds since start of epoch, so clients detecting reboots
+    /// through epoch regressions see one consistent gateway.
+    pub fn uptime(&self) -> Duration {
+        self.registry.uptime()
+    }
+
+    /// Get the current `CONFIGID.UPNP.ORG` value sent in SSDP messages.
+    ///
+    /// Starts at 1 and increases whenever the description or an SCPD is
+    /// replaced at runtime.
+    pub fn config_id(&self) -> u32 {
+        self.registry.config_id()
+    }
+
+    /// Get the system clock time the server started at.
+    ///
+    /// Request timestamps are relative to it; see also
+    /// [`ReceivedRequest::received_at`](crate::ReceivedRequest::received_at).
+    pub fn start_time(&self) -> SystemTime {
+        self.registry.started_at()
+    }
+
+    /// Get the seed of the generator behind randomized behaviors.
+    ///
+    /// Pass it to [`MockIgdServerBuilder::with_seed`] to reproduce a run.
+    pub fn seed(&self) -> u64 {
+        self.registry.rng().seed()
     }
 
     /// Get the HTTP server address.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server was started with
+    /// [`MockIgdServerBuilder::ssdp_only`], which has no HTTP server. The
+    /// URL accessors above panic likewise.
     pub fn http_addr(&self) -> SocketAddr {
         self.http_addr
+            .expect("discovery-only server has no HTTP address")
     }
 
     /// Get the SSDP server address (if enabled).
@@ -60,9 +645,11 @@ impl MockIgdServer {
     }
 
     /// Register a mock for the given action.
-    pub async fn mock(&self, action: impl Into<Action>, responder: impl Into<Responder>) {
-        let mock = Mock::new(action, responder);
-        self.registry.register(mock).await;
+    ///
+    /// Returns the identifier assigned to the mock, as reported in
+    /// [`metrics()`](Self::metrics) and tracing events.
+    pub async fn mock(&self, action: impl Into<Action>, responder: impl Into<Responder>) -> u64 {
+        self.register(Mock::new(action, responder)).await
     }
 
     /// Register a mock with a specific priority (higher = checked first).
@@ -71,9 +658,9 @@ impl MockIgdServer {
         action: impl Into<Action>,
         responder: impl Into<Responder>,
         priority: u32,
-    ) {
-        let mock = Mock::new(action, responder).with_priority(priority);
-        self.registry.register(mock).await;
+    ) -> u64 {
+        self.register(Mock::new(action, responder).with_priority(priority))
+            .await
     }
 
     /// Register a mock that only matches a limited number of times.
@@ -82,20 +669,295 @@ impl MockIgdServer {
         action: impl Into<Action>,
         responder: impl Into<Responder>,
         times: u32,
-    ) {
-        let mock = Mock::new(action, responder).times(times);
-        self.registry.register(mock).await;
+    ) -> u64 {
+        self.register(Mock::new(action, responder).times(times))
+            .await
+    }
+
+    /// Register a mock that only matches requests from the client with IP
+    /// address `ip` (see [`Mock::for_client`]).
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// // Two clients on one server: only the second one gets conflicts
+    /// server.mock_for_client(first, Action::add_port_mapping(), Responder::success()).await;
+    /// server
+    ///     .mock_for_client(
+    ///         second,
+    ///         Action::add_port_mapping(),
+    ///         Responder::error(718, "ConflictInMappingEntry"),
+    ///     )
+    ///     .await;
+    /// ```
+    pub async fn mock_for_client(
+        &self,
+        ip: IpAddr,
+        action: impl Into<Action>,
+        responder: impl Into<Responder>,
+    ) -> u64 {
+        self.register(Mock::new(action, responder).for_client(ip))
+            .await
+    }
+
+    /// Decide how M-SEARCH requests matching `matcher` are handled.
+    ///
+    /// The first matching SSDP mock wins; searches no SSDP mock matches are
+    /// answered as usual. Useful to check that clients obey UPnP 1.1
+    /// unicast-search rules or retry with a different MX.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// // Only answer multicast searches
+    /// server
+    ///     .mock_ssdp(
+    ///         SsdpMatcher::any().with_delivery(SsdpDelivery::Unicast),
+    ///         SsdpResponse::Ignore,
+    ///     )
+    ///     .await;
+    /// ```
+    pub async fn mock_ssdp(&self, matcher: SsdpMatcher, response: SsdpResponse) {
+        self.registry.register_ssdp(matcher, response);
+    }
+
+    /// Register a fully configured [`Mock`].
+    ///
+    /// Returns the identifier assigned to the mock.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server was built with [`ResponseLint::Deny`] and the
+    /// mock's success responder lacks fields required by its action.
+    pub async fn register(&self, mock: Mock) -> u64 {
+        self.lint(&mock);
+        self.registry.register(mock)
+    }
+
+    /// Check the mock's responder according to the configured lint level.
+    fn lint(&self, mock: &Mock) {
+        if self.config.response_lint == ResponseLint::Off {
+            return;
+        }
+        let missing = mock.missing_response_fields();
+        if missing.is_empty() {
+            return;
+        }
+        let message = format!(
+            "{} is missing response fields: {}",
+            mock.describe(),
+            missing.join(", ")
+        );
+        if self.config.response_lint == ResponseLint::Deny {
+            panic!("{message}");
+        }
+        tracing::warn!(target: TRACE_TARGET, "{}", message);
+    }
+
+    /// Load a [`Scenario`] so it can be activated with
+    /// [`switch_scenario()`](Self::switch_scenario).
+    ///
+    /// Replaces a previously loaded scenario with the same name; if that
+    /// scenario is active, the change takes effect on the next switch.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server was built with [`ResponseLint::Deny`] and one of
+    /// the scenario's success responders lacks fields required by its action.
+    pub async fn load_scenario(&self, scenario: Scenario) {
+        for mock in &scenario.mocks {
+            self.lint(mock);
+        }
+        self.registry.load_scenario(scenario);
+    }
+
+    /// Activate a loaded scenario, replacing the mocks and mapping quirks of
+    /// the previously active scenario.
+    ///
+    /// The scenario's mocks start with fresh match counts and deadlines.
+    /// Mocks registered outside scenarios and quirks set on the builder are
+    /// kept.
+    pub async fn switch_scenario(&self, name: &str) -> Result<()> {
+        if self.registry.switch_scenario(name) {
+            tracing::info!(target: TRACE_TARGET, scenario = name, "switched scenario");
+            Ok(())
+        } else {
+            Err(Error::UnknownScenario(name.to_string()))
+        }
+    }
+
+    /// Name of the active scenario, if any.
+    pub async fn active_scenario(&self) -> Option<String> {
+        self.registry.active_scenario()
+    }
+
+    /// Current contents of the port mapping table, in insertion order.
+    ///
+    /// Always empty unless the table was enabled with
+    /// [`MockIgdServerBuilder::with_port_mapping_table`].
+    pub async fn port_mappings(&self) -> Vec<PortMapping> {
+        self.registry.port_mappings()
+    }
+
+    /// Check whether `external_port` is open from outside, as a dial-back
+    /// service would: `true` if an enabled mapping in the port mapping table
+    /// forwards it.
+    ///
+    /// With [`MappingQuirk::NotForwarded`], every port is reported closed
+    /// even though the mapping was accepted.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// client.add_port_mapping(8080).await?;
+    /// assert!(server.is_port_reachable(8080, Protocol::TCP).await);
+    /// ```
+    pub async fn is_port_reachable(&self, external_port: u16, protocol: Protocol) -> bool {
+        self.registry.forwarding(external_port, protocol).is_some()
+    }
+
+    /// Get the port mapping table of the second WANDevice (see
+    /// [`MockIgdServerBuilder::with_second_wan_device`]).
+    pub async fn second_wan_port_mappings(&self) -> Vec<PortMapping> {
+        self.registry.wan_port_mappings(1)
+    }
+
+    /// Serve `xml` at `/rootDesc.xml` instead of the generated description,
+    /// e.g. an exact capture from a real router.
+    ///
+    /// Takes effect immediately and increases `CONFIGID.UPNP.ORG`; the
+    /// document is served as is, except for the configured
+    /// [`ResponseEncoding`].
+    pub async fn set_description_xml(&self, xml: impl Into<String>) {
+        self.registry.set_description_xml(xml.into());
+    }
+
+    /// Change the device description mid-test, as a firmware update or
+    /// configuration change would.
+    ///
+    /// `update` edits the description currently served (the generated one
+    /// unless replaced with [`set_description_xml`](Self::set_description_xml))
+    /// and `CONFIGID.UPNP.ORG` increases, so clients caching descriptions
+    /// can be tested. Call [`announce`](Self::announce) to also re-announce
+    /// the device.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// server
+    ///     .update_description(|xml| *xml = xml.replace("Mock IGD", "Renamed IGD"))
+    ///     .await;
+    /// server.announce().await?;
+    /// ```
+    pub async fn update_description(&self, update: impl FnOnce(&mut String)) {
+        let mut xml = self
+            .registry
+            .description_xml()
+            .unwrap_or_else(|| http::device_description(&self.config));
+        update(&mut xml);
+        self.registry.set_description_xml(xml);
+    }
+
+    /// Serve `xml` instead of the generated SCPD of `service`.
+    ///
+    /// Only the service's name matters; the WANIPConnection and
+    /// WANCommonInterfaceConfig SCPDs can be replaced. Increases
+    /// `CONFIGID.UPNP.ORG`.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let scpd = std::fs::read_to_string("captures/fritzbox/WANIPConnection.xml")?;
+    /// server.set_scpd_xml(ServiceType::WANIPConnection(1), scpd).await;
+    /// ```
+    pub async fn set_scpd_xml(&self, service: ServiceType, xml: impl Into<String>) {
+        self.registry.set_scpd_xml(service.name(), xml.into());
+    }
+
+    /// Get the result of every NOTIFY sent to event subscribers, in order of
+    /// completion.
+    ///
+    /// Useful to check the callback server of the client under test:
+    /// whether it answered, with which status, and how quickly.
+    pub async fn event_deliveries(&self) -> Vec<EventDelivery> {
+        self.registry.events().deliveries()
+    }
+
+    /// Add a mapping as if another device on the LAN had created it.
+    ///
+    /// Replaces any mapping with the same remote host, external port and
+    /// protocol. Subscribers get a `PortMappingNumberOfEntries` event unless
+    /// the server uses [`MappingQuirk::NoEventing`]. Has no effect unless
+    /// the port mapping table is enabled.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// server.add_external_mapping(PortMapping {
+    ///     remote_host: String::new(),
+    ///     external_port: 25565,
+    ///     protocol: Protocol::TCP,
+    ///     internal_port: 25565,
+    ///     internal_client: "192.168.1.50".parse()?,
+    ///     enabled: true,
+    ///     description: "game server".to_string(),
+    ///     lease_duration: 0,
+    /// }).await;
+    /// ```
+    pub async fn add_external_mapping(&self, mapping: PortMapping) {
+        if !self.registry.insert_port_mapping(mapping) {
+            tracing::warn!(target: TRACE_TARGET, "port mapping table is disabled");
+        }
+    }
+
+    /// Remove mappings of `external_port` and `protocol` as if another
+    /// device on the LAN had deleted them; returns `false` if there were
+    /// none.
+    ///
+    /// Subscribers are notified as for
+    /// [`add_external_mapping`](Self::add_external_mapping).
+    pub async fn remove_external_mapping(&self, external_port: u16, protocol: Protocol) -> bool {
+        self.registry.remove_port_mapping(external_port, protocol)
+    }
+
+    /// Change the external address reported by both WAN connection services,
+    /// as after the ISP assigns a new one.
+    ///
+    /// Subscribers get an `ExternalIPAddress` event. A WANPPPConnection
+    /// address set with [`MockIgdServerBuilder::with_ppp_external_ip`] is
+    /// kept.
+    pub async fn set_external_ip(&self, ip: IpAddr) {
+        self.registry.set_external_ip(ip);
+    }
+
+    /// Simulate a gateway restart.
+    ///
+    /// The port mapping table is wiped, as on most routers, unless the
+    /// server was built with [`MockIgdServerBuilder::with_persistent_mappings`].
+    /// Event subscriptions are dropped, `BOOTID.UPNP.ORG` is increased and
+    /// the [`uptime`](Self::uptime) starts over. Registered mocks and
+    /// recorded requests are kept.
+    pub async fn simulate_reboot(&self) {
+        tracing::info!(target: TRACE_TARGET, "simulating reboot");
+        self.registry.next_boot();
+        for wan_device in 0..self.registry.wan_device_count() {
+            self.registry.wan_events(wan_device).clear();
+        }
+        if !self.config.persistent_mappings {
+            self.registry.clear_port_mappings();
+        }
     }
 
     /// Clear all registered mocks.
     pub async fn clear_mocks(&self) {
-        self.registry.clear().await;
+        self.registry.clear();
     }
 
     /// Get all received requests.
     ///
     /// Returns a list of all SOAP requests received by the server.
     /// Useful for verifying that expected requests were made.
+    /// The recorded requests are shared, so taking a snapshot is cheap.
     ///
     /// # Example
     ///
@@ -104,13 +966,28 @@ impl MockIgdServer {
     /// assert_eq!(requests.len(), 1);
     /// assert_eq!(requests[0].action_name, "GetExternalIPAddress");
     /// ```
-    pub async fn received_requests(&self) -> Vec<ReceivedRequest> {
-        self.registry.received_requests().await
+    pub async fn received_requests(&self) -> Vec<Arc<ReceivedRequest>> {
+        self.registry.received_requests()
+    }
+
+    /// Get the requests received after the first `n` ones.
+    ///
+    /// Useful for polling in a wait loop without re-reading the whole history.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let seen = server.received_requests().await.len();
+    /// // ... run more client code ...
+    /// let new_requests = server.received_requests_since(seen).await;
+    /// ```
+    pub async fn received_requests_since(&self, n: usize) -> Vec<Arc<ReceivedRequest>> {
+        self.registry.received_requests_since(n)
     }
 
     /// Clear all received requests.
     pub async fn clear_received_requests(&self) {
-        self.registry.clear_received_requests().await;
+        self.registry.clear_received_requests();
     }
 
     /// Get all received SSDP requests (M-SEARCH).
@@ -125,37 +1002,310 @@ impl MockIgdServer {
     /// assert_eq!(requests.len(), 1);
     /// assert_eq!(requests[0].search_target, "ssdp:all");
     /// ```
-    pub async fn received_ssdp_requests(&self) -> Vec<ReceivedSsdpRequest> {
-        self.registry.received_ssdp_requests().await
+    pub async fn received_ssdp_requests(&self) -> Vec<Arc<ReceivedSsdpRequest>> {
+        self.registry.received_ssdp_requests()
     }
 
-    /// Clear all received SSDP requests.
-    pub async fn clear_received_ssdp_requests(&self) {
-        self.registry.clear_received_ssdp_requests().await;
+    /// Get the SSDP requests received after the first `n` ones.
+    pub async fn received_ssdp_requests_since(&self, n: usize) -> Vec<Arc<ReceivedSsdpRequest>> {
+        self.registry.received_ssdp_requests_since(n)
     }
 
-    /// Shutdown the server.
-    pub fn shutdown(mut self) {
-        if let Some(tx) = self.shutdown_tx.take() {
-            let _ = tx.send(());
-        }
+    /// Get the SSDP searches and SOAP requests grouped by client IP, in
+    /// order of first appearance.
+    ///
+    /// Lets tests with several clients check each one's discovery and
+    /// control traffic separately.
+    pub async fn client_activity(&self) -> Vec<ClientActivity> {
+        self.registry.client_activity()
     }
-}
 
-impl Drop for MockIgdServer {
-    fn drop(&mut self) {
-        if let Some(tx) = self.shutdown_tx.take() {
-            let _ = tx.send(());
-        }
+    /// Get the `User-Agent` values and `SOAPAction` header forms seen from
+    /// each client IP, ordered by IP.
+    ///
+    /// Helps tell which client versions touched a mock shared by several
+    /// test suites.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// for client in server.client_agents().await {
+    ///     println!("{}: {:?}", client.ip, client.user_agents);
+    /// }
+    /// ```
+    pub async fn client_agents(&self) -> Vec<ClientAgentStats> {
+        self.registry.client_agents()
     }
-}
 
-/// Builder for configuring a mock IGD server.
-#[derive(Default)]
-pub struct MockIgdServerBuilder {
+    /// Clear all received SSDP requests.
+    pub async fn clear_received_ssdp_requests(&self) {
+        self.registry.clear_received_ssdp_requests();
+    }
+
+    /// Get all SSDP advertisements (NOTIFY) received from other devices.
+    ///
+    /// Useful when the client under test is itself a UPnP device stack that
+    /// announces its presence.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let notifications = server.received_ssdp_notifications().await;
+    /// assert_eq!(notifications[0].notification_subtype, "ssdp:alive");
+    /// ```
+    pub async fn received_ssdp_notifications(&self) -> Vec<Arc<ReceivedSsdpNotification>> {
+        self.registry.received_ssdp_notifications()
+    }
+
+    /// Clear all received SSDP advertisements.
+    pub async fn clear_received_ssdp_notifications(&self) {
+        self.registry.clear_received_ssdp_notifications();
+    }
+
+    /// Multicast `ssdp:alive` advertisements for the root device with the
+    /// current `BOOTID.UPNP.ORG` and `CONFIGID.UPNP.ORG`, e.g. after
+    /// [`update_description`](Self::update_description).
+    ///
+    /// They go to the SSDP multicast group on the port SSDP is served on.
+    ///
+    /// # Errors
+    ///
+    /// Fails if SSDP is not enabled or the advertisements can't be sent.
+    pub async fn announce(&self) -> Result<()> {
+        let (Some(ssdp_addr), Some(location)) = (self.ssdp_addr, &self.ssdp_location) else {
+            return Err(Error::ServerNotRunning);
+        };
+        ssdp::announce_alive(ssdp_addr.port(), location, &self.registry, &self.config).await
+    }
+
+    /// Get all SSDP advertisements sent by [`announce`](Self::announce).
+    pub async fn sent_ssdp_notifications(&self) -> Vec<Arc<SentSsdpNotification>> {
+        self.registry.sent_ssdp_notifications()
+    }
+
+    /// Get a snapshot of the request metrics collected so far.
+    ///
+    /// Includes request counts per action and per mock, the number of
+    /// unmatched requests, SSDP searches answered and handling latency.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let metrics = server.metrics().await;
+    /// assert_eq!(metrics.unmatched_requests, 0);
+    /// println!("{}", metrics.to_prometheus());
+    /// ```
+    pub async fn metrics(&self) -> Metrics {
+        self.registry.metrics()
+    }
+
+    /// Get per-action summaries of the time the mock spent handling SOAP
+    /// requests, to tell mock-side from client-side latency in load tests.
+    pub async fn latency_stats(&self) -> BTreeMap<String, LatencyStats> {
+        self.registry.metrics().latency_stats()
+    }
+
+    /// Get the UPnP rule violations found in client requests so far.
+    ///
+    /// Only populated when the server was built with
+    /// [`MockIgdServerBuilder::with_compliance_checks`].
+    pub async fn compliance_violations(&self) -> Vec<ComplianceViolation> {
+        self.registry.compliance_violations()
+    }
+
+    /// Check that the server is working, instead of letting a test hang or
+    /// see refused connections without a hint.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first failure recorded so far, e.g. a server task that
+    /// panicked, a port taken during [`restart`](Self::restart), or a mock
+    /// that panicked while answering (the client got a 500); see
+    /// [`server_errors`](Self::server_errors) for all of them.
+    pub async fn health(&self) -> Result<()> {
+        match self.registry.server_errors().into_iter().next() {
+            Some(error) => Err(Error::ServerFailure(error)),
+            None => Ok(()),
+        }
+    }
+
+    /// Get the panics of custom responders so far.
+    ///
+    /// A panicking [`Responder::custom`] closure is answered with a
+    /// `501 ActionFailed` fault, and also reported by
+    /// [`health`](Self::health).
+    pub async fn responder_panics(&self) -> Vec<ResponderPanic> {
+        self.registry.responder_panics()
+    }
+
+    /// Get every server failure recorded so far; see [`health`](Self::health).
+    pub async fn server_errors(&self) -> Vec<String> {
+        self.registry.server_errors()
+    }
+
+    /// Panic if any client request violated a UPnP rule, listing the
+    /// violations.
+    pub async fn assert_compliant(&self) {
+        let violations = self.registry.compliance_violations();
+        if !violations.is_empty() {
+            let list: Vec<String> = violations.iter().map(|v| format!("  {v}")).collect();
+            panic!("client sent non-compliant requests:\n{}", list.join("\n"));
+        }
+    }
+
+    /// Wait for `window` and panic if any HTTP request or SSDP search
+    /// arrives meanwhile, e.g. to check that a client stops polling after
+    /// unsubscribing or shutting down.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// client.shutdown().await;
+    /// server.assert_quiet_for(Duration::from_secs(2)).await;
+    /// ```
+    pub async fn assert_quiet_for(&self, window: Duration) {
+        let mut traffic = self.registry.watch_traffic();
+        if tokio::time::timeout(window, traffic.changed())
+            .await
+            .is_ok()
+        {
+            let request = traffic.borrow().clone().unwrap_or_default();
+            panic!("expected no traffic for {window:?}, but received {request}");
+        }
+    }
+
+    /// Report which known IGD actions the client exercised so far.
+    ///
+    /// Useful for tracking the protocol coverage of a client library's
+    /// integration suite.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let coverage = server.action_coverage().await;
+    /// println!("{coverage}");
+    /// assert!(coverage.missing.is_empty());
+    /// ```
+    pub async fn action_coverage(&self) -> ActionCoverage {
+        self.registry.metrics().action_coverage()
+    }
+
+    /// Dump all received SOAP and SSDP requests as a human-readable log.
+    ///
+    /// Each entry is timestamped relative to server start and shows the
+    /// request together with the responder that answered it, which makes
+    /// the output suitable for pasting into bug reports.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// println!("{}", server.dump_requests().await);
+    /// ```
+    pub async fn dump_requests(&self) -> String {
+        self.registry.dump_requests()
+    }
+
+    /// Shutdown the server.
+    pub fn shutdown(mut self) {
+        self.stop_listeners();
+    }
+
+    /// Stop the HTTP and SSDP servers and start them again on the same ports,
+    /// as a device whose network services restart would.
+    ///
+    /// Registered mocks, scenarios, recorded requests, the port mapping table
+    /// and event subscriptions are kept, so tests can check that clients
+    /// reconnect without re-declaring anything. Open connections are closed
+    /// once their in-flight requests are answered. Use
+    /// [`simulate_reboot`](Self::simulate_reboot) to also lose state.
+    ///
+    /// Listeners and sockets passed to the builder are replaced by ones bound
+    /// the default way on the same ports. Pending MX-delayed search answers
+    /// are dropped, and announcements start over as after startup.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a port was taken by someone else in the meantime.
+    pub async fn restart(&mut self) -> Result<()> {
+        self.stop_listeners();
+        let http_task = self.http_task.take();
+        let ssdp_task = self.ssdp_task.take();
+        // Wait for the sockets to be released
+        if let Some(task) = http_task {
+            let _ = task.await;
+        }
+        if let Some(task) = ssdp_task {
+            let _ = task.await;
+        }
+        tracing::info!(target: TRACE_TARGET, "restarting server");
+
+        if let Some(http_addr) = self.http_addr {
+            let listener = tokio::net::TcpListener::bind(http_addr)
+                .await
+                .inspect_err(|e| {
+                    self.registry
+                        .record_server_error(format!("HTTP server failed to restart: {e}"));
+                })?;
+            let (shutdown_tx, task) =
+                spawn_http_server(listener, self.registry.clone(), self.config.clone());
+            self.shutdown_tx = Some(shutdown_tx);
+            self.http_task = Some(task);
+        }
+        if let (Some(ssdp_addr), Some(location)) = (self.ssdp_addr, &self.ssdp_location) {
+            let socket = ssdp::create_multicast_socket(ssdp_addr.port(), &self.config)
+                .inspect_err(|e| {
+                    self.registry
+                        .record_server_error(format!("SSDP server failed to restart: {e}"));
+                })?;
+            let (_, task) = ssdp::start_ssdp_server(
+                socket.into(),
+                location.clone(),
+                self.registry.clone(),
+                self.config.clone(),
+            )
+            .await?;
+            self.ssdp_task = Some(task);
+            self.announce_task =
+                spawn_announcements(ssdp_addr, location, &self.registry, &self.config);
+        }
+        Ok(())
+    }
+
+    /// Signal the HTTP server to stop and abort the SSDP server.
+    fn stop_listeners(&mut self) {
+        if let Some(tx) = self.shutdown_tx.take() {
+            let _ = tx.send(());
+        }
+        if let Some(task) = &self.ssdp_task {
+            task.abort();
+        }
+        if let Some(task) = self.announce_task.take() {
+            task.abort();
+        }
+    }
+}
+
+impl Drop for MockIgdServer {
+    fn drop(&mut self) {
+        self.stop_listeners();
+    }
+}
+
+/// Builder for configuring a mock IGD server.
+#[derive(Default)]
+pub struct MockIgdServerBuilder {
     http_port: Option<u16>,
+    /// Pre-bound HTTP listener (e.g. a [`ReservedPort`]).
+    http_listener: Option<std::net::TcpListener>,
+    /// Pre-bound SSDP socket.
+    ssdp_socket: Option<std::net::UdpSocket>,
     enable_ssdp: bool,
     ssdp_port: Option<u16>,
+    /// Advertised LOCATION of a discovery-only server.
+    ssdp_location: Option<String>,
+    /// Registry of another server to serve instead of a new one.
+    shared_registry: Option<Arc<MockRegistry>>,
+    config: ServerConfig,
 }
 
 impl MockIgdServerBuilder {
@@ -165,6 +1315,72 @@ impl MockIgdServerBuilder {
         self
     }
 
+    /// Serve HTTP on a port reserved with [`MockIgdServer::reserve_port`],
+    /// instead of binding a new one. Takes precedence over
+    /// [`http_port`](Self::http_port).
+    pub fn with_reserved_port(mut self, port: ReservedPort) -> Self {
+        self.http_listener = Some(port.listener);
+        self
+    }
+
+    /// Serve HTTP on a listener configured by the caller (e.g. with socket2
+    /// options, `SO_BINDTODEVICE` or in another network namespace), instead
+    /// of binding 127.0.0.1. Takes precedence over
+    /// [`http_port`](Self::http_port).
+    ///
+    /// Server URLs use the listener's address, with an unspecified IP
+    /// replaced by 127.0.0.1.
+    pub fn with_listener(mut self, listener: std::net::TcpListener) -> Self {
+        self.http_listener = Some(listener);
+        self
+    }
+
+    /// Serve the mocks, state and request history of `server` instead of
+    /// a fresh set, e.g. to listen on IPv4 and IPv6 with mocks defined
+    /// once.
+    ///
+    /// Mocks registered through either server apply to both, and each
+    /// server's verification methods see the requests of both. Both
+    /// describe the same device. Builder options that set up the shared
+    /// state (the port mapping table, link rates, seed, ...) are taken from
+    /// `server`, and [`start`](Self::start) fails if the seed, UDN, link
+    /// rates or external IP are set here; listener and protocol options
+    /// apply to the new server.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let v4 = MockIgdServer::start().await?;
+    /// let v6 = MockIgdServer::builder()
+    ///     .with_listener(std::net::TcpListener::bind("[::1]:0")?)
+    ///     .with_shared_registry(&v4)
+    ///     .start()
+    ///     .await?;
+    /// v4.mock(Action::GetExternalIPAddress, Responder::success()).await;
+    /// ```
+    pub fn with_shared_registry(mut self, server: &MockIgdServer) -> Self {
+        self.shared_registry = Some(server.registry.clone());
+        self.config.udns = server.config.udns.clone();
+        self.config.second_wan_ip = server.config.second_wan_ip;
+        self
+    }
+
+    /// Answer SSDP on a socket configured by the caller, instead of binding
+    /// one. Enables SSDP and takes precedence over
+    /// [`ssdp_port`](Self::ssdp_port).
+    ///
+    /// The socket is used as is: joining the multicast group and the
+    /// [`ssdp_interface`](Self::ssdp_interface),
+    /// [`ssdp_multicast_ttl`](Self::ssdp_multicast_ttl) and
+    /// [`ssdp_multicast_loop`](Self::ssdp_multicast_loop) options are up to
+    /// the caller, and [`start`](Self::start) fails if they are set on the
+    /// builder too.
+    pub fn with_ssdp_socket(mut self, socket: std::net::UdpSocket) -> Self {
+        self.ssdp_socket = Some(socket);
+        self.enable_ssdp = true;
+        self
+    }
+
     /// Enable SSDP discovery responses.
     pub fn with_ssdp(mut self) -> Self {
         self.enable_ssdp = true;
@@ -178,40 +1394,1018 @@ impl MockIgdServerBuilder {
         self
     }
 
-    /// Start the server with the configured options.
-    pub async fn start(self) -> Result<MockIgdServer> {
-        let registry = Arc::new(MockRegistry::new());
-        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    /// Start only the SSDP responder, advertising `location` (e.g. a real
+    /// device or another mock) as the description URL.
+    ///
+    /// No HTTP server is started, so discovery tests don't pay for it and
+    /// [`MockIgdServer::http_addr`] and the URL accessors panic, and HTTP
+    /// listener options are rejected. Failing to start the SSDP responder is
+    /// an error in this mode.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::builder()
+    ///     .ssdp_only("http://192.168.1.1:5000/rootDesc.xml")
+    ///     .ssdp_port(0)
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn ssdp_only(mut self, location: impl Into<String>) -> Self {
+        self.ssdp_location = Some(location.into());
+        self.enable_ssdp = true;
+        self
+    }
+
+    /// Join the SSDP multicast group on the interface with this address and
+    /// send multicast traffic from it, instead of letting the OS choose.
+    ///
+    /// Useful on machines with several NICs or VPNs. Enables SSDP.
+    pub fn ssdp_interface(mut self, interface: Ipv4Addr) -> Self {
+        self.config.ssdp_interface = Some(interface);
+        self.enable_ssdp = true;
+        self
+    }
+
+    /// Set the TTL of multicast SSDP traffic, e.g. above 1 for test rigs
+    /// spanning several hosts. Enables SSDP.
+    pub fn ssdp_multicast_ttl(mut self, ttl: u32) -> Self {
+        self.config.ssdp_multicast_ttl = Some(ttl);
+        self.enable_ssdp = true;
+        self
+    }
+
+    /// Set whether multicast SSDP traffic is looped back to the local host,
+    /// instead of relying on the OS default. Enables SSDP.
+    pub fn ssdp_multicast_loop(mut self, enabled: bool) -> Self {
+        self.config.ssdp_multicast_loop = Some(enabled);
+        self.enable_ssdp = true;
+        self
+    }
+
+    /// Delay each M-SEARCH response by a random time up to the request's MX
+    /// value (capped at 5 seconds), as the UPnP spec asks devices to.
+    ///
+    /// Requests without a valid MX are answered immediately. Enables SSDP.
+    pub fn with_ssdp_mx_delay(mut self) -> Self {
+        self.config.ssdp_mx_delay = true;
+        self.enable_ssdp = true;
+        self
+    }
+
+    /// Send the `USN` header of SSDP responses and announcements in the given
+    /// format. Enables SSDP.
+    pub fn with_usn_format(mut self, format: UsnFormat) -> Self {
+        self.config.usn_format = format;
+        self.enable_ssdp = true;
+        self
+    }
+
+    /// Answer M-SEARCH requests for these search targets only, instead of
+    /// `ssdp:all`, `upnp:rootdevice` and the InternetGatewayDevice,
+    /// WANIPConnection and WANPPPConnection URNs. A URN without a version
+    /// matches every version. Enables SSDP.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// // Only answer clients searching for an IGD:2
+    /// let server = MockIgdServer::builder()
+    ///     .with_ssdp_search_targets(["urn:schemas-upnp-org:device:InternetGatewayDevice:2"])
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_ssdp_search_targets(
+        mut self,
+        targets: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.config.ssdp_search_targets = Some(targets.into_iter().map(Into::into).collect());
+        self.enable_ssdp = true;
+        self
+    }
+
+    /// Only answer M-SEARCH requests sent from `source`, e.g. the test
+    /// client's address; may be called several times. Enables SSDP.
+    ///
+    /// Keeps a mock running on a real LAN from being discovered by other
+    /// devices, which would otherwise pick it over the real router. SSDP
+    /// datagrams from other sources, searches and `NOTIFY`s alike, are
+    /// ignored without being recorded. Announcements are still multicast, so
+    /// avoid them on a shared network.
+    ///
+    /// This is an SSDP-only [`ClientFilter::allow`], applied on top of
+    /// [`with_client_filter`](Self::with_client_filter).
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::builder()
+    ///     .with_quiet_ssdp("127.0.0.1".parse()?)
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_quiet_ssdp(mut self, source: IpAddr) -> Self {
+        self.config
+            .ssdp_client_filter
+            .get_or_insert_with(|| ClientFilter::allow([]))
+            .admit(source);
+        self.enable_ssdp = true;
+        self
+    }
+
+    /// Send M-SEARCH responses from a new socket on an ephemeral port
+    /// instead of the SSDP port, as some routers do, to catch clients that
+    /// wrongly only accept responses from port 1900. Enables SSDP.
+    pub fn with_ssdp_ephemeral_reply_port(mut self) -> Self {
+        self.config.ssdp_ephemeral_reply_port = true;
+        self.enable_ssdp = true;
+        self
+    }
+
+    /// Send `CACHE-CONTROL: max-age=<seconds>` in SSDP responses and
+    /// announcements, instead of 1800. Enables SSDP.
+    pub fn with_ssdp_max_age(mut self, seconds: u32) -> Self {
+        self.config.ssdp_max_age = Some(seconds);
+        self.enable_ssdp = true;
+        self
+    }
+
+    /// Announce the device with a short `max-age` a few times after startup,
+    /// then stop, so clients' device expiry can be tested quickly.
+    ///
+    /// `ssdp:alive` is multicast `announcements` times, every half
+    /// `max_age` seconds; the last one lapses `max_age` seconds after it was
+    /// sent, at which point clients should consider the gateway gone.
+    /// Searches are still answered. Enables SSDP.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// // Announced at 0s and 1s; expired from 3s
+    /// let server = MockIgdServer::builder()
+    ///     .with_expiring_announcements(2, 2)
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_expiring_announcements(mut self, max_age: u32, announcements: u32) -> Self {
+        self.config.ssdp_max_age = Some(max_age);
+        self.config.ssdp_announcements = announcements;
+        self.enable_ssdp = true;
+        self
+    }
+
+    /// Use `udn` as the root device's UDN instead of a random one, e.g. the
+    /// `uuid:upnp-InternetGatewayDevice-1_0-<MAC>` names some firmware
+    /// derives from the MAC address.
+    pub fn with_udn(mut self, udn: impl Into<String>) -> Self {
+        self.config.root_udn = Some(udn.into());
+        self
+    }
+
+    /// Run `interceptor` on every SOAP request before it is answered.
+    ///
+    /// The hook receives the request and the response the mocks planned
+    /// (`None` if nothing matched, which is answered with 401 Invalid
+    /// Action) and returns the response to send instead: the planned one to
+    /// merely observe, a different one to replace it, or `None` to answer
+    /// as if nothing matched. Requests are recorded and mocks count as hit
+    /// before the hook runs.
+    ///
+    /// An escape hatch for behaviors the mock API doesn't cover yet.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::builder()
+    ///     .with_interceptor(|request, planned| {
+    ///         if request.argument("NewExternalPort") == Some("22") {
+    ///             return Some(ResponseBody::SoapFault {
+    ///                 code: 718,
+    ///                 description: "ConflictInMappingEntry".to_string(),
+    ///             });
+    ///         }
+    ///         planned
+    ///     })
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_interceptor<F>(mut self, interceptor: F) -> Self
+    where
+        F: Fn(&SoapRequest, Option<ResponseBody>) -> Option<ResponseBody> + Send + Sync + 'static,
+    {
+        self.config.interceptor = Some(Interceptor(Arc::new(interceptor)));
+        self
+    }
+
+    /// Seed the generator behind every randomized behavior (UDNs, MX delays),
+    /// so a run can be reproduced.
+    ///
+    /// Without a seed, a random one is picked; see [`MockIgdServer::seed`].
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.config.seed = Some(seed);
+        self
+    }
+
+    /// Enable verbose wire-level logging.
+    ///
+    /// Raw SOAP requests, responses and SSDP datagrams are emitted as
+    /// `DEBUG` events under the `mock_igd::wire` tracing target.
+    pub fn with_wire_logging(mut self) -> Self {
+        self.config.wire_logging = true;
+        self
+    }
+
+    /// Limit the number of SOAP requests handled concurrently.
+    ///
+    /// Embedded routers often serve a single SOAP request at a time; requests
+    /// beyond the limit wait until a slot frees up. Default: unlimited.
+    pub fn with_max_concurrent_requests(mut self, n: usize) -> Self {
+        self.config.max_concurrent_requests = Some(n);
+        self
+    }
+
+    /// Throttle clients that send more than `requests_per_sec` SOAP requests
+    /// per second.
+    ///
+    /// Requests over the limit are answered with `503 Service Unavailable`
+    /// and a `Retry-After` header, like routers that throttle chatty UPnP
+    /// clients. Clients are told apart by IP address. Default: unlimited.
+    pub fn with_rate_limit(mut self, requests_per_sec: u32) -> Self {
+        self.config.rate_limit = Some(requests_per_sec);
+        self
+    }
+
+    /// Only talk to the clients `filter` admits, over both HTTP and SSDP,
+    /// e.g. to scope a mock in a shared lab to the test hosts.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::builder()
+    ///     .with_ssdp()
+    ///     .with_client_filter(ClientFilter::deny(["10.0.0.7".parse()?]))
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_client_filter(mut self, filter: ClientFilter) -> Self {
+        self.config.client_filter = Some(filter);
+        self
+    }
+
+    /// Simulate a router with a badly set clock: HTTP `Date` headers and
+    /// GENA subscription `TIMEOUT`s are skewed as `skew` says, for clients
+    /// computing lease and renewal times from the gateway's timestamps.
+    /// SSDP messages carry no `DATE` header and are left as they are.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::builder()
+    ///     .with_clock_skew(ClockSkew::ahead(Duration::from_secs(86_400)))
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_clock_skew(mut self, skew: ClockSkew) -> Self {
+        self.config.clock_skew = Some(skew);
+        self
+    }
+
+    /// Control how long HTTP connections are kept open.
+    ///
+    /// By default connections stay open until the client closes them.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// // Behave like routers that close every connection
+    /// let server = MockIgdServer::builder()
+    ///     .with_keep_alive(KeepAlive::close())
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_keep_alive(mut self, keep_alive: KeepAlive) -> Self {
+        self.config.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// Reject SOAP requests whose body exceeds `bytes` with
+    /// `413 Payload Too Large`.
+    ///
+    /// Mimics the tiny HTTP stacks of embedded routers. Default: axum's
+    /// built-in limit of 2 MB.
+    pub fn with_max_request_body_size(mut self, bytes: usize) -> Self {
+        self.config.max_request_body_size = Some(bytes);
+        self
+    }
+
+    /// Require HTTP Basic auth on the control URLs.
+    ///
+    /// SOAP requests without matching credentials are answered with
+    /// `401 Unauthorized` and a `WWW-Authenticate` challenge, like vendor
+    /// firmwares that protect their control URLs. Description documents
+    /// stay public.
+    pub fn with_basic_auth(mut self, user: impl Into<String>, pass: impl Into<String>) -> Self {
+        self.config.basic_auth = Some((user.into(), pass.into()));
+        self
+    }
+
+    /// Answer `/rootDesc.xml` with a redirect to the canonical description URL.
+    ///
+    /// Some routers redirect their advertised description URL, and client
+    /// HTTP stacks differ in whether they follow redirects.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::builder()
+    ///     .with_description_redirect(DescriptionRedirect::Found("/desc/igd.xml".into()))
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_description_redirect(mut self, redirect: DescriptionRedirect) -> Self {
+        self.config.description_redirect = Some(redirect);
+        self
+    }
+
+    /// Answer `/rootDesc.xml` with 404, as if the description vanished
+    /// after discovery. Control URLs keep working.
+    pub fn without_description(mut self) -> Self {
+        self.config.disable_description = true;
+        self
+    }
+
+    /// Answer SCPD fetches with 404, for clients that cache service
+    /// descriptions or must cope without them.
+    pub fn without_scpds(mut self) -> Self {
+        self.config.disable_scpds = true;
+        self
+    }
+
+    /// Delay answers to `/rootDesc.xml` by `delay`.
+    ///
+    /// Only the description fetch is slowed down, so discovery-phase
+    /// timeouts can be tested without affecting later SOAP calls.
+    pub fn with_description_delay(mut self, delay: Duration) -> Self {
+        self.config.description_delay = Some(delay);
+        self
+    }
+
+    /// Delay answers to SCPD fetches (`/WANIPCn.xml`, `/WANCommonIFC1.xml`)
+    /// by `delay`.
+    pub fn with_scpd_delay(mut self, delay: Duration) -> Self {
+        self.config.scpd_delay = Some(delay);
+        self
+    }
+
+    /// Pad the device description with `devices` filler embedded devices,
+    /// each with one service.
+    ///
+    /// Produces pathologically large descriptions for testing the size and
+    /// memory limits of client description parsers. The real IGD devices
+    /// come first, so discovery still works.
+    pub fn with_description_padding(mut self, devices: usize) -> Self {
+        self.config.description_padding = devices;
+        self
+    }
 
-        // Start HTTP server
-        let http_addr = format!("127.0.0.1:{}", self.http_port.unwrap_or(0));
-        let listener = tokio::net::TcpListener::bind(&http_addr).await?;
-        let http_addr = listener.local_addr()?;
+    /// Serve descriptions, SCPDs and SOAP responses in the given character
+    /// encoding (UTF-8 with BOM, UTF-16, ISO-8859-1). Default: plain UTF-8.
+    pub fn with_response_encoding(mut self, encoding: ResponseEncoding) -> Self {
+        self.config.response_encoding = encoding;
+        self
+    }
 
-        let http_registry = registry.clone();
-        tokio::spawn(async move {
-            http::run_http_server(listener, http_registry, shutdown_rx).await;
-        });
+    /// Record all traffic but answer nothing, to see what a client attempts
+    /// when no gateway answers (timeouts, retries, fallbacks).
+    ///
+    /// SOAP requests are recorded and held open until the client gives up
+    /// or the server stops; M-SEARCH requests are recorded unanswered. Use
+    /// [`with_observer_allowed`](Self::with_observer_allowed) to answer some
+    /// of them anyway.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::builder().with_observer_mode().start().await?;
+    /// let _ = client.add_port_mapping(&server.control_url(), ...).await;
+    /// assert_eq!(server.received_requests().await.len(), 3); // with retries
+    /// ```
+    pub fn with_observer_mode(mut self) -> Self {
+        self.config.observer.get_or_insert_with(Vec::new);
+        self
+    }
+
+    /// In observer mode, answer requests for the SOAP action `name`, or
+    /// M-SEARCH requests if `name` is `"M-SEARCH"`, as usual. Enables
+    /// observer mode.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// // Discovery works, but the gateway never answers port mapping requests
+    /// let server = MockIgdServer::builder()
+    ///     .with_ssdp()
+    ///     .with_observer_allowed("M-SEARCH")
+    ///     .with_observer_allowed("GetExternalIPAddress")
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_observer_allowed(mut self, name: impl Into<String>) -> Self {
+        self.config
+            .observer
+            .get_or_insert_with(Vec::new)
+            .push(name.into());
+        self
+    }
+
+    /// Check every client request against UPnP rules (quoted SOAPAction,
+    /// required arguments, argument order, boolean values, and the MAN, MX
+    /// and HOST headers of M-SEARCH requests).
+    ///
+    /// By default violations are logged as warnings and collected for
+    /// [`MockIgdServer::compliance_violations`]; requests are still answered.
+    /// Use [`with_compliance_rule`](Self::with_compliance_rule) to change
+    /// how individual rules are handled.
+    pub fn with_compliance_checks(mut self) -> Self {
+        self.config.compliance_checks = true;
+        self
+    }
+
+    /// Set how violations of `rule` are handled, enabling compliance checks.
+    ///
+    /// [`ComplianceLevel::Reject`] answers offending requests with UPnP
+    /// error 402 (`Invalid Args`) without consulting the mocks, reproducing
+    /// strict gateways.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::builder()
+    ///     .with_compliance_rule(ComplianceRule::QuotedSoapAction, ComplianceLevel::Reject)
+    ///     .with_compliance_rule(ComplianceRule::ArgumentOrder, ComplianceLevel::Ignore)
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_compliance_rule(mut self, rule: ComplianceRule, level: ComplianceLevel) -> Self {
+        self.config.compliance_checks = true;
+        self.config.compliance_levels.insert(rule, level);
+        self
+    }
+
+    /// Keep port mappings in an in-memory table, like a real gateway.
+    ///
+    /// `AddPortMapping`, `DeletePortMapping`, `GetSpecificPortMappingEntry`
+    /// and `GetGenericPortMappingEntry` requests that no mock matches are
+    /// answered from the table, with the usual UPnP errors (713, 714, 716,
+    /// 718). Mocks still take precedence, so failures can be injected on
+    /// top.
+    pub fn with_port_mapping_table(mut self) -> Self {
+        self.config.port_mapping_table = true;
+        self
+    }
+
+    /// Set how strictly the `CALLBACK` header of event subscriptions is
+    /// checked (default: [`CallbackValidation::Lenient`]).
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// // Answer callbacks outside the LAN with 412 Precondition Failed
+    /// let server = MockIgdServer::builder()
+    ///     .with_callback_validation(CallbackValidation::Strict)
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_callback_validation(mut self, validation: CallbackValidation) -> Self {
+        self.config.callback_validation = validation;
+        self
+    }
+
+    /// Inject an error into the `SEQ` numbers of the events sent to each
+    /// subscriber; may be called several times.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// // SEQ 0, 1, 1, 2, 4, ...
+    /// let server = MockIgdServer::builder()
+    ///     .with_port_mapping_table()
+    ///     .with_event_seq_fault(EventSeqFault::DuplicateAt(2))
+    ///     .with_event_seq_fault(EventSeqFault::SkipAt(4))
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_event_seq_fault(mut self, fault: EventSeqFault) -> Self {
+        self.config.event_seq_faults.push(fault);
+        self
+    }
+
+    /// Moderate an evented variable: send it to each subscriber at most once
+    /// per `min_interval`, coalescing the changes in between into one event
+    /// carrying the latest value (UPnP Device Architecture 4.3).
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::builder()
+    ///     .with_port_mapping_table()
+    ///     .with_event_moderation("PortMappingNumberOfEntries", Duration::from_millis(200))
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_event_moderation(
+        mut self,
+        variable: impl Into<String>,
+        min_interval: Duration,
+    ) -> Self {
+        self.config
+            .event_moderation
+            .push((variable.into(), min_interval));
+        self
+    }
+
+    /// Simulate a link with the given upstream and downstream rates in
+    /// bits per second.
+    ///
+    /// `GetCommonLinkProperties` requests that no mock matches report these
+    /// as `NewLayer1UpstreamMaxBitRate` / `NewLayer1DownstreamMaxBitRate`,
+    /// so clients cross-checking the rates against observed throughput see
+    /// a consistent gateway. Use the rates any bandwidth throttling in the
+    /// test applies.
+    pub fn with_link_rates(mut self, upstream: u32, downstream: u32) -> Self {
+        self.config.link_rates = Some((upstream, downstream));
+        self
+    }
+
+    /// Answer `GetStatusInfo` requests that no mock matches as a connected
+    /// gateway, reporting its [`uptime`](MockIgdServer::uptime) as
+    /// `NewUptime`, so the uptime clients see drops with every
+    /// [`simulate_reboot`](MockIgdServer::simulate_reboot).
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::builder()
+    ///     .with_status_info()
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_status_info(mut self) -> Self {
+        self.config.status_info = true;
+        self
+    }
+
+    /// Simulate a multi-homed gateway with a second WANDevice reporting
+    /// `external_ip`.
+    ///
+    /// Its WANIPConnection service ([`MockIgdServer::second_wan_control_url`])
+    /// has a port mapping table of its own when the table is enabled, and
+    /// its own event subscriptions. Mocks apply to both WANDevices; use
+    /// [`ReceivedRequest::wan_device`] to tell which one a client picked.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::builder()
+    ///     .with_port_mapping_table()
+    ///     .with_external_ip("203.0.113.1".parse()?)
+    ///     .with_second_wan_device("198.51.100.7".parse()?)
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_second_wan_device(mut self, external_ip: IpAddr) -> Self {
+        self.config.second_wan_ip = Some(external_ip);
+        self
+    }
+
+    /// Advertise a WANPPPConnection service next to WANIPConnection, as
+    /// routers with a PPPoE uplink do. Its control URL is
+    /// [`MockIgdServer::ppp_control_url`].
+    pub fn with_wan_ppp_connection(mut self) -> Self {
+        self.config.wan_ppp_connection = true;
+        self
+    }
+
+    /// Report `ip` as the external address.
+    ///
+    /// `GetExternalIPAddress` requests that no mock matches get this address
+    /// from both WANIPConnection and WANPPPConnection, so clients probing
+    /// both services see one gateway. Change it later with
+    /// [`MockIgdServer::set_external_ip`].
+    pub fn with_external_ip(mut self, ip: IpAddr) -> Self {
+        self.config.external_ip = Some(ip);
+        self
+    }
+
+    /// Make WANPPPConnection report `ip` instead of the shared external
+    /// address, for testing clients against gateways whose services
+    /// disagree.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::builder()
+    ///     .with_wan_ppp_connection()
+    ///     .with_external_ip("203.0.113.1".parse()?)
+    ///     .with_ppp_external_ip("198.51.100.7".parse()?)
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_ppp_external_ip(mut self, ip: IpAddr) -> Self {
+        self.config.ppp_external_ip = Some(ip);
+        self
+    }
+
+    /// Answer `GetGenericPortMappingEntry` requests that nothing matches
+    /// with 713 "SpecifiedArrayIndexInvalid" instead of 401 "Invalid Action".
+    /// The fault is recorded as the request's response.
+    ///
+    /// Clients enumerate mappings until they get 713, so the default 401
+    /// breaks their loops in confusing ways. With this, only the entries a
+    /// test cares about need mocks.
+    pub fn with_unmatched_index_invalid(mut self) -> Self {
+        self.config.unmatched_index_invalid = true;
+        self
+    }
+
+    /// Panic on SOAP requests that no mock or fallback answers, naming the
+    /// mock that came closest to matching and the fields that differed.
+    /// Requests answered by the port mapping table or other fallbacks, or
+    /// with 713 through
+    /// [`with_unmatched_index_invalid`](Self::with_unmatched_index_invalid),
+    /// don't panic.
+    ///
+    /// Like any panic while answering, the client gets a 500 and the
+    /// message is reported by [`MockIgdServer::health`]. The nearest miss
+    /// is recorded in [`ReceivedRequest::near_miss`] either way.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::builder().with_strict_matching().start().await?;
+    /// // ... run the client ...
+    /// server.health().await?; // Err: no mock matched AddPortMapping request;
+    ///                         // nearest miss: mock #1: external_port: expected 8080, got 8081
+    /// ```
+    pub fn with_strict_matching(mut self) -> Self {
+        self.config.strict_matching = true;
+        self
+    }
+
+    /// Echo TCP connections and UDP datagrams sent to the internal client
+    /// and port of each mapping in the port mapping table, enabling it.
+    ///
+    /// Stands in for the service behind a mapping, so a "map a port, then
+    /// connect through it" test needs nothing but the mock. The internal
+    /// client must be an address of this machine, e.g. `127.0.0.1`;
+    /// endpoints stop when their mappings are deleted or expire.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::builder().with_echo_endpoints().start().await?;
+    /// client.add_port_mapping(8080, "127.0.0.1:9000").await?;
+    /// let mut stream = TcpStream::connect("127.0.0.1:9000").await?;
+    /// stream.write_all(b"ping").await?; // reads back "ping"
+    /// ```
+    pub fn with_echo_endpoints(mut self) -> Self {
+        self.config.port_mapping_table = true;
+        self.config.echo_endpoints = true;
+        self
+    }
+
+    /// Forward TCP connections and UDP datagrams sent to `127.0.0.1` on the
+    /// external port of each mapping in the port mapping table to its
+    /// internal client and port, approximating the gateway's NAT, and enable
+    /// the table.
+    ///
+    /// Only enabled mappings forward traffic, and none do with
+    /// [`MappingQuirk::NotForwarded`]. A mapping whose external port is
+    /// taken on this machine (e.g. because it equals the internal port of
+    /// a `127.0.0.1` mapping) is not forwarded. Combine with
+    /// [`with_echo_endpoints`](Self::with_echo_endpoints) to test the whole
+    /// "map, then traverse" flow without a service of your own.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::builder().with_nat_forwarding().start().await?;
+    /// client.add_port_mapping(18080, "127.0.0.1:9000").await?;
+    /// // Reaches the client's service on 127.0.0.1:9000
+    /// let stream = TcpStream::connect("127.0.0.1:18080").await?;
+    /// ```
+    pub fn with_nat_forwarding(mut self) -> Self {
+        self.config.port_mapping_table = true;
+        self.config.nat_forwarding = true;
+        self
+    }
+
+    /// Reproduce a firmware quirk in the port mapping table, enabling it.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = MockIgdServer::builder()
+    ///     .with_mapping_quirk(MappingQuirk::IgnoreLeaseDuration)
+    ///     .start()
+    ///     .await?;
+    /// ```
+    pub fn with_mapping_quirk(mut self, quirk: MappingQuirk) -> Self {
+        self.config.port_mapping_table = true;
+        self.config.mapping_quirks.push(quirk);
+        self
+    }
+
+    /// Keep the port mapping table across
+    /// [`MockIgdServer::simulate_reboot`], like routers that persist UPnP
+    /// mappings. Enables the table.
+    pub fn with_persistent_mappings(mut self) -> Self {
+        self.config.port_mapping_table = true;
+        self.config.persistent_mappings = true;
+        self
+    }
+
+    /// Check success responders for missing required fields on registration.
+    pub fn with_response_lint(mut self, lint: ResponseLint) -> Self {
+        self.config.response_lint = lint;
+        self
+    }
+
+    /// Create the registry holding the server's mocks and state.
+    fn build_registry(&mut self) -> MockRegistry {
+        let mut registry = MockRegistry::new();
+        if let Some(seed) = self.config.seed {
+            registry = registry.with_seed(seed);
+        }
+        tracing::debug!(target: TRACE_TARGET, seed = registry.rng().seed(), "starting server");
+        let second_wan = self.config.second_wan_ip.is_some();
+        self.config.udns = DeviceUdns::generate(registry.rng(), second_wan);
+        if let Some(udn) = &self.config.root_udn {
+            self.config.udns.root = udn.clone();
+        }
+        if self.config.port_mapping_table {
+            registry = registry.with_port_mapping_table(self.config.mapping_quirks.clone());
+        }
+        if let Some(ip) = self.config.second_wan_ip {
+            let quirks = self
+                .config
+                .port_mapping_table
+                .then(|| self.config.mapping_quirks.clone());
+            registry = registry.with_second_wan(ip, quirks);
+        }
+        if let Some((upstream, downstream)) = self.config.link_rates {
+            registry = registry.with_link_rates(upstream, downstream);
+        }
+        if self.config.status_info {
+            registry = registry.with_status_info();
+        }
+        if self.config.unmatched_index_invalid {
+            registry = registry.with_unmatched_index_invalid();
+        }
+        if let Some(ip) = self.config.external_ip {
+            registry.set_external_ip(ip);
+        }
+        if let Some(ip) = self.config.ppp_external_ip {
+            registry.set_ppp_external_ip(ip);
+        }
+        for wan_device in 0..registry.wan_device_count() {
+            let events = registry.wan_events(wan_device);
+            events.set_seq_faults(self.config.event_seq_faults.clone());
+            for (variable, interval) in &self.config.event_moderation {
+                events.moderate(variable.clone(), *interval);
+            }
+        }
+        registry
+    }
+
+    /// Reject options that can't be used together or would hang the server.
+    fn validate(&self) -> Result<()> {
+        let invalid = |reason: &str| {
+            Err(Error::InvalidBuilderConfig {
+                reason: reason.to_string(),
+            })
+        };
+        let http_options = self.http_port.is_some() || self.http_listener.is_some();
+        if self.ssdp_location.is_some() && http_options {
+            return invalid(
+                "ssdp_only() starts no HTTP server; drop http_port(), with_listener() and \
+                 with_reserved_port(), or use with_ssdp() to serve both",
+            );
+        }
+        let config = &self.config;
+        if self.ssdp_socket.is_some()
+            && (config.ssdp_interface.is_some()
+                || config.ssdp_multicast_ttl.is_some()
+                || config.ssdp_multicast_loop.is_some())
+        {
+            return invalid(
+                "with_ssdp_socket() uses the socket as is; set the multicast interface, TTL and \
+                 loopback on the socket instead of with ssdp_interface(), ssdp_multicast_ttl() \
+                 and ssdp_multicast_loop()",
+            );
+        }
+        if self.shared_registry.is_some()
+            && (config.seed.is_some()
+                || config.root_udn.is_some()
+                || config.link_rates.is_some()
+                || config.status_info
+                || config.unmatched_index_invalid
+                || config.external_ip.is_some())
+        {
+            return invalid(
+                "with_shared_registry() serves the state of another server; set with_seed(), \
+                 with_udn(), with_link_rates(), with_status_info(), \
+                 with_unmatched_index_invalid() and with_external_ip() on that server instead",
+            );
+        }
+        if config.ppp_external_ip.is_some() && !config.wan_ppp_connection {
+            return invalid(
+                "with_ppp_external_ip() needs with_wan_ppp_connection() to serve WANPPPConnection",
+            );
+        }
+        if config.max_concurrent_requests == Some(0) {
+            return invalid("with_max_concurrent_requests(0) would never handle a request");
+        }
+        if config.ssdp_announcements > 0 && config.ssdp_max_age == Some(0) {
+            return invalid("with_expiring_announcements() needs a max_age of at least 1 second");
+        }
+        if let (Some(filter), Some(quiet)) = (&config.client_filter, &config.ssdp_client_filter)
+            && quiet
+                .allowed
+                .iter()
+                .flatten()
+                .any(|source| !filter.admits(*source))
+        {
+            return invalid(
+                "with_quiet_ssdp() names a source that with_client_filter() rejects, so it \
+                 would never be answered; admit it in the client filter",
+            );
+        }
+        Ok(())
+    }
+
+    /// Start the server with the configured options.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::InvalidBuilderConfig`] if options conflict, and
+    /// with [`Error::Bind`] if the HTTP listener can't be bound. Failing to
+    /// start SSDP ([`Error::SsdpBindFailed`], [`Error::MulticastJoinFailed`])
+    /// is only logged, unless [`ssdp_only`](Self::ssdp_only) is used.
+    pub async fn start(mut self) -> Result<MockIgdServer> {
+        self.validate()?;
+        let registry = match self.shared_registry.take() {
+            Some(registry) => registry,
+            None => Arc::new(self.build_registry()),
+        };
+
+        // Start HTTP server, unless only discovery is mocked
+        let (http_addr, http_server) = match &self.ssdp_location {
+            Some(_) => (None, None),
+            None => {
+                let listener = match self.http_listener.take() {
+                    Some(listener) => {
+                        listener.set_nonblocking(true)?;
+                        tokio::net::TcpListener::from_std(listener)?
+                    }
+                    None => {
+                        let http_addr = format!("127.0.0.1:{}", self.http_port.unwrap_or(0));
+                        tokio::net::TcpListener::bind(&http_addr).await?
+                    }
+                };
+                let http_addr = match listener.local_addr()? {
+                    SocketAddr::V4(addr) if addr.ip().is_unspecified() => {
+                        SocketAddr::from((Ipv4Addr::LOCALHOST, addr.port()))
+                    }
+                    addr => addr,
+                };
+                let server = spawn_http_server(listener, registry.clone(), self.config.clone());
+                (Some(http_addr), Some(server))
+            }
+        };
+        let (shutdown_tx, http_task) = http_server.unzip();
 
         // Start SSDP server if enabled
-        let ssdp_addr = if self.enable_ssdp {
-            let port = self.ssdp_port.unwrap_or(1900);
-            match ssdp::start_ssdp_server(http_addr, port, registry.clone()).await {
-                Ok(addr) => Some(addr),
-                Err(e) => {
-                    tracing::warn!("Failed to start SSDP server: {}", e);
-                    None
+        let ssdp_location = self
+            .enable_ssdp
+            .then(|| match (&self.ssdp_location, http_addr) {
+                (Some(location), _) => location.clone(),
+                (None, Some(http_addr)) => format!("http://{http_addr}/rootDesc.xml"),
+                (None, None) => unreachable!("HTTP runs unless a location is given"),
+            });
+        let (ssdp_addr, ssdp_task) = match &ssdp_location {
+            Some(location) => {
+                let port = self.ssdp_port.unwrap_or(1900);
+                let socket = self.ssdp_socket.take();
+                let server = async {
+                    let socket = match socket {
+                        Some(socket) => socket,
+                        None => ssdp::create_multicast_socket(port, &self.config)?.into(),
+                    };
+                    let (registry, config) = (registry.clone(), self.config.clone());
+                    ssdp::start_ssdp_server(socket, location.clone(), registry, config).await
+                }
+                .await;
+                match server {
+                    Ok((addr, task)) => (Some(addr), Some(task)),
+                    Err(e) if self.ssdp_location.is_some() => return Err(e),
+                    Err(e) => {
+                        tracing::warn!(target: TRACE_TARGET, "Failed to start SSDP server: {}", e);
+                        (None, None)
+                    }
                 }
             }
-        } else {
-            None
+            None => (None, None),
+        };
+        let announce_task = match (ssdp_addr, &ssdp_location) {
+            (Some(ssdp_addr), Some(location)) => {
+                spawn_announcements(ssdp_addr, location, &registry, &self.config)
+            }
+            _ => None,
         };
+        let mut mapping_tasks = Vec::new();
+        if self.config.echo_endpoints {
+            let task = tokio::spawn(forward::run_echo_endpoints(registry.clone()));
+            mapping_tasks.push(forward::AbortOnDrop(task));
+        }
+        if self.config.nat_forwarding {
+            let task = tokio::spawn(forward::run_nat(registry.clone()));
+            mapping_tasks.push(forward::AbortOnDrop(task));
+        }
 
         Ok(MockIgdServer {
             http_addr,
             ssdp_addr,
             registry,
-            shutdown_tx: Some(shutdown_tx),
+            config: self.config,
+            ssdp_location,
+            shutdown_tx,
+            http_task,
+            ssdp_task,
+            announce_task,
+            _mapping_tasks: mapping_tasks,
         })
     }
 }
+
+/// Start the configured `ssdp:alive` announcements, if any.
+fn spawn_announcements(
+    ssdp_addr: SocketAddr,
+    location: &str,
+    registry: &Arc<MockRegistry>,
+    config: &ServerConfig,
+) -> Option<JoinHandle<()>> {
+    (config.ssdp_announcements > 0).then(|| {
+        tokio::spawn(ssdp::announce_periodically(
+            config.ssdp_announcements,
+            ssdp_addr.port(),
+            location.to_string(),
+            registry.clone(),
+            config.clone(),
+        ))
+    })
+}
+
+/// Run the HTTP server on `listener` until the returned sender fires.
+fn spawn_http_server(
+    listener: tokio::net::TcpListener,
+    registry: Arc<MockRegistry>,
+    config: ServerConfig,
+) -> (oneshot::Sender<()>, JoinHandle<()>) {
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let server = http::run_http_server(listener, registry.clone(), config, shutdown_rx);
+    let task = tokio::spawn(async move {
+        if let Err(panic) = CatchUnwind(Box::pin(server)).await {
+            registry.record_server_error(format!(
+                "HTTP server panicked: {}",
+                panic_message(panic.as_ref())
+            ));
+        }
+    });
+    (shutdown_tx, task)
+}
+
+/// Future adapter turning a panic of the wrapped future into an error.
+pub(crate) struct CatchUnwind<F>(pub(crate) Pin<Box<F>>);
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = std::thread::Result<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = self.0.as_mut();
+        match std::panic::catch_unwind(AssertUnwindSafe(|| inner.poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(panic) => Poll::Ready(Err(panic)),
+        }
+    }
+}
+
+/// The message a panic was raised with.
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "non-string panic payload".to_string(),
+    }
+}
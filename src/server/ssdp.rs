@@ -1,23 +1,97 @@
 //! SSDP (Simple Service Discovery Protocol) server implementation.
 
-use crate::mock::{MockRegistry, ReceivedSsdpRequest};
-use crate::Result;
+use super::{CatchUnwind, ServerConfig, TRACE_TARGET, WIRE_TRACE_TARGET, panic_message};
+use crate::compliance::{self, ComplianceLevel};
+use crate::mock::{
+    MockRegistry, ReceivedSsdpNotification, ReceivedSsdpRequest, SentSsdpNotification, SsdpHeaders,
+};
+use crate::responder::SsdpResponse;
+use crate::{Error, Result};
 use socket2::{Domain, Protocol, Socket, Type};
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::sync::Arc;
+use std::time::Duration;
+#[cfg(target_os = "linux")]
+use tokio::io::Interest;
 use tokio::net::UdpSocket;
+use tokio::task::{JoinHandle, JoinSet};
 
 /// SSDP multicast address.
 const SSDP_MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
 
-/// Start the SSDP server for device discovery.
+/// Largest MX honored when delaying responses, as in UPnP 1.1.
+const MAX_MX: u32 = 5;
+
+/// CACHE-CONTROL max-age, in seconds, unless configured otherwise.
+const DEFAULT_MAX_AGE: u32 = 1800;
+
+/// Search targets answered unless configured otherwise.
+const DEFAULT_SEARCH_TARGETS: &[&str] = &[
+    "ssdp:all",
+    "upnp:rootdevice",
+    "urn:schemas-upnp-org:device:InternetGatewayDevice",
+    "urn:schemas-upnp-org:service:WANIPConnection",
+    "urn:schemas-upnp-org:service:WANPPPConnection",
+];
+
+/// Search target sent in responses to `ssdp:all` searches.
+const IGD_SEARCH_TARGET: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+
+/// Format of the `USN` header in SSDP responses and announcements.
+///
+/// Some clients parse nonstandard variants, and reproducing interop bugs
+/// with a vendor's gateway may need its exact format.
+///
+/// # Example
+///
+/// ```ignore
+/// // USN: uuid:...::upnp:rootdevice, as some routers send whatever was
+/// // searched for
+/// let server = MockIgdServer::builder()
+///     .with_ssdp()
+///     .with_usn_format(UsnFormat::RootDevice)
+///     .start()
+///     .await?;
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum UsnFormat {
+    /// `<UDN>::<ST>`, or the UDN alone for the UDN itself, as the UPnP
+    /// Device Architecture specifies.
+    #[default]
+    Standard,
+    /// The UDN alone.
+    UdnOnly,
+    /// `<UDN>::upnp:rootdevice`, whatever the search target.
+    RootDevice,
+    /// A template in which `{udn}` and `{st}` are replaced by the UDN and
+    /// search target.
+    Custom(String),
+}
+
+impl UsnFormat {
+    /// The `USN` header value for `udn` answering with search target `st`.
+    fn format(&self, udn: &str, st: &str) -> String {
+        match self {
+            UsnFormat::Standard if st == udn => udn.to_string(),
+            UsnFormat::Standard => format!("{udn}::{st}"),
+            UsnFormat::UdnOnly => udn.to_string(),
+            UsnFormat::RootDevice => format!("{udn}::upnp:rootdevice"),
+            UsnFormat::Custom(template) => template.replace("{udn}", udn).replace("{st}", st),
+        }
+    }
+}
+
+/// Start the SSDP server for device discovery on `socket`; it runs until the
+/// returned task is aborted.
 pub async fn start_ssdp_server(
-    http_addr: SocketAddr,
-    port: u16,
+    socket: std::net::UdpSocket,
+    location: String,
     registry: Arc<MockRegistry>,
-) -> Result<SocketAddr> {
-    let socket = create_multicast_socket(port)?;
-    let socket = UdpSocket::from_std(socket.into())?;
+    config: ServerConfig,
+) -> Result<(SocketAddr, JoinHandle<()>)> {
+    socket.set_nonblocking(true)?;
+    report_destinations(&socket);
+    let socket = UdpSocket::from_std(socket)?;
     let local_addr = socket.local_addr()?;
 
     // The socket is bound to 0.0.0.0 (UNSPECIFIED), so `local_addr` returns an
@@ -31,15 +105,21 @@ pub async fn start_ssdp_server(
         other => other,
     };
 
-    tokio::spawn(async move {
-        run_ssdp_server(socket, http_addr, registry).await;
+    let server = run_ssdp_server(socket, location, registry.clone(), config);
+    let task = tokio::spawn(async move {
+        if let Err(panic) = CatchUnwind(Box::pin(server)).await {
+            registry.record_server_error(format!(
+                "SSDP server panicked: {}",
+                panic_message(panic.as_ref())
+            ));
+        }
     });
 
-    Ok(advertised_addr)
+    Ok((advertised_addr, task))
 }
 
 /// Create a UDP socket for SSDP multicast.
-fn create_multicast_socket(port: u16) -> Result<Socket> {
+pub fn create_multicast_socket(port: u16, config: &ServerConfig) -> Result<Socket> {
     let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
     socket.set_reuse_address(true)?;
 
@@ -47,59 +127,368 @@ fn create_multicast_socket(port: u16) -> Result<Socket> {
     socket.set_reuse_port(true)?;
 
     let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port);
-    socket.bind(&addr.into())?;
+    socket
+        .bind(&addr.into())
+        .map_err(|io| Error::SsdpBindFailed { port, io })?;
 
-    socket.join_multicast_v4(&SSDP_MULTICAST_ADDR, &Ipv4Addr::UNSPECIFIED)?;
+    let interface = config.ssdp_interface.unwrap_or(Ipv4Addr::UNSPECIFIED);
+    socket
+        .join_multicast_v4(&SSDP_MULTICAST_ADDR, &interface)
+        .map_err(|io| Error::MulticastJoinFailed { interface, io })?;
+    set_multicast_options(&socket, config)?;
     socket.set_nonblocking(true)?;
 
     Ok(socket)
 }
 
+/// Apply the configured outgoing multicast interface, TTL and loopback.
+fn set_multicast_options(socket: &Socket, config: &ServerConfig) -> Result<()> {
+    if let Some(interface) = config.ssdp_interface {
+        socket.set_multicast_if_v4(&interface)?;
+    }
+    if let Some(ttl) = config.ssdp_multicast_ttl {
+        socket.set_multicast_ttl_v4(ttl)?;
+    }
+    if let Some(enabled) = config.ssdp_multicast_loop {
+        socket.set_multicast_loop_v4(enabled)?;
+    }
+    Ok(())
+}
+
+/// Multicast `ssdp:alive` advertisements for the root device to the group
+/// on `port`, recording each one sent.
+pub(crate) async fn announce_alive(
+    port: u16,
+    location: &str,
+    registry: &MockRegistry,
+    config: &ServerConfig,
+) -> Result<()> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    set_multicast_options(&socket, config)?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0).into())?;
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from_std(socket.into())?;
+
+    let udn = &config.udns.root;
+    let max_age = config.ssdp_max_age.unwrap_or(DEFAULT_MAX_AGE);
+    for nt in ["upnp:rootdevice", udn, IGD_SEARCH_TARGET] {
+        let usn = config.usn_format.format(udn, nt);
+        let notification = format!(
+            "NOTIFY * HTTP/1.1\r\n\
+             HOST: {SSDP_MULTICAST_ADDR}:{port}\r\n\
+             CACHE-CONTROL: max-age={max_age}\r\n\
+             LOCATION: {location}\r\n\
+             NT: {nt}\r\n\
+             NTS: ssdp:alive\r\n\
+             SERVER: mock-igd/0.1 UPnP/1.0\r\n\
+             USN: {usn}\r\n\
+             BOOTID.UPNP.ORG: {}\r\n\
+             CONFIGID.UPNP.ORG: {}\r\n\
+             \r\n",
+            registry.boot_id(),
+            registry.config_id()
+        );
+        if config.wire_logging {
+            tracing::debug!(
+                target: WIRE_TRACE_TARGET,
+                datagram = %notification,
+                "sending SSDP NOTIFY"
+            );
+        }
+        socket
+            .send_to(notification.as_bytes(), (SSDP_MULTICAST_ADDR, port))
+            .await?;
+        registry.record_sent_ssdp_notification(SentSsdpNotification {
+            notification_type: nt.to_string(),
+            notification_subtype: "ssdp:alive".to_string(),
+            usn,
+            raw: notification,
+            timestamp: registry.start_time().elapsed(),
+        });
+    }
+    Ok(())
+}
+
+/// Announce the device `count` times, every half `max-age`, then fall
+/// silent so the announcements lapse.
+pub(crate) async fn announce_periodically(
+    count: u32,
+    port: u16,
+    location: String,
+    registry: Arc<MockRegistry>,
+    config: ServerConfig,
+) {
+    let max_age = config.ssdp_max_age.unwrap_or(DEFAULT_MAX_AGE);
+    let interval = Duration::from_secs(max_age.into()) / 2;
+    for i in 0..count {
+        if i > 0 {
+            tokio::time::sleep(interval).await;
+        }
+        if let Err(e) = announce_alive(port, &location, &registry, &config).await {
+            tracing::warn!(target: TRACE_TARGET, "Failed to send SSDP announcement: {}", e);
+        }
+    }
+    tracing::info!(target: TRACE_TARGET, "stopped SSDP announcements");
+}
+
 /// Run the SSDP server loop.
-async fn run_ssdp_server(socket: UdpSocket, http_addr: SocketAddr, registry: Arc<MockRegistry>) {
+async fn run_ssdp_server(
+    socket: UdpSocket,
+    location: String,
+    registry: Arc<MockRegistry>,
+    config: ServerConfig,
+) {
+    let socket = Arc::new(socket);
+    let config = Arc::new(config);
     let mut buf = [0u8; 2048];
+    // MX-delayed answers, cancelled along with the server
+    let mut delayed_answers = JoinSet::new();
 
     loop {
-        match socket.recv_from(&mut buf).await {
-            Ok((len, src)) => {
+        match recv_datagram(&socket, &mut buf).await {
+            Ok((len, src, destination)) => {
                 let request = String::from_utf8_lossy(&buf[..len]).to_string();
-                if is_msearch_request(&request) {
+                if config.wire_logging {
+                    tracing::debug!(
+                        target: WIRE_TRACE_TARGET,
+                        source = %src,
+                        datagram = %request,
+                        "received SSDP datagram"
+                    );
+                }
+                if !config.admits_ssdp_client(src.ip()) {
+                    tracing::debug!(
+                        target: TRACE_TARGET,
+                        source = %src,
+                        "ignored SSDP datagram from a rejected client"
+                    );
+                    continue;
+                }
+                if is_msearch_request(&request, &config) {
                     // Record the request
-                    let received = parse_ssdp_request(&request, src, registry.start_time());
-                    registry.record_ssdp_request(received).await;
+                    let mut received = parse_ssdp_request(&request, src, registry.start_time());
+                    received.destination = destination;
+                    let rejected =
+                        config.compliance_checks && reject_msearch(&received, &registry, &config);
+                    let observed = config
+                        .observer
+                        .as_ref()
+                        .is_some_and(|allowed| !allowed.iter().any(|name| name == "M-SEARCH"));
+                    received.answered = !rejected
+                        && !observed
+                        && registry.ssdp_response(&received) == SsdpResponse::Answer;
+                    let answered = received.answered;
+                    tracing::info!(
+                        target: TRACE_TARGET,
+                        source = %src,
+                        search_target = %received.search_target,
+                        mx = received.mx,
+                        answered,
+                        "received SSDP M-SEARCH"
+                    );
+                    let delay = match received.mx {
+                        Some(mx) if config.ssdp_mx_delay && mx > 0 => {
+                            let max = Duration::from_secs(mx.min(MAX_MX).into());
+                            registry.rng().duration_below(max)
+                        }
+                        _ => Duration::ZERO,
+                    };
+                    let search_target = received.search_target.clone();
+                    registry.record_ssdp_request(received);
+                    if !answered {
+                        continue;
+                    }
 
-                    if let Err(e) = send_msearch_response(&socket, src, http_addr).await {
-                        tracing::warn!("Failed to send M-SEARCH response: {}", e);
+                    let answer = {
+                        let socket = socket.clone();
+                        let location = location.clone();
+                        let registry = registry.clone();
+                        let config = config.clone();
+                        async move {
+                            answer_msearch(
+                                &socket,
+                                src,
+                                &search_target,
+                                &location,
+                                &registry,
+                                &config,
+                            )
+                            .await;
+                        }
+                    };
+                    if delay.is_zero() {
+                        answer.await;
+                    } else {
+                        while delayed_answers.try_join_next().is_some() {}
+                        delayed_answers.spawn(async move {
+                            tokio::time::sleep(delay).await;
+                            answer.await;
+                        });
                     }
+                } else if request.starts_with("NOTIFY") {
+                    let received = parse_ssdp_notification(&request, src, registry.start_time());
+                    tracing::debug!(
+                        target: TRACE_TARGET,
+                        source = %src,
+                        nt = %received.notification_type,
+                        nts = %received.notification_subtype,
+                        "recorded SSDP NOTIFY"
+                    );
+                    registry.record_ssdp_notification(received);
                 }
             }
             Err(e) => {
-                tracing::warn!("SSDP receive error: {}", e);
+                tracing::warn!(target: TRACE_TARGET, "SSDP receive error: {}", e);
             }
         }
     }
 }
 
+/// Ask the OS to report the destination address of datagrams received on
+/// `socket`, to tell multicast searches from unicast ones.
+///
+/// Only supported on Linux; elsewhere, and on sockets for which it fails,
+/// datagrams are received without their destination.
+fn report_destinations(socket: &std::net::UdpSocket) {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+
+        let enable: libc::c_int = 1;
+        // SAFETY: the option value is a live c_int of the given size
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_IP,
+                libc::IP_PKTINFO,
+                (&enable as *const libc::c_int).cast(),
+                size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            let e = std::io::Error::last_os_error();
+            tracing::debug!(target: TRACE_TARGET, "Failed to enable IP_PKTINFO: {}", e);
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = socket;
+}
+
+/// Receive a datagram with its source and, if reported, its destination
+/// address.
+async fn recv_datagram(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> std::io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+    #[cfg(target_os = "linux")]
+    {
+        socket
+            .async_io(Interest::READABLE, || recv_with_destination(socket, buf))
+            .await
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let (len, source) = socket.recv_from(buf).await?;
+        Ok((len, source, None))
+    }
+}
+
+/// Receive a datagram with `recvmsg`, reading its destination address from
+/// the `IP_PKTINFO` control message, if any.
+#[cfg(target_os = "linux")]
+fn recv_with_destination(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> std::io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: all-zero is a valid sockaddr_storage and msghdr
+    let mut source: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    // Room for an in_pktinfo control message, aligned for cmsghdr
+    let mut control = [0u64; 8];
+    msg.msg_name = (&mut source as *mut libc::sockaddr_storage).cast();
+    msg.msg_namelen = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = size_of_val(&control);
+
+    // SAFETY: msg points to live buffers of the sizes it declares
+    let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if len < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: recvmsg filled in msg_namelen bytes of the address
+    let source = unsafe { socket2::SockAddr::new(source, msg.msg_namelen) };
+    let source = source
+        .as_socket()
+        .ok_or_else(|| std::io::Error::other("datagram from a non-IP address"))?;
+
+    let mut destination = None;
+    // SAFETY: recvmsg filled in msg_controllen bytes of control messages,
+    // which the CMSG macros walk within those bounds
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::IPPROTO_IP && (*cmsg).cmsg_type == libc::IP_PKTINFO {
+                let info: libc::in_pktinfo = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast());
+                let addr = Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr));
+                destination = Some(IpAddr::V4(addr));
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    Ok((len as usize, source, destination))
+}
+
 /// Parse an SSDP M-SEARCH request into a structured format.
 fn parse_ssdp_request(
     request: &str,
     source: SocketAddr,
-    start_time: std::time::Instant,
+    start_time: tokio::time::Instant,
 ) -> ReceivedSsdpRequest {
-    let search_target = extract_header(request, "ST")
-        .unwrap_or_default();
-    let man = extract_header(request, "MAN")
-        .unwrap_or_default();
-    let mx = extract_header(request, "MX")
-        .and_then(|s| s.parse().ok());
+    let search_target = extract_header(request, "ST").unwrap_or_default();
+    let man = extract_header(request, "MAN").unwrap_or_default();
+    let mx = extract_header(request, "MX").and_then(|s| s.parse().ok());
+    let user_agent = extract_header(request, "USER-AGENT");
 
     ReceivedSsdpRequest {
         source,
+        destination: None,
         search_target,
         man,
         mx,
+        user_agent,
+        headers: parse_headers(request),
+        raw: request.to_string(),
+        timestamp: start_time.elapsed(),
+        answered: true,
+    }
+}
+
+/// Parse an SSDP NOTIFY advertisement into a structured format.
+fn parse_ssdp_notification(
+    request: &str,
+    source: SocketAddr,
+    start_time: tokio::time::Instant,
+) -> ReceivedSsdpNotification {
+    let headers = parse_headers(request);
+    let header = |name| headers.get(name).unwrap_or_default().to_string();
+
+    ReceivedSsdpNotification {
+        source,
+        notification_type: header("NT"),
+        notification_subtype: header("NTS"),
+        usn: header("USN"),
+        location: headers.get("LOCATION").map(str::to_string),
         raw: request.to_string(),
         timestamp: start_time.elapsed(),
+        headers,
     }
 }
 
@@ -107,7 +496,10 @@ fn parse_ssdp_request(
 fn extract_header(request: &str, header: &str) -> Option<String> {
     for line in request.lines() {
         let line = line.trim();
-        if line.to_uppercase().starts_with(&format!("{}:", header.to_uppercase())) {
+        if line
+            .to_uppercase()
+            .starts_with(&format!("{}:", header.to_uppercase()))
+        {
             let value = line[header.len() + 1..].trim();
             // Remove surrounding quotes if present
             let value = value.trim_matches('"');
@@ -117,33 +509,140 @@ fn extract_header(request: &str, header: &str) -> Option<String> {
     None
 }
 
-/// Check if the request is an M-SEARCH request for IGD.
-fn is_msearch_request(request: &str) -> bool {
-    request.starts_with("M-SEARCH")
-        && (request.contains("ssdp:all")
-            || request.contains("upnp:rootdevice")
-            || request.contains("urn:schemas-upnp-org:device:InternetGatewayDevice")
-            || request.contains("urn:schemas-upnp-org:service:WANIPConnection"))
+/// Parse every header line after the request line.
+fn parse_headers(request: &str) -> SsdpHeaders {
+    SsdpHeaders::new(
+        request
+            .lines()
+            .skip(1)
+            .take_while(|line| !line.trim().is_empty())
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect(),
+    )
+}
+
+/// Check if the request is an M-SEARCH for a target the server answers.
+fn is_msearch_request(request: &str, config: &ServerConfig) -> bool {
+    if !request.starts_with("M-SEARCH") {
+        return false;
+    }
+    let Some(search_target) = extract_header(request, "ST") else {
+        return false;
+    };
+    match &config.ssdp_search_targets {
+        Some(targets) => targets
+            .iter()
+            .any(|target| search_target_matches(target, &search_target)),
+        None => DEFAULT_SEARCH_TARGETS
+            .iter()
+            .any(|target| search_target_matches(target, &search_target)),
+    }
+}
+
+/// Whether `search_target` is `accepted`, or a version of it when
+/// `accepted` has none.
+fn search_target_matches(accepted: &str, search_target: &str) -> bool {
+    search_target == accepted
+        || search_target
+            .strip_prefix(accepted)
+            .and_then(|version| version.strip_prefix(':'))
+            .is_some_and(|version| version.parse::<u32>().is_ok())
+}
+
+/// Check an M-SEARCH against the discovery rules, recording violations;
+/// returns whether the search must go unanswered.
+fn reject_msearch(
+    request: &ReceivedSsdpRequest,
+    registry: &MockRegistry,
+    config: &ServerConfig,
+) -> bool {
+    let violations: Vec<_> = compliance::check_msearch(request)
+        .into_iter()
+        .filter(|v| config.compliance_level(v.rule) != ComplianceLevel::Ignore)
+        .collect();
+    for violation in &violations {
+        tracing::warn!(target: TRACE_TARGET, %violation, "non-compliant search");
+    }
+    let reject = violations
+        .iter()
+        .any(|v| config.compliance_level(v.rule) == ComplianceLevel::Reject);
+    registry.record_compliance_violations(violations);
+    reject
+}
+
+/// Answer an M-SEARCH for `search_target` and count it as answered once
+/// sent.
+async fn answer_msearch(
+    socket: &UdpSocket,
+    dest: SocketAddr,
+    search_target: &str,
+    location: &str,
+    registry: &MockRegistry,
+    config: &ServerConfig,
+) {
+    // Responses repeat the target searched for
+    let search_target = match search_target {
+        "ssdp:all" => IGD_SEARCH_TARGET,
+        search_target => search_target,
+    };
+    let sent = if config.ssdp_ephemeral_reply_port {
+        // Reply from another port, as some routers do
+        match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await {
+            Ok(socket) => {
+                send_msearch_response(&socket, dest, search_target, location, registry, config)
+                    .await
+            }
+            Err(e) => Err(e.into()),
+        }
+    } else {
+        send_msearch_response(socket, dest, search_target, location, registry, config).await
+    };
+    match sent {
+        Ok(()) => registry.record_ssdp_answered(),
+        Err(e) => {
+            tracing::warn!(target: TRACE_TARGET, "Failed to send M-SEARCH response: {}", e);
+        }
+    }
 }
 
 /// Send M-SEARCH response.
 async fn send_msearch_response(
     socket: &UdpSocket,
     dest: SocketAddr,
-    http_addr: SocketAddr,
+    search_target: &str,
+    location: &str,
+    registry: &MockRegistry,
+    config: &ServerConfig,
 ) -> Result<()> {
     let response = format!(
         "HTTP/1.1 200 OK\r\n\
-         CACHE-CONTROL: max-age=1800\r\n\
-         ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
-         USN: uuid:mock-igd-001::urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+         CACHE-CONTROL: max-age={}\r\n\
+         ST: {}\r\n\
+         USN: {}\r\n\
          EXT:\r\n\
          SERVER: mock-igd/0.1 UPnP/1.0\r\n\
-         LOCATION: http://{}/rootDesc.xml\r\n\
+         LOCATION: {}\r\n\
+         BOOTID.UPNP.ORG: {}\r\n\
+         CONFIGID.UPNP.ORG: {}\r\n\
          \r\n",
-        http_addr
+        config.ssdp_max_age.unwrap_or(DEFAULT_MAX_AGE),
+        search_target,
+        config.usn_format.format(&config.udns.root, search_target),
+        location,
+        registry.boot_id(),
+        registry.config_id()
     );
 
+    if config.wire_logging {
+        tracing::debug!(
+            target: WIRE_TRACE_TARGET,
+            destination = %dest,
+            datagram = %response,
+            "sending SSDP response"
+        );
+    }
+
     socket.send_to(response.as_bytes(), dest).await?;
     Ok(())
 }
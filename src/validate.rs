@@ -0,0 +1,264 @@
+//! Validation of served XML documents.
+//!
+//! Enabled by the `validate` feature in debug builds: every SOAP response,
+//! device description and SCPD served by the mock is checked against the
+//! structure required by the UPnP IGD specifications, and the server panics
+//! on the first violation so template regressions and malformed raw
+//! responders surface immediately.
+
+use crate::responder::ResponseBody;
+use quick_xml::Reader;
+use quick_xml::events::Event;
+
+/// A parsed XML element (namespace prefixes stripped).
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    fn require(&self, name: &str) -> Result<&Element, String> {
+        self.child(name)
+            .ok_or_else(|| format!("<{}> is missing <{}>", self.name, name))
+    }
+
+    fn require_text(&self, name: &str) -> Result<&str, String> {
+        let child = self.require(name)?;
+        if child.text.is_empty() {
+            return Err(format!("<{}> has an empty <{}>", self.name, name));
+        }
+        Ok(&child.text)
+    }
+}
+
+/// Parse an XML document into an element tree, checking well-formedness.
+fn parse(xml: &str) -> Result<Element, String> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut stack: Vec<Element> = vec![Element::default()];
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| format!("malformed XML at byte {}: {e}", reader.buffer_position()))?;
+        match event {
+            Event::Start(e) => stack.push(Element {
+                name: String::from_utf8_lossy(e.local_name().as_ref()).to_string(),
+                ..Default::default()
+            }),
+            Event::Empty(e) => {
+                let element = Element {
+                    name: String::from_utf8_lossy(e.local_name().as_ref()).to_string(),
+                    ..Default::default()
+                };
+                stack.last_mut().unwrap().children.push(element);
+            }
+            Event::End(_) => {
+                let element = stack.pop().unwrap();
+                stack
+                    .last_mut()
+                    .ok_or("unbalanced closing tag")?
+                    .children
+                    .push(element);
+            }
+            Event::Text(t) => {
+                let text = t.unescape().map_err(|e| format!("invalid text: {e}"))?;
+                stack.last_mut().unwrap().text.push_str(&text);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    let mut document = stack.pop().ok_or("unbalanced XML")?;
+    if !stack.is_empty() {
+        return Err("unclosed element".to_string());
+    }
+    if document.children.len() != 1 {
+        return Err("document must have exactly one root element".to_string());
+    }
+    Ok(document.children.remove(0))
+}
+
+/// Validate a SOAP response body sent for `action_name`.
+///
+/// `scpds` are the service descriptions whose actions define the expected
+/// output arguments. Vendor extension elements may follow them.
+pub(crate) fn validate_soap_response(
+    action_name: &str,
+    xml: &str,
+    scpds: &[&str],
+) -> Result<(), String> {
+    let envelope = parse(xml)?;
+    if envelope.name != "Envelope" {
+        return Err(format!(
+            "root element is <{}>, expected <Envelope>",
+            envelope.name
+        ));
+    }
+    let body = envelope.require("Body")?;
+    let content = body.children.first().ok_or("<Body> is empty")?;
+
+    if content.name == "Fault" {
+        let error = content.require("detail")?.require("UPnPError")?;
+        let code = error.require_text("errorCode")?;
+        code.parse::<u16>()
+            .map_err(|_| format!("<errorCode> is not a number: {code}"))?;
+        return Ok(());
+    }
+
+    let expected_name = format!("{action_name}Response");
+    if content.name != expected_name {
+        return Err(format!(
+            "response element is <{}>, expected <{expected_name}>",
+            content.name
+        ));
+    }
+
+    for scpd in scpds {
+        if let Some(expected) = scpd_out_arguments(scpd, action_name)? {
+            let actual: Vec<&str> = content.children.iter().map(|c| c.name.as_str()).collect();
+            let standard = actual.iter().take(expected.len());
+            if actual.len() < expected.len() || !standard.eq(expected.iter()) {
+                return Err(format!(
+                    "<{expected_name}> has arguments {actual:?}, expected {expected:?}"
+                ));
+            }
+            return Ok(());
+        }
+    }
+    // Actions not described by any SCPD only need a well-formed envelope
+    Ok(())
+}
+
+/// Output argument names of `action_name` in the given SCPD, in order
+/// (`None` if the SCPD doesn't define the action).
+fn scpd_out_arguments(scpd: &str, action_name: &str) -> Result<Option<Vec<String>>, String> {
+    let scpd = parse(scpd)?;
+    let Some(actions) = scpd.child("actionList") else {
+        return Ok(None);
+    };
+    for action in &actions.children {
+        if action.child("name").map(|n| n.text.as_str()) != Some(action_name) {
+            continue;
+        }
+        let arguments = action
+            .child("argumentList")
+            .map(|list| list.children.as_slice())
+            .unwrap_or_default();
+        let out = arguments
+            .iter()
+            .filter(|arg| arg.child("direction").map(|d| d.text.as_str()) == Some("out"))
+            .filter_map(|arg| arg.child("name").map(|n| n.text.clone()))
+            .collect();
+        return Ok(Some(out));
+    }
+    Ok(None)
+}
+
+/// Validate a response body produced by a responder.
+///
+/// Raw bodies are only checked when their content type declares XML;
+/// byte, streamed and full HTTP response bodies are not checked.
+pub(crate) fn validate_response_body(
+    action_name: &str,
+    body: &ResponseBody,
+    scpds: &[&str],
+) -> Result<(), String> {
+    match body {
+        ResponseBody::Soap(xml) => validate_soap_response(action_name, xml, scpds),
+        ResponseBody::Raw { content_type, body } if content_type.contains("xml") => {
+            validate_soap_response(action_name, body, scpds)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Validate a UPnP device description document.
+pub(crate) fn validate_device_description(xml: &str) -> Result<(), String> {
+    let root = parse(xml)?;
+    if root.name != "root" {
+        return Err(format!("root element is <{}>, expected <root>", root.name));
+    }
+    validate_spec_version(&root)?;
+    validate_device(root.require("device")?)
+}
+
+fn validate_device(device: &Element) -> Result<(), String> {
+    for field in ["deviceType", "friendlyName", "UDN"] {
+        device.require_text(field)?;
+    }
+    if let Some(services) = device.child("serviceList") {
+        for service in &services.children {
+            for field in [
+                "serviceType",
+                "serviceId",
+                "SCPDURL",
+                "controlURL",
+                "eventSubURL",
+            ] {
+                service.require_text(field)?;
+            }
+        }
+    }
+    if let Some(devices) = device.child("deviceList") {
+        for device in &devices.children {
+            validate_device(device)?;
+        }
+    }
+    Ok(())
+}
+
+/// Validate a service control protocol description (SCPD) document.
+pub(crate) fn validate_scpd(xml: &str) -> Result<(), String> {
+    let scpd = parse(xml)?;
+    if scpd.name != "scpd" {
+        return Err(format!("root element is <{}>, expected <scpd>", scpd.name));
+    }
+    validate_spec_version(&scpd)?;
+
+    let state_variables: Vec<&str> = scpd
+        .require("serviceStateTable")?
+        .children
+        .iter()
+        .filter_map(|v| v.child("name").map(|n| n.text.as_str()))
+        .collect();
+
+    if let Some(actions) = scpd.child("actionList") {
+        for action in &actions.children {
+            let name = action.require_text("name")?;
+            let Some(arguments) = action.child("argumentList") else {
+                continue;
+            };
+            for argument in &arguments.children {
+                let arg_name = argument.require_text("name")?;
+                let direction = argument.require_text("direction")?;
+                if direction != "in" && direction != "out" {
+                    return Err(format!(
+                        "argument {name}/{arg_name} has invalid direction {direction:?}"
+                    ));
+                }
+                let related = argument.require_text("relatedStateVariable")?;
+                if !state_variables.contains(&related) {
+                    return Err(format!(
+                        "argument {name}/{arg_name} refers to unknown state variable {related}"
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_spec_version(root: &Element) -> Result<(), String> {
+    let version = root.require("specVersion")?;
+    version.require_text("major")?;
+    version.require_text("minor")?;
+    Ok(())
+}
@@ -1,10 +1,20 @@
 //! Integration tests for mock-igd server.
 
-use mock_igd::{Action, MockIgdServer, Protocol, Responder};
+use mock_igd::{Action, MockIgdServer, Protocol, Responder, ResponseLint, assert_add_port_mapping};
 use std::net::UdpSocket;
 
 /// Helper to send a SOAP request and return the response body.
 async fn soap_request(url: &str, action: &str, body: &str) -> (u16, String) {
+    soap_request_with_client(&reqwest::Client::new(), url, action, body).await
+}
+
+/// Helper to send a SOAP request through an existing client.
+async fn soap_request_with_client(
+    client: &reqwest::Client,
+    url: &str,
+    action: &str,
+    body: &str,
+) -> (u16, String) {
     let soap_body = format!(
         r#"<?xml version="1.0"?>
 <s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
@@ -15,7 +25,6 @@ async fn soap_request(url: &str, action: &str, body: &str) -> (u16, String) {
         body
     );
 
-    let client = reqwest::Client::new();
     let response = client
         .post(url)
         .header("Content-Type", "text/xml; charset=\"utf-8\"")
@@ -95,6 +104,29 @@ async fn test_get_status_info() {
     assert!(body.contains("<NewUptime>86400</NewUptime>"));
 }
 
+#[tokio::test]
+async fn test_get_status_info_defaults() {
+    let server = MockIgdServer::start().await.unwrap();
+
+    server
+        .mock(Action::GetStatusInfo, Responder::success())
+        .await;
+
+    let (status, body) = soap_request(
+        &server.control_url(),
+        "GetStatusInfo",
+        r#"<u:GetStatusInfo xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetStatusInfo>"#,
+    )
+    .await;
+
+    assert_eq!(status, 200);
+    assert!(body.contains("<u:GetStatusInfoResponse"));
+    assert!(body.contains("<NewConnectionStatus>Connected</NewConnectionStatus>"));
+    assert!(body.contains("<NewLastConnectionError>ERROR_NONE</NewLastConnectionError>"));
+    assert!(body.contains("<NewUptime>0</NewUptime>"));
+}
+
 #[tokio::test]
 async fn test_get_external_ip_address_error() {
     let server = MockIgdServer::start().await.unwrap();
@@ -530,9 +562,7 @@ async fn test_received_requests() {
 async fn test_received_requests_multiple() {
     let server = MockIgdServer::start().await.unwrap();
 
-    server
-        .mock(Action::any(), Responder::success())
-        .await;
+    server.mock(Action::any(), Responder::success()).await;
 
     // Send multiple requests
     let _ = soap_request(
@@ -578,9 +608,7 @@ async fn test_received_requests_multiple() {
 async fn test_clear_received_requests() {
     let server = MockIgdServer::start().await.unwrap();
 
-    server
-        .mock(Action::any(), Responder::success())
-        .await;
+    server.mock(Action::any(), Responder::success()).await;
 
     // Send a request
     let _ = soap_request(
@@ -615,6 +643,42 @@ async fn test_clear_received_requests() {
     assert_eq!(requests[0].action_name, "DeletePortMapping");
 }
 
+#[tokio::test]
+async fn test_received_requests_since() {
+    let server = MockIgdServer::start().await.unwrap();
+
+    server.mock(Action::any(), Responder::success()).await;
+
+    let _ = soap_request(
+        &server.control_url(),
+        "GetExternalIPAddress",
+        r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetExternalIPAddress>"#,
+    )
+    .await;
+
+    let seen = server.received_requests().await.len();
+    assert_eq!(seen, 1);
+
+    let _ = soap_request(
+        &server.control_url(),
+        "GetStatusInfo",
+        r#"<u:GetStatusInfo xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetStatusInfo>"#,
+    )
+    .await;
+
+    let new_requests = server.received_requests_since(seen).await;
+    assert_eq!(new_requests.len(), 1);
+    assert_eq!(new_requests[0].action_name, "GetStatusInfo");
+
+    // Snapshots share the recorded requests instead of cloning them
+    let all = server.received_requests().await;
+    assert!(std::sync::Arc::ptr_eq(&all[1], &new_requests[0]));
+
+    assert!(server.received_requests_since(10).await.is_empty());
+}
+
 // =============================================================================
 // SSDP request recording tests
 // =============================================================================
@@ -673,10 +737,7 @@ async fn test_received_ssdp_requests() {
 
 #[tokio::test]
 async fn test_received_ssdp_requests_multiple() {
-    let server = MockIgdServer::builder()
-        .ssdp_port(0)
-        .start()
-        .await;
+    let server = MockIgdServer::builder().ssdp_port(0).start().await;
 
     let server = match server {
         Ok(s) if s.ssdp_addr().is_some() => s,
@@ -692,7 +753,10 @@ async fn test_received_ssdp_requests_multiple() {
     send_msearch_request(ssdp_addr, "ssdp:all");
     tokio::time::sleep(std::time::Duration::from_millis(50)).await;
 
-    send_msearch_request(ssdp_addr, "urn:schemas-upnp-org:device:InternetGatewayDevice:1");
+    send_msearch_request(
+        ssdp_addr,
+        "urn:schemas-upnp-org:device:InternetGatewayDevice:1",
+    );
     tokio::time::sleep(std::time::Duration::from_millis(100)).await;
 
     let requests = server.received_ssdp_requests().await;
@@ -705,12 +769,292 @@ async fn test_received_ssdp_requests_multiple() {
 }
 
 #[tokio::test]
-async fn test_clear_received_ssdp_requests() {
+async fn test_received_ssdp_request_headers() {
+    use mock_igd::ReceivedSsdpRequest;
+
+    let server = MockIgdServer::builder().ssdp_port(0).start().await;
+
+    let server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+
+    let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+    let request = "M-SEARCH * HTTP/1.1\r\n\
+                   HOST: 239.255.255.250:1900\r\n\
+                   MAN: \"ssdp:discover\"\r\n\
+                   MX: 2\r\n\
+                   ST: upnp:rootdevice\r\n\
+                   USER-AGENT: Linux/6.1 UPnP/1.1 test-client/1.0\r\n\
+                   CPFN.UPNP.ORG: test-client\r\n\
+                   \r\n";
+    socket
+        .send_to(request.as_bytes(), server.ssdp_addr().unwrap())
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let requests = server.received_ssdp_requests().await;
+    let received: &ReceivedSsdpRequest = &requests[0];
+    assert_eq!(
+        received.user_agent.as_deref(),
+        Some("Linux/6.1 UPnP/1.1 test-client/1.0")
+    );
+    assert_eq!(received.headers.len(), 6);
+    assert_eq!(received.headers.get("cpfn.upnp.org"), Some("test-client"));
+    assert_eq!(received.headers.get("Man"), Some("\"ssdp:discover\""));
+    assert!(!received.headers.contains("TCPPORT.UPNP.ORG"));
+}
+
+#[tokio::test]
+async fn test_received_ssdp_notifications() {
+    let server = MockIgdServer::builder().ssdp_port(0).start().await;
+
+    let server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+
+    let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+    let notify = "NOTIFY * HTTP/1.1\r\n\
+                  HOST: 239.255.255.250:1900\r\n\
+                  CACHE-CONTROL: max-age=1800\r\n\
+                  LOCATION: http://192.168.1.20:5000/desc.xml\r\n\
+                  NT: upnp:rootdevice\r\n\
+                  NTS: ssdp:alive\r\n\
+                  USN: uuid:device-1::upnp:rootdevice\r\n\
+                  \r\n";
+    socket
+        .send_to(notify.as_bytes(), server.ssdp_addr().unwrap())
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // Recorded separately from M-SEARCH requests
+    assert!(server.received_ssdp_requests().await.is_empty());
+    let notifications = server.received_ssdp_notifications().await;
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0].notification_type, "upnp:rootdevice");
+    assert_eq!(notifications[0].notification_subtype, "ssdp:alive");
+    assert_eq!(notifications[0].usn, "uuid:device-1::upnp:rootdevice");
+    assert_eq!(
+        notifications[0].location.as_deref(),
+        Some("http://192.168.1.20:5000/desc.xml")
+    );
+    assert_eq!(
+        notifications[0].headers.get("cache-control"),
+        Some("max-age=1800")
+    );
+
+    server.clear_received_ssdp_notifications().await;
+    assert!(server.received_ssdp_notifications().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_ssdp_mocks() {
+    use mock_igd::{SsdpDelivery, SsdpMatcher, SsdpResponse};
+
+    let server = MockIgdServer::builder().ssdp_port(0).start().await;
+
+    let server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+    let ssdp_addr = server.ssdp_addr().unwrap();
+
+    server
+        .mock_ssdp(
+            SsdpMatcher::any().with_delivery(SsdpDelivery::Unicast),
+            SsdpResponse::Ignore,
+        )
+        .await;
+    server
+        .mock_ssdp(SsdpMatcher::any().with_mx(5), SsdpResponse::Ignore)
+        .await;
+
+    let multicast_addr = std::net::SocketAddr::from(([239, 255, 255, 250], ssdp_addr.port()));
+    let search = |to: std::net::SocketAddr, mx: u32| async move {
+        let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await.unwrap();
+        // Always the multicast HOST, even when sent unicast
+        let request = format!(
+            "M-SEARCH * HTTP/1.1\r\n\
+             HOST: 239.255.255.250:1900\r\n\
+             MAN: \"ssdp:discover\"\r\n\
+             MX: {mx}\r\n\
+             ST: ssdp:all\r\n\
+             \r\n"
+        );
+        socket.send_to(request.as_bytes(), to).await.ok()?;
+        let mut buf = [0u8; 2048];
+        let response = socket.recv_from(&mut buf);
+        Some(
+            tokio::time::timeout(std::time::Duration::from_millis(300), response)
+                .await
+                .is_ok(),
+        )
+    };
+
+    let Some(answered) = search(multicast_addr, 3).await else {
+        eprintln!("Skipping SSDP test - could not send multicast");
+        return;
+    };
+    assert!(answered);
+    assert_eq!(search(multicast_addr, 5).await, Some(false));
+
+    let requests = server.received_ssdp_requests().await;
+    let answered: Vec<bool> = requests.iter().map(|r| r.answered).collect();
+    assert_eq!(answered, [true, false]);
+    assert_eq!(requests[0].delivery(), SsdpDelivery::Multicast);
+
+    // Only Linux reports where a datagram was sent; elsewhere HOST decides
+    if cfg!(target_os = "linux") {
+        assert_eq!(search(ssdp_addr, 3).await, Some(false));
+        let requests = server.received_ssdp_requests().await;
+        assert_eq!(requests[2].delivery(), SsdpDelivery::Unicast);
+    }
+}
+
+#[tokio::test]
+async fn test_ssdp_interface() {
+    let server = MockIgdServer::builder()
+        .ssdp_port(0)
+        .ssdp_interface(std::net::Ipv4Addr::LOCALHOST)
+        .start()
+        .await;
+
+    let server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not join the multicast group on loopback");
+            return;
+        }
+    };
+
+    send_msearch_request(server.ssdp_addr().unwrap(), "ssdp:all");
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    assert_eq!(server.received_ssdp_requests().await.len(), 1);
+}
+
+#[tokio::test]
+async fn test_ssdp_multicast_options() {
+    let server = MockIgdServer::builder()
+        .ssdp_port(0)
+        .ssdp_multicast_ttl(4)
+        .ssdp_multicast_loop(true)
+        .start()
+        .await;
+
+    let server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+
+    send_msearch_request(server.ssdp_addr().unwrap(), "ssdp:all");
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    assert_eq!(server.received_ssdp_requests().await.len(), 1);
+}
+
+#[tokio::test]
+async fn test_ssdp_only() {
     let server = MockIgdServer::builder()
+        .ssdp_only("http://192.0.2.1:5000/desc.xml")
         .ssdp_port(0)
         .start()
         .await;
 
+    let server = match server {
+        Ok(s) => s,
+        Err(_) => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let request = "M-SEARCH * HTTP/1.1\r\n\
+                   HOST: 239.255.255.250:1900\r\n\
+                   MAN: \"ssdp:discover\"\r\n\
+                   MX: 1\r\n\
+                   ST: ssdp:all\r\n\
+                   \r\n";
+    socket
+        .send_to(request.as_bytes(), server.ssdp_addr().unwrap())
+        .await
+        .unwrap();
+    let mut buf = [0u8; 2048];
+    let response = socket.recv_from(&mut buf);
+    let (len, _) = tokio::time::timeout(std::time::Duration::from_secs(1), response)
+        .await
+        .expect("no search response")
+        .unwrap();
+    let response = String::from_utf8_lossy(&buf[..len]);
+    assert!(response.contains("LOCATION: http://192.0.2.1:5000/desc.xml\r\n"));
+}
+
+#[tokio::test]
+async fn test_ssdp_boot_and_config_ids() {
+    let server = MockIgdServer::builder().ssdp_port(0).start().await;
+
+    let server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+
+    let search = || async {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let request = "M-SEARCH * HTTP/1.1\r\n\
+                       HOST: 239.255.255.250:1900\r\n\
+                       MAN: \"ssdp:discover\"\r\n\
+                       MX: 1\r\n\
+                       ST: ssdp:all\r\n\
+                       \r\n";
+        socket
+            .send_to(request.as_bytes(), server.ssdp_addr().unwrap())
+            .await
+            .unwrap();
+        let mut buf = [0u8; 2048];
+        let response = socket.recv_from(&mut buf);
+        let (len, _) = tokio::time::timeout(std::time::Duration::from_secs(1), response)
+            .await
+            .expect("no search response")
+            .unwrap();
+        String::from_utf8_lossy(&buf[..len]).to_string()
+    };
+
+    let response = search().await;
+    assert!(response.contains("BOOTID.UPNP.ORG: 1\r\n"));
+    assert!(response.contains("CONFIGID.UPNP.ORG: 1\r\n"));
+
+    server.simulate_reboot().await;
+    server
+        .set_description_xml(r#"<?xml version="1.0"?><root/>"#)
+        .await;
+    assert_eq!((server.boot_id(), server.config_id()), (2, 2));
+
+    let response = search().await;
+    assert!(response.contains("BOOTID.UPNP.ORG: 2\r\n"));
+    assert!(response.contains("CONFIGID.UPNP.ORG: 2\r\n"));
+}
+
+#[tokio::test]
+async fn test_clear_received_ssdp_requests() {
+    let server = MockIgdServer::builder().ssdp_port(0).start().await;
+
     let server = match server {
         Ok(s) if s.ssdp_addr().is_some() => s,
         _ => {
@@ -746,10 +1090,7 @@ async fn test_clear_received_ssdp_requests() {
 
 #[tokio::test]
 async fn test_ssdp_request_contains_raw_data() {
-    let server = MockIgdServer::builder()
-        .ssdp_port(0)
-        .start()
-        .await;
+    let server = MockIgdServer::builder().ssdp_port(0).start().await;
 
     let server = match server {
         Ok(s) if s.ssdp_addr().is_some() => s,
@@ -780,3 +1121,4672 @@ async fn test_ssdp_request_contains_raw_data() {
     // Verify timestamp is reasonable
     assert!(requests[0].timestamp.as_secs() < 10);
 }
+
+// =============================================================================
+// Request dump tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_dump_requests() {
+    let server = MockIgdServer::builder().ssdp_port(0).start().await.unwrap();
+
+    server
+        .mock(
+            Action::add_port_mapping().with_external_port(80),
+            Responder::error(718, "ConflictInMappingEntry"),
+        )
+        .await;
+
+    if let Some(ssdp_addr) = server.ssdp_addr() {
+        send_msearch_request(ssdp_addr, "ssdp:all");
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    let _ = soap_request(
+        &server.control_url(),
+        "AddPortMapping",
+        r#"<u:AddPortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+            <NewRemoteHost></NewRemoteHost>
+            <NewExternalPort>80</NewExternalPort>
+            <NewProtocol>TCP</NewProtocol>
+            <NewInternalPort>80</NewInternalPort>
+            <NewInternalClient>192.168.1.100</NewInternalClient>
+            <NewEnabled>1</NewEnabled>
+            <NewPortMappingDescription>Web</NewPortMappingDescription>
+            <NewLeaseDuration>0</NewLeaseDuration>
+        </u:AddPortMapping>"#,
+    )
+    .await;
+
+    let _ = soap_request(
+        &server.control_url(),
+        "GetExternalIPAddress",
+        r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetExternalIPAddress>"#,
+    )
+    .await;
+
+    let dump = server.dump_requests().await;
+    assert!(dump.contains("WANIPConnection:1#AddPortMapping"));
+    assert!(dump.contains("response: error 718 (ConflictInMappingEntry)"));
+    assert!(dump.contains("WANIPConnection:1#GetExternalIPAddress"));
+    assert!(dump.contains("response: no matching mock"));
+
+    // SOAP entries are ordered by arrival time
+    let add = dump.find("#AddPortMapping").unwrap();
+    let get = dump.find("#GetExternalIPAddress").unwrap();
+    assert!(add < get);
+
+    if server.ssdp_addr().is_some() {
+        assert!(dump.contains("SSDP M-SEARCH from"));
+        assert!(dump.find("SSDP M-SEARCH").unwrap() < add);
+    }
+}
+
+// =============================================================================
+// Tracing tests
+// =============================================================================
+
+/// A tracing writer that captures formatted output in memory.
+#[derive(Clone, Default)]
+struct CapturedLogs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl CapturedLogs {
+    fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).to_string()
+    }
+}
+
+#[tokio::test]
+async fn test_tracing_events() {
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    let logs = CapturedLogs::default();
+    let writer = logs.clone();
+    let _guard = tracing_subscriber::fmt()
+        .with_env_filter("mock_igd=debug")
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish()
+        .set_default();
+
+    let server = MockIgdServer::builder()
+        .with_wire_logging()
+        .start()
+        .await
+        .unwrap();
+
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("192.0.2.1".parse().unwrap()),
+        )
+        .await;
+
+    let _ = soap_request(
+        &server.control_url(),
+        "GetExternalIPAddress",
+        r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetExternalIPAddress>"#,
+    )
+    .await;
+
+    let output = logs.contents();
+    assert!(output.contains("handled SOAP request"));
+    assert!(output.contains("action=GetExternalIPAddress"));
+    assert!(output.contains("mock_id=1"));
+    assert!(output.contains("status=200"));
+    assert!(output.contains("latency_us="));
+    assert!(output.contains("mock_igd::wire"));
+    assert!(output.contains("received SOAP request"));
+}
+
+// =============================================================================
+// Metrics tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_metrics() {
+    let server = MockIgdServer::start().await.unwrap();
+
+    let mock_id = server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("192.0.2.1".parse().unwrap()),
+        )
+        .await;
+
+    for _ in 0..2 {
+        let _ = soap_request(
+            &server.control_url(),
+            "GetExternalIPAddress",
+            r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+            </u:GetExternalIPAddress>"#,
+        )
+        .await;
+    }
+
+    let _ = soap_request(
+        &server.control_url(),
+        "GetStatusInfo",
+        r#"<u:GetStatusInfo xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetStatusInfo>"#,
+    )
+    .await;
+
+    let metrics = server.metrics().await;
+    assert_eq!(metrics.total_requests, 3);
+    assert_eq!(metrics.requests_by_action["GetExternalIPAddress"], 2);
+    assert_eq!(metrics.requests_by_action["GetStatusInfo"], 1);
+    assert_eq!(metrics.requests_by_mock[&mock_id], 2);
+    assert_eq!(metrics.unmatched_requests, 1);
+    assert!(metrics.average_latency().is_some());
+
+    let text = metrics.to_prometheus();
+    assert!(text.contains("mock_igd_soap_requests_total{action=\"GetExternalIPAddress\"} 2"));
+    assert!(text.contains("mock_igd_unmatched_requests_total 1"));
+}
+
+#[tokio::test]
+async fn test_latency_stats() {
+    use mock_igd::LatencyStats;
+    use std::time::Duration;
+
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("192.0.2.1".parse().unwrap()),
+        )
+        .await;
+
+    for _ in 0..3 {
+        let _ = soap_request(
+            &server.control_url(),
+            "GetExternalIPAddress",
+            r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+            </u:GetExternalIPAddress>"#,
+        )
+        .await;
+    }
+
+    let stats = server.latency_stats().await;
+    assert_eq!(stats.len(), 1);
+    let stats = stats["GetExternalIPAddress"];
+    assert_eq!(stats.count, 3);
+    assert!(stats.min <= stats.p50 && stats.p50 <= stats.p99 && stats.p99 <= stats.max);
+
+    // Percentiles are bucket estimates, less than a quarter above the samples
+    let samples: Vec<_> = (1..=100).map(Duration::from_millis).collect();
+    let stats = LatencyStats::from_samples(&samples).unwrap();
+    for (estimate, millis) in [(stats.p50, 50), (stats.p90, 90), (stats.p99, 99)] {
+        let exact = Duration::from_millis(millis);
+        assert!(
+            exact <= estimate && estimate < exact * 5 / 4,
+            "{estimate:?}"
+        );
+    }
+    assert_eq!(stats.min, Duration::from_millis(1));
+    assert_eq!(stats.max, Duration::from_millis(100));
+    assert_eq!(stats.mean, Duration::from_micros(50_500));
+    assert!(LatencyStats::from_samples(&[]).is_none());
+}
+
+// =============================================================================
+// Concurrency tests
+// =============================================================================
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_requests() {
+    let server = MockIgdServer::start().await.unwrap();
+
+    for port in 1000..1100 {
+        server
+            .mock(
+                Action::add_port_mapping().with_external_port(port),
+                Responder::success(),
+            )
+            .await;
+    }
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("192.0.2.1".parse().unwrap()),
+        )
+        .await;
+
+    let url = server.control_url();
+    let client = reqwest::Client::new();
+    let tasks: Vec<_> = (0..200)
+        .map(|_| {
+            let url = url.clone();
+            let client = client.clone();
+            tokio::spawn(async move {
+                soap_request_with_client(
+                    &client,
+                    &url,
+                    "GetExternalIPAddress",
+                    r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+                    </u:GetExternalIPAddress>"#,
+                )
+                .await
+            })
+        })
+        .collect();
+
+    for task in tasks {
+        let (status, body) = task.await.unwrap();
+        assert_eq!(status, 200);
+        assert!(body.contains("192.0.2.1"));
+    }
+
+    assert_eq!(server.received_requests().await.len(), 200);
+    assert_eq!(server.metrics().await.total_requests, 200);
+}
+
+// =============================================================================
+// Streaming response tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_streaming_response_body() {
+    use mock_igd::responder::ResponseBody;
+
+    let server = MockIgdServer::start().await.unwrap();
+
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::custom(|_| {
+                let chunks =
+                    (0..100).map(|i| Ok(bytes::Bytes::from(format!("<chunk>{i}</chunk>"))));
+                ResponseBody::stream("text/xml", futures_util::stream::iter(chunks))
+            }),
+        )
+        .await;
+
+    let (status, body) = soap_request(
+        &server.control_url(),
+        "GetExternalIPAddress",
+        r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetExternalIPAddress>"#,
+    )
+    .await;
+
+    assert_eq!(status, 200);
+    assert!(body.starts_with("<chunk>0</chunk><chunk>1</chunk>"));
+    assert!(body.ends_with("<chunk>99</chunk>"));
+}
+
+#[tokio::test]
+async fn test_full_http_response() {
+    use mock_igd::responder::ResponseBody;
+
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::custom(|_| ResponseBody::Http {
+                status: 503,
+                headers: vec![
+                    ("Retry-After".to_string(), "3".to_string()),
+                    ("X-Firmware".to_string(), "a".to_string()),
+                    ("X-Firmware".to_string(), "b".to_string()),
+                ],
+                body: "busy".to_string(),
+            }),
+        )
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(server.control_url())
+        .header(
+            "SOAPAction",
+            "\"urn:schemas-upnp-org:service:WANIPConnection:1#GetExternalIPAddress\"",
+        )
+        .body("")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 503);
+    assert_eq!(response.headers()["retry-after"], "3");
+    let firmware: Vec<_> = response.headers().get_all("x-firmware").iter().collect();
+    assert_eq!(firmware, ["a", "b"]);
+    assert!(response.headers().get("content-type").is_none());
+    assert_eq!(response.text().await.unwrap(), "busy");
+}
+
+#[tokio::test]
+async fn test_raw_bytes_response() {
+    use mock_igd::responder::ResponseBody;
+
+    let garbage = vec![0x1f, 0x8b, 0x08, 0x00, 0xff, 0xfe, 0x00, 0xc3];
+    let server = MockIgdServer::start().await.unwrap();
+    {
+        let garbage = garbage.clone();
+        server
+            .mock(
+                Action::GetExternalIPAddress,
+                Responder::custom(move |_| ResponseBody::RawBytes {
+                    content_type: "text/xml".to_string(),
+                    body: garbage.clone(),
+                }),
+            )
+            .await;
+    }
+
+    let response = reqwest::Client::new()
+        .post(server.control_url())
+        .header(
+            "SOAPAction",
+            "\"urn:schemas-upnp-org:service:WANIPConnection:1#GetExternalIPAddress\"",
+        )
+        .body("")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.bytes().await.unwrap().as_ref(), garbage.as_slice());
+}
+
+// =============================================================================
+// Concurrency limit tests
+// =============================================================================
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_max_concurrent_requests() {
+    use mock_igd::responder::ResponseBody;
+
+    let server = MockIgdServer::builder()
+        .with_max_concurrent_requests(1)
+        .start()
+        .await
+        .unwrap();
+
+    let in_flight = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let max_in_flight = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    {
+        let in_flight = in_flight.clone();
+        let max_in_flight = max_in_flight.clone();
+        server
+            .mock(
+                Action::GetExternalIPAddress,
+                Responder::custom(move |_| {
+                    use std::sync::atomic::Ordering;
+                    let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(current, Ordering::SeqCst);
+                    std::thread::sleep(std::time::Duration::from_millis(50));
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    ResponseBody::Raw {
+                        content_type: "text/plain".to_string(),
+                        body: String::new(),
+                    }
+                }),
+            )
+            .await;
+    }
+
+    let url = server.control_url();
+    let client = reqwest::Client::new();
+    let tasks: Vec<_> = (0..4)
+        .map(|_| {
+            let url = url.clone();
+            let client = client.clone();
+            tokio::spawn(async move {
+                soap_request_with_client(
+                    &client,
+                    &url,
+                    "GetExternalIPAddress",
+                    r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+                    </u:GetExternalIPAddress>"#,
+                )
+                .await
+            })
+        })
+        .collect();
+
+    for task in tasks {
+        let (status, _) = task.await.unwrap();
+        assert_eq!(status, 200);
+    }
+
+    assert_eq!(max_in_flight.load(std::sync::atomic::Ordering::SeqCst), 1);
+}
+
+// =============================================================================
+// Named mock tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_named_mock() {
+    use mock_igd::Mock;
+
+    let server = MockIgdServer::start().await.unwrap();
+
+    server
+        .register(
+            Mock::new(
+                Action::add_port_mapping().with_external_port(80),
+                Responder::error(718, "ConflictInMappingEntry"),
+            )
+            .named("conflict-on-80")
+            .times(1),
+        )
+        .await;
+
+    let body = r#"<u:AddPortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+            <NewRemoteHost></NewRemoteHost>
+            <NewExternalPort>80</NewExternalPort>
+            <NewProtocol>TCP</NewProtocol>
+            <NewInternalPort>80</NewInternalPort>
+            <NewInternalClient>192.168.1.100</NewInternalClient>
+            <NewEnabled>1</NewEnabled>
+            <NewPortMappingDescription>Web</NewPortMappingDescription>
+            <NewLeaseDuration>0</NewLeaseDuration>
+        </u:AddPortMapping>"#;
+
+    let (status, _) = soap_request(&server.control_url(), "AddPortMapping", body).await;
+    assert_eq!(status, 500);
+
+    // The mock is exhausted after one match
+    let _ = soap_request(&server.control_url(), "AddPortMapping", body).await;
+
+    let requests = server.received_requests().await;
+    assert_eq!(requests[0].mock_name.as_deref(), Some("conflict-on-80"));
+    assert_eq!(requests[1].mock_name, None);
+
+    let dump = server.dump_requests().await;
+    assert!(dump.contains("[mock \"conflict-on-80\"]"));
+}
+
+// =============================================================================
+// Exhaustion policy tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_exhaustion_policy() {
+    use mock_igd::{ExhaustionPolicy, Mock};
+
+    let server = MockIgdServer::start().await.unwrap();
+
+    server
+        .register(
+            Mock::new(
+                Action::GetExternalIPAddress,
+                Responder::success().with_external_ip("192.0.2.1".parse().unwrap()),
+            )
+            .times(1)
+            .on_exhausted(ExhaustionPolicy::Error {
+                code: 501,
+                description: "MockExhausted".to_string(),
+            }),
+        )
+        .await;
+    server
+        .register(
+            Mock::new(Action::GetStatusInfo, Responder::success())
+                .times(1)
+                .on_exhausted(ExhaustionPolicy::RepeatLast),
+        )
+        .await;
+
+    let get_ip = r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetExternalIPAddress>"#;
+    let get_status = r#"<u:GetStatusInfo xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetStatusInfo>"#;
+
+    let (status, _) = soap_request(&server.control_url(), "GetExternalIPAddress", get_ip).await;
+    assert_eq!(status, 200);
+    let (status, body) = soap_request(&server.control_url(), "GetExternalIPAddress", get_ip).await;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>501</errorCode>"));
+    assert!(body.contains("MockExhausted"));
+
+    for _ in 0..3 {
+        let (status, body) = soap_request(&server.control_url(), "GetStatusInfo", get_status).await;
+        assert_eq!(status, 200);
+        assert!(body.contains("GetStatusInfoResponse"));
+    }
+}
+
+#[tokio::test]
+async fn test_exhaustion_policy_prefers_other_matching_mocks() {
+    use mock_igd::{ExhaustionPolicy, Mock};
+
+    let server = MockIgdServer::start().await.unwrap();
+
+    server
+        .register(
+            Mock::new(Action::GetExternalIPAddress, Responder::error(501, "First"))
+                .with_priority(10)
+                .times(1)
+                .on_exhausted(ExhaustionPolicy::Panic),
+        )
+        .await;
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("192.0.2.1".parse().unwrap()),
+        )
+        .await;
+
+    let get_ip = r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetExternalIPAddress>"#;
+
+    let (status, _) = soap_request(&server.control_url(), "GetExternalIPAddress", get_ip).await;
+    assert_eq!(status, 500);
+    let (status, body) = soap_request(&server.control_url(), "GetExternalIPAddress", get_ip).await;
+    assert_eq!(status, 200);
+    assert!(body.contains("192.0.2.1"));
+}
+
+#[tokio::test]
+async fn test_exhaustion_panic_records_request() {
+    use mock_igd::{ExhaustionPolicy, Mock};
+
+    let server = MockIgdServer::start().await.unwrap();
+    let exhausted = server
+        .register(
+            Mock::new(Action::GetExternalIPAddress, Responder::success())
+                .named("once")
+                .times(1)
+                .on_exhausted(ExhaustionPolicy::Panic),
+        )
+        .await;
+    let get_ip = r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetExternalIPAddress>"#;
+    let (status, _) = soap_request(&server.control_url(), "GetExternalIPAddress", get_ip).await;
+    assert_eq!(status, 200);
+
+    // Only the request that panics can activate this mock
+    server
+        .register(
+            Mock::new(
+                Action::GetStatusInfo,
+                Responder::success().with_connection_status("Connected"),
+            )
+            .after(Action::GetExternalIPAddress),
+        )
+        .await;
+    let (status, _) = soap_request(&server.control_url(), "GetExternalIPAddress", get_ip).await;
+    assert_eq!(status, 500);
+    let (status, _) = soap_request(
+        &server.control_url(),
+        "GetStatusInfo",
+        r#"<u:GetStatusInfo xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetStatusInfo>"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+
+    let requests = server.received_requests().await;
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[1].mock_id, Some(exhausted));
+    assert_eq!(
+        requests[1].responder.as_deref(),
+        Some("panic, mock exhausted")
+    );
+    assert!(
+        server
+            .dump_requests()
+            .await
+            .contains("panic, mock exhausted [mock \"once\"]")
+    );
+    let error = server.health().await.unwrap_err().to_string();
+    assert!(
+        error.contains("mock \"once\" exhausted after 1 matches"),
+        "{error}"
+    );
+}
+
+// =============================================================================
+// Response lint tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_response_lint_accepts_complete_responders() {
+    let server = MockIgdServer::builder()
+        .with_response_lint(ResponseLint::Deny)
+        .start()
+        .await
+        .unwrap();
+
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("192.0.2.1".parse().unwrap()),
+        )
+        .await;
+    server
+        .mock(Action::add_port_mapping(), Responder::success())
+        .await;
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::error(501, "ActionFailed"),
+        )
+        .await;
+}
+
+#[tokio::test]
+#[should_panic(expected = "missing response fields: external_ip")]
+async fn test_response_lint_deny_missing_fields() {
+    let server = MockIgdServer::builder()
+        .with_response_lint(ResponseLint::Deny)
+        .start()
+        .await
+        .unwrap();
+
+    server
+        .mock(Action::GetExternalIPAddress, Responder::success())
+        .await;
+}
+
+// =============================================================================
+// Response validation tests
+// =============================================================================
+
+#[cfg(all(feature = "validate", debug_assertions))]
+#[tokio::test]
+async fn test_validate_rejects_malformed_raw_response() {
+    use mock_igd::responder::ResponseBody;
+
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::custom(|_| ResponseBody::Raw {
+                content_type: "text/xml".to_string(),
+                body: "<s:Envelope><s:Body></s:Envelope>".to_string(),
+            }),
+        )
+        .await;
+
+    // The invalid document is reported as a server error
+    let response = reqwest::Client::new()
+        .post(server.control_url())
+        .header(
+            "SOAPAction",
+            "\"urn:schemas-upnp-org:service:WANIPConnection:1#GetExternalIPAddress\"",
+        )
+        .body("")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 500);
+    assert!(
+        response
+            .text()
+            .await
+            .unwrap()
+            .contains("invalid SOAP response")
+    );
+    let error = server.health().await.unwrap_err().to_string();
+    assert!(
+        error.contains("invalid response to GetExternalIPAddress"),
+        "{error}"
+    );
+}
+
+#[cfg(all(feature = "validate", debug_assertions))]
+#[tokio::test]
+async fn test_validate_accepts_generated_documents() {
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(
+            Action::get_specific_port_mapping_entry(),
+            Responder::success()
+                .with_internal_port(8080)
+                .with_internal_client("192.168.1.100"),
+        )
+        .await;
+
+    for path in ["/rootDesc.xml", "/WANIPCn.xml", "/WANCommonIFC1.xml"] {
+        let response = reqwest::get(format!("{}{}", server.url(), path))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    let (status, body) = soap_request(
+        &server.control_url(),
+        "GetSpecificPortMappingEntry",
+        r#"<u:GetSpecificPortMappingEntry xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewRemoteHost></NewRemoteHost>
+<NewExternalPort>8080</NewExternalPort>
+<NewProtocol>TCP</NewProtocol>
+</u:GetSpecificPortMappingEntry>"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert!(body.contains("GetSpecificPortMappingEntryResponse"));
+}
+
+// =============================================================================
+// Rate limiting tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_rate_limit_returns_503() {
+    let server = MockIgdServer::builder()
+        .with_rate_limit(2)
+        .start()
+        .await
+        .unwrap();
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("192.0.2.1".parse().unwrap()),
+        )
+        .await;
+
+    let client = reqwest::Client::new();
+    let mut statuses = Vec::new();
+    for _ in 0..3 {
+        let (status, _) = soap_request_with_client(
+            &client,
+            &server.control_url(),
+            "GetExternalIPAddress",
+            r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+            </u:GetExternalIPAddress>"#,
+        )
+        .await;
+        statuses.push(status);
+    }
+
+    assert_eq!(statuses, vec![200, 200, 503]);
+    // Throttled requests never reach the mocks
+    assert_eq!(server.received_requests().await.len(), 2);
+}
+
+// =============================================================================
+// Request size limit tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_max_request_body_size_returns_413() {
+    let server = MockIgdServer::builder()
+        .with_max_request_body_size(256)
+        .start()
+        .await
+        .unwrap();
+    server.mock(Action::any(), Responder::success()).await;
+
+    let description = "x".repeat(512);
+    let (status, _) = soap_request(
+        &server.control_url(),
+        "AddPortMapping",
+        &format!(
+            r#"<u:AddPortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewPortMappingDescription>{description}</NewPortMappingDescription>
+</u:AddPortMapping>"#
+        ),
+    )
+    .await;
+
+    assert_eq!(status, 413);
+    assert!(server.received_requests().await.is_empty());
+}
+
+// =============================================================================
+// Basic auth tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_basic_auth_challenges_unauthenticated_requests() {
+    let server = MockIgdServer::builder()
+        .with_basic_auth("admin", "secret")
+        .start()
+        .await
+        .unwrap();
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("192.0.2.1".parse().unwrap()),
+        )
+        .await;
+
+    let send = |credentials: Option<(&str, &str)>| {
+        let mut request = reqwest::Client::new()
+            .post(server.control_url())
+            .header(
+                "SOAPAction",
+                "\"urn:schemas-upnp-org:service:WANIPConnection:1#GetExternalIPAddress\"",
+            )
+            .body(
+                r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+<s:Body><u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1"/></s:Body>
+</s:Envelope>"#,
+            );
+        if let Some((user, pass)) = credentials {
+            request = request.basic_auth(user, Some(pass));
+        }
+        request.send()
+    };
+
+    let response = send(None).await.unwrap();
+    assert_eq!(response.status(), 401);
+    assert_eq!(
+        response.headers()["www-authenticate"],
+        "Basic realm=\"mock-igd\""
+    );
+
+    let response = send(Some(("admin", "wrong"))).await.unwrap();
+    assert_eq!(response.status(), 401);
+
+    let response = send(Some(("admin", "secret"))).await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response.text().await.unwrap().contains("192.0.2.1"));
+
+    // Description documents don't require credentials
+    let response = reqwest::get(server.description_url()).await.unwrap();
+    assert_eq!(response.status(), 200);
+}
+
+// =============================================================================
+// Description redirect tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_description_redirect() {
+    use mock_igd::DescriptionRedirect;
+
+    let server = MockIgdServer::builder()
+        .with_description_redirect(DescriptionRedirect::MovedPermanently(
+            "/desc/igd.xml".to_string(),
+        ))
+        .start()
+        .await
+        .unwrap();
+
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = client.get(server.description_url()).send().await.unwrap();
+    assert_eq!(response.status(), 301);
+    assert_eq!(response.headers()["location"], "/desc/igd.xml");
+
+    // A client that follows redirects ends up at the served description
+    let response = reqwest::get(server.description_url()).await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response.url().path().ends_with("/desc/igd.xml"));
+    assert!(
+        response
+            .text()
+            .await
+            .unwrap()
+            .contains("InternetGatewayDevice")
+    );
+}
+
+// =============================================================================
+// Description delay tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_description_and_scpd_delays() {
+    use std::time::{Duration, Instant};
+
+    let server = MockIgdServer::builder()
+        .with_description_delay(Duration::from_millis(300))
+        .with_scpd_delay(Duration::from_millis(150))
+        .start()
+        .await
+        .unwrap();
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("192.0.2.1".parse().unwrap()),
+        )
+        .await;
+
+    let started = Instant::now();
+    reqwest::get(server.description_url()).await.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(300));
+
+    let started = Instant::now();
+    reqwest::get(format!("{}/WANIPCn.xml", server.url()))
+        .await
+        .unwrap();
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(150));
+    assert!(elapsed < Duration::from_millis(300));
+
+    // SOAP calls are not delayed
+    let client = reqwest::Client::new();
+    let started = Instant::now();
+    let (status, _) = soap_request_with_client(
+        &client,
+        &server.control_url(),
+        "GetExternalIPAddress",
+        r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetExternalIPAddress>"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert!(started.elapsed() < Duration::from_millis(150));
+}
+
+// =============================================================================
+// Description padding tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_description_padding() {
+    let server = MockIgdServer::builder()
+        .with_description_padding(500)
+        .start()
+        .await
+        .unwrap();
+
+    let body = reqwest::get(server.description_url())
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert_eq!(body.matches("<device>").count(), 3 + 500);
+    assert!(body.len() > 250_000);
+    assert!(body.contains("urn:schemas-upnp-org:service:WANIPConnection:1"));
+}
+
+// =============================================================================
+// Response encoding tests
+// =============================================================================
+
+async fn fetch_encoded(encoding: mock_igd::ResponseEncoding) -> (String, Vec<u8>, Vec<u8>) {
+    let server = MockIgdServer::builder()
+        .with_response_encoding(encoding)
+        .start()
+        .await
+        .unwrap();
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("192.0.2.1".parse().unwrap()),
+        )
+        .await;
+
+    let response = reqwest::get(server.description_url()).await.unwrap();
+    let content_type = response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let description = response.bytes().await.unwrap().to_vec();
+
+    let response = reqwest::Client::new()
+        .post(server.control_url())
+        .header(
+            "SOAPAction",
+            "\"urn:schemas-upnp-org:service:WANIPConnection:1#GetExternalIPAddress\"",
+        )
+        .body(
+            r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+<s:Body><u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1"/></s:Body>
+</s:Envelope>"#,
+        )
+        .send()
+        .await
+        .unwrap();
+    let soap = response.bytes().await.unwrap().to_vec();
+
+    (content_type, description, soap)
+}
+
+#[tokio::test]
+async fn test_response_encoding_utf8_bom() {
+    let (content_type, description, soap) =
+        fetch_encoded(mock_igd::ResponseEncoding::Utf8Bom).await;
+
+    assert_eq!(content_type, "text/xml; charset=\"utf-8\"");
+    assert!(description.starts_with(b"\xEF\xBB\xBF<?xml"));
+    assert!(soap.starts_with(b"\xEF\xBB\xBF<?xml"));
+}
+
+#[tokio::test]
+async fn test_response_encoding_utf16() {
+    let (content_type, description, soap) = fetch_encoded(mock_igd::ResponseEncoding::Utf16).await;
+
+    assert_eq!(content_type, "text/xml; charset=\"utf-16\"");
+    let decode = |bytes: &[u8]| {
+        assert_eq!(&bytes[..2], b"\xFF\xFE");
+        let units: Vec<u16> = bytes[2..]
+            .chunks(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        String::from_utf16(&units).unwrap()
+    };
+    let description = decode(&description);
+    assert!(description.starts_with(r#"<?xml version="1.0" encoding="UTF-16"?>"#));
+    assert!(description.contains("InternetGatewayDevice"));
+    assert!(decode(&soap).contains("192.0.2.1"));
+}
+
+#[tokio::test]
+async fn test_response_encoding_latin1() {
+    let (content_type, description, soap) = fetch_encoded(mock_igd::ResponseEncoding::Latin1).await;
+
+    assert_eq!(content_type, "text/xml; charset=\"iso-8859-1\"");
+    assert!(description.starts_with(br#"<?xml version="1.0" encoding="ISO-8859-1"?>"#));
+    assert!(soap.starts_with(br#"<?xml version="1.0" encoding="ISO-8859-1"?>"#));
+}
+
+// =============================================================================
+// Content-Type override tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_mock_content_type_override() {
+    use mock_igd::Mock;
+
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .register(
+            Mock::new(
+                Action::GetExternalIPAddress,
+                Responder::success().with_external_ip("192.0.2.1".parse().unwrap()),
+            )
+            .with_content_type("application/xml"),
+        )
+        .await;
+    server
+        .register(
+            Mock::new(Action::GetStatusInfo, Responder::error(501, "ActionFailed"))
+                .with_content_type("text/xml; charset=\"utf-16\""),
+        )
+        .await;
+
+    let send = |action: &'static str| {
+        reqwest::Client::new()
+            .post(server.control_url())
+            .header(
+                "SOAPAction",
+                format!("\"urn:schemas-upnp-org:service:WANIPConnection:1#{action}\""),
+            )
+            .body(format!(
+                r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+<s:Body><u:{action} xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1"/></s:Body>
+</s:Envelope>"#
+            ))
+            .send()
+    };
+
+    let response = send("GetExternalIPAddress").await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "application/xml");
+
+    let response = send("GetStatusInfo").await.unwrap();
+    assert_eq!(response.status(), 500);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/xml; charset=\"utf-16\""
+    );
+}
+
+// =============================================================================
+// Mock expiration tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_mock_expires_after() {
+    use mock_igd::Mock;
+    use std::time::Duration;
+
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(
+            Action::GetStatusInfo,
+            Responder::success().with_connection_status("Connected"),
+        )
+        .await;
+    server
+        .register(
+            Mock::new(
+                Action::GetStatusInfo,
+                Responder::success().with_connection_status("Disconnected"),
+            )
+            .with_priority(10)
+            .expires_after(Duration::from_millis(300)),
+        )
+        .await;
+
+    let url = server.control_url();
+    let get_status = || {
+        soap_request(
+            &url,
+            "GetStatusInfo",
+            r#"<u:GetStatusInfo xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+            </u:GetStatusInfo>"#,
+        )
+    };
+
+    let (_, body) = get_status().await;
+    assert!(body.contains("<NewConnectionStatus>Disconnected</NewConnectionStatus>"));
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+
+    let (_, body) = get_status().await;
+    assert!(body.contains("<NewConnectionStatus>Connected</NewConnectionStatus>"));
+}
+
+// =============================================================================
+// Conditional mock tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_mock_active_after_prior_request() {
+    use mock_igd::Mock;
+
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(
+            Action::get_specific_port_mapping_entry(),
+            Responder::error(714, "NoSuchEntryInArray"),
+        )
+        .await;
+    server
+        .mock(Action::add_port_mapping(), Responder::success())
+        .await;
+    server
+        .register(
+            Mock::new(
+                Action::get_specific_port_mapping_entry().with_external_port(8080),
+                Responder::success()
+                    .with_internal_port(8080)
+                    .with_internal_client("192.168.1.100"),
+            )
+            .with_priority(10)
+            .after(Action::add_port_mapping().with_external_port(8080)),
+        )
+        .await;
+
+    let client = reqwest::Client::new();
+    let url = server.control_url();
+    let get_entry = || {
+        soap_request_with_client(
+            &client,
+            &url,
+            "GetSpecificPortMappingEntry",
+            r#"<u:GetSpecificPortMappingEntry xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewRemoteHost></NewRemoteHost>
+<NewExternalPort>8080</NewExternalPort>
+<NewProtocol>TCP</NewProtocol>
+</u:GetSpecificPortMappingEntry>"#,
+        )
+    };
+    let add_mapping = |port: u16| {
+        let body = format!(
+            r#"<u:AddPortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewRemoteHost></NewRemoteHost>
+<NewExternalPort>{port}</NewExternalPort>
+<NewProtocol>TCP</NewProtocol>
+<NewInternalPort>{port}</NewInternalPort>
+<NewInternalClient>192.168.1.100</NewInternalClient>
+<NewEnabled>1</NewEnabled>
+<NewPortMappingDescription>test</NewPortMappingDescription>
+<NewLeaseDuration>0</NewLeaseDuration>
+</u:AddPortMapping>"#
+        );
+        let client = client.clone();
+        let url = url.clone();
+        async move { soap_request_with_client(&client, &url, "AddPortMapping", &body).await }
+    };
+
+    let (status, _) = get_entry().await;
+    assert_eq!(status, 500);
+
+    // A mapping for another port doesn't activate the mock
+    add_mapping(9090).await;
+    let (status, _) = get_entry().await;
+    assert_eq!(status, 500);
+
+    add_mapping(8080).await;
+    let (status, body) = get_entry().await;
+    assert_eq!(status, 200);
+    assert!(body.contains("<NewInternalClient>192.168.1.100</NewInternalClient>"));
+}
+
+// =============================================================================
+// Scenario tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_switch_scenarios() {
+    use mock_igd::Scenario;
+
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("192.0.2.1".parse().unwrap()),
+        )
+        .await;
+    server
+        .load_scenario(
+            Scenario::new("healthy")
+                .mock(
+                    Action::GetStatusInfo,
+                    Responder::success().with_connection_status("Connected"),
+                )
+                .mock(Action::add_port_mapping(), Responder::success()),
+        )
+        .await;
+    server
+        .load_scenario(
+            Scenario::new("degraded")
+                .mock(
+                    Action::GetStatusInfo,
+                    Responder::success().with_connection_status("Disconnected"),
+                )
+                .mock(
+                    Action::add_port_mapping(),
+                    Responder::error(501, "ActionFailed"),
+                ),
+        )
+        .await;
+
+    let client = reqwest::Client::new();
+    let url = server.control_url();
+    let request = |action: &'static str| {
+        let client = client.clone();
+        let url = url.clone();
+        async move {
+            let body = format!(
+                r#"<u:{action} xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1"></u:{action}>"#
+            );
+            soap_request_with_client(&client, &url, action, &body).await
+        }
+    };
+
+    assert!(server.switch_scenario("missing").await.is_err());
+    assert_eq!(server.active_scenario().await, None);
+
+    server.switch_scenario("healthy").await.unwrap();
+    let (_, body) = request("GetStatusInfo").await;
+    assert!(body.contains("<NewConnectionStatus>Connected</NewConnectionStatus>"));
+
+    server.switch_scenario("degraded").await.unwrap();
+    assert_eq!(server.active_scenario().await.as_deref(), Some("degraded"));
+    let (_, body) = request("GetStatusInfo").await;
+    assert!(body.contains("<NewConnectionStatus>Disconnected</NewConnectionStatus>"));
+    let (status, _) = request("AddPortMapping").await;
+    assert_eq!(status, 500);
+
+    // Mocks registered outside scenarios survive switches
+    let (status, body) = request("GetExternalIPAddress").await;
+    assert_eq!(status, 200);
+    assert!(body.contains("192.0.2.1"));
+
+    server.switch_scenario("healthy").await.unwrap();
+    let (status, _) = request("AddPortMapping").await;
+    assert_eq!(status, 200);
+}
+
+#[tokio::test]
+async fn test_scenario_mapping_quirks() {
+    use mock_igd::{MappingQuirk, Scenario};
+
+    let server = MockIgdServer::builder()
+        .with_port_mapping_table()
+        .start()
+        .await
+        .unwrap();
+    server.load_scenario(Scenario::new("healthy")).await;
+    server
+        .load_scenario(Scenario::new("lying").with_mapping_quirk(MappingQuirk::NotForwarded))
+        .await;
+    soap_request(
+        &server.control_url(),
+        "AddPortMapping",
+        &add_port_mapping_body(8080, 0),
+    )
+    .await;
+    assert!(server.is_port_reachable(8080, Protocol::TCP).await);
+
+    server.switch_scenario("lying").await.unwrap();
+    assert!(!server.is_port_reachable(8080, Protocol::TCP).await);
+
+    server.switch_scenario("healthy").await.unwrap();
+    assert!(server.is_port_reachable(8080, Protocol::TCP).await);
+}
+
+// =============================================================================
+// Recorded response tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_received_request_records_answering_mock() {
+    use mock_igd::responder::ResponseBody;
+
+    let server = MockIgdServer::start().await.unwrap();
+    let ip_mock = server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("192.0.2.1".parse().unwrap()),
+        )
+        .await;
+    let error_mock = server
+        .mock(
+            Action::add_port_mapping(),
+            Responder::error(718, "ConflictInMappingEntry"),
+        )
+        .await;
+
+    let client = reqwest::Client::new();
+    let url = server.control_url();
+    for action in ["GetExternalIPAddress", "AddPortMapping", "GetStatusInfo"] {
+        let body = format!(
+            r#"<u:{action} xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1"></u:{action}>"#
+        );
+        soap_request_with_client(&client, &url, action, &body).await;
+    }
+
+    let requests = server.received_requests().await;
+    assert_eq!(requests[0].mock_id, Some(ip_mock));
+    assert!(matches!(
+        &requests[0].response,
+        Some(ResponseBody::Soap(xml)) if xml.contains("192.0.2.1")
+    ));
+    assert_eq!(requests[1].mock_id, Some(error_mock));
+    assert!(matches!(
+        requests[1].response,
+        Some(ResponseBody::SoapFault { code: 718, .. })
+    ));
+    assert_eq!(requests[2].mock_id, None);
+    assert!(requests[2].response.is_none());
+}
+
+// =============================================================================
+// Simple server tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_start_simple() {
+    let server = MockIgdServer::start_simple("203.0.113.1".parse().unwrap())
+        .await
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let url = server.control_url();
+
+    let (status, body) = soap_request_with_client(
+        &client,
+        &url,
+        "GetExternalIPAddress",
+        r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetExternalIPAddress>"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert!(body.contains("<NewExternalIPAddress>203.0.113.1</NewExternalIPAddress>"));
+
+    let (status, _) = soap_request_with_client(
+        &client,
+        &url,
+        "AddPortMapping",
+        r#"<u:AddPortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewRemoteHost></NewRemoteHost>
+<NewExternalPort>8080</NewExternalPort>
+<NewProtocol>TCP</NewProtocol>
+<NewInternalPort>8080</NewInternalPort>
+<NewInternalClient>192.168.1.100</NewInternalClient>
+<NewEnabled>1</NewEnabled>
+<NewPortMappingDescription>test</NewPortMappingDescription>
+<NewLeaseDuration>0</NewLeaseDuration>
+</u:AddPortMapping>"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+
+    let (status, _) = soap_request_with_client(
+        &client,
+        &url,
+        "DeletePortMapping",
+        r#"<u:DeletePortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewRemoteHost></NewRemoteHost>
+<NewExternalPort>8080</NewExternalPort>
+<NewProtocol>TCP</NewProtocol>
+</u:DeletePortMapping>"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+
+    let (status, body) = soap_request_with_client(
+        &client,
+        &url,
+        "GetGenericPortMappingEntry",
+        r#"<u:GetGenericPortMappingEntry xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewPortMappingIndex>0</NewPortMappingIndex>
+</u:GetGenericPortMappingEntry>"#,
+    )
+    .await;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>713</errorCode>"));
+}
+
+// =============================================================================
+// Service type tests
+// =============================================================================
+
+#[test]
+fn test_service_type_urn_round_trip() {
+    use mock_igd::ServiceType;
+
+    let service = ServiceType::parse("urn:schemas-upnp-org:service:WANIPConnection:2");
+    assert_eq!(service, ServiceType::WANIPConnection(2));
+    assert_eq!(service.version(), Some(2));
+    assert_eq!(
+        service.urn(),
+        "urn:schemas-upnp-org:service:WANIPConnection:2"
+    );
+    assert_eq!(
+        ServiceType::WANCommonInterfaceConfig(1).to_string(),
+        "urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1"
+    );
+    assert_eq!(
+        ServiceType::parse("urn:example-com:service:Vendor:1"),
+        ServiceType::Other("urn:example-com:service:Vendor:1".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_service_type_in_requests_and_responses() {
+    use mock_igd::{Mock, ServiceType};
+
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("192.0.2.1".parse().unwrap()),
+        )
+        .await;
+    server
+        .register(
+            Mock::new(
+                Action::GetExternalIPAddress,
+                Responder::success().with_external_ip("192.0.2.2".parse().unwrap()),
+            )
+            .for_service(ServiceType::WANIPConnection(2))
+            .with_priority(10),
+        )
+        .await;
+
+    let send = |service: &'static str| {
+        reqwest::Client::new()
+            .post(server.control_url())
+            .header("SOAPAction", format!("\"{service}#GetExternalIPAddress\""))
+            .body(format!(
+                r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+<s:Body><u:GetExternalIPAddress xmlns:u="{service}"/></s:Body>
+</s:Envelope>"#
+            ))
+            .send()
+    };
+
+    let v1 = send("urn:schemas-upnp-org:service:WANIPConnection:1")
+        .await
+        .unwrap();
+    let v1 = v1.text().await.unwrap();
+    assert!(v1.contains("192.0.2.1"));
+    assert!(v1.contains(r#"xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1""#));
+
+    let v2 = send("urn:schemas-upnp-org:service:WANIPConnection:2")
+        .await
+        .unwrap();
+    let v2 = v2.text().await.unwrap();
+    assert!(v2.contains("192.0.2.2"));
+    assert!(v2.contains(r#"xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:2""#));
+
+    let requests = server.received_requests().await;
+    assert_eq!(requests[0].service_type, ServiceType::WANIPConnection(1));
+    assert_eq!(requests[1].service_type, ServiceType::WANIPConnection(2));
+}
+
+// =============================================================================
+// Unknown action tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_unknown_action_arguments() {
+    use mock_igd::matcher::SoapRequestBody;
+    use mock_igd::responder::ResponseBody;
+
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(
+            Action::any(),
+            Responder::custom(|request| match &request.body {
+                SoapRequestBody::Unknown(unknown)
+                    if unknown.argument("NewMode") == Some("fast") =>
+                {
+                    ResponseBody::SoapFault {
+                        code: 606,
+                        description: "Action not authorized".to_string(),
+                    }
+                }
+                _ => ResponseBody::SoapFault {
+                    code: 501,
+                    description: format!("ActionFailed ({} bytes)", request.raw_body.len()),
+                },
+            }),
+        )
+        .await;
+
+    let (status, body) = soap_request(
+        &server.control_url(),
+        "X_SetVendorMode",
+        r#"<u:X_SetVendorMode xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewMode>fast</NewMode>
+<NewFlags/>
+<NewLevel>3</NewLevel>
+</u:X_SetVendorMode>"#,
+    )
+    .await;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>606</errorCode>"));
+
+    let requests = server.received_requests().await;
+    let SoapRequestBody::Unknown(unknown) = &requests[0].body else {
+        panic!("expected an unknown request, got {:?}", requests[0].body);
+    };
+    assert_eq!(unknown.action_name, "X_SetVendorMode");
+    assert_eq!(
+        unknown.arguments,
+        vec![
+            ("NewMode".to_string(), "fast".to_string()),
+            ("NewFlags".to_string(), String::new()),
+            ("NewLevel".to_string(), "3".to_string()),
+        ]
+    );
+    assert!(requests[0].raw_body.contains("<NewLevel>3</NewLevel>"));
+}
+
+#[tokio::test]
+async fn test_arguments_on_known_actions() {
+    use mock_igd::responder::ResponseBody;
+
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(
+            Action::add_port_mapping(),
+            Responder::custom(|request| {
+                // The typed request can't tell an empty description from a missing one
+                if request.argument("NewPortMappingDescription") == Some("") {
+                    ResponseBody::SoapFault {
+                        code: 402,
+                        description: "Invalid Args".to_string(),
+                    }
+                } else {
+                    ResponseBody::Soap(String::new())
+                }
+            }),
+        )
+        .await;
+
+    let (status, _) = soap_request(
+        &server.control_url(),
+        "AddPortMapping",
+        r#"<u:AddPortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewRemoteHost></NewRemoteHost>
+<NewExternalPort>8080</NewExternalPort>
+<NewProtocol>TCP</NewProtocol>
+<NewInternalPort>8080</NewInternalPort>
+<NewInternalClient>192.168.1.100</NewInternalClient>
+<NewEnabled>1</NewEnabled>
+<NewPortMappingDescription></NewPortMappingDescription>
+<NewLeaseDuration>0</NewLeaseDuration>
+</u:AddPortMapping>"#,
+    )
+    .await;
+    assert_eq!(status, 500);
+
+    let requests = server.received_requests().await;
+    let names: Vec<_> = requests[0]
+        .arguments
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "NewRemoteHost",
+            "NewExternalPort",
+            "NewProtocol",
+            "NewInternalPort",
+            "NewInternalClient",
+            "NewEnabled",
+            "NewPortMappingDescription",
+            "NewLeaseDuration",
+        ]
+    );
+    assert_eq!(requests[0].arguments[1].1, "8080");
+}
+
+// =============================================================================
+// Action coverage tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_action_coverage() {
+    let server = MockIgdServer::start().await.unwrap();
+    server.mock(Action::any(), Responder::success()).await;
+
+    let client = reqwest::Client::new();
+    let url = server.control_url();
+    for action in [
+        "GetExternalIPAddress",
+        "GetStatusInfo",
+        "GetStatusInfo",
+        "X_Vendor",
+    ] {
+        let body = format!(
+            r#"<u:{action} xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1"></u:{action}>"#
+        );
+        soap_request_with_client(&client, &url, action, &body).await;
+    }
+
+    let coverage = server.action_coverage().await;
+    assert_eq!(coverage.exercised.get("GetExternalIPAddress"), Some(&1));
+    assert_eq!(coverage.exercised.get("GetStatusInfo"), Some(&2));
+    assert_eq!(coverage.exercised.len(), 2);
+    assert_eq!(coverage.missing.len(), 7);
+    assert!(coverage.missing.contains(&"AddPortMapping".to_string()));
+    assert_eq!(coverage.unknown.get("X_Vendor"), Some(&1));
+
+    let report = coverage.to_string();
+    assert!(report.starts_with("IGD action coverage: 2/9"));
+    assert!(report.contains("[x] GetStatusInfo (2)"));
+    assert!(report.contains("[ ] AddPortMapping"));
+}
+
+// =============================================================================
+// Compliance check tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_compliance_violations() {
+    use mock_igd::ComplianceRule;
+
+    let server = MockIgdServer::builder()
+        .with_compliance_checks()
+        .start()
+        .await
+        .unwrap();
+    server.mock(Action::any(), Responder::success()).await;
+
+    // Compliant request
+    soap_request(
+        &server.control_url(),
+        "DeletePortMapping",
+        r#"<u:DeletePortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewRemoteHost></NewRemoteHost>
+<NewExternalPort>8080</NewExternalPort>
+<NewProtocol>TCP</NewProtocol>
+</u:DeletePortMapping>"#,
+    )
+    .await;
+    assert!(server.compliance_violations().await.is_empty());
+
+    // Unquoted SOAPAction, arguments out of order, missing arguments and an
+    // invalid boolean
+    reqwest::Client::new()
+        .post(server.control_url())
+        .header(
+            "SOAPAction",
+            "urn:schemas-upnp-org:service:WANIPConnection:1#AddPortMapping",
+        )
+        .body(
+            r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+<s:Body>
+<u:AddPortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewExternalPort>8080</NewExternalPort>
+<NewRemoteHost></NewRemoteHost>
+<NewProtocol>TCP</NewProtocol>
+<NewInternalPort>8080</NewInternalPort>
+<NewInternalClient>192.168.1.100</NewInternalClient>
+<NewEnabled>enabled</NewEnabled>
+</u:AddPortMapping>
+</s:Body>
+</s:Envelope>"#,
+        )
+        .send()
+        .await
+        .unwrap();
+
+    let rules: Vec<_> = server
+        .compliance_violations()
+        .await
+        .iter()
+        .map(|violation| violation.rule)
+        .collect();
+    assert_eq!(
+        rules,
+        [
+            ComplianceRule::QuotedSoapAction,
+            ComplianceRule::RequiredArguments,
+            ComplianceRule::ArgumentOrder,
+            ComplianceRule::BooleanValues,
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_compliance_rule_levels() {
+    use mock_igd::{ComplianceLevel, ComplianceRule};
+
+    let server = MockIgdServer::builder()
+        .with_compliance_rule(ComplianceRule::BooleanValues, ComplianceLevel::Reject)
+        .with_compliance_rule(ComplianceRule::ArgumentOrder, ComplianceLevel::Ignore)
+        .start()
+        .await
+        .unwrap();
+    server.mock(Action::any(), Responder::success()).await;
+
+    let client = reqwest::Client::new();
+    let url = server.control_url();
+    let add_mapping = |enabled: &'static str| {
+        let body = format!(
+            r#"<u:AddPortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewExternalPort>8080</NewExternalPort>
+<NewRemoteHost></NewRemoteHost>
+<NewProtocol>TCP</NewProtocol>
+<NewInternalPort>8080</NewInternalPort>
+<NewInternalClient>192.168.1.100</NewInternalClient>
+<NewEnabled>{enabled}</NewEnabled>
+<NewPortMappingDescription>test</NewPortMappingDescription>
+<NewLeaseDuration>0</NewLeaseDuration>
+</u:AddPortMapping>"#
+        );
+        let client = client.clone();
+        let url = url.clone();
+        async move { soap_request_with_client(&client, &url, "AddPortMapping", &body).await }
+    };
+
+    // Misordered arguments are ignored
+    let (status, _) = add_mapping("1").await;
+    assert_eq!(status, 200);
+    assert!(server.compliance_violations().await.is_empty());
+
+    // Invalid booleans are rejected like a strict gateway would
+    let (status, body) = add_mapping("on").await;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>402</errorCode>"));
+    assert_eq!(server.compliance_violations().await.len(), 1);
+    assert_eq!(server.received_requests().await.len(), 1);
+}
+
+// =============================================================================
+// Port mapping table tests
+// =============================================================================
+
+/// AddPortMapping arguments for a TCP mapping to 192.168.1.100.
+fn add_port_mapping_body(external_port: u16, lease_duration: u32) -> String {
+    format!(
+        r#"<u:AddPortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewRemoteHost></NewRemoteHost>
+<NewExternalPort>{external_port}</NewExternalPort>
+<NewProtocol>TCP</NewProtocol>
+<NewInternalPort>8080</NewInternalPort>
+<NewInternalClient>192.168.1.100</NewInternalClient>
+<NewEnabled>1</NewEnabled>
+<NewPortMappingDescription>test</NewPortMappingDescription>
+<NewLeaseDuration>{lease_duration}</NewLeaseDuration>
+</u:AddPortMapping>"#
+    )
+}
+
+/// GetSpecificPortMappingEntry arguments for a TCP mapping.
+fn get_specific_port_mapping_entry_body(external_port: u16) -> String {
+    format!(
+        r#"<u:GetSpecificPortMappingEntry xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewRemoteHost></NewRemoteHost>
+<NewExternalPort>{external_port}</NewExternalPort>
+<NewProtocol>TCP</NewProtocol>
+</u:GetSpecificPortMappingEntry>"#
+    )
+}
+
+#[tokio::test]
+async fn test_port_mapping_table() {
+    let server = MockIgdServer::builder()
+        .with_port_mapping_table()
+        .start()
+        .await
+        .unwrap();
+    let url = server.control_url();
+
+    let (status, _) =
+        soap_request(&url, "AddPortMapping", &add_port_mapping_body(8080, 3600)).await;
+    assert_eq!(status, 200);
+
+    let (status, body) = soap_request(
+        &url,
+        "GetSpecificPortMappingEntry",
+        &get_specific_port_mapping_entry_body(8080),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert!(body.contains("<NewInternalClient>192.168.1.100</NewInternalClient>"));
+    assert!(body.contains("<NewLeaseDuration>3600</NewLeaseDuration>"));
+
+    let (status, body) = soap_request(
+        &url,
+        "GetSpecificPortMappingEntry",
+        &get_specific_port_mapping_entry_body(9090),
+    )
+    .await;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>714</errorCode>"));
+
+    let mappings = server.port_mappings().await;
+    assert_eq!(mappings.len(), 1);
+    assert_eq!(mappings[0].external_port, 8080);
+    assert_eq!(mappings[0].protocol, Protocol::TCP);
+}
+
+#[tokio::test]
+async fn test_mapping_quirk_ignore_lease_duration() {
+    use mock_igd::MappingQuirk;
+
+    let server = MockIgdServer::builder()
+        .with_mapping_quirk(MappingQuirk::IgnoreLeaseDuration)
+        .start()
+        .await
+        .unwrap();
+    let url = server.control_url();
+
+    let (status, _) =
+        soap_request(&url, "AddPortMapping", &add_port_mapping_body(8080, 3600)).await;
+    assert_eq!(status, 200);
+
+    let (status, body) = soap_request(
+        &url,
+        "GetSpecificPortMappingEntry",
+        &get_specific_port_mapping_entry_body(8080),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert!(body.contains("<NewLeaseDuration>0</NewLeaseDuration>"));
+}
+
+#[tokio::test]
+async fn test_mapping_quirk_shift_external_port() {
+    use mock_igd::MappingQuirk;
+
+    let server = MockIgdServer::builder()
+        .with_mapping_quirk(MappingQuirk::ShiftExternalPort(1000))
+        .start()
+        .await
+        .unwrap();
+    let url = server.control_url();
+
+    // The request "succeeds", but no mapping exists on the requested port
+    let (status, _) = soap_request(&url, "AddPortMapping", &add_port_mapping_body(8080, 0)).await;
+    assert_eq!(status, 200);
+    let (status, body) = soap_request(
+        &url,
+        "GetSpecificPortMappingEntry",
+        &get_specific_port_mapping_entry_body(8080),
+    )
+    .await;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>714</errorCode>"));
+
+    let (status, body) = soap_request(
+        &url,
+        "GetGenericPortMappingEntry",
+        r#"<u:GetGenericPortMappingEntry xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewPortMappingIndex>0</NewPortMappingIndex>
+</u:GetGenericPortMappingEntry>"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert!(body.contains("<NewExternalPort>9080</NewExternalPort>"));
+
+    // AddAnyPortMapping reports the port actually reserved
+    let add_any = add_port_mapping_body(8081, 0).replace("AddPortMapping", "AddAnyPortMapping");
+    let (status, body) = soap_request(&url, "AddAnyPortMapping", &add_any).await;
+    assert_eq!(status, 200);
+    assert!(body.contains("<NewReservedPort>9081</NewReservedPort>"));
+
+    // No port left above the requested one
+    let (status, body) =
+        soap_request(&url, "AddPortMapping", &add_port_mapping_body(65000, 0)).await;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>728</errorCode>"));
+}
+
+#[tokio::test]
+async fn test_add_any_port_mapping_reads_arguments_like_add_port_mapping() {
+    let server = MockIgdServer::builder()
+        .with_port_mapping_table()
+        .start()
+        .await
+        .unwrap();
+
+    // No protocol and a differently cased NewEnabled
+    let add_any = add_port_mapping_body(8080, 0)
+        .replace("AddPortMapping", "AddAnyPortMapping")
+        .replace("<NewProtocol>TCP</NewProtocol>\n", "")
+        .replace(
+            "<NewEnabled>1</NewEnabled>",
+            "<NewEnabled>True</NewEnabled>",
+        );
+    let (status, _) = soap_request(&server.control_url(), "AddAnyPortMapping", &add_any).await;
+    assert_eq!(status, 200);
+    let mappings = server.port_mappings().await;
+    assert_eq!(mappings.len(), 1);
+    assert_eq!(mappings[0].protocol, Protocol::TCP);
+    assert!(mappings[0].enabled);
+}
+
+#[tokio::test]
+async fn test_port_mappings_across_reboot() {
+    for persistent in [false, true] {
+        let builder = MockIgdServer::builder().with_port_mapping_table();
+        let builder = if persistent {
+            builder.with_persistent_mappings()
+        } else {
+            builder
+        };
+        let server = builder.start().await.unwrap();
+
+        let (status, _) = soap_request(
+            &server.control_url(),
+            "AddPortMapping",
+            &add_port_mapping_body(8080, 0),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(server.port_mappings().await.len(), 1);
+
+        server.simulate_reboot().await;
+        let expected = if persistent { 1 } else { 0 };
+        assert_eq!(server.port_mappings().await.len(), expected);
+    }
+}
+
+// =============================================================================
+// Eventing tests
+// =============================================================================
+
+/// Accept one NOTIFY request on `listener`, acknowledge it and return it.
+async fn accept_notify(listener: &tokio::net::TcpListener) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (mut stream, _) =
+        tokio::time::timeout(std::time::Duration::from_secs(5), listener.accept())
+            .await
+            .expect("no NOTIFY received")
+            .unwrap();
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !String::from_utf8_lossy(&request).contains("</e:propertyset>") {
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(n > 0, "connection closed before the NOTIFY body");
+        request.extend_from_slice(&buf[..n]);
+    }
+    stream
+        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+        .await
+        .unwrap();
+    String::from_utf8(request).unwrap()
+}
+
+#[tokio::test]
+async fn test_port_mapping_number_of_entries_eventing() {
+    let server = MockIgdServer::builder()
+        .with_port_mapping_table()
+        .start()
+        .await
+        .unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let callback = format!("<http://{}/notify>", listener.local_addr().unwrap());
+
+    let client = reqwest::Client::new();
+    let subscribe = reqwest::Method::from_bytes(b"SUBSCRIBE").unwrap();
+    let response = client
+        .request(subscribe.clone(), server.event_url())
+        .header("CALLBACK", &callback)
+        .header("NT", "upnp:event")
+        .header("TIMEOUT", "Second-300")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["TIMEOUT"], "Second-300");
+    let sid = response.headers()["SID"].to_str().unwrap().to_string();
+
+    // Initial event
+    let notify = accept_notify(&listener).await;
+    assert!(notify.starts_with("NOTIFY /notify HTTP/1.1"));
+    assert!(notify.contains(&format!("SID: {sid}")));
+    assert!(notify.contains("SEQ: 0"));
+    assert!(notify.contains("<PortMappingNumberOfEntries>0</PortMappingNumberOfEntries>"));
+
+    // Adding a mapping is evented
+    soap_request(
+        &server.control_url(),
+        "AddPortMapping",
+        &add_port_mapping_body(8080, 0),
+    )
+    .await;
+    let notify = accept_notify(&listener).await;
+    assert!(notify.contains("SEQ: 1"));
+    assert!(notify.contains("<PortMappingNumberOfEntries>1</PortMappingNumberOfEntries>"));
+
+    // Renewal must not repeat NT/CALLBACK
+    let response = client
+        .request(subscribe.clone(), server.event_url())
+        .header("SID", &sid)
+        .header("NT", "upnp:event")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    let unsubscribe = reqwest::Method::from_bytes(b"UNSUBSCRIBE").unwrap();
+    let response = client
+        .request(unsubscribe.clone(), server.event_url())
+        .header("SID", &sid)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response = client
+        .request(unsubscribe, server.event_url())
+        .header("SID", &sid)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 412);
+}
+
+#[tokio::test]
+async fn test_external_mapping_changes() {
+    use mock_igd::PortMapping;
+
+    let server = MockIgdServer::builder()
+        .with_port_mapping_table()
+        .start()
+        .await
+        .unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let response = reqwest::Client::new()
+        .request(
+            reqwest::Method::from_bytes(b"SUBSCRIBE").unwrap(),
+            server.event_url(),
+        )
+        .header(
+            "CALLBACK",
+            format!("<http://{}/>", listener.local_addr().unwrap()),
+        )
+        .header("NT", "upnp:event")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    accept_notify(&listener).await;
+
+    server
+        .add_external_mapping(PortMapping {
+            remote_host: String::new(),
+            external_port: 25565,
+            protocol: Protocol::TCP,
+            internal_port: 25565,
+            internal_client: "192.168.1.50".parse().unwrap(),
+            enabled: true,
+            description: "game server".to_string(),
+            lease_duration: 0,
+        })
+        .await;
+    let notify = accept_notify(&listener).await;
+    assert!(notify.contains("<PortMappingNumberOfEntries>1</PortMappingNumberOfEntries>"));
+
+    // The client sees the mapping it didn't create
+    let (status, body) = soap_request(
+        &server.control_url(),
+        "GetGenericPortMappingEntry",
+        r#"<u:GetGenericPortMappingEntry xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewPortMappingIndex>0</NewPortMappingIndex>
+</u:GetGenericPortMappingEntry>"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert!(body.contains("<NewInternalClient>192.168.1.50</NewInternalClient>"));
+
+    assert!(server.remove_external_mapping(25565, Protocol::TCP).await);
+    let notify = accept_notify(&listener).await;
+    assert!(notify.contains("<PortMappingNumberOfEntries>0</PortMappingNumberOfEntries>"));
+    assert!(!server.remove_external_mapping(25565, Protocol::TCP).await);
+}
+
+// =============================================================================
+// Description endpoint tests
+// =============================================================================
+
+#[tokio::test]
+async fn test_without_description_endpoints() {
+    let server = MockIgdServer::builder()
+        .without_description()
+        .without_scpds()
+        .start()
+        .await
+        .unwrap();
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("203.0.113.1".parse().unwrap()),
+        )
+        .await;
+
+    for path in ["/rootDesc.xml", "/WANIPCn.xml", "/WANCommonIFC1.xml"] {
+        let response = reqwest::get(format!("{}{path}", server.url()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404, "{path}");
+    }
+
+    // Control URLs still work
+    let (status, _) = soap_request(
+        &server.control_url(),
+        "GetExternalIPAddress",
+        r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1"/>"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+}
+
+#[tokio::test]
+async fn test_document_overrides() {
+    use mock_igd::ServiceType;
+
+    let server = MockIgdServer::start().await.unwrap();
+    let description = r#"<?xml version="1.0"?><root xmlns="urn:schemas-upnp-org:device-1-0"/>"#;
+    let scpd = r#"<?xml version="1.0"?><scpd xmlns="urn:schemas-upnp-org:service-1-0"/>"#;
+
+    server.set_description_xml(description).await;
+    server
+        .set_scpd_xml(ServiceType::WANIPConnection(1), scpd)
+        .await;
+
+    let get = |path: &str| reqwest::get(format!("{}{path}", server.url()));
+    assert_eq!(
+        get("/rootDesc.xml").await.unwrap().text().await.unwrap(),
+        description
+    );
+    assert_eq!(
+        get("/WANIPCn.xml").await.unwrap().text().await.unwrap(),
+        scpd
+    );
+    // Other documents are still generated
+    let common = get("/WANCommonIFC1.xml")
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(common.contains("GetTotalBytesSent"));
+}
+
+#[tokio::test]
+async fn test_unique_udns() {
+    let first = MockIgdServer::start().await.unwrap();
+    let second = MockIgdServer::start().await.unwrap();
+
+    assert!(first.udn().starts_with("uuid:"));
+    assert_ne!(first.udn(), second.udn());
+
+    let description = reqwest::get(first.description_url())
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(description.contains(&format!("<UDN>{}</UDN>", first.udn())));
+    assert!(!description.contains(second.udn()));
+}
+
+#[tokio::test]
+async fn test_seeded_randomness() {
+    let first = MockIgdServer::builder()
+        .with_seed(42)
+        .start()
+        .await
+        .unwrap();
+    let second = MockIgdServer::builder()
+        .with_seed(42)
+        .start()
+        .await
+        .unwrap();
+    let other = MockIgdServer::builder()
+        .with_seed(43)
+        .start()
+        .await
+        .unwrap();
+
+    assert_eq!(first.seed(), 42);
+    assert_eq!(first.udn(), second.udn());
+    assert_ne!(first.udn(), other.udn());
+
+    // Unseeded servers report the seed they picked
+    let unseeded = MockIgdServer::start().await.unwrap();
+    let replay = MockIgdServer::builder()
+        .with_seed(unseeded.seed())
+        .start()
+        .await
+        .unwrap();
+    assert_eq!(unseeded.udn(), replay.udn());
+}
+
+#[tokio::test]
+async fn test_ssdp_mx_delay() {
+    let server = MockIgdServer::builder()
+        .ssdp_port(0)
+        .with_ssdp_mx_delay()
+        .start()
+        .await;
+
+    let server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let request = "M-SEARCH * HTTP/1.1\r\n\
+                   HOST: 239.255.255.250:1900\r\n\
+                   MAN: \"ssdp:discover\"\r\n\
+                   MX: 1\r\n\
+                   ST: ssdp:all\r\n\
+                   \r\n";
+    socket
+        .send_to(request.as_bytes(), server.ssdp_addr().unwrap())
+        .await
+        .unwrap();
+
+    // Answered within MX, without holding up the server
+    let mut buf = [0u8; 2048];
+    let response = socket.recv_from(&mut buf);
+    let (len, _) = tokio::time::timeout(std::time::Duration::from_millis(1500), response)
+        .await
+        .expect("no search response within MX")
+        .unwrap();
+    assert!(String::from_utf8_lossy(&buf[..len]).starts_with("HTTP/1.1 200 OK"));
+}
+
+#[tokio::test]
+async fn test_interceptor() {
+    use mock_igd::responder::ResponseBody;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let observed = Arc::new(AtomicUsize::new(0));
+    let counter = observed.clone();
+    let server = MockIgdServer::builder()
+        .with_interceptor(move |request, planned| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            match request.argument("NewExternalPort") {
+                Some("22") => Some(ResponseBody::SoapFault {
+                    code: 718,
+                    description: "ConflictInMappingEntry".to_string(),
+                }),
+                _ => planned,
+            }
+        })
+        .start()
+        .await
+        .unwrap();
+    server
+        .mock(Action::add_port_mapping(), Responder::success())
+        .await;
+
+    let (status, _) = soap_request(
+        &server.control_url(),
+        "AddPortMapping",
+        &add_port_mapping_body(8080, 0),
+    )
+    .await;
+    assert_eq!(status, 200);
+
+    let (status, body) = soap_request(
+        &server.control_url(),
+        "AddPortMapping",
+        &add_port_mapping_body(22, 0),
+    )
+    .await;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>718</errorCode>"));
+
+    // Unmatched requests reach the hook too
+    let (status, _) = soap_request(
+        &server.control_url(),
+        "GetStatusInfo",
+        r#"<u:GetStatusInfo xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1"/>"#,
+    )
+    .await;
+    assert_eq!(status, 500);
+    assert_eq!(observed.load(Ordering::SeqCst), 3);
+}
+
+/// Fetch the description over an existing connection and return the
+/// response head.
+async fn get_description_on(stream: &mut tokio::net::TcpStream) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    stream
+        .write_all(b"GET /rootDesc.xml HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let text = String::from_utf8_lossy(&response).to_string();
+        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+            let head = head.to_ascii_lowercase();
+            let length: usize = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map(|value| value.trim().parse().unwrap())
+                .unwrap();
+            if body.len() >= length {
+                return head;
+            }
+        }
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(n > 0, "connection closed before the response ended");
+        response.extend_from_slice(&buf[..n]);
+    }
+}
+
+/// Whether the server closes the connection within a second.
+async fn is_closed(stream: &mut tokio::net::TcpStream) -> bool {
+    use tokio::io::AsyncReadExt;
+
+    let mut buf = [0u8; 16];
+    let read = stream.read(&mut buf);
+    matches!(
+        tokio::time::timeout(std::time::Duration::from_secs(1), read).await,
+        Ok(Ok(0) | Err(_))
+    )
+}
+
+#[tokio::test]
+async fn test_keep_alive_close() {
+    use mock_igd::KeepAlive;
+
+    let server = MockIgdServer::builder()
+        .with_keep_alive(KeepAlive::close())
+        .start()
+        .await
+        .unwrap();
+    let mut stream = tokio::net::TcpStream::connect(server.http_addr())
+        .await
+        .unwrap();
+
+    let head = get_description_on(&mut stream).await;
+    assert!(head.contains("connection: close"));
+    assert!(is_closed(&mut stream).await);
+}
+
+#[tokio::test]
+async fn test_keep_alive_max_requests() {
+    use mock_igd::KeepAlive;
+
+    let server = MockIgdServer::builder()
+        .with_keep_alive(KeepAlive::enabled().with_max_requests(2))
+        .start()
+        .await
+        .unwrap();
+    let mut stream = tokio::net::TcpStream::connect(server.http_addr())
+        .await
+        .unwrap();
+
+    let head = get_description_on(&mut stream).await;
+    assert!(!head.contains("connection: close"));
+    let head = get_description_on(&mut stream).await;
+    assert!(head.contains("connection: close"));
+    assert!(is_closed(&mut stream).await);
+}
+
+#[tokio::test]
+async fn test_keep_alive_idle_timeout() {
+    use mock_igd::KeepAlive;
+
+    let server = MockIgdServer::builder()
+        .with_keep_alive(
+            KeepAlive::enabled().with_idle_timeout(std::time::Duration::from_millis(200)),
+        )
+        .start()
+        .await
+        .unwrap();
+    let mut stream = tokio::net::TcpStream::connect(server.http_addr())
+        .await
+        .unwrap();
+
+    let head = get_description_on(&mut stream).await;
+    assert!(!head.contains("connection: close"));
+    assert!(is_closed(&mut stream).await);
+
+    // The SOAP endpoints still see the client address
+    let (status, body) = soap_request(
+        &server.control_url(),
+        "GetStatusInfo",
+        r#"<u:GetStatusInfo xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1"/>"#,
+    )
+    .await;
+    assert_eq!(status, 500);
+    assert!(body.contains("Invalid Action"));
+}
+
+#[tokio::test]
+async fn test_restart_keeps_mocks() {
+    let mut server = MockIgdServer::builder().ssdp_port(0).start().await.unwrap();
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("203.0.113.7".parse().unwrap()),
+        )
+        .await;
+    let http_addr = server.http_addr();
+    let ssdp_addr = server.ssdp_addr();
+
+    server.restart().await.unwrap();
+
+    assert_eq!(server.http_addr(), http_addr);
+    assert_eq!(server.ssdp_addr(), ssdp_addr);
+    let (status, body) = soap_request(
+        &server.control_url(),
+        "GetExternalIPAddress",
+        r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1"/>"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert!(body.contains("203.0.113.7"));
+
+    if let Some(ssdp_addr) = ssdp_addr {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let request = "M-SEARCH * HTTP/1.1\r\n\
+                       HOST: 239.255.255.250:1900\r\n\
+                       MAN: \"ssdp:discover\"\r\n\
+                       MX: 1\r\n\
+                       ST: ssdp:all\r\n\
+                       \r\n";
+        socket.send_to(request.as_bytes(), ssdp_addr).await.unwrap();
+        let mut buf = [0u8; 2048];
+        let response = socket.recv_from(&mut buf);
+        tokio::time::timeout(std::time::Duration::from_secs(1), response)
+            .await
+            .expect("no search response after restart")
+            .unwrap();
+        assert_eq!(server.received_ssdp_requests().await.len(), 1);
+    }
+}
+
+#[tokio::test]
+async fn test_reserved_port() {
+    let reserved = MockIgdServer::reserve_port().unwrap();
+    let port = reserved.port();
+    assert_eq!(reserved.addr().port(), port);
+
+    // The port stays taken until the server starts
+    assert!(std::net::TcpListener::bind(("127.0.0.1", port)).is_err());
+
+    let server = MockIgdServer::builder()
+        .with_reserved_port(reserved)
+        .start()
+        .await
+        .unwrap();
+    assert_eq!(server.http_addr().port(), port);
+    let response = reqwest::get(server.description_url()).await.unwrap();
+    assert_eq!(response.status(), 200);
+}
+
+#[tokio::test]
+async fn test_user_provided_sockets() {
+    let listener = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let ssdp_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let ssdp_addr = ssdp_socket.local_addr().unwrap();
+
+    let server = MockIgdServer::builder()
+        .with_listener(listener)
+        .with_ssdp_socket(ssdp_socket)
+        .start()
+        .await
+        .unwrap();
+
+    assert_eq!(server.http_addr(), ([127, 0, 0, 1], port).into());
+    let response = reqwest::get(server.description_url()).await.unwrap();
+    assert_eq!(response.status(), 200);
+
+    assert_eq!(server.ssdp_addr(), Some(ssdp_addr));
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let request = "M-SEARCH * HTTP/1.1\r\n\
+                   HOST: 239.255.255.250:1900\r\n\
+                   MAN: \"ssdp:discover\"\r\n\
+                   MX: 1\r\n\
+                   ST: ssdp:all\r\n\
+                   \r\n";
+    socket.send_to(request.as_bytes(), ssdp_addr).await.unwrap();
+    let mut buf = [0u8; 2048];
+    let (len, _) = tokio::time::timeout(
+        std::time::Duration::from_secs(1),
+        socket.recv_from(&mut buf),
+    )
+    .await
+    .expect("no search response")
+    .unwrap();
+    let response = String::from_utf8_lossy(&buf[..len]);
+    assert!(response.contains(&format!("LOCATION: {}", server.description_url())));
+}
+
+#[tokio::test]
+async fn test_health_reports_panicking_interceptor() {
+    let server = MockIgdServer::builder()
+        .with_interceptor(|_, _| panic!("interceptor bug"))
+        .start()
+        .await
+        .unwrap();
+    assert!(server.health().await.is_ok());
+
+    let (status, body) = soap_request(
+        &server.control_url(),
+        "GetExternalIPAddress",
+        r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1"/>"#,
+    )
+    .await;
+    assert_eq!(status, 500);
+    assert!(body.contains("interceptor bug"));
+
+    let error = server.health().await.unwrap_err();
+    assert!(error.to_string().contains("interceptor bug"));
+    assert_eq!(server.server_errors().await.len(), 1);
+}
+
+#[tokio::test]
+async fn test_responder_panics() {
+    use mock_igd::Mock;
+
+    let server = MockIgdServer::start().await.unwrap();
+    let mock_id = server
+        .register(
+            Mock::new(
+                Action::GetExternalIPAddress,
+                Responder::custom(|_| panic!("responder bug")),
+            )
+            .named("buggy"),
+        )
+        .await;
+
+    let (status, body) = soap_request(
+        &server.control_url(),
+        "GetExternalIPAddress",
+        r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1"/>"#,
+    )
+    .await;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>501</errorCode>"));
+    assert!(body.contains("ActionFailed"));
+
+    let panics = server.responder_panics().await;
+    assert_eq!(panics.len(), 1);
+    assert_eq!(panics[0].mock_id, mock_id);
+    assert_eq!(panics[0].mock_name.as_deref(), Some("buggy"));
+    assert_eq!(panics[0].action_name, "GetExternalIPAddress");
+    assert_eq!(panics[0].message, "responder bug");
+    assert!(server.health().await.is_err());
+
+    // The request is recorded like any other
+    assert_eq!(server.received_requests().await.len(), 1);
+}
+
+#[tokio::test]
+async fn test_delayed_responder() {
+    use mock_igd::responder::ResponseBody;
+    use std::time::{Duration, Instant};
+
+    let server = MockIgdServer::start().await.unwrap();
+    let custom = Responder::custom(|_| ResponseBody::Raw {
+        content_type: "text/plain".to_string(),
+        body: "late".to_string(),
+    });
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::delayed(
+                Responder::delayed(custom, Duration::from_millis(100)),
+                Duration::from_millis(100),
+            ),
+        )
+        .await;
+
+    let started = Instant::now();
+    let (status, body) = soap_request(
+        &server.control_url(),
+        "GetExternalIPAddress",
+        r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1"/>"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body, "late");
+    assert!(started.elapsed() >= Duration::from_millis(200));
+
+    let received = server.received_requests().await;
+    assert_eq!(
+        received[0].responder.as_deref(),
+        Some("custom after 100ms after 100ms")
+    );
+    let stats = server.latency_stats().await;
+    assert!(stats["GetExternalIPAddress"].min >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn test_repeat_then_responder() {
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(
+            Action::add_port_mapping(),
+            Responder::repeat_then(
+                Responder::error(718, "ConflictInMappingEntry"),
+                3,
+                Responder::success(),
+            ),
+        )
+        .await;
+
+    let mut statuses = Vec::new();
+    for _ in 0..5 {
+        let (status, _) = soap_request(
+            &server.control_url(),
+            "AddPortMapping",
+            &add_port_mapping_body(80, 0),
+        )
+        .await;
+        statuses.push(status);
+    }
+    assert_eq!(statuses, [500, 500, 500, 200, 200]);
+
+    let received = server.received_requests().await;
+    assert_eq!(
+        received[0].responder.as_deref(),
+        Some("error 718 (ConflictInMappingEntry) 3 times, then success")
+    );
+}
+
+#[tokio::test]
+async fn test_success_response_with_vendor_fields() {
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(
+            Action::GetStatusInfo,
+            Responder::success()
+                .with_dns_servers(["192.168.178.1".parse().unwrap(), "8.8.8.8".parse().unwrap()])
+                .with_vendor_field("NewX_AVM_DE_DNSServer1", "192.168.178.1")
+                .with_vendor_field("NewX_Vendor_Note", "a<b & c"),
+        )
+        .await;
+
+    let (status, body) = soap_request(
+        &server.control_url(),
+        "GetStatusInfo",
+        r#"<u:GetStatusInfo xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetStatusInfo>"#,
+    )
+    .await;
+
+    assert_eq!(status, 200);
+    assert!(body.contains(
+        "<NewUptime>0</NewUptime>\n\
+         <NewDNSServers>192.168.178.1,8.8.8.8</NewDNSServers>\n\
+         <NewX_AVM_DE_DNSServer1>192.168.178.1</NewX_AVM_DE_DNSServer1>\n\
+         <NewX_Vendor_Note>a&lt;b &amp; c</NewX_Vendor_Note>\n\
+         </u:GetStatusInfoResponse>"
+    ));
+}
+
+#[tokio::test]
+async fn test_protocol_parsing_and_wildcard() {
+    assert_eq!("tcp".parse::<Protocol>().unwrap(), Protocol::TCP);
+    assert_eq!(" Udp ".parse::<Protocol>().unwrap(), Protocol::UDP);
+    assert!("icmp".parse::<Protocol>().is_err());
+
+    let server = MockIgdServer::builder()
+        .with_port_mapping_table()
+        .start()
+        .await
+        .unwrap();
+    server
+        .mock(
+            Action::delete_port_mapping().with_protocol(Protocol::Any),
+            Responder::error(606, "Action not authorized"),
+        )
+        .await;
+    let url = server.control_url();
+
+    // Lowercase protocols reach the mapping table
+    let body = add_port_mapping_body(8080, 0).replace("TCP", "tcp");
+    let (status, _) = soap_request(&url, "AddPortMapping", &body).await;
+    assert_eq!(status, 200);
+    let body = get_specific_port_mapping_entry_body(8080).replace("TCP", "tcp");
+    let (status, _) = soap_request(&url, "GetSpecificPortMappingEntry", &body).await;
+    assert_eq!(status, 200);
+    assert_eq!(server.port_mappings().await[0].protocol, Protocol::TCP);
+
+    // The wildcard matches either protocol
+    for protocol in ["udp", "TCP"] {
+        let (status, body) = soap_request(
+            &url,
+            "DeletePortMapping",
+            &format!(
+                r#"<u:DeletePortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewRemoteHost></NewRemoteHost>
+<NewExternalPort>8080</NewExternalPort>
+<NewProtocol>{protocol}</NewProtocol>
+</u:DeletePortMapping>"#
+            ),
+        )
+        .await;
+        assert_eq!(status, 500);
+        assert!(body.contains("<errorCode>606</errorCode>"));
+    }
+}
+
+#[tokio::test]
+async fn test_received_request_port_mapping() {
+    use mock_igd::PortMapping;
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(Action::add_port_mapping(), Responder::success())
+        .await;
+
+    soap_request(
+        &server.control_url(),
+        "AddPortMapping",
+        &add_port_mapping_body(8080, 3600),
+    )
+    .await;
+    soap_request(
+        &server.control_url(),
+        "AddPortMapping",
+        &add_port_mapping_body(8081, 0).replace("192.168.1.100", "not-an-ip"),
+    )
+    .await;
+
+    let received = server.received_requests().await;
+    assert_eq!(
+        received[0].port_mapping(),
+        Some(PortMapping {
+            remote_host: String::new(),
+            external_port: 8080,
+            protocol: Protocol::TCP,
+            internal_port: 8080,
+            internal_client: "192.168.1.100".parse().unwrap(),
+            enabled: true,
+            description: "test".to_string(),
+            lease_duration: 3600,
+        })
+    );
+    assert_eq!(received[1].port_mapping(), None);
+}
+
+#[tokio::test]
+async fn test_success_response_from_mapping() {
+    use mock_igd::PortMapping;
+    use mock_igd::responder::SuccessResponseBuilder;
+
+    let mapping = PortMapping {
+        remote_host: String::new(),
+        external_port: 25565,
+        protocol: Protocol::UDP,
+        internal_port: 25566,
+        internal_client: "192.168.1.50".parse().unwrap(),
+        enabled: false,
+        description: "game".to_string(),
+        lease_duration: 600,
+    };
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(
+            Action::get_generic_port_mapping_entry(),
+            SuccessResponseBuilder::from_mapping(mapping),
+        )
+        .await;
+
+    let (status, body) = soap_request(
+        &server.control_url(),
+        "GetGenericPortMappingEntry",
+        r#"<u:GetGenericPortMappingEntry xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewPortMappingIndex>0</NewPortMappingIndex>
+</u:GetGenericPortMappingEntry>"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert!(body.contains("<NewExternalPort>25565</NewExternalPort>"));
+    assert!(body.contains("<NewProtocol>UDP</NewProtocol>"));
+    assert!(body.contains("<NewInternalPort>25566</NewInternalPort>"));
+    assert!(body.contains("<NewInternalClient>192.168.1.50</NewInternalClient>"));
+    assert!(body.contains("<NewEnabled>0</NewEnabled>"));
+    assert!(body.contains("<NewPortMappingDescription>game</NewPortMappingDescription>"));
+    assert!(body.contains("<NewLeaseDuration>600</NewLeaseDuration>"));
+}
+
+#[tokio::test]
+async fn test_port_mapping_list_responder() {
+    use mock_igd::PortMapping;
+
+    let mapping = |external_port, description: &str| PortMapping {
+        remote_host: String::new(),
+        external_port,
+        protocol: Protocol::TCP,
+        internal_port: external_port,
+        internal_client: "192.168.1.50".parse().unwrap(),
+        enabled: true,
+        description: description.to_string(),
+        lease_duration: 0,
+    };
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(
+            Action::get_generic_port_mapping_entry(),
+            Responder::port_mapping_list(vec![mapping(80, "web"), mapping(22, "ssh")]),
+        )
+        .await;
+
+    let mut descriptions = Vec::new();
+    for index in 0.. {
+        let (status, body) = soap_request(
+            &server.control_url(),
+            "GetGenericPortMappingEntry",
+            &format!(
+                r#"<u:GetGenericPortMappingEntry xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewPortMappingIndex>{index}</NewPortMappingIndex>
+</u:GetGenericPortMappingEntry>"#
+            ),
+        )
+        .await;
+        if status != 200 {
+            assert!(body.contains("<errorCode>713</errorCode>"));
+            break;
+        }
+        let start = body.find("<NewPortMappingDescription>").unwrap() + 27;
+        let end = body.find("</NewPortMappingDescription>").unwrap();
+        descriptions.push(body[start..end].to_string());
+    }
+    assert_eq!(descriptions, ["web", "ssh"]);
+
+    let received = server.received_requests().await;
+    assert_eq!(
+        received[0].responder.as_deref(),
+        Some("port mapping list (2 entries)")
+    );
+}
+
+#[tokio::test]
+async fn test_unmatched_index_invalid() {
+    let server = MockIgdServer::builder()
+        .with_unmatched_index_invalid()
+        .start()
+        .await
+        .unwrap();
+    server
+        .mock(
+            Action::get_generic_port_mapping_entry().with_index(0),
+            Responder::success()
+                .with_external_port(80)
+                .with_internal_port(80)
+                .with_internal_client("192.168.1.50"),
+        )
+        .await;
+
+    let get_entry = |index: u32| {
+        format!(
+            r#"<u:GetGenericPortMappingEntry xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewPortMappingIndex>{index}</NewPortMappingIndex>
+</u:GetGenericPortMappingEntry>"#
+        )
+    };
+    let url = server.control_url();
+    let (status, _) = soap_request(&url, "GetGenericPortMappingEntry", &get_entry(0)).await;
+    assert_eq!(status, 200);
+    let (status, body) = soap_request(&url, "GetGenericPortMappingEntry", &get_entry(1)).await;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>713</errorCode>"));
+    let requests = server.received_requests().await;
+    assert!(matches!(
+        requests[1].response,
+        Some(mock_igd::responder::ResponseBody::SoapFault { code: 713, .. })
+    ));
+    assert!(
+        server
+            .dump_requests()
+            .await
+            .contains("response: error 713 (SpecifiedArrayIndexInvalid), no matching mock")
+    );
+
+    // Other unmatched actions keep the default fault
+    let (_, body) = soap_request(
+        &url,
+        "GetStatusInfo",
+        r#"<u:GetStatusInfo xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetStatusInfo>"#,
+    )
+    .await;
+    assert!(body.contains("<errorCode>401</errorCode>"));
+}
+
+#[tokio::test]
+async fn test_received_request_accessors() {
+    let server = MockIgdServer::start().await.unwrap();
+    server.mock(Action::any(), Responder::success()).await;
+
+    soap_request(
+        &server.control_url(),
+        "DeletePortMapping",
+        r#"<u:DeletePortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewRemoteHost></NewRemoteHost>
+<NewExternalPort>8080</NewExternalPort>
+<NewProtocol>UDP</NewProtocol>
+</u:DeletePortMapping>"#,
+    )
+    .await;
+
+    let requests = server.received_requests().await;
+    mock_igd::assert_delete_port_mapping!(requests[0], external_port = 8080, protocol = "UDP");
+    assert!(requests[0].as_add_port_mapping().is_none());
+    assert!(requests[0].as_get_generic_port_mapping_entry().is_none());
+
+    let result = std::panic::catch_unwind(|| assert_add_port_mapping!(requests[0]));
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_assert_add_port_mapping() {
+    let server = MockIgdServer::start().await.unwrap();
+    server.mock(Action::any(), Responder::success()).await;
+
+    soap_request(
+        &server.control_url(),
+        "AddPortMapping",
+        &add_port_mapping_body(8080, 3600),
+    )
+    .await;
+
+    let requests = server.received_requests().await;
+    assert_add_port_mapping!(
+        requests[0],
+        external_port = 8080,
+        protocol = "TCP",
+        lease_duration = 3600,
+    );
+    let add = requests[0].as_add_port_mapping().unwrap();
+    assert_eq!(add.internal_port, 8080);
+    assert!(add.enabled);
+
+    let result =
+        std::panic::catch_unwind(|| assert_add_port_mapping!(requests[0], external_port = 80));
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_received_request_system_time() {
+    use std::time::{Duration, SystemTime};
+
+    let before = SystemTime::now();
+    let server = MockIgdServer::start().await.unwrap();
+    server.mock(Action::any(), Responder::success()).await;
+    assert!(server.start_time() >= before);
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    soap_request(
+        &server.control_url(),
+        "GetStatusInfo",
+        r#"<u:GetStatusInfo xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetStatusInfo>"#,
+    )
+    .await;
+
+    let request = &server.received_requests().await[0];
+    let since_start = request
+        .received_at
+        .duration_since(server.start_time())
+        .unwrap();
+    assert!(since_start >= Duration::from_millis(50));
+    assert!(request.received_at <= SystemTime::now());
+}
+
+#[tokio::test]
+async fn test_client_activity() {
+    let server = MockIgdServer::builder().ssdp_port(0).start().await;
+    let server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+    server.mock(Action::any(), Responder::success()).await;
+
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let search = "M-SEARCH * HTTP/1.1\r\n\
+                  HOST: 239.255.255.250:1900\r\n\
+                  MAN: \"ssdp:discover\"\r\n\
+                  MX: 1\r\n\
+                  ST: upnp:rootdevice\r\n\
+                  \r\n";
+    let ssdp_port = server.ssdp_addr().unwrap().port();
+    socket
+        .send_to(search.as_bytes(), ("127.0.0.1", ssdp_port))
+        .await
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    soap_request(
+        &server.control_url(),
+        "GetStatusInfo",
+        r#"<u:GetStatusInfo xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetStatusInfo>"#,
+    )
+    .await;
+
+    let requests = server.received_requests().await;
+    assert_eq!(requests[0].client.unwrap().ip().to_string(), "127.0.0.1");
+
+    let clients = server.client_activity().await;
+    assert_eq!(clients.len(), 1);
+    assert_eq!(clients[0].ip.to_string(), "127.0.0.1");
+    assert_eq!(clients[0].ssdp_requests.len(), 1);
+    assert_eq!(clients[0].requests.len(), 1);
+    assert!(clients[0].ssdp_requests[0].timestamp <= clients[0].requests[0].timestamp);
+}
+
+#[tokio::test]
+async fn test_mock_for_client() {
+    use std::net::IpAddr;
+
+    let first: IpAddr = "127.0.0.1".parse().unwrap();
+    let second: IpAddr = "127.0.0.2".parse().unwrap();
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock_for_client(first, Action::add_port_mapping(), Responder::success())
+        .await;
+    server
+        .mock_for_client(
+            second,
+            Action::add_port_mapping(),
+            Responder::error(718, "ConflictInMappingEntry"),
+        )
+        .await;
+
+    let mut statuses = Vec::new();
+    for ip in [first, second, "127.0.0.3".parse().unwrap()] {
+        let client = reqwest::Client::builder()
+            .local_address(ip)
+            .build()
+            .unwrap();
+        let (status, _) = soap_request_with_client(
+            &client,
+            &server.control_url(),
+            "AddPortMapping",
+            &add_port_mapping_body(8080, 0),
+        )
+        .await;
+        statuses.push(status);
+    }
+    // The third client matches no mock and gets 401 "Invalid Action"
+    assert_eq!(statuses, [200, 500, 500]);
+
+    let received = server.received_requests().await;
+    assert_eq!(received[1].client.unwrap().ip(), second);
+    assert!(received[1].responder.as_deref().unwrap().contains("718"));
+    assert_eq!(received[2].responder, None);
+}
+
+#[tokio::test]
+async fn test_byte_counter_wraparound() {
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(
+            Action::GetTotalBytesReceived,
+            Responder::byte_counter(u32::MAX as u64 - 1_500_000, 1_000_000),
+        )
+        .await;
+    server
+        .mock(
+            Action::GetTotalBytesSent,
+            Responder::success()
+                .with_total_bytes(u32::MAX as u64 + 42)
+                .with_counter_wraparound(),
+        )
+        .await;
+    let body = |action: &str| {
+        format!(
+            r#"<u:{action} xmlns:u="urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1">
+</u:{action}>"#
+        )
+    };
+    let url = server.control_url();
+
+    let mut counters = Vec::new();
+    for _ in 0..3 {
+        let (_, response) = soap_request(
+            &url,
+            "GetTotalBytesReceived",
+            &body("GetTotalBytesReceived"),
+        )
+        .await;
+        let start = response.find("<NewTotalBytesReceived>").unwrap() + 23;
+        let end = response.find("</NewTotalBytesReceived>").unwrap();
+        counters.push(response[start..end].parse::<u64>().unwrap());
+    }
+    assert_eq!(counters, [4_293_467_295, 4_294_467_295, 499_999]);
+
+    let (_, response) = soap_request(&url, "GetTotalBytesSent", &body("GetTotalBytesSent")).await;
+    assert!(response.contains("<NewTotalBytesSent>41</NewTotalBytesSent>"));
+}
+
+#[tokio::test]
+async fn test_link_rates() {
+    let server = MockIgdServer::builder()
+        .with_link_rates(2_000_000, 16_000_000)
+        .start()
+        .await
+        .unwrap();
+
+    let (status, body) = soap_request(
+        &server.control_url(),
+        "GetCommonLinkProperties",
+        r#"<u:GetCommonLinkProperties xmlns:u="urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1">
+</u:GetCommonLinkProperties>"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert!(body.contains("<NewLayer1UpstreamMaxBitRate>2000000</NewLayer1UpstreamMaxBitRate>"));
+    assert!(
+        body.contains("<NewLayer1DownstreamMaxBitRate>16000000</NewLayer1DownstreamMaxBitRate>")
+    );
+    let received = server.received_requests().await;
+    assert_eq!(received[0].responder.as_deref(), Some("link rates"));
+}
+
+#[tokio::test]
+async fn test_strict_callback_validation() {
+    use mock_igd::CallbackValidation;
+
+    let server = MockIgdServer::builder()
+        .with_callback_validation(CallbackValidation::Strict)
+        .start()
+        .await
+        .unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let lan = format!("<http://{}/notify>", listener.local_addr().unwrap());
+
+    let client = reqwest::Client::new();
+    let subscribe = |callback: String| {
+        client
+            .request(
+                reqwest::Method::from_bytes(b"SUBSCRIBE").unwrap(),
+                server.event_url(),
+            )
+            .header("CALLBACK", callback)
+            .header("NT", "upnp:event")
+            .send()
+    };
+    for callback in [
+        "<http://203.0.113.5/notify>".to_string(),
+        "<http://router.example/notify>".to_string(),
+        "<http://192.168.1.5:http/notify>".to_string(),
+        "http://192.168.1.5/notify".to_string(),
+        format!("{lan}<https://192.168.1.5/notify>"),
+    ] {
+        let response = subscribe(callback.clone()).await.unwrap();
+        assert_eq!(response.status(), 412, "{callback}");
+    }
+
+    let response = subscribe(format!("{lan} <http://[fe80::1]:8080/notify>"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    accept_notify(&listener).await;
+}
+
+#[tokio::test]
+async fn test_event_deliveries() {
+    use mock_igd::DeliveryOutcome;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = MockIgdServer::builder()
+        .with_port_mapping_table()
+        .start()
+        .await
+        .unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let callback = format!("http://{}/notify", listener.local_addr().unwrap());
+    let response = reqwest::Client::new()
+        .request(
+            reqwest::Method::from_bytes(b"SUBSCRIBE").unwrap(),
+            server.event_url(),
+        )
+        .header("CALLBACK", format!("<{callback}>"))
+        .header("NT", "upnp:event")
+        .send()
+        .await
+        .unwrap();
+    let sid = response.headers()["SID"].to_str().unwrap().to_string();
+    accept_notify(&listener).await;
+
+    // A subscriber rejecting the event
+    let url = server.control_url();
+    soap_request(&url, "AddPortMapping", &add_port_mapping_body(8080, 0)).await;
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut buf = [0u8; 4096];
+    let _ = stream.read(&mut buf).await.unwrap();
+    stream
+        .write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n")
+        .await
+        .unwrap();
+
+    // A subscriber that went away
+    drop(stream);
+    drop(listener);
+    soap_request(&url, "AddPortMapping", &add_port_mapping_body(8081, 0)).await;
+
+    let mut deliveries = Vec::new();
+    for _ in 0..50 {
+        deliveries = server.event_deliveries().await;
+        if deliveries.len() == 3 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(deliveries.len(), 3);
+    assert!(
+        deliveries
+            .iter()
+            .all(|d| d.sid == sid && d.callback == callback)
+    );
+    assert_eq!(
+        deliveries.iter().map(|d| d.seq).collect::<Vec<_>>(),
+        [0, 1, 2]
+    );
+    assert_eq!(deliveries[0].outcome, DeliveryOutcome::Status(200));
+    assert_eq!(deliveries[1].outcome, DeliveryOutcome::Status(503));
+    assert_eq!(
+        deliveries[1].properties,
+        [("PortMappingNumberOfEntries".to_string(), "1".to_string())]
+    );
+    assert!(matches!(deliveries[2].outcome, DeliveryOutcome::Failed(_)));
+}
+
+#[tokio::test]
+async fn test_event_seq_faults() {
+    use mock_igd::EventSeqFault;
+
+    let server = MockIgdServer::builder()
+        .with_port_mapping_table()
+        .with_event_seq_fault(EventSeqFault::ZeroAt(1))
+        .with_event_seq_fault(EventSeqFault::SkipAt(2))
+        .with_event_seq_fault(EventSeqFault::DuplicateAt(3))
+        .start()
+        .await
+        .unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    reqwest::Client::new()
+        .request(
+            reqwest::Method::from_bytes(b"SUBSCRIBE").unwrap(),
+            server.event_url(),
+        )
+        .header(
+            "CALLBACK",
+            format!("<http://{}/>", listener.local_addr().unwrap()),
+        )
+        .header("NT", "upnp:event")
+        .send()
+        .await
+        .unwrap();
+
+    let seq = |notify: String| {
+        let line = notify.lines().find(|l| l.starts_with("SEQ: ")).unwrap();
+        line[5..].parse::<u32>().unwrap()
+    };
+    let mut seqs = vec![seq(accept_notify(&listener).await)];
+    for port in [8080, 8081, 8082] {
+        soap_request(
+            &server.control_url(),
+            "AddPortMapping",
+            &add_port_mapping_body(port, 0),
+        )
+        .await;
+        seqs.push(seq(accept_notify(&listener).await));
+    }
+    assert_eq!(seqs, [0, 0, 3, 3]);
+}
+
+#[tokio::test]
+async fn test_event_moderation_coalesces_changes() {
+    let server = MockIgdServer::builder()
+        .with_port_mapping_table()
+        .with_event_moderation(
+            "PortMappingNumberOfEntries",
+            std::time::Duration::from_secs(1),
+        )
+        .start()
+        .await
+        .unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    reqwest::Client::new()
+        .request(
+            reqwest::Method::from_bytes(b"SUBSCRIBE").unwrap(),
+            server.event_url(),
+        )
+        .header(
+            "CALLBACK",
+            format!("<http://{}/>", listener.local_addr().unwrap()),
+        )
+        .header("NT", "upnp:event")
+        .send()
+        .await
+        .unwrap();
+    let initial = accept_notify(&listener).await;
+    assert!(initial.contains("<PortMappingNumberOfEntries>0</PortMappingNumberOfEntries>"));
+
+    for port in [8080, 8081, 8082] {
+        soap_request(
+            &server.control_url(),
+            "AddPortMapping",
+            &add_port_mapping_body(port, 0),
+        )
+        .await;
+    }
+    // The three changes arrive in one event once the interval has passed
+    let coalesced = accept_notify(&listener).await;
+    assert!(coalesced.contains("SEQ: 1"));
+    assert!(coalesced.contains("<PortMappingNumberOfEntries>3</PortMappingNumberOfEntries>"));
+    let more = tokio::time::timeout(std::time::Duration::from_millis(500), listener.accept()).await;
+    assert!(more.is_err());
+    assert_eq!(server.event_deliveries().await.len(), 2);
+}
+
+/// Ask the given WAN connection service for its external address.
+async fn get_external_ip(url: &str, service: &str) -> String {
+    let body = format!(
+        r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+<s:Body><u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:{service}:1"/></s:Body>
+</s:Envelope>"#
+    );
+    let response = reqwest::Client::new()
+        .post(url)
+        .header(
+            "SOAPAction",
+            format!("\"urn:schemas-upnp-org:service:{service}:1#GetExternalIPAddress\""),
+        )
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let text = response.text().await.unwrap();
+    let start = text.find("<NewExternalIPAddress>").unwrap() + "<NewExternalIPAddress>".len();
+    let end = text.find("</NewExternalIPAddress>").unwrap();
+    text[start..end].to_string()
+}
+
+#[tokio::test]
+async fn test_external_ip_shared_by_wan_connection_services() {
+    let server = MockIgdServer::builder()
+        .with_wan_ppp_connection()
+        .with_external_ip("203.0.113.1".parse().unwrap())
+        .start()
+        .await
+        .unwrap();
+    let description = reqwest::get(server.description_url())
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(description.contains("urn:schemas-upnp-org:service:WANPPPConnection:1"));
+
+    assert_eq!(
+        get_external_ip(&server.control_url(), "WANIPConnection").await,
+        "203.0.113.1"
+    );
+    assert_eq!(
+        get_external_ip(&server.ppp_control_url(), "WANPPPConnection").await,
+        "203.0.113.1"
+    );
+
+    server.set_external_ip("203.0.113.9".parse().unwrap()).await;
+    assert_eq!(
+        get_external_ip(&server.control_url(), "WANIPConnection").await,
+        "203.0.113.9"
+    );
+    assert_eq!(
+        get_external_ip(&server.ppp_control_url(), "WANPPPConnection").await,
+        "203.0.113.9"
+    );
+}
+
+#[tokio::test]
+async fn test_ppp_external_ip_diverges() {
+    let server = MockIgdServer::builder()
+        .with_wan_ppp_connection()
+        .with_external_ip("203.0.113.1".parse().unwrap())
+        .with_ppp_external_ip("198.51.100.7".parse().unwrap())
+        .start()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        get_external_ip(&server.control_url(), "WANIPConnection").await,
+        "203.0.113.1"
+    );
+    assert_eq!(
+        get_external_ip(&server.ppp_control_url(), "WANPPPConnection").await,
+        "198.51.100.7"
+    );
+}
+
+#[tokio::test]
+async fn test_second_wan_device() {
+    let server = MockIgdServer::builder()
+        .with_port_mapping_table()
+        .with_external_ip("203.0.113.1".parse().unwrap())
+        .with_second_wan_device("198.51.100.7".parse().unwrap())
+        .start()
+        .await
+        .unwrap();
+    let description = reqwest::get(server.description_url())
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(description.matches("device:WANDevice:1").count(), 2);
+    assert!(description.contains("<controlURL>/ctl/IPConn2</controlURL>"));
+
+    // Each WANDevice reports its own address
+    assert_eq!(
+        get_external_ip(&server.control_url(), "WANIPConnection").await,
+        "203.0.113.1"
+    );
+    assert_eq!(
+        get_external_ip(&server.second_wan_control_url(), "WANIPConnection").await,
+        "198.51.100.7"
+    );
+
+    // and keeps its own mappings
+    let (status, _) = soap_request(
+        &server.second_wan_control_url(),
+        "AddPortMapping",
+        &add_port_mapping_body(8080, 0),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert!(server.port_mappings().await.is_empty());
+    assert_eq!(
+        server.second_wan_port_mappings().await[0].external_port,
+        8080
+    );
+    let (status, _) = soap_request(
+        &server.control_url(),
+        "GetSpecificPortMappingEntry",
+        &get_specific_port_mapping_entry_body(8080),
+    )
+    .await;
+    assert_eq!(status, 500);
+
+    let requests = server.received_requests().await;
+    assert_eq!(
+        requests.iter().map(|r| r.wan_device).collect::<Vec<_>>(),
+        [0, 1, 1, 0]
+    );
+}
+
+#[tokio::test]
+async fn test_servers_sharing_registry() {
+    let first = MockIgdServer::start().await.unwrap();
+    let second = MockIgdServer::builder()
+        .with_shared_registry(&first)
+        .start()
+        .await
+        .unwrap();
+    assert_ne!(first.control_url(), second.control_url());
+    assert_eq!(first.udn(), second.udn());
+
+    // A mock registered on one server answers on both
+    second
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("203.0.113.1".parse().unwrap()),
+        )
+        .await;
+    for server in [&first, &second] {
+        let (status, body) = soap_request(
+            &server.control_url(),
+            "GetExternalIPAddress",
+            r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1"/>"#,
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert!(body.contains("203.0.113.1"));
+    }
+    assert_eq!(first.received_requests().await.len(), 2);
+    assert_eq!(second.received_requests().await.len(), 2);
+}
+
+#[tokio::test]
+async fn test_usn_format_and_fixed_udn() {
+    use mock_igd::UsnFormat;
+
+    let udn = "uuid:upnp-InternetGatewayDevice-1_0-001122334455";
+    let server = MockIgdServer::builder()
+        .ssdp_port(0)
+        .with_udn(udn)
+        .with_usn_format(UsnFormat::Custom("{udn}::{st}::vendor".to_string()))
+        .start()
+        .await;
+    let server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+    assert_eq!(server.udn(), udn);
+
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let request = "M-SEARCH * HTTP/1.1\r\n\
+                   HOST: 239.255.255.250:1900\r\n\
+                   MAN: \"ssdp:discover\"\r\n\
+                   MX: 1\r\n\
+                   ST: ssdp:all\r\n\
+                   \r\n";
+    socket
+        .send_to(request.as_bytes(), server.ssdp_addr().unwrap())
+        .await
+        .unwrap();
+    let mut buf = [0u8; 2048];
+    let response = socket.recv_from(&mut buf);
+    let (len, _) = tokio::time::timeout(std::time::Duration::from_secs(1), response)
+        .await
+        .expect("no search response")
+        .unwrap();
+    let response = String::from_utf8_lossy(&buf[..len]);
+    assert!(response.contains(&format!(
+        "USN: {udn}::urn:schemas-upnp-org:device:InternetGatewayDevice:1::vendor\r\n"
+    )));
+
+    let description = reqwest::get(server.description_url())
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(description.contains(&format!("<UDN>{udn}</UDN>")));
+}
+
+#[tokio::test]
+async fn test_update_description_and_announce() {
+    let server = MockIgdServer::builder().ssdp_port(0).start().await;
+    let server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+    assert_eq!(server.config_id(), 1);
+
+    server
+        .update_description(|xml| *xml = xml.replace("Mock IGD", "Renamed IGD"))
+        .await;
+    let description = reqwest::get(server.description_url())
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(description.contains("<friendlyName>Renamed IGD</friendlyName>"));
+    assert_eq!(server.config_id(), 2);
+
+    server.announce().await.unwrap();
+    let sent = server.sent_ssdp_notifications().await;
+    assert_eq!(sent.len(), 3);
+    assert_eq!(sent[0].notification_type, "upnp:rootdevice");
+    assert!(sent.iter().all(|n| n.notification_subtype == "ssdp:alive"));
+    assert!(
+        sent.iter()
+            .all(|n| n.raw.contains("CONFIGID.UPNP.ORG: 2\r\n"))
+    );
+    let udn = server.udn();
+    let usns: Vec<_> = sent.iter().map(|n| n.usn.as_str()).collect();
+    assert_eq!(
+        usns,
+        [
+            format!("{udn}::upnp:rootdevice"),
+            udn.to_string(),
+            format!("{udn}::urn:schemas-upnp-org:device:InternetGatewayDevice:1"),
+        ]
+    );
+
+    // Announcements follow the configured USN format
+    let server = MockIgdServer::builder()
+        .ssdp_port(0)
+        .with_usn_format(mock_igd::UsnFormat::RootDevice)
+        .start()
+        .await
+        .unwrap();
+    server.announce().await.unwrap();
+    let rootdevice = format!("{}::upnp:rootdevice", server.udn());
+    let sent = server.sent_ssdp_notifications().await;
+    assert!(
+        sent.iter()
+            .all(|n| n.usn == rootdevice && n.raw.contains(&rootdevice))
+    );
+}
+
+#[tokio::test]
+async fn test_expiring_announcements() {
+    let server = MockIgdServer::builder()
+        .ssdp_port(0)
+        .with_expiring_announcements(1, 3)
+        .start()
+        .await;
+    let server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+
+    // Three rounds of root device, UDN and device type advertisements,
+    // half a max-age apart
+    tokio::time::sleep(std::time::Duration::from_millis(1300)).await;
+    let sent = server.sent_ssdp_notifications().await;
+    assert_eq!(sent.len(), 9);
+    assert!(
+        sent.iter()
+            .all(|n| n.raw.contains("CACHE-CONTROL: max-age=1\r\n"))
+    );
+    let gap = sent[3].timestamp - sent[0].timestamp;
+    assert!(gap >= std::time::Duration::from_millis(450), "{gap:?}");
+
+    // Then nothing more
+    tokio::time::sleep(std::time::Duration::from_millis(700)).await;
+    assert_eq!(server.sent_ssdp_notifications().await.len(), 9);
+}
+
+#[tokio::test]
+async fn test_restart_announces_again() {
+    let server = MockIgdServer::builder()
+        .ssdp_port(0)
+        .with_expiring_announcements(1, 1)
+        .start()
+        .await;
+    let mut server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert_eq!(server.sent_ssdp_notifications().await.len(), 3);
+
+    server.restart().await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert_eq!(server.sent_ssdp_notifications().await.len(), 6);
+}
+
+#[tokio::test]
+async fn test_ssdp_ephemeral_reply_port() {
+    let server = MockIgdServer::builder()
+        .ssdp_port(0)
+        .with_ssdp_ephemeral_reply_port()
+        .start()
+        .await;
+    let server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let request = "M-SEARCH * HTTP/1.1\r\n\
+                   HOST: 239.255.255.250:1900\r\n\
+                   MAN: \"ssdp:discover\"\r\n\
+                   MX: 1\r\n\
+                   ST: ssdp:all\r\n\
+                   \r\n";
+    let ssdp_addr = server.ssdp_addr().unwrap();
+    socket.send_to(request.as_bytes(), ssdp_addr).await.unwrap();
+    let mut buf = [0u8; 2048];
+    let response = socket.recv_from(&mut buf);
+    let (len, source) = tokio::time::timeout(std::time::Duration::from_secs(1), response)
+        .await
+        .expect("no search response")
+        .unwrap();
+    assert!(buf[..len].starts_with(b"HTTP/1.1 200 OK"));
+    assert_ne!(source.port(), ssdp_addr.port());
+}
+
+/// Send an M-SEARCH for `search_target` and return the response, if any.
+async fn msearch(ssdp_addr: std::net::SocketAddr, search_target: &str) -> Option<String> {
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\n\
+         HOST: 239.255.255.250:1900\r\n\
+         MAN: \"ssdp:discover\"\r\n\
+         MX: 1\r\n\
+         ST: {search_target}\r\n\
+         \r\n"
+    );
+    socket.send_to(request.as_bytes(), ssdp_addr).await.unwrap();
+    let mut buf = [0u8; 2048];
+    let response = socket.recv_from(&mut buf);
+    let (len, _) = tokio::time::timeout(std::time::Duration::from_millis(300), response)
+        .await
+        .ok()?
+        .unwrap();
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+#[tokio::test]
+async fn test_ssdp_search_targets() {
+    let server = MockIgdServer::builder().ssdp_port(0).start().await;
+    let server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+    let ssdp_addr = server.ssdp_addr().unwrap();
+    for target in [
+        "urn:schemas-upnp-org:service:WANPPPConnection:1",
+        "urn:schemas-upnp-org:device:InternetGatewayDevice:2",
+    ] {
+        let response = msearch(ssdp_addr, target)
+            .await
+            .expect("no search response");
+        assert!(response.contains(&format!("ST: {target}\r\n")));
+        assert!(response.contains(&format!("USN: {}::{target}\r\n", server.udn())));
+    }
+    assert!(
+        msearch(ssdp_addr, "urn:schemas-upnp-org:device:MediaServer:1")
+            .await
+            .is_none()
+    );
+
+    let server = MockIgdServer::builder()
+        .ssdp_port(0)
+        .with_ssdp_search_targets(["urn:schemas-upnp-org:device:InternetGatewayDevice:2"])
+        .start()
+        .await
+        .unwrap();
+    let ssdp_addr = server.ssdp_addr().unwrap();
+    let igd2 = msearch(
+        ssdp_addr,
+        "urn:schemas-upnp-org:device:InternetGatewayDevice:2",
+    )
+    .await;
+    assert!(igd2.is_some());
+    let igd1 = msearch(
+        ssdp_addr,
+        "urn:schemas-upnp-org:device:InternetGatewayDevice:1",
+    )
+    .await;
+    assert!(igd1.is_none());
+}
+
+#[tokio::test]
+async fn test_msearch_compliance() {
+    use mock_igd::{ComplianceLevel, ComplianceRule};
+
+    let server = MockIgdServer::builder()
+        .ssdp_port(0)
+        .with_compliance_rule(ComplianceRule::SearchMan, ComplianceLevel::Reject)
+        .start()
+        .await;
+    let server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+    let ssdp_addr = server.ssdp_addr().unwrap();
+
+    // A compliant search is answered without violations
+    assert!(msearch(ssdp_addr, "ssdp:all").await.is_some());
+    assert!(server.compliance_violations().await.is_empty());
+
+    // Unquoted MAN, out-of-range MX and a wrong port, multicast
+    let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await.unwrap();
+    let request = "M-SEARCH * HTTP/1.1\r\n\
+                   HOST: 239.255.255.250:1901\r\n\
+                   MAN: ssdp:discover\r\n\
+                   MX: 10\r\n\
+                   ST: ssdp:all\r\n\
+                   \r\n";
+    let multicast_addr = std::net::SocketAddr::from(([239, 255, 255, 250], ssdp_addr.port()));
+    if socket
+        .send_to(request.as_bytes(), multicast_addr)
+        .await
+        .is_err()
+    {
+        eprintln!("Skipping SSDP test - could not send multicast");
+        return;
+    }
+    let mut buf = [0u8; 2048];
+    let response = socket.recv_from(&mut buf);
+    let response = tokio::time::timeout(std::time::Duration::from_millis(300), response).await;
+    assert!(response.is_err(), "rejected search was answered");
+
+    let rules: Vec<_> = server
+        .compliance_violations()
+        .await
+        .into_iter()
+        .map(|v| (v.action_name, v.rule))
+        .collect();
+    assert_eq!(
+        rules,
+        [
+            ("M-SEARCH".to_string(), ComplianceRule::SearchMan),
+            ("M-SEARCH".to_string(), ComplianceRule::SearchHost),
+            ("M-SEARCH".to_string(), ComplianceRule::SearchMx),
+        ]
+    );
+    assert!(!server.received_ssdp_requests().await[1].answered);
+}
+
+#[tokio::test]
+async fn test_observer_mode() {
+    let server = MockIgdServer::builder()
+        .with_observer_allowed("GetExternalIPAddress")
+        .start()
+        .await
+        .unwrap();
+    server
+        .mock(Action::add_port_mapping(), Responder::success())
+        .await;
+    server
+        .mock(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("203.0.113.1".parse().unwrap()),
+        )
+        .await;
+
+    // Not answered, even though a mock matches
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_millis(300))
+        .build()
+        .unwrap();
+    let result = client
+        .post(server.control_url())
+        .header(
+            "SOAPAction",
+            "\"urn:schemas-upnp-org:service:WANIPConnection:1#AddPortMapping\"",
+        )
+        .body(add_port_mapping_body(8080, 0))
+        .send()
+        .await;
+    assert!(result.unwrap_err().is_timeout());
+
+    // Allowed actions are answered as usual
+    assert_eq!(
+        get_external_ip(&server.control_url(), "WANIPConnection").await,
+        "203.0.113.1"
+    );
+
+    let requests = server.received_requests().await;
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].action_name, "AddPortMapping");
+    assert!(requests[0].responder.is_none());
+}
+
+#[tokio::test]
+async fn test_observer_mode_ssdp() {
+    let server = MockIgdServer::builder()
+        .ssdp_port(0)
+        .with_observer_mode()
+        .start()
+        .await;
+    let server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+
+    assert!(
+        msearch(server.ssdp_addr().unwrap(), "ssdp:all")
+            .await
+            .is_none()
+    );
+    let searches = server.received_ssdp_requests().await;
+    assert_eq!(searches.len(), 1);
+    assert!(!searches[0].answered);
+}
+
+#[tokio::test]
+async fn test_assert_quiet_for() {
+    let server = MockIgdServer::start_simple("203.0.113.1".parse().unwrap())
+        .await
+        .unwrap();
+    get_external_ip(&server.control_url(), "WANIPConnection").await;
+
+    // Earlier requests don't count
+    server
+        .assert_quiet_for(std::time::Duration::from_millis(100))
+        .await;
+}
+
+#[tokio::test]
+#[should_panic(expected = "but received GET /rootDesc.xml")]
+async fn test_assert_quiet_for_fails_on_traffic() {
+    let server = MockIgdServer::start().await.unwrap();
+    let url = server.description_url();
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        reqwest::get(url).await.unwrap();
+    });
+
+    server
+        .assert_quiet_for(std::time::Duration::from_secs(5))
+        .await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_lease_renewal_in_virtual_time() {
+    use std::time::Duration;
+
+    let server = MockIgdServer::builder()
+        .with_port_mapping_table()
+        .start()
+        .await
+        .unwrap();
+    let url = server.control_url();
+    let wall_clock = std::time::Instant::now();
+
+    // Renew a one-hour lease every 50 minutes for a day
+    for _ in 0..29 {
+        let (status, _) =
+            soap_request(&url, "AddPortMapping", &add_port_mapping_body(8080, 3600)).await;
+        assert_eq!(status, 200);
+        tokio::time::sleep(Duration::from_secs(50 * 60)).await;
+    }
+    let mappings = server.port_mappings().await;
+    assert_eq!(mappings.len(), 1);
+    assert_eq!(mappings[0].lease_duration, 600);
+
+    // Stop renewing and let the lease run out
+    tokio::time::sleep(Duration::from_secs(601)).await;
+    let (status, body) = soap_request(
+        &url,
+        "GetSpecificPortMappingEntry",
+        &get_specific_port_mapping_entry_body(8080),
+    )
+    .await;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>714</errorCode>"));
+    assert!(server.port_mappings().await.is_empty());
+
+    let requests = server.received_requests().await;
+    assert_eq!(requests.len(), 30);
+    assert!(requests[29].timestamp > Duration::from_secs(24 * 60 * 60));
+    assert!(wall_clock.elapsed() < Duration::from_secs(10));
+}
+
+#[tokio::test]
+async fn test_client_agent_stats() {
+    let server = MockIgdServer::start_simple("203.0.113.1".parse().unwrap())
+        .await
+        .unwrap();
+    let client = reqwest::Client::builder()
+        .user_agent("igd-client/1.2")
+        .build()
+        .unwrap();
+    client.get(server.description_url()).send().await.unwrap();
+    let body = r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetExternalIPAddress>"#;
+    soap_request_with_client(&client, &server.control_url(), "GetExternalIPAddress", body).await;
+    get_external_ip(&server.control_url(), "WANIPConnection").await;
+
+    let agents = server.client_agents().await;
+    assert_eq!(agents.len(), 1);
+    assert_eq!(
+        agents[0].ip,
+        "127.0.0.1".parse::<std::net::IpAddr>().unwrap()
+    );
+    assert_eq!(agents[0].user_agents.get("igd-client/1.2"), Some(&2));
+    assert_eq!(
+        agents[0]
+            .soap_actions
+            .get("\"urn:schemas-upnp-org:service:WANIPConnection:1#*\""),
+        Some(&2)
+    );
+}
+
+#[tokio::test]
+async fn test_near_miss_for_unmatched_request() {
+    use mock_igd::Mock;
+
+    let server = MockIgdServer::builder()
+        .with_strict_matching()
+        .start()
+        .await
+        .unwrap();
+    server
+        .register(
+            Mock::new(
+                Action::add_port_mapping()
+                    .with_external_port(8080)
+                    .with_protocol(Protocol::UDP),
+                Responder::success(),
+            )
+            .named("game port"),
+        )
+        .await;
+    server
+        .mock(Action::delete_port_mapping(), Responder::success())
+        .await;
+
+    let (status, _) = soap_request(
+        &server.control_url(),
+        "AddPortMapping",
+        &add_port_mapping_body(8081, 0),
+    )
+    .await;
+    assert_eq!(status, 500);
+
+    let requests = server.received_requests().await;
+    let near_miss = requests[0].near_miss.as_ref().unwrap();
+    assert_eq!(near_miss.mock_name.as_deref(), Some("game port"));
+    let fields: Vec<_> = near_miss
+        .mismatches
+        .iter()
+        .map(|m| m.field.as_str())
+        .collect();
+    assert_eq!(fields, ["external_port", "protocol"]);
+    assert_eq!(near_miss.mismatches[0].expected, "8080");
+    assert_eq!(near_miss.mismatches[0].actual, "8081");
+
+    let error = server.health().await.unwrap_err().to_string();
+    assert!(error.contains(
+        "no mock matched AddPortMapping request; nearest miss: mock \"game port\": \
+         external_port: expected 8080, got 8081; protocol: expected UDP, got TCP"
+    ));
+}
+
+#[tokio::test]
+async fn test_strict_matching_with_fallbacks() {
+    let server = MockIgdServer::builder()
+        .with_strict_matching()
+        .with_unmatched_index_invalid()
+        .with_port_mapping_table()
+        .start()
+        .await
+        .unwrap();
+    server
+        .mock(
+            Action::add_port_mapping().with_external_port(8080),
+            Responder::success(),
+        )
+        .await;
+    let url = server.control_url();
+
+    // The end of the mapping list is answered, not a strict matching failure
+    let (status, body) = soap_request(
+        &url,
+        "GetGenericPortMappingEntry",
+        r#"<u:GetGenericPortMappingEntry xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewPortMappingIndex>0</NewPortMappingIndex>
+</u:GetGenericPortMappingEntry>"#,
+    )
+    .await;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>713</errorCode>"));
+
+    // The table answers what the mock doesn't, so there is no near miss
+    let (status, _) = soap_request(&url, "AddPortMapping", &add_port_mapping_body(8081, 0)).await;
+    assert_eq!(status, 200);
+    let requests = server.received_requests().await;
+    assert!(requests.iter().all(|r| r.near_miss.is_none()));
+    assert!(!server.dump_requests().await.contains("nearest miss"));
+    server.health().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_match_add_port_mapping_enabled_flag() {
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock(
+            Action::add_port_mapping().with_enabled(false),
+            Responder::success(),
+        )
+        .await;
+    server
+        .mock(
+            Action::add_port_mapping().with_enabled(true),
+            Responder::error(718, "ConflictInMappingEntry"),
+        )
+        .await;
+
+    let disabled = add_port_mapping_body(8080, 0)
+        .replace("<NewEnabled>1</NewEnabled>", "<NewEnabled>0</NewEnabled>");
+    let (status, _) = soap_request(&server.control_url(), "AddPortMapping", &disabled).await;
+    assert_eq!(status, 200);
+    let (status, body) = soap_request(
+        &server.control_url(),
+        "AddPortMapping",
+        &add_port_mapping_body(8080, 0),
+    )
+    .await;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>718</errorCode>"));
+}
+
+#[tokio::test]
+async fn test_port_reachability() {
+    use mock_igd::MappingQuirk;
+
+    let server = MockIgdServer::builder()
+        .with_port_mapping_table()
+        .start()
+        .await
+        .unwrap();
+    assert!(!server.is_port_reachable(8080, Protocol::TCP).await);
+    soap_request(
+        &server.control_url(),
+        "AddPortMapping",
+        &add_port_mapping_body(8080, 0),
+    )
+    .await;
+    assert!(server.is_port_reachable(8080, Protocol::TCP).await);
+    assert!(!server.is_port_reachable(8080, Protocol::UDP).await);
+
+    // The mapping is accepted and listed, but the port stays closed
+    let lying = MockIgdServer::builder()
+        .with_mapping_quirk(MappingQuirk::NotForwarded)
+        .start()
+        .await
+        .unwrap();
+    let (status, _) = soap_request(
+        &lying.control_url(),
+        "AddPortMapping",
+        &add_port_mapping_body(8080, 0),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(lying.port_mappings().await.len(), 1);
+    assert!(!lying.is_port_reachable(8080, Protocol::TCP).await);
+}
+
+/// AddPortMapping arguments for a mapping to a port on this machine.
+fn add_local_port_mapping_body(external_port: u16, protocol: &str, internal_port: u16) -> String {
+    add_port_mapping_body(external_port, 0)
+        .replace(">TCP<", &format!(">{protocol}<"))
+        .replace(
+            "<NewInternalPort>8080</NewInternalPort>",
+            &format!("<NewInternalPort>{internal_port}</NewInternalPort>"),
+        )
+        .replace("192.168.1.100", "127.0.0.1")
+}
+
+/// A port that was free a moment ago.
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Connect to `addr`, retrying while the listener comes up.
+async fn connect_with_retry(addr: std::net::SocketAddr) -> tokio::net::TcpStream {
+    for _ in 0..50 {
+        if let Ok(stream) = tokio::net::TcpStream::connect(addr).await {
+            return stream;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    panic!("nothing listens on {addr}");
+}
+
+/// Send `payload` to a UDP port on localhost and return the first reply,
+/// resending while the receiving side comes up.
+async fn udp_round_trip(port: u16, payload: &[u8]) -> Vec<u8> {
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buf = [0u8; 1024];
+    for _ in 0..50 {
+        socket.send_to(payload, ("127.0.0.1", port)).await.unwrap();
+        let received = socket.recv_from(&mut buf);
+        if let Ok(Ok((len, _))) =
+            tokio::time::timeout(std::time::Duration::from_millis(50), received).await
+        {
+            return buf[..len].to_vec();
+        }
+    }
+    panic!("no reply from UDP port {port}");
+}
+
+#[tokio::test]
+async fn test_echo_endpoints() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = MockIgdServer::builder()
+        .with_echo_endpoints()
+        .start()
+        .await
+        .unwrap();
+    let (tcp_port, udp_port) = (free_port(), free_port());
+    let url = server.control_url();
+    soap_request(
+        &url,
+        "AddPortMapping",
+        &add_local_port_mapping_body(8080, "TCP", tcp_port),
+    )
+    .await;
+    soap_request(
+        &url,
+        "AddPortMapping",
+        &add_local_port_mapping_body(8080, "UDP", udp_port),
+    )
+    .await;
+
+    let tcp_addr = std::net::SocketAddr::from(([127, 0, 0, 1], tcp_port));
+    let mut stream = connect_with_retry(tcp_addr).await;
+    stream.write_all(b"ping").await.unwrap();
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"ping");
+
+    assert_eq!(udp_round_trip(udp_port, b"pong").await, b"pong");
+
+    // Deleting the mapping stops its endpoint
+    soap_request(
+        &url,
+        "DeletePortMapping",
+        r#"<u:DeletePortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+            <NewRemoteHost></NewRemoteHost>
+            <NewExternalPort>8080</NewExternalPort>
+            <NewProtocol>TCP</NewProtocol>
+        </u:DeletePortMapping>"#,
+    )
+    .await;
+    let mut closed = false;
+    for _ in 0..50 {
+        if tokio::net::TcpStream::connect(tcp_addr).await.is_err() {
+            closed = true;
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert!(closed);
+}
+
+#[tokio::test]
+async fn test_nat_forwarding_to_echo_endpoint() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = MockIgdServer::builder()
+        .with_nat_forwarding()
+        .with_echo_endpoints()
+        .start()
+        .await
+        .unwrap();
+    let url = server.control_url();
+    let (external, tcp_port, udp_port) = (free_port(), free_port(), free_port());
+    let tcp_mapping = add_local_port_mapping_body(external, "TCP", tcp_port);
+    soap_request(&url, "AddPortMapping", &tcp_mapping).await;
+    let udp_mapping = add_local_port_mapping_body(external, "UDP", udp_port);
+    soap_request(&url, "AddPortMapping", &udp_mapping).await;
+
+    // Through the external port to the echo endpoint and back
+    let external_addr = std::net::SocketAddr::from(([127, 0, 0, 1], external));
+    let mut stream = connect_with_retry(external_addr).await;
+    stream.write_all(b"through nat").await.unwrap();
+    let mut reply = [0u8; 11];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"through nat");
+
+    assert_eq!(udp_round_trip(external, b"datagram").await, b"datagram");
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_uptime_resets_on_reboot() {
+    let server = MockIgdServer::builder()
+        .with_status_info()
+        .start()
+        .await
+        .unwrap();
+    let url = server.control_url();
+    let get_status = || {
+        soap_request(
+            &url,
+            "GetStatusInfo",
+            r#"<u:GetStatusInfo xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+            </u:GetStatusInfo>"#,
+        )
+    };
+
+    tokio::time::sleep(std::time::Duration::from_secs(3600)).await;
+    let (status, body) = get_status().await;
+    assert_eq!(status, 200);
+    assert!(body.contains("<NewConnectionStatus>Connected</NewConnectionStatus>"));
+    assert!(body.contains("<NewUptime>3600</NewUptime>"));
+    assert_eq!(server.uptime().as_secs(), 3600);
+
+    server.simulate_reboot().await;
+    tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+    let (_, body) = get_status().await;
+    assert!(body.contains("<NewUptime>5</NewUptime>"));
+    assert_eq!(server.boot_id(), 2);
+}
+
+#[tokio::test]
+async fn test_builder_rejects_conflicting_options() {
+    let result = MockIgdServer::builder()
+        .ssdp_only("http://192.0.2.1:5000/desc.xml")
+        .http_port(0)
+        .start()
+        .await;
+    match result {
+        Err(mock_igd::Error::InvalidBuilderConfig { reason }) => {
+            assert!(reason.contains("ssdp_only()"), "{reason}");
+        }
+        other => panic!("expected InvalidBuilderConfig, got {:?}", other.err()),
+    }
+
+    let result = MockIgdServer::builder()
+        .with_ppp_external_ip("198.51.100.7".parse().unwrap())
+        .start()
+        .await;
+    assert!(matches!(
+        result,
+        Err(mock_igd::Error::InvalidBuilderConfig { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_ssdp_bind_failure_names_port() {
+    // Without SO_REUSEPORT, this socket keeps the SSDP socket from binding
+    let taken = UdpSocket::bind("0.0.0.0:0").unwrap();
+    let port = taken.local_addr().unwrap().port();
+
+    let result = MockIgdServer::builder()
+        .ssdp_only("http://192.0.2.1:5000/desc.xml")
+        .ssdp_port(port)
+        .start()
+        .await;
+    match result {
+        Err(mock_igd::Error::SsdpBindFailed { port: failed, .. }) => assert_eq!(failed, port),
+        other => panic!("expected SsdpBindFailed, got {:?}", other.err()),
+    }
+}
+
+#[tokio::test]
+async fn test_quiet_ssdp() {
+    let start = |source: &str| {
+        MockIgdServer::builder()
+            .ssdp_port(0)
+            .with_quiet_ssdp(source.parse().unwrap())
+            .start()
+    };
+    let server = match start("192.0.2.1").await {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+    assert_eq!(msearch(server.ssdp_addr().unwrap(), "ssdp:all").await, None);
+    assert!(server.received_ssdp_requests().await.is_empty());
+
+    let server = start("127.0.0.1").await.unwrap();
+    let response = msearch(server.ssdp_addr().unwrap(), "ssdp:all").await;
+    assert!(response.unwrap().starts_with("HTTP/1.1 200 OK"));
+    assert_eq!(server.received_ssdp_requests().await.len(), 1);
+}
+
+#[tokio::test]
+async fn test_client_filter() {
+    use mock_igd::{ClientFilter, ClientRejection};
+
+    let localhost = "127.0.0.1".parse().unwrap();
+    let filter = ClientFilter::deny([localhost]).with_rejection(ClientRejection::Forbidden);
+    let server = MockIgdServer::builder()
+        .with_client_filter(filter)
+        .start()
+        .await
+        .unwrap();
+    let (status, _) = soap_request(
+        &server.control_url(),
+        "GetExternalIPAddress",
+        r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        </u:GetExternalIPAddress>"#,
+    )
+    .await;
+    assert_eq!(status, 403);
+    assert!(server.received_requests().await.is_empty());
+
+    let server = MockIgdServer::builder()
+        .with_client_filter(ClientFilter::allow(["192.0.2.1".parse().unwrap()]))
+        .start()
+        .await
+        .unwrap();
+    assert!(reqwest::get(server.description_url()).await.is_err());
+
+    let server = MockIgdServer::builder()
+        .with_client_filter(ClientFilter::allow([localhost]))
+        .start()
+        .await
+        .unwrap();
+    let response = reqwest::get(server.description_url()).await.unwrap();
+    assert_eq!(response.status(), 200);
+
+    let server = MockIgdServer::builder()
+        .ssdp_port(0)
+        .with_client_filter(ClientFilter::deny([localhost]))
+        .start()
+        .await;
+    if let Ok(server) = server
+        && let Some(ssdp_addr) = server.ssdp_addr()
+    {
+        assert_eq!(msearch(ssdp_addr, "ssdp:all").await, None);
+        assert!(server.received_ssdp_requests().await.is_empty());
+    }
+
+    // A quiet SSDP source the client filter rejects would never be answered
+    let result = MockIgdServer::builder()
+        .with_client_filter(ClientFilter::deny([localhost]))
+        .with_quiet_ssdp(localhost)
+        .start()
+        .await;
+    match result {
+        Err(mock_igd::Error::InvalidBuilderConfig { reason }) => {
+            assert!(reason.contains("with_quiet_ssdp()"), "{reason}");
+        }
+        other => panic!("expected InvalidBuilderConfig, got {:?}", other.err()),
+    }
+}
+
+#[tokio::test]
+async fn test_clock_skew() {
+    use mock_igd::ClockSkew;
+
+    let hour = std::time::Duration::from_secs(3600);
+    let server = MockIgdServer::builder()
+        .with_clock_skew(ClockSkew::behind(hour).with_timeout_skew(60))
+        .start()
+        .await
+        .unwrap();
+
+    let response = reqwest::get(server.description_url()).await.unwrap();
+    let date = response.headers()["Date"].to_str().unwrap();
+    let date = httpdate::parse_http_date(date).unwrap();
+    let behind = std::time::SystemTime::now().duration_since(date).unwrap();
+    assert!(
+        behind >= hour - std::time::Duration::from_secs(5),
+        "{behind:?}"
+    );
+    assert!(
+        behind <= hour + std::time::Duration::from_secs(5),
+        "{behind:?}"
+    );
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let response = reqwest::Client::new()
+        .request(
+            reqwest::Method::from_bytes(b"SUBSCRIBE").unwrap(),
+            server.event_url(),
+        )
+        .header(
+            "CALLBACK",
+            format!("<http://{}/notify>", listener.local_addr().unwrap()),
+        )
+        .header("NT", "upnp:event")
+        .header("TIMEOUT", "Second-300")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["TIMEOUT"], "Second-360");
+
+    let server = MockIgdServer::builder()
+        .with_clock_skew(ClockSkew::ahead(hour))
+        .start()
+        .await
+        .unwrap();
+    let response = reqwest::get(server.description_url()).await.unwrap();
+    let date = response.headers()["Date"].to_str().unwrap();
+    let date = httpdate::parse_http_date(date).unwrap();
+    let ahead = date.duration_since(std::time::SystemTime::now()).unwrap();
+    assert!(
+        ahead >= hour - std::time::Duration::from_secs(5),
+        "{ahead:?}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_mock_times_limit_under_concurrency() {
+    let server = MockIgdServer::start().await.unwrap();
+    server
+        .mock_with_times(
+            Action::GetExternalIPAddress,
+            Responder::success().with_external_ip("1.1.1.1".parse().unwrap()),
+            1,
+        )
+        .await;
+
+    let url = server.control_url();
+    let requests = (0..50).map(|_| {
+        let url = url.clone();
+        tokio::spawn(async move {
+            soap_request(
+                &url,
+                "GetExternalIPAddress",
+                r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+                </u:GetExternalIPAddress>"#,
+            )
+            .await
+        })
+    });
+    let responses = futures_util::future::join_all(requests).await;
+    let successes = responses
+        .into_iter()
+        .filter(|response| response.as_ref().unwrap().0 == 200)
+        .count();
+    assert_eq!(successes, 1);
+}
+
+#[tokio::test]
+async fn test_msearch_compliance_follows_real_delivery() {
+    use mock_igd::ComplianceRule;
+
+    let server = MockIgdServer::builder()
+        .ssdp_port(0)
+        .with_compliance_checks()
+        .start()
+        .await;
+    let server = match server {
+        Ok(s) if s.ssdp_addr().is_some() => s,
+        _ => {
+            eprintln!("Skipping SSDP test - could not start SSDP server");
+            return;
+        }
+    };
+    let ssdp_addr = server.ssdp_addr().unwrap();
+    let multicast_addr = std::net::SocketAddr::from(([239, 255, 255, 250], ssdp_addr.port()));
+    let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await.unwrap();
+    let search = |host: &str| {
+        format!(
+            "M-SEARCH * HTTP/1.1\r\n\
+             HOST: {host}\r\n\
+             MAN: \"ssdp:discover\"\r\n\
+             ST: ssdp:all\r\n\
+             \r\n"
+        )
+    };
+
+    // Multicast, but addressed to a device and without MX
+    let request = search("192.168.1.1:1900");
+    if socket
+        .send_to(request.as_bytes(), multicast_addr)
+        .await
+        .is_err()
+    {
+        eprintln!("Skipping SSDP test - could not send multicast");
+        return;
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let rules: Vec<_> = server
+        .compliance_violations()
+        .await
+        .into_iter()
+        .map(|v| v.rule)
+        .collect();
+    assert_eq!(
+        rules,
+        [ComplianceRule::SearchHost, ComplianceRule::SearchMx]
+    );
+
+    // Unicast with the multicast HOST needs no MX; only Linux reports the
+    // destination
+    if cfg!(target_os = "linux") {
+        let request = search("239.255.255.250:1900");
+        socket.send_to(request.as_bytes(), ssdp_addr).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert_eq!(server.compliance_violations().await.len(), 2);
+        assert_eq!(server.received_ssdp_requests().await.len(), 2);
+    }
+}